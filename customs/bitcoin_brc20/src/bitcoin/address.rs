@@ -1,8 +1,11 @@
 //! Utilities to derive, display, and parse bitcoin addresses.
 
+use crate::destination::Destination;
 use ic_btc_interface::Network;
+use ic_crypto_extended_bip32::{DerivationIndex, DerivationPath, ExtendedBip32DerivationOutput};
 use ic_crypto_sha2::Sha256;
 use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
 
 // See https://en.bitcoin.it/wiki/List_of_address_prefixes.
 const BTC_MAINNET_PREFIX: u8 = 0;
@@ -72,6 +75,46 @@ pub fn main_bitcoin_address(ecdsa_public_key: &ECDSAPublicKey) -> BitcoinAddress
     pubkey_to_bitcoin_address(ecdsa_public_key)
 }
 
+/// Returns the derivation path of the deposit address of the destination.
+pub fn derivation_path(destination: &Destination) -> Vec<ByteBuf> {
+    const SCHEMA_V1: u8 = 1;
+    vec![
+        ByteBuf::from(vec![SCHEMA_V1]),
+        ByteBuf::from(destination.target_chain_id.as_bytes()),
+        ByteBuf::from(destination.receiver.as_bytes()),
+    ]
+}
+
+/// Derives the public key of the deposit address of the destination.
+pub fn derive_public_key(
+    ecdsa_public_key: &ECDSAPublicKey,
+    destination: &Destination,
+) -> ECDSAPublicKey {
+    let ExtendedBip32DerivationOutput {
+        derived_public_key,
+        derived_chain_code,
+    } = DerivationPath::new(
+        derivation_path(destination)
+            .into_iter()
+            .map(|x| DerivationIndex(x.into_vec()))
+            .collect(),
+    )
+    .public_key_derivation(&ecdsa_public_key.public_key, &ecdsa_public_key.chain_code)
+    .expect("bug: failed to derive an ECDSA public key from valid inputs");
+    ECDSAPublicKey {
+        public_key: derived_public_key,
+        chain_code: derived_chain_code,
+    }
+}
+
+/// Derives the deposit address of the destination.
+pub fn destination_to_bitcoin_address(
+    ecdsa_public_key: &ECDSAPublicKey,
+    destination: &Destination,
+) -> BitcoinAddress {
+    pubkey_to_bitcoin_address(&derive_public_key(ecdsa_public_key, destination))
+}
+
 /// Constructs the bitcoin address corresponding to the specified destination.
 pub fn pubkey_to_bitcoin_address(ecdsa_public_key: &ECDSAPublicKey) -> BitcoinAddress {
     use ripemd::{Digest, Ripemd160};
@@ -119,3 +162,62 @@ pub fn hrp(network: Network) -> &'static str {
         ic_btc_interface::Network::Regtest => "bcrt",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn destination() -> Destination {
+        Destination {
+            target_chain_id: "eICP".to_string(),
+            receiver: "rrkah-fqaaa-aaaaa-aaaaq-cai".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_derivation_path() {
+        let path: Vec<Vec<u8>> = derivation_path(&destination())
+            .into_iter()
+            .map(|b| b.into_vec())
+            .collect();
+        assert_eq!(
+            path,
+            vec![
+                vec![1],
+                b"eICP".to_vec(),
+                b"rrkah-fqaaa-aaaaa-aaaaq-cai".to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_destination_to_bitcoin_address() {
+        // The generator point of secp256k1 as the public key.
+        let ecdsa_public_key = ECDSAPublicKey {
+            public_key: hex::decode(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap(),
+            chain_code: vec![1; 32],
+        };
+        let derived = derive_public_key(&ecdsa_public_key, &destination());
+        assert_eq!(
+            hex::encode(&derived.public_key),
+            "026e720973e8f8ab50cc7a8e442c74dc3276c77ecb198aaf426b8f691182217fa7"
+        );
+        assert_eq!(
+            destination_to_bitcoin_address(&ecdsa_public_key, &destination())
+                .display(Network::Testnet),
+            "tb1qws0mx3f2730a6crw7mprt6qv8ltmyugfm7eyn8"
+        );
+        // Another receiver has another address.
+        let other = Destination {
+            receiver: "ryjl3-tyaaa-aaaaa-aaaba-cai".to_string(),
+            ..destination()
+        };
+        assert_ne!(
+            destination_to_bitcoin_address(&ecdsa_public_key, &other).display(Network::Testnet),
+            "tb1qws0mx3f2730a6crw7mprt6qv8ltmyugfm7eyn8"
+        );
+    }
+}
@@ -1,15 +1,19 @@
 use crate::call_error::{CallError, Reason};
 use crate::constants::FINALIZE_LOCK_TICKET_NAME;
+use crate::destination::Destination;
 use crate::generate_ticket::GenerateTicketError::InvalidArgs;
 use crate::generate_ticket::{GenerateTicketArgs, GenerateTicketError};
 use crate::hub;
-use crate::ord::inscription::brc20::{Brc20, Brc20Transfer201};
-use crate::ord::mempool_rpc_types::TxInfo;
-use crate::ord::parser::OrdParser;
+use crate::inscription_index::{transfer_inscription, DepositUtxos, InscriptionProvenance};
+use crate::ord::inscription::brc20::Brc20Transfer201;
+use crate::ord::mempool_rpc_types::{MerkleProof, TxInfo};
 use crate::retry::call_rpc_with_retry;
-use crate::state::{deposit_addr, finalization_time_estimate, mutate_state, read_state};
+use crate::state::{
+    bitcoin_network, finalization_time_estimate, mutate_state, read_state, Brc20State,
+};
 use crate::types::{create_query_brc20_transfer_args, LockTicketRequest};
-use bitcoin::Transaction;
+use bitcoin::{Address, ScriptBuf, Transaction};
+use serde::de::DeserializeOwned;
 use ic_btc_interface::{Network, Txid};
 use ic_canister_log::log;
 use ic_cdk::api::management_canister::http_request::{
@@ -19,14 +23,24 @@ use ic_cdk::api::management_canister::http_request::{
 use omnity_types::brc20::{Brc20TransferEvent, QueryBrc20TransferArgs};
 use omnity_types::ic_log::{CRITICAL, ERROR, INFO, WARNING};
 
+/// Checks the transfer of the request, returns the transfer inscription, the
+/// address the tokens are deposited to and the provenance of the inscription.
+/// The inscription of a deposit to the main deposit address names its
+/// destination, a deposit to the derived address of the destination can be a
+/// plain transfer inscription.
 pub async fn check_transaction(
     req: GenerateTicketArgs,
-) -> Result<Brc20Transfer201, GenerateTicketError> {
+) -> Result<(Brc20Transfer201, String, InscriptionProvenance), GenerateTicketError> {
     let token = read_state(|s| s.tokens.get(&req.token_id).cloned())
         .ok_or(InvalidArgs(serde_json::to_string(&req).unwrap()))?;
     let chain = read_state(|s| s.counterparties.get(&req.target_chain_id).cloned())
         .ok_or(InvalidArgs(serde_json::to_string(&req).unwrap()))?;
-    let transfer_transfer = call_rpc_with_retry(&req.txid, query_transaction).await?;
+    let transfer_transfer = call_rpc_with_retry(&req.txid, query_raw_transaction).await?;
+    let output_address = |script: &ScriptBuf| {
+        Address::from_script(script, bitcoin_network())
+            .ok()
+            .map(|address| address.to_string())
+    };
     //check whether need to pay fees for transfer. If fee is None, that means paying fees is not need
     let (fee, addr) = read_state(|s|s.get_transfer_fee_info(&req.target_chain_id));
     match fee {
@@ -34,11 +48,10 @@ pub async fn check_transaction(
         Some(fee_value) => {
             let mut found_fee_utxo = false;
             let fee_collector = addr.unwrap();
-            for out in transfer_transfer.vout.clone() {
-                if out.scriptpubkey_address
-                    .clone()
+            for out in transfer_transfer.output.iter() {
+                if output_address(&out.script_pubkey)
                     .is_some_and(|address| address.eq(&fee_collector)) &&
-                    out.value as u128 == fee_value {
+                    out.value.to_sat() as u128 == fee_value {
                     found_fee_utxo = true;
                     break;
                 }
@@ -50,43 +63,96 @@ pub async fn check_transaction(
     }
 
     let receiver = transfer_transfer
-        .vout
+        .output
         .first()
-        .cloned()
-        .unwrap()
-        .scriptpubkey_address
-        .unwrap();
-    if receiver != deposit_addr().to_string() {
-        return Err(GenerateTicketError::InvalidTxId);
+        .and_then(|out| output_address(&out.script_pubkey))
+        .ok_or(GenerateTicketError::InvalidTxId)?;
+    let to_derived_addr = read_state(|s| is_derived_deposit(s, &receiver, &req))?;
+    let (t, provenance) = transfer_inscription(&transfer_transfer).await?;
+    if let Some(deposit_txid) =
+        read_state(|s| s.indexed_inscriptions.get(&provenance.inscription_id).cloned())
+    {
+        return Err(InvalidArgs(format!(
+            "the inscription {} is deposited by {}",
+            provenance.inscription_id, deposit_txid
+        )));
     }
-    let inscribe_txid = transfer_transfer.vin.first().cloned().unwrap().txid;
-    let inscribe_transfer: Transaction = call_rpc_with_retry(&inscribe_txid, query_transaction)
-        .await?
-        .try_into()
-        .map_err(|e: anyhow::Error| GenerateTicketError::RpcError(e.to_string()))?;
-    let (_inscription_id, parsed_inscription) = OrdParser::parse_one(&inscribe_transfer, 0)
-        .map_err(|e| GenerateTicketError::OrdTxError(e.to_string()))?;
-    let brc20 = Brc20::try_from(parsed_inscription)
-        .map_err(|e| GenerateTicketError::OrdTxError(e.to_string()))?;
-    log!(INFO, "brc20 info:{:?}", serde_json::to_string(&brc20));
-    match brc20 {
-        Brc20::TransferBrc201(t) => {
-
-            if t.amt != req.amount
-                || !t.tick.eq_ignore_ascii_case(&token.name)
-                || !t.refx.eq_ignore_ascii_case(&req.receiver)
-                || t.chain != chain.chain_id
-            {
-                Err(InvalidArgs(serde_json::to_string(&t).unwrap()))
-            } else {
-                Ok(t)
-            }
-        }
-        _ => Err(GenerateTicketError::NotBridgeTx),
+    if t.amt != req.amount
+        || !t.tick.eq_ignore_ascii_case(&token.name)
+        || (!to_derived_addr
+            && (!t.refx.eq_ignore_ascii_case(&req.receiver) || t.chain != chain.chain_id))
+    {
+        Err(InvalidArgs(serde_json::to_string(&t).unwrap()))
+    } else {
+        Ok((t, receiver, provenance))
+    }
+}
+
+/// Returns true if the tokens are deposited to the derived address of the
+/// destination of the request, false if they are deposited to the main deposit
+/// address. The derived address is derived again, it's known even if it was
+/// never asked for.
+fn is_derived_deposit(
+    s: &Brc20State,
+    deposit_addr: &str,
+    req: &GenerateTicketArgs,
+) -> Result<bool, GenerateTicketError> {
+    let destination = Destination {
+        target_chain_id: req.target_chain_id.clone(),
+        receiver: req.receiver.clone(),
+    };
+    if s.ecdsa_public_key.is_some() && s.destination_address(&destination) == deposit_addr {
+        Ok(true)
+    } else if s.deposit_addr.as_deref() == Some(deposit_addr) {
+        Ok(false)
+    } else {
+        Err(GenerateTicketError::InvalidTxId)
     }
 }
 
 pub async fn query_transaction(txid: &String) -> Result<TxInfo, GenerateTicketError> {
+    query_mempool(format!("tx/{}", txid)).await
+}
+
+/// Returns the raw transaction, checked to hash to the txid.
+pub async fn query_raw_transaction(txid: &String) -> Result<Transaction, GenerateTicketError> {
+    let raw = query_mempool_text(format!("tx/{}/hex", txid)).await?;
+    let bytes = hex::decode(raw.trim())
+        .map_err(|e| GenerateTicketError::RpcError(format!("invalid transaction hex: {}", e)))?;
+    let tx: Transaction = bitcoin::consensus::deserialize(&bytes)
+        .map_err(|e| GenerateTicketError::RpcError(format!("invalid transaction: {}", e)))?;
+    if tx.txid().to_string() != *txid {
+        return Err(GenerateTicketError::RpcError(format!(
+            "the transaction returned for {} hashes to {}",
+            txid,
+            tx.txid()
+        )));
+    }
+    Ok(tx)
+}
+
+/// Returns the merkle branch of the confirmed transaction, it proves nothing until
+/// it's checked against a block header.
+pub async fn query_merkle_proof(txid: &String) -> Result<MerkleProof, GenerateTicketError> {
+    query_mempool(format!("tx/{}/merkle-proof", txid)).await
+}
+
+/// Returns the latest transactions of the address, the unconfirmed ones first.
+pub async fn query_address_transactions(
+    address: &String,
+) -> Result<Vec<TxInfo>, GenerateTicketError> {
+    query_mempool(format!("address/{}/txs", address)).await
+}
+
+async fn query_mempool<T: DeserializeOwned>(path: String) -> Result<T, GenerateTicketError> {
+    let body = query_mempool_text(path).await?;
+    serde_json::from_str(&body).map_err(|e| {
+        log!(CRITICAL, "json error {:?}", e);
+        GenerateTicketError::RpcError("failed to decode transaction from json".to_string())
+    })
+}
+
+async fn query_mempool_text(path: String) -> Result<String, GenerateTicketError> {
     let nw = read_state(|s| s.btc_network);
     let network_str = match nw {
         Network::Mainnet => "".to_string(),
@@ -96,7 +162,7 @@ pub async fn query_transaction(txid: &String) -> Result<TxInfo, GenerateTicketEr
         }
     };
     const MAX_CYCLES: u128 = 60_000_000_000;
-    let url = format!("https://mempool.space/{}/api/tx/{}", network_str, txid);
+    let url = format!("https://mempool.space/{}/api/{}", network_str, path);
 
     let request = CanisterHttpRequestArgument {
         url: url.to_string(),
@@ -126,13 +192,7 @@ pub async fn query_transaction(txid: &String) -> Result<TxInfo, GenerateTicketEr
                     )
                 })?;
                 log!(INFO, "tx content: {}", &body);
-                let tx: TxInfo = serde_json::from_str(&body).map_err(|e| {
-                    log!(CRITICAL, "json error {:?}", e);
-                    GenerateTicketError::RpcError(
-                        "failed to decode transaction from json".to_string(),
-                    )
-                })?;
-                Ok(tx)
+                Ok(body)
             } else {
                 Err(GenerateTicketError::RpcError(
                     "http response not 200".to_string(),
@@ -168,8 +228,9 @@ pub async fn finalize_lock_ticket_request() {
             .collect::<Vec<(Txid, LockTicketRequest)>>()
     });
     let deposit_addr = read_state(|s| s.deposit_addr.clone().unwrap());
+    let mut utxos = DepositUtxos::default();
     for (txid, gen_ticket_request) in can_check_finalizations.clone() {
-        finalize_lock(txid, gen_ticket_request, deposit_addr.clone()).await;
+        finalize_lock(txid, gen_ticket_request, deposit_addr.clone(), &mut utxos).await;
     }
 }
 
@@ -177,6 +238,7 @@ pub async fn finalize_lock(
     txid: Txid,
     gen_ticket_request: LockTicketRequest,
     deposit_addr: String,
+    utxos: &mut DepositUtxos,
 ) {
     let token = read_state(|s| s.tokens.get(&gen_ticket_request.token_id).cloned());
     match token {
@@ -188,12 +250,51 @@ pub async fn finalize_lock(
             );
         }
         Some(token) => {
+            let deposit_addr = gen_ticket_request.deposit_addr.clone().unwrap_or(deposit_addr);
+            let inscription = gen_ticket_request.inscription.as_ref();
+            match utxos
+                .is_deposit_confirmed(txid, &deposit_addr, inscription)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    log!(
+                        WARNING,
+                        "deposit {} is not confirmed by the bitcoin canister, will retry",
+                        txid
+                    );
+                    return;
+                }
+                Err(e) => {
+                    log!(ERROR, "query utxos of {} error: {}", deposit_addr, e);
+                    return;
+                }
+            }
             let args = create_query_brc20_transfer_args(
                 gen_ticket_request.clone(),
                 deposit_addr.clone(),
                 token.decimals,
             );
             let query = query_indexed_transfer(args).await;
+            // The balance is transferred from the address the inscription is inscribed
+            // to, taken from the reveal transaction proven to be in a block.
+            if let (Ok(Some(t)), Some(i)) = (&query, inscription) {
+                if i.inscriber != t.from {
+                    log!(
+                        CRITICAL,
+                        "indexer reports deposit {} from {}, but it's inscribed to {}, rejected",
+                        txid,
+                        t.from,
+                        i.inscriber
+                    );
+                    mutate_state(|s| {
+                        if let Some(req) = s.pending_lock_ticket_requests.remove(&txid) {
+                            s.rejected_lock_ticket_requests.insert(txid, req);
+                        }
+                    });
+                    return;
+                }
+            }
             if let Ok(Some(t)) = query {
                 //Check success
                 //FINALIZED TO HUB:
@@ -206,6 +307,10 @@ pub async fn finalize_lock(
                 mutate_state(|s| {
                     let v = s.pending_lock_ticket_requests.remove(&txid);
                     s.finalized_lock_ticket_requests.insert(txid, v.unwrap());
+                    // The tokens of a derived address are swept to the main deposit address.
+                    if s.deposit_destinations.contains_key(&deposit_addr) {
+                        s.pending_sweeps.insert(txid, gen_ticket_request.clone());
+                    }
                 });
                 log!(INFO, "lock ticket finalized:{:?}", t);
             } else {
@@ -235,3 +340,68 @@ pub async fn query_indexed_transfer(
             })?;
     Ok(resp.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::ECDSAPublicKey;
+    use crate::service::InitArgs;
+    use candid::Principal;
+
+    const MAIN_ADDR: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+    // The address derived for eICP and rrkah-fqaaa-aaaaa-aaaaq-cai.
+    const DERIVED_ADDR: &str = "tb1qws0mx3f2730a6crw7mprt6qv8ltmyugfm7eyn8";
+
+    fn new_state() -> Brc20State {
+        let mut s = Brc20State::init(InitArgs {
+            admins: vec![],
+            hub_principal: Principal::anonymous(),
+            network: omnity_types::Network::Testnet,
+            chain_id: "Bitcoin".to_string(),
+            indexer_principal: Principal::anonymous(),
+            fee_token: "ICP".to_string(),
+        })
+        .unwrap();
+        s.ecdsa_public_key = Some(ECDSAPublicKey {
+            public_key: hex::decode(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap(),
+            chain_code: vec![1; 32],
+        });
+        s.deposit_addr = Some(MAIN_ADDR.to_string());
+        s
+    }
+
+    fn request(receiver: &str) -> GenerateTicketArgs {
+        GenerateTicketArgs {
+            txid: "".to_string(),
+            amount: "1".to_string(),
+            target_chain_id: "eICP".to_string(),
+            token_id: "Bitcoin-brc20-ordi".to_string(),
+            receiver: receiver.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_is_derived_deposit() {
+        let s = new_state();
+        let req = request("rrkah-fqaaa-aaaaa-aaaaq-cai");
+        assert_eq!(is_derived_deposit(&s, DERIVED_ADDR, &req), Ok(true));
+        assert_eq!(is_derived_deposit(&s, MAIN_ADDR, &req), Ok(false));
+        // The derived address of another destination.
+        let other = request("ryjl3-tyaaa-aaaaa-aaaba-cai");
+        assert_eq!(
+            is_derived_deposit(&s, DERIVED_ADDR, &other),
+            Err(GenerateTicketError::InvalidTxId)
+        );
+        assert_eq!(is_derived_deposit(&s, MAIN_ADDR, &other), Ok(false));
+        // The address is not derived without the ecdsa public key.
+        let mut s = new_state();
+        s.ecdsa_public_key = None;
+        assert_eq!(
+            is_derived_deposit(&s, DERIVED_ADDR, &req),
+            Err(GenerateTicketError::InvalidTxId)
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::ops::Div;
 use std::str::FromStr;
 
@@ -21,18 +22,26 @@ use omnity_types::ic_log::{CRITICAL, ERROR, INFO};
 use omnity_types::{Seq, Ticket};
 
 use crate::custom_to_bitcoin::CustomToBitcoinError::{
-    ArgumentError, BuildTransactionFailed, SignFailed,
+    ArgumentError, BuildTransactionFailed, SendTransactionFailed, SignFailed,
 };
 
+use crate::deposit_sweep::is_unlock_held;
+use crate::fee_bump::{
+    bump_stuck_unlock, check_fee_bump, is_stuck, release_bump_inputs, transfer_txids, FeeBump,
+};
+use crate::generate_ticket::GenerateTicketError;
 use crate::hub::update_tx_hash;
 use crate::ord::builder::fees::Fees;
 use crate::ord::builder::signer::MixSigner;
 use crate::ord::builder::spend_transaction::spend_utxo_transaction;
 use crate::ord::builder::{
-    CreateCommitTransactionArgsV2, OrdTransactionBuilder, RevealTransactionArgs,
-    SignCommitTransactionArgs, Utxo,
+    CreateBatchCommitTransactionArgs, CreateCommitTransactionArgsV2, OrdTransactionBuilder,
+    RevealTransactionArgs, SignCommitTransactionArgs, Utxo,
 };
 use crate::ord::inscription::brc20::Brc20;
+use crate::ord::mempool_rpc_types::TxInfo;
+use crate::ord::result::OrdError;
+use crate::unlock_retry::{record_unlock_failure, retry_failed_unlock_tickets};
 use crate::{management, state};
 
 use crate::ord::parser::POSTAGE;
@@ -49,18 +58,61 @@ pub enum CustomToBitcoinError {
     BuildTransactionFailed(String),
     #[error("ArgumentError: {0}")]
     ArgumentError(String),
+    #[error("send transaction error: {0}")]
+    SendTransactionFailed(String),
     #[error("InsufficientFunds")]
     InsufficientFunds,
+    #[error("management call error: {0}")]
+    ManagementCallFailed(String),
+    #[error("insufficient balance: required: {required}, available: {available}")]
+    InsufficientBalance { required: u64, available: u64 },
 }
 pub type CustomToBitcoinResult<T> = Result<T, CustomToBitcoinError>;
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+impl From<OrdError> for CustomToBitcoinError {
+    fn from(e: OrdError) -> Self {
+        match e {
+            OrdError::ManagementError(msg) => CustomToBitcoinError::ManagementCallFailed(msg),
+            OrdError::InsufficientBalance {
+                required,
+                available,
+            } => CustomToBitcoinError::InsufficientBalance {
+                required,
+                available,
+            },
+            e => BuildTransactionFailed(e.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct SendTicketResult {
     pub txs: Vec<Transaction>,
     pub success: bool,
     pub err_step: Option<u8>,
     pub err_info: Option<CallError>,
     pub time_at: u64,
+    /// The tickets unlocked with the same commit transaction, mapped to their
+    /// reveal and transfer transactions, empty if the ticket is unlocked alone.
+    #[serde(default)]
+    pub batch: BTreeMap<Seq, UnlockTxids>,
+    /// The fee rate in satoshi per vbyte paid by the transactions.
+    #[serde(default)]
+    pub fee_rate: u64,
+    /// The fee utxos spent by the commit transaction.
+    #[serde(default)]
+    pub commit_inputs: Vec<Utxo>,
+    /// The fee utxos added to the transfer transaction by a CPFP bump.
+    #[serde(default)]
+    pub bump_inputs: Vec<Utxo>,
+    #[serde(default)]
+    pub fee_bumps: Vec<FeeBump>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UnlockTxids {
+    pub reveal_txid: String,
+    pub transfer_txid: String,
 }
 
 pub async fn send_tickets_to_bitcoin() {
@@ -69,21 +121,150 @@ pub async fn send_tickets_to_bitcoin() {
     if from < to {
         log!(INFO, "submit unlock tx: from {} to {}", from, to);
         let fee_rate = estimate_fee_per_vbyte().await / 1000;
+        let batch_size = read_state(|s| s.unlock_batch_size);
+        if batch_size > 1 {
+            send_ticket_batches_to_bitcoin(from, to, batch_size, fee_rate).await;
+            return;
+        }
         for seq in from..to {
-            let r = process_unlock_ticket(seq, fee_rate).await;
-            match r {
-                Ok(_) => {
-                    mutate_state(|s| s.next_consume_ticket_seq = seq + 1);
+            if read_state(|s| is_unlock_held(s, seq)) {
+                log!(
+                    INFO,
+                    "unlock ticket {} waits for the sweeps of its token",
+                    seq
+                );
+                break;
+            }
+            if !unlock_or_record_failure(seq, fee_rate).await {
+                break;
+            }
+            mutate_state(|s| s.next_consume_ticket_seq = seq + 1);
+        }
+    }
+}
+
+/// Unlocks the ticket, moving it to the failed unlock tickets on error so that
+/// the later tickets are not blocked. Returns false if the fee utxos are used up,
+/// the ticket is left to the next round then.
+async fn unlock_or_record_failure(seq: Seq, fee_rate: u64) -> bool {
+    match process_unlock_ticket(seq, fee_rate).await {
+        Ok(_) => true,
+        Err(CustomToBitcoinError::InsufficientFunds) => {
+            log!(
+                ERROR,
+                "send unlock error: ticket seq: {}, insufficient fee utxos",
+                seq
+            );
+            false
+        }
+        Err(e) => {
+            log!(ERROR, "send unlock error: ticket seq: {}, error{}", seq, e);
+            record_unlock_failure(seq, &e);
+            true
+        }
+    }
+}
+
+/// Unlocks the consecutive tickets of the same tick with one commit transaction.
+async fn send_ticket_batches_to_bitcoin(from: Seq, to: Seq, batch_size: u64, fee_rate: u64) {
+    let mut seq = from;
+    while seq < to {
+        let batch = next_unlock_batch(seq, to, batch_size);
+        // The tickets of a batch share the token, they wait for its sweeps together.
+        if let Some((first, _)) = batch.first() {
+            if read_state(|s| is_unlock_held(s, *first)) {
+                log!(
+                    INFO,
+                    "unlock ticket {} waits for the sweeps of its token",
+                    first
+                );
+                break;
+            }
+        }
+        // An empty batch means the remaining tickets are all processed or missing.
+        let next_seq = batch.last().map(|(s, _)| s + 1).unwrap_or(to);
+        if !batch.is_empty() {
+            let seqs: Vec<Seq> = batch.iter().map(|(s, _)| *s).collect();
+            match submit_unlock_batch(batch, fee_rate).await {
+                Ok(results) => {
+                    for (seq, info) in results {
+                        record_unlock_result(seq, info);
+                    }
                 }
-                Err(e) => {
-                    log!(ERROR, "send unlock error: ticket seq: {}, error{}", seq, e);
+                Err(CustomToBitcoinError::InsufficientFunds) => {
+                    log!(
+                        ERROR,
+                        "send ticket batch to bitcoin failed {:?}, insufficient fee utxos",
+                        seqs
+                    );
                     break;
                 }
+                Err(e) => {
+                    log!(
+                        CRITICAL,
+                        "send ticket batch to bitcoin failed {:?}, {}",
+                        seqs,
+                        &e
+                    );
+                    // Unlocks the tickets one by one to find the failing ones.
+                    let mut unlocked = true;
+                    for seq in seqs {
+                        if !unlock_or_record_failure(seq, fee_rate).await {
+                            unlocked = false;
+                            break;
+                        }
+                        mutate_state(|s| s.next_consume_ticket_seq = seq + 1);
+                    }
+                    if !unlocked {
+                        break;
+                    }
+                }
             }
         }
+        mutate_state(|s| s.next_consume_ticket_seq = next_seq);
+        seq = next_seq;
     }
 }
 
+/// Returns the unprocessed tickets from `from`, stopping at the first ticket of
+/// another tick, the tickets missing from the queue are skipped.
+fn next_unlock_batch(from: Seq, to: Seq, batch_size: u64) -> Vec<(Seq, Ticket)> {
+    read_state(|s| {
+        let mut batch: Vec<(Seq, Ticket)> = vec![];
+        for seq in from..to {
+            let ticket = match s.tickets_queue.get(&seq) {
+                Some(ticket) => ticket,
+                None if batch.is_empty() => continue,
+                None => break,
+            };
+            if batch.first().is_some_and(|(_, t)| t.token != ticket.token) {
+                break;
+            }
+            if s.finalized_unlock_ticket_map.contains_key(&seq)
+                || s.flight_unlock_ticket_map.contains_key(&seq)
+            {
+                if batch.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            batch.push((seq, ticket));
+            if batch.len() as u64 >= batch_size {
+                break;
+            }
+        }
+        batch
+    })
+}
+
+fn record_unlock_result(seq: Seq, info: SendTicketResult) {
+    let reveal_utxo_index = format!("{}:0", info.txs[1].txid());
+    mutate_state(|s| {
+        s.flight_unlock_ticket_map.insert(seq, info);
+        s.reveal_utxo_index.insert(reveal_utxo_index);
+    });
+}
+
 pub async fn process_unlock_ticket(seq: Seq, fee_rate: u64) -> Result<(), CustomToBitcoinError> {
     let res = submit_unlock_ticket(seq, fee_rate).await;
     if res.is_err() {
@@ -94,13 +275,7 @@ pub async fn process_unlock_ticket(seq: Seq, fee_rate: u64) -> Result<(), Custom
         let r = res.ok().unwrap();
         match r {
             None => {}
-            Some(info) => {
-                let reveal_utxo_index = format!("{}:0", info.txs[1].txid());
-                mutate_state(|s| {
-                    s.flight_unlock_ticket_map.insert(seq, info);
-                    s.reveal_utxo_index.insert(reveal_utxo_index);
-                });
-            }
+            Some(info) => record_unlock_result(seq, info),
         }
     }
     Ok(())
@@ -123,39 +298,72 @@ pub async fn finalize_flight_unlock_tickets() {
             s.min_confirmations as u32,
         )
     });
-    for (seq, send_result) in can_check_finalizations.clone() {
-        let need_check_tx = send_result.txs.last().cloned().unwrap();
-        let transfer_txid = need_check_tx.txid().to_string();
-        let tx = query_transaction(&transfer_txid).await;
-        match tx {
-            Ok(t) => {
-                if t.status.confirmed {
-                    mutate_state(|s| {
-                        let r = s.flight_unlock_ticket_map.remove(&seq).unwrap();
-                        let reveal_utxo_index = format!("{}:0", r.txs[1].txid());
-                        s.reveal_utxo_index.remove(&reveal_utxo_index);
-                        s.finalized_unlock_ticket_map.insert(seq, r);
-                    });
-                    let (hub_principal, ticket) =
-                        read_state(|s| (s.hub_principal, s.tickets_queue.get(&seq).unwrap()));
-                    if let Err(err) =
-                        update_tx_hash(hub_principal, ticket.ticket_id, transfer_txid).await
-                    {
-                        log!(
-                            CRITICAL,
-                            "[rewrite tx_hash] failed to write brc20 release tx hash, reason: {}",
-                            err
-                        );
-                    } else {
-                        log!(INFO, "unlock ticket finalize success! ticket: {}", seq);
-                    }
-                }
+    let mut fee_rate = None;
+    for (seq, send_result) in can_check_finalizations {
+        let send_result = check_fee_bump(seq, send_result, now).await;
+        let confirmed = query_confirmed_transfer(&send_result).await;
+        if let Ok(Some((transfer_txid, transfer))) = confirmed {
+            let mut r = mutate_state(|s| s.flight_unlock_ticket_map.remove(&seq)).unwrap();
+            release_bump_inputs(&mut r, &transfer_txid, &transfer);
+            mutate_state(|s| {
+                let reveal_utxo_index = format!("{}:0", r.txs[1].txid());
+                s.reveal_utxo_index.remove(&reveal_utxo_index);
+                s.finalized_unlock_ticket_map.insert(seq, r);
+            });
+            let (hub_principal, ticket) =
+                read_state(|s| (s.hub_principal, s.tickets_queue.get(&seq).unwrap()));
+            if let Err(err) = update_tx_hash(hub_principal, ticket.ticket_id, transfer_txid).await {
+                log!(
+                    CRITICAL,
+                    "[rewrite tx_hash] failed to write brc20 release tx hash, reason: {}",
+                    err
+                );
+            } else {
+                log!(INFO, "unlock ticket finalize success! ticket: {}", seq);
             }
-            Err(e) => {
-                log!(ERROR, "confirm flight ticket error: {:?}", e);
+            continue;
+        }
+        if let Err(e) = &confirmed {
+            log!(ERROR, "confirm flight ticket error: {:?}", e);
+        }
+        // The transfer is not found if it failed to be sent.
+        let bump = match confirmed {
+            Ok(_) => is_stuck(&send_result, now),
+            Err(_) => !send_result.success && is_stuck(&send_result, now),
+        };
+        if bump {
+            let estimate = match fee_rate {
+                Some(r) => r,
+                None => {
+                    let r = estimate_fee_per_vbyte().await / 1000;
+                    fee_rate = Some(r);
+                    r
+                }
+            };
+            bump_stuck_unlock(seq, send_result, estimate).await;
+        }
+    }
+}
+
+/// Returns the confirmed transfer of the ticket with its txid, the current
+/// transfer or one replaced by a fee bump. The error is the one of the current
+/// transfer query.
+async fn query_confirmed_transfer(
+    send_result: &SendTicketResult,
+) -> Result<Option<(String, TxInfo)>, GenerateTicketError> {
+    let txids = transfer_txids(send_result);
+    let current = match query_transaction(&txids[0]).await {
+        Ok(t) if t.status.confirmed => return Ok(Some((txids[0].clone(), t))),
+        r => r.map(|_| None),
+    };
+    for txid in txids.iter().skip(1) {
+        if let Ok(t) = query_transaction(txid).await {
+            if t.status.confirmed {
+                return Ok(Some((txid.clone(), t)));
             }
         }
     }
+    current
 }
 
 pub async fn submit_unlock_ticket(
@@ -172,7 +380,7 @@ pub async fn submit_unlock_ticket(
             if read_state(|s| s.flight_unlock_ticket_map.contains_key(&seq)) {
                 return Ok(None);
             }
-            let mut vins = select_utxos(fee_rate, FIXED_COMMIT_TX_VBYTES)?;
+            let mut vins = select_utxos(fee_rate, FIXED_COMMIT_TX_VBYTES, 1)?;
             let fees = create_fees(vins.len() as u64, fee_rate);
             let tx_vec = generate_brc20_transactions(vins.clone(), &fees, &t)
                 .await
@@ -186,6 +394,11 @@ pub async fn submit_unlock_ticket(
                 err_step: None,
                 err_info: None,
                 time_at: ic_cdk::api::time(),
+                batch: BTreeMap::new(),
+                fee_rate,
+                commit_inputs: vins.clone(),
+                bump_inputs: vec![],
+                fee_bumps: vec![],
             };
             for (index, tx) in tx_vec.into_iter().enumerate() {
                 let r = crate::management::send_transaction(&tx).await;
@@ -204,12 +417,158 @@ pub async fn submit_unlock_ticket(
                 }
             } else {
                 mutate_state(|s| s.deposit_addr_utxo.append(&mut vins));
+                // Nothing is sent, the ticket can be unlocked again from scratch.
+                let err = send_res.err_info.map(|e| e.to_string()).unwrap_or_default();
+                return Err(SendTransactionFailed(err));
             }
             Ok(Some(send_res))
         }
     }
 }
 
+/// Sends one commit transaction for the tickets, followed by the reveal and the
+/// transfer transactions of every ticket.
+pub async fn submit_unlock_batch(
+    tickets: Vec<(Seq, Ticket)>,
+    fee_rate: u64,
+) -> Result<Vec<(Seq, SendTicketResult)>, CustomToBitcoinError> {
+    let count = tickets.len() as u64;
+    let fixed_size =
+        estimate_batch_commit_size(0, count) + count * (REVEAL_TX_VBYTES + TRANSFER_TX_VBYTES);
+    let mut vins = select_utxos(fee_rate, fixed_size, count)?;
+    let fees = create_batch_fees(vins.len() as u64, count, fee_rate);
+    let (commit_tx, unlock_txs, leftover) =
+        generate_batch_brc20_transactions(vins.clone(), &fees, &tickets)
+            .await
+            .map_err(|e| {
+                mutate_state(|s| s.deposit_addr_utxo.append(&mut vins));
+                e
+            })?;
+    let batch: BTreeMap<Seq, UnlockTxids> = tickets
+        .iter()
+        .zip(unlock_txs.iter())
+        .map(|((seq, _), (reveal, transfer))| {
+            (
+                *seq,
+                UnlockTxids {
+                    reveal_txid: reveal.txid().to_string(),
+                    transfer_txid: transfer.txid().to_string(),
+                },
+            )
+        })
+        .collect();
+    let time_at = ic_cdk::api::time();
+    if let Err(e) = crate::management::send_transaction(&commit_tx).await {
+        mutate_state(|s| s.deposit_addr_utxo.append(&mut vins));
+        return Err(SendTransactionFailed(e.to_string()));
+    }
+    let mut results = vec![];
+    for ((seq, _), (reveal, transfer)) in tickets.into_iter().zip(unlock_txs.into_iter()) {
+        let txs = vec![commit_tx.clone(), reveal, transfer];
+        let mut send_res = SendTicketResult {
+            txs: txs.clone(),
+            success: true,
+            err_step: None,
+            err_info: None,
+            time_at,
+            batch: batch.clone(),
+            fee_rate,
+            commit_inputs: vins.clone(),
+            bump_inputs: vec![],
+            fee_bumps: vec![],
+        };
+        for (index, tx) in txs.iter().enumerate().skip(1) {
+            let r = crate::management::send_transaction(tx).await;
+            if r.is_err() {
+                send_res.success = false;
+                send_res.err_step = Some(index as u8);
+                send_res.err_info = r.err();
+                break;
+            }
+        }
+        results.push((seq, send_res));
+    }
+    if let Some(u) = leftover {
+        mutate_state(|s| s.deposit_addr_utxo.push(u));
+    }
+    Ok(results)
+}
+
+/// Builds the commit transaction of the tickets and the reveal and transfer
+/// transactions of each ticket, in the order of the tickets.
+/// Also returns the leftover output of the commit transaction, if any.
+pub async fn generate_batch_brc20_transactions(
+    vins: Vec<Utxo>,
+    fees: &Fees,
+    tickets: &[(Seq, Ticket)],
+) -> CustomToBitcoinResult<(Transaction, Vec<(Transaction, Transaction)>, Option<Utxo>)> {
+    let mut inscriptions = vec![];
+    for (_, ticket) in tickets {
+        let token = read_state(|s| s.tokens.get(&ticket.token).cloned().unwrap());
+        let amount: u128 = ticket.amount.parse().unwrap();
+        let amt = Decimal::from(amount).div(Decimal::from(10u128.pow(token.decimals as u32)));
+        inscriptions.push(Brc20::transfer(token.name.clone(), amt));
+    }
+    let mut builder = OrdTransactionBuilder::p2tr(
+        PublicKey::from_str(deposit_pubkey().as_str()).unwrap(),
+        deposit_addr(),
+    );
+    let commit_tx = builder
+        .build_batch_commit_transaction_with_fixed_fees(
+            bitcoin_network(),
+            CreateBatchCommitTransactionArgs {
+                inputs: vins.clone(),
+                inscriptions,
+                txin_script_pubkey: deposit_addr().script_pubkey(),
+                fees: fees.clone(),
+            },
+        )
+        .await?;
+    let signed_commit_tx = builder
+        .sign_commit_transaction(
+            commit_tx.unsigned_tx,
+            SignCommitTransactionArgs {
+                inputs: vins,
+                txin_script_pubkey: deposit_addr().script_pubkey(),
+            },
+        )
+        .await
+        .map_err(|e| SignFailed(e.to_string()))?;
+    // The leftover output follows the outputs of the inscriptions.
+    let leftover = (commit_tx.leftover_amount > Amount::ZERO).then(|| Utxo {
+        id: signed_commit_tx.txid(),
+        index: tickets.len() as u32,
+        amount: commit_tx.leftover_amount,
+    });
+    let mut unlock_txs = vec![];
+    for (index, ((_, ticket), reveal)) in tickets.iter().zip(commit_tx.reveals).enumerate() {
+        let reveal_transaction = builder
+            .build_reveal_transaction_with_payload(
+                RevealTransactionArgs {
+                    input: Utxo {
+                        id: signed_commit_tx.txid(),
+                        index: index as u32,
+                        amount: reveal.reveal_balance,
+                    },
+                    spend_fee: fees.spend_fee,
+                    recipient_address: deposit_addr(),
+                    redeem_script: reveal.redeem_script,
+                },
+                &reveal.taproot_payload,
+            )
+            .await?;
+        let real_utxo = Utxo {
+            id: reveal_transaction.txid(),
+            index: 0,
+            amount: Amount::from_sat(POSTAGE + fees.spend_fee.to_sat()),
+        };
+        let transfer_transaction =
+            build_transfer_transfer(&ticket.receiver, real_utxo, Some(&builder.signer())).await?;
+        unlock_txs.push((reveal_transaction, transfer_transaction));
+    }
+    Ok((signed_commit_tx, unlock_txs, leftover))
+}
+
 pub async fn generate_brc20_transactions(
     vins: Vec<Utxo>,
     fees: &Fees,
@@ -218,6 +577,29 @@ pub async fn generate_brc20_transactions(
     let token = read_state(|s| s.tokens.get(&ticket.token).cloned().unwrap());
     let amount: u128 = ticket.amount.parse().unwrap();
     let amt = Decimal::from(amount).div(Decimal::from(10u128.pow(token.decimals as u32)));
+    let receiver = Address::from_str(&ticket.receiver)
+        .map_err(|e| ArgumentError(e.to_string()))?
+        .assume_checked();
+    generate_transfer_transactions(
+        vins,
+        fees,
+        Brc20::transfer(token.name.clone(), amt),
+        None,
+        receiver,
+    )
+    .await
+}
+
+/// Builds the commit, reveal and transfer transactions of a transfer inscription.
+/// The inscription is revealed to the holder of the tokens, the main deposit
+/// address if none, then sent from the holder to the receiver.
+pub async fn generate_transfer_transactions(
+    vins: Vec<Utxo>,
+    fees: &Fees,
+    inscription: Brc20,
+    holder: Option<MixSigner>,
+    receiver: Address,
+) -> CustomToBitcoinResult<Vec<Transaction>> {
     let mut builder = OrdTransactionBuilder::p2tr(
         PublicKey::from_str(deposit_pubkey().as_str()).unwrap(),
         deposit_addr(),
@@ -227,13 +609,12 @@ pub async fn generate_brc20_transactions(
             bitcoin_network(),
             CreateCommitTransactionArgsV2 {
                 inputs: vins.clone(),
-                inscription: Brc20::transfer(token.name.clone(), amt),
+                inscription,
                 txin_script_pubkey: deposit_addr().script_pubkey(),
                 fees: fees.clone(),
             },
         )
-        .await
-        .map_err(|e| BuildTransactionFailed(e.to_string()))?;
+        .await?;
     let signed_commit_tx = builder
         .sign_commit_transaction(
             commit_tx.unsigned_tx,
@@ -244,6 +625,7 @@ pub async fn generate_brc20_transactions(
         )
         .await
         .map_err(|e| SignFailed(e.to_string()))?;
+    let holder = holder.unwrap_or(builder.signer());
     let reveal_transaction = builder
         .build_reveal_transaction(RevealTransactionArgs {
             input: Utxo {
@@ -252,18 +634,22 @@ pub async fn generate_brc20_transactions(
                 amount: commit_tx.reveal_balance,
             },
             spend_fee: fees.spend_fee,
-            recipient_address: deposit_addr(), // NOTE: it's correct, see README.md to read about how transfer works
+            recipient_address: holder.signer_addr.clone(), // NOTE: it's correct, see README.md to read about how transfer works
             redeem_script: commit_tx.redeem_script,
         })
-        .await
-        .map_err(|e| BuildTransactionFailed(e.to_string()))?;
+        .await?;
     let real_utxo = Utxo {
         id: reveal_transaction.txid(),
         index: 0,
         amount: Amount::from_sat(POSTAGE + fees.spend_fee.to_sat()),
     };
-    let transfer_trasaction =
-        build_transfer_transfer(&ticket.receiver, real_utxo, Some(&builder.signer())).await?;
+    let transfer_trasaction = spend_utxo_transaction(
+        Some(&holder),
+        receiver,
+        Amount::from_sat(POSTAGE),
+        vec![real_utxo],
+    )
+    .await?;
     Ok(vec![
         signed_commit_tx,
         reveal_transaction,
@@ -299,12 +685,16 @@ pub async fn build_transfer_transfer(
     Ok(transfer)
 }
 
-pub fn select_utxos(fee_rate: u64, fixed_size: u64) -> CustomToBitcoinResult<Vec<Utxo>> {
+pub fn select_utxos(
+    fee_rate: u64,
+    fixed_size: u64,
+    inscription_count: u64,
+) -> CustomToBitcoinResult<Vec<Utxo>> {
     let mut selected_utxos: Vec<Utxo> = vec![];
     let mut selected_amount = 0u64;
     let mut estimate_size = fixed_size;
     mutate_state(|s| loop {
-        if selected_amount >= fee_rate * estimate_size + POSTAGE {
+        if selected_amount >= fee_rate * estimate_size + POSTAGE * inscription_count {
             return Ok(selected_utxos);
         }
         let u = s.deposit_addr_utxo.pop();
@@ -340,6 +730,7 @@ pub fn submit_unlock_tickets_task() {
                 None => return,
             };
         send_tickets_to_bitcoin().await;
+        retry_failed_unlock_tickets().await;
     });
 }
 
@@ -387,6 +778,142 @@ pub fn create_fees(input_count: u64, fee_rate: u64) -> Fees {
     }
 }
 
+/// The fees of a batch, the reveal and the spend fees are paid for each inscription.
+pub fn create_batch_fees(input_count: u64, inscription_count: u64, fee_rate: u64) -> Fees {
+    Fees {
+        commit_fee: Amount::from_sat(
+            estimate_batch_commit_size(input_count, inscription_count) * fee_rate,
+        ),
+        reveal_fee: Amount::from_sat(REVEAL_TX_VBYTES * fee_rate),
+        spend_fee: Amount::from_sat(TRANSFER_TX_VBYTES * fee_rate),
+    }
+}
+
 pub fn estimate_commit_size(input_count: u64) -> u64 {
     input_count * INPUT_SIZE_VBYTES + 2 * OUTPUT_SIZE_VBYTES + TX_OVERHEAD_VBYTES
 }
+
+/// The size of a commit transaction with one output per inscription and the leftover output.
+pub fn estimate_batch_commit_size(input_count: u64, inscription_count: u64) -> u64 {
+    input_count * INPUT_SIZE_VBYTES
+        + (inscription_count + 1) * OUTPUT_SIZE_VBYTES
+        + TX_OVERHEAD_VBYTES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::InitArgs;
+    use crate::state::{replace_state, Brc20State};
+    use candid::Principal;
+    use omnity_types::{TicketType, TxAction};
+
+    fn init_state() {
+        replace_state(
+            Brc20State::init(InitArgs {
+                admins: vec![],
+                hub_principal: Principal::anonymous(),
+                network: omnity_types::Network::Testnet,
+                chain_id: "Bitcoin".to_string(),
+                indexer_principal: Principal::anonymous(),
+                fee_token: "ICP".to_string(),
+            })
+            .unwrap(),
+        );
+    }
+
+    fn queue_ticket(seq: Seq, token: &str) {
+        let ticket = Ticket {
+            ticket_id: seq.to_string(),
+            ticket_type: TicketType::Normal,
+            ticket_time: 0,
+            src_chain: "eICP".to_string(),
+            dst_chain: "Bitcoin".to_string(),
+            action: TxAction::Redeem,
+            token: token.to_string(),
+            amount: "1000".to_string(),
+            sender: None,
+            receiver: "bc1p5d7rjq7g6rdk2yhzks9smlaqtedr4dekq08ge8ztwac72sfr9rusxg3297".to_string(),
+            memo: None,
+        };
+        mutate_state(|s| s.tickets_queue.insert(seq, ticket));
+    }
+
+    fn seqs(batch: Vec<(Seq, Ticket)>) -> Vec<Seq> {
+        batch.into_iter().map(|(seq, _)| seq).collect()
+    }
+
+    #[test]
+    fn test_next_unlock_batch_stops_at_another_tick() {
+        init_state();
+        queue_ticket(0, "Bitcoin-brc20-ordi");
+        queue_ticket(1, "Bitcoin-brc20-ordi");
+        queue_ticket(2, "Bitcoin-brc20-sats");
+        queue_ticket(3, "Bitcoin-brc20-ordi");
+
+        assert_eq!(seqs(next_unlock_batch(0, 4, 10)), vec![0, 1]);
+        assert_eq!(seqs(next_unlock_batch(2, 4, 10)), vec![2]);
+        assert_eq!(seqs(next_unlock_batch(3, 4, 10)), vec![3]);
+    }
+
+    #[test]
+    fn test_next_unlock_batch_skips_processed_and_missing_tickets() {
+        init_state();
+        for seq in [0, 1, 2, 4, 5] {
+            queue_ticket(seq, "Bitcoin-brc20-ordi");
+        }
+        mutate_state(|s| {
+            s.finalized_unlock_ticket_map
+                .insert(0, SendTicketResult::default());
+            s.flight_unlock_ticket_map
+                .insert(1, SendTicketResult::default());
+            s.flight_unlock_ticket_map
+                .insert(5, SendTicketResult::default());
+        });
+
+        // The leading processed tickets are skipped, the batch stops at the gap.
+        assert_eq!(seqs(next_unlock_batch(0, 6, 10)), vec![2]);
+        // The leading missing ticket is skipped, the batch stops at the processed one.
+        assert_eq!(seqs(next_unlock_batch(3, 6, 10)), vec![4]);
+        assert!(next_unlock_batch(5, 6, 10).is_empty());
+        assert!(next_unlock_batch(6, 8, 10).is_empty());
+    }
+
+    #[test]
+    fn test_next_unlock_batch_respects_batch_size() {
+        init_state();
+        for seq in 0..5 {
+            queue_ticket(seq, "Bitcoin-brc20-ordi");
+        }
+
+        assert_eq!(seqs(next_unlock_batch(0, 5, 2)), vec![0, 1]);
+        assert_eq!(seqs(next_unlock_batch(2, 5, 2)), vec![2, 3]);
+        assert_eq!(seqs(next_unlock_batch(4, 5, 2)), vec![4]);
+        // The range end is exclusive.
+        assert_eq!(seqs(next_unlock_batch(0, 3, 10)), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_estimate_batch_commit_size() {
+        // One output per inscription and the leftover output.
+        assert_eq!(
+            estimate_batch_commit_size(2, 3),
+            2 * INPUT_SIZE_VBYTES + 4 * OUTPUT_SIZE_VBYTES + TX_OVERHEAD_VBYTES
+        );
+        // A batch of one inscription is as large as a single commit transaction.
+        assert_eq!(estimate_batch_commit_size(3, 1), estimate_commit_size(3));
+    }
+
+    #[test]
+    fn test_create_batch_fees() {
+        let fees = create_batch_fees(2, 3, 10);
+        assert_eq!(
+            fees.commit_fee,
+            Amount::from_sat(estimate_batch_commit_size(2, 3) * 10)
+        );
+        // The reveal and the spend fees are per inscription.
+        assert_eq!(fees.reveal_fee, Amount::from_sat(REVEAL_TX_VBYTES * 10));
+        assert_eq!(fees.spend_fee, Amount::from_sat(TRANSFER_TX_VBYTES * 10));
+        assert_eq!(create_fees(2, 10).reveal_fee, fees.reveal_fee);
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bitcoin::{Address, PublicKey};
+use ic_btc_interface::Txid;
+use ic_canister_log::log;
+use rust_decimal::Decimal;
+
+use omnity_types::brc20::QueryBrc20TransferArgs;
+use omnity_types::ic_log::{CRITICAL, ERROR, INFO, WARNING};
+use omnity_types::Seq;
+
+use crate::bitcoin::{derivation_path, derive_public_key};
+use crate::bitcoin_to_custom::{query_indexed_transfer, query_transaction};
+use crate::constants::{FIXED_COMMIT_TX_VBYTES, MAX_SWEEPS_PER_ROUND, SWEEP_DEPOSITS_NAME};
+use crate::custom_to_bitcoin::CustomToBitcoinError::{ArgumentError, SendTransactionFailed};
+use crate::custom_to_bitcoin::{
+    create_fees, estimate_fee_per_vbyte, find_commit_remain_fee, generate_transfer_transactions,
+    select_utxos, CustomToBitcoinError, CustomToBitcoinResult, SendTicketResult,
+};
+use crate::management;
+use crate::ord::builder::signer::MixSigner;
+use crate::ord::inscription::brc20::Brc20;
+use crate::state::{deposit_addr, mutate_state, read_state, Brc20State};
+use crate::types::LockTicketRequest;
+
+pub fn sweep_deposits_task() {
+    ic_cdk::spawn(async {
+        let _guard = match crate::guard::TimerLogicGuard::new(SWEEP_DEPOSITS_NAME.to_string()) {
+            Some(guard) => guard,
+            None => return,
+        };
+        finalize_flight_sweeps().await;
+        sweep_deposits().await;
+    });
+}
+
+/// Returns true if the unlock of the ticket waits for the sweeps of its token,
+/// the main deposit address may miss the swept balance before they are confirmed
+/// and indexed.
+pub fn is_unlock_held(s: &Brc20State, seq: Seq) -> bool {
+    let token_id = match s.tickets_queue.get(&seq) {
+        Some(ticket) => ticket.token,
+        None => return false,
+    };
+    s.pending_sweeps
+        .values()
+        .any(|req| req.token_id == token_id)
+        || s.flight_sweeps.keys().any(|txid| {
+            s.finalized_lock_ticket_requests
+                .get(txid)
+                .is_some_and(|req| req.token_id == token_id)
+        })
+}
+
+/// Moves the tokens of the finalized deposits to derived addresses over to the
+/// main deposit address, where the unlock transactions spend them from.
+async fn sweep_deposits() {
+    let sweeps: Vec<(Txid, LockTicketRequest)> = read_state(|s| {
+        s.pending_sweeps
+            .iter()
+            .take(MAX_SWEEPS_PER_ROUND)
+            .map(|(txid, req)| (*txid, req.clone()))
+            .collect()
+    });
+    if sweeps.is_empty() {
+        return;
+    }
+    let fee_rate = estimate_fee_per_vbyte().await / 1000;
+    for (txid, req) in sweeps {
+        match sweep_deposit(&req, fee_rate).await {
+            Ok(send_res) => {
+                log!(
+                    INFO,
+                    "sweep deposit {} to the main deposit address: {}",
+                    txid,
+                    send_res.txs[2].txid()
+                );
+                mutate_state(|s| {
+                    s.pending_sweeps.remove(&txid);
+                    s.flight_sweeps.insert(txid, send_res);
+                });
+            }
+            // The fee utxos are used up, the later sweeps would fail the same way.
+            Err(CustomToBitcoinError::InsufficientFunds) => break,
+            Err(e) => {
+                log!(ERROR, "sweep deposit {} error: {}", txid, e);
+            }
+        }
+    }
+}
+
+/// Inscribes a transfer of the deposit to its derived address, paid by the fee
+/// utxos of the main deposit address, and transfers it to the main address.
+async fn sweep_deposit(
+    req: &LockTicketRequest,
+    fee_rate: u64,
+) -> CustomToBitcoinResult<SendTicketResult> {
+    let holder_addr = req.deposit_addr.clone().unwrap_or_default();
+    let (destination, ecdsa_public_key) = read_state(|s| {
+        (
+            s.deposit_destinations.get(&holder_addr).cloned(),
+            s.ecdsa_public_key.clone(),
+        )
+    });
+    let destination = destination.ok_or(ArgumentError(format!(
+        "{} is not a derived deposit address",
+        holder_addr
+    )))?;
+    let ecdsa_public_key =
+        ecdsa_public_key.ok_or(ArgumentError("the ecdsa public key is not set".into()))?;
+    let holder_pubkey = derive_public_key(&ecdsa_public_key, &destination);
+    let holder = MixSigner::derived(
+        PublicKey::from_slice(&holder_pubkey.public_key)
+            .map_err(|e| ArgumentError(e.to_string()))?,
+        Address::from_str(&holder_addr)
+            .map_err(|e| ArgumentError(e.to_string()))?
+            .assume_checked(),
+        derivation_path(&destination),
+    );
+    let amount = Decimal::from_str(&req.amount).map_err(|e| ArgumentError(e.to_string()))?;
+    let inscription = Brc20::transfer(req.ticker.clone(), amount);
+
+    let mut vins = select_utxos(fee_rate, FIXED_COMMIT_TX_VBYTES, 1)?;
+    let fees = create_fees(vins.len() as u64, fee_rate);
+    let txs = generate_transfer_transactions(
+        vins.clone(),
+        &fees,
+        inscription,
+        Some(holder),
+        deposit_addr(),
+    )
+    .await
+    .map_err(|e| {
+        mutate_state(|s| s.deposit_addr_utxo.append(&mut vins));
+        e
+    })?;
+    if let Err(e) = management::send_transaction(&txs[0]).await {
+        mutate_state(|s| s.deposit_addr_utxo.append(&mut vins));
+        return Err(SendTransactionFailed(e.to_string()));
+    }
+    if let Some(u) = find_commit_remain_fee(&txs[0]) {
+        mutate_state(|s| s.deposit_addr_utxo.push(u));
+    }
+    let mut send_res = SendTicketResult {
+        txs: txs.clone(),
+        success: true,
+        err_step: None,
+        err_info: None,
+        time_at: ic_cdk::api::time(),
+        batch: BTreeMap::new(),
+        fee_rate,
+        commit_inputs: vins,
+        bump_inputs: vec![],
+        fee_bumps: vec![],
+    };
+    send_remaining(&mut send_res, 1).await;
+    Ok(send_res)
+}
+
+/// Sends the transactions of the sweep from the index on, recording the first
+/// one that fails to be sent.
+async fn send_remaining(send_res: &mut SendTicketResult, from: usize) {
+    send_res.success = true;
+    send_res.err_step = None;
+    send_res.err_info = None;
+    for (index, tx) in send_res.txs.clone().iter().enumerate().skip(from) {
+        if let Err(e) = management::send_transaction(tx).await {
+            send_res.success = false;
+            send_res.err_step = Some(index as u8);
+            send_res.err_info = Some(e);
+            break;
+        }
+    }
+}
+
+/// Removes the sweeps whose transfer is confirmed and accepted by the indexer,
+/// and sends again the transactions that failed to be sent.
+async fn finalize_flight_sweeps() {
+    let flights: Vec<(Txid, SendTicketResult)> = read_state(|s| {
+        s.flight_sweeps
+            .iter()
+            .map(|(txid, r)| (*txid, r.clone()))
+            .collect()
+    });
+    for (txid, mut send_res) in flights {
+        if let Some(step) = send_res.err_step {
+            send_remaining(&mut send_res, step as usize).await;
+            mutate_state(|s| s.flight_sweeps.insert(txid, send_res));
+            continue;
+        }
+        let transfer_txid = send_res.txs[2].txid().to_string();
+        match query_transaction(&transfer_txid).await {
+            Ok(tx) if tx.status.confirmed => {
+                check_indexed_sweep(txid, &transfer_txid).await;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log!(ERROR, "query sweep of deposit {} error: {:?}", txid, e);
+            }
+        }
+    }
+}
+
+/// Removes the sweep once the indexer credits its transfer to the main deposit
+/// address, it's moved to the failed sweeps if the indexer rejects it.
+async fn check_indexed_sweep(txid: Txid, transfer_txid: &str) {
+    let (req, token, main_addr) = read_state(|s| {
+        let req = s.finalized_lock_ticket_requests.get(&txid).cloned();
+        let token = req
+            .as_ref()
+            .and_then(|r| s.tokens.get(&r.token_id).cloned());
+        (req, token, s.deposit_addr.clone().unwrap_or_default())
+    });
+    let (req, token) = match (req, token) {
+        (Some(req), Some(token)) => (req, token),
+        _ => {
+            log!(ERROR, "the deposit {} of the sweep is not found", txid);
+            return;
+        }
+    };
+    let args = QueryBrc20TransferArgs {
+        tx_id: transfer_txid.to_string(),
+        ticker: req.ticker.clone(),
+        to_addr: main_addr.clone(),
+        amt: req.amount.clone(),
+        decimals: token.decimals,
+    };
+    match query_indexed_transfer(args).await {
+        Ok(Some(t))
+            if t.valid && Some(&t.from) == req.deposit_addr.as_ref() && t.to == main_addr =>
+        {
+            log!(
+                INFO,
+                "sweep of deposit {} is confirmed: {}",
+                txid,
+                transfer_txid
+            );
+            mutate_state(|s| s.flight_sweeps.remove(&txid));
+        }
+        Ok(Some(t)) => {
+            log!(
+                CRITICAL,
+                "the indexer rejects the sweep {} of deposit {}: {:?}",
+                transfer_txid,
+                txid,
+                t
+            );
+            mutate_state(|s| {
+                if let Some(r) = s.flight_sweeps.remove(&txid) {
+                    s.failed_sweeps.insert(txid, r);
+                }
+            });
+        }
+        Ok(None) => {
+            log!(
+                WARNING,
+                "the sweep {} of deposit {} is not indexed yet",
+                transfer_txid,
+                txid
+            );
+        }
+        Err(e) => {
+            log!(
+                ERROR,
+                "query the indexed sweep of deposit {} error: {}",
+                txid,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::InitArgs;
+    use candid::Principal;
+    use omnity_types::{Ticket, TicketType, TxAction};
+
+    fn new_state() -> Brc20State {
+        let mut s = Brc20State::init(InitArgs {
+            admins: vec![],
+            hub_principal: Principal::anonymous(),
+            network: omnity_types::Network::Testnet,
+            chain_id: "Bitcoin".to_string(),
+            indexer_principal: Principal::anonymous(),
+            fee_token: "ICP".to_string(),
+        })
+        .unwrap();
+        for (seq, token) in [(0, "Bitcoin-brc20-ordi"), (1, "Bitcoin-brc20-sats")] {
+            let ticket = Ticket {
+                ticket_id: seq.to_string(),
+                ticket_type: TicketType::Normal,
+                ticket_time: 0,
+                src_chain: "eICP".to_string(),
+                dst_chain: "Bitcoin".to_string(),
+                action: TxAction::Redeem,
+                token: token.to_string(),
+                amount: "1000".to_string(),
+                sender: None,
+                receiver: "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".to_string(),
+                memo: None,
+            };
+            s.tickets_queue.insert(seq, ticket);
+        }
+        s
+    }
+
+    fn deposit(n: u8, token_id: &str) -> LockTicketRequest {
+        LockTicketRequest {
+            target_chain_id: "eICP".to_string(),
+            receiver: "rrkah-fqaaa-aaaaa-aaaaq-cai".to_string(),
+            token_id: token_id.to_string(),
+            ticker: "ordi".to_string(),
+            amount: "1".to_string(),
+            txid: Txid::from_str(&format!("{:064x}", n)).unwrap(),
+            received_at: 0,
+            deposit_addr: None,
+            inscription: None,
+        }
+    }
+
+    #[test]
+    fn test_is_unlock_held() {
+        let mut s = new_state();
+        assert!(!is_unlock_held(&s, 0));
+
+        let pending = deposit(1, "Bitcoin-brc20-ordi");
+        s.pending_sweeps.insert(pending.txid, pending);
+        assert!(is_unlock_held(&s, 0));
+        assert!(!is_unlock_held(&s, 1));
+        // A missing ticket is not held.
+        assert!(!is_unlock_held(&s, 2));
+
+        s.pending_sweeps.clear();
+        let flight = deposit(2, "Bitcoin-brc20-sats");
+        s.flight_sweeps
+            .insert(flight.txid, SendTicketResult::default());
+        s.finalized_lock_ticket_requests
+            .insert(flight.txid, flight.clone());
+        assert!(!is_unlock_held(&s, 0));
+        assert!(is_unlock_held(&s, 1));
+
+        // The failed sweeps are left to the admin.
+        let r = s.flight_sweeps.remove(&flight.txid).unwrap();
+        s.failed_sweeps.insert(flight.txid, r);
+        assert!(!is_unlock_held(&s, 1));
+    }
+}
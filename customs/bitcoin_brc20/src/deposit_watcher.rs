@@ -0,0 +1,354 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use candid::{CandidType, Principal};
+use ic_btc_interface::Txid;
+use ic_canister_log::log;
+use serde::{Deserialize, Serialize};
+
+use omnity_types::ic_log::{ERROR, INFO, WARNING};
+use omnity_types::ChainState;
+
+use crate::bitcoin_to_custom::{query_address_transactions, query_raw_transaction};
+use crate::constants::{
+    DEPOSIT_WATCH_EXPIRATION, MAX_ADDRESSES_PER_SCAN, MAX_DEPOSIT_WATCHES,
+    MAX_DEPOSIT_WATCHES_PER_CALLER, MAX_RECEIVER_LENGTH, MIN_SCAN_INTERVAL, WATCH_DEPOSITS_NAME,
+};
+use crate::destination::Destination;
+use crate::generate_ticket::{generate_ticket, GenerateTicketArgs, GenerateTicketError};
+use crate::inscription_index::transfer_inscription;
+use crate::ord::mempool_rpc_types::TxInfo;
+use crate::state::{mutate_state, read_state, Brc20State};
+use crate::types::{GenTicketStatus, GetBtcAddressArgs};
+
+/// A derived deposit address scanned for new deposits.
+#[derive(CandidType, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DepositWatch {
+    /// The address stops being scanned [DEPOSIT_WATCH_EXPIRATION] after it's
+    /// registered, asking for the address again registers it again.
+    pub registered_at: u64,
+    pub last_scanned_at: u64,
+    /// The transactions that are not valid deposits, they are not checked again.
+    pub ignored_txids: BTreeSet<String>,
+    /// The destination of the deposits to the address, none for the watches
+    /// registered before it was kept here.
+    #[serde(default)]
+    pub destination: Option<Destination>,
+    #[serde(default)]
+    pub registered_by: Option<Principal>,
+}
+
+/// Returns the deposit address of the destination and scans it for new deposits.
+pub async fn get_btc_address(args: GetBtcAddressArgs) -> Result<String, String> {
+    let caller = ic_cdk::api::caller();
+    if caller == Principal::anonymous() {
+        return Err("the anonymous caller can't watch a deposit address".into());
+    }
+    validate_receiver(&args.receiver)?;
+    if read_state(|s| {
+        !s.counterparties
+            .get(&args.target_chain_id)
+            .is_some_and(|c| c.chain_state == ChainState::Active)
+    }) {
+        return Err(format!("unsupported chain id: {}", args.target_chain_id));
+    }
+    if read_state(|s| s.ecdsa_public_key.is_none()) {
+        return Err("the deposit address is not generated yet".into());
+    }
+    let destination = Destination {
+        target_chain_id: args.target_chain_id,
+        receiver: args.receiver,
+    };
+    let now = ic_cdk::api::time();
+    mutate_state(|s| {
+        let address = s.destination_address(&destination);
+        register_watch(s, address.clone(), destination, caller, now)?;
+        Ok(address)
+    })
+}
+
+/// Checks the receiver can be a destination, its format on the target chain is
+/// checked by the target chain.
+pub fn validate_receiver(receiver: &str) -> Result<(), String> {
+    if receiver.is_empty() || receiver.len() > MAX_RECEIVER_LENGTH {
+        return Err(format!(
+            "the receiver must have 1 to {} characters",
+            MAX_RECEIVER_LENGTH
+        ));
+    }
+    if !receiver.chars().all(|c| c.is_ascii_graphic()) {
+        return Err("the receiver must only have printable ascii characters".into());
+    }
+    Ok(())
+}
+
+/// Watches the address, or renews its watch if it's watched already. Every
+/// caller watches a bounded number of addresses.
+fn register_watch(
+    s: &mut Brc20State,
+    address: String,
+    destination: Destination,
+    caller: Principal,
+    now: u64,
+) -> Result<(), String> {
+    if let Some(watch) = s.deposit_watches.get_mut(&address) {
+        watch.registered_at = now;
+        return Ok(());
+    }
+    if s.deposit_watches.len() >= MAX_DEPOSIT_WATCHES {
+        return Err("too many deposit addresses are watched, retry later".into());
+    }
+    let watches_of_caller = s
+        .deposit_watches
+        .values()
+        .filter(|w| w.registered_by == Some(caller))
+        .count();
+    if watches_of_caller >= MAX_DEPOSIT_WATCHES_PER_CALLER {
+        return Err(format!(
+            "a caller watches {} deposit addresses at most",
+            MAX_DEPOSIT_WATCHES_PER_CALLER
+        ));
+    }
+    s.deposit_watches.insert(
+        address,
+        DepositWatch {
+            registered_at: now,
+            destination: Some(destination),
+            registered_by: Some(caller),
+            ..Default::default()
+        },
+    );
+    Ok(())
+}
+
+pub fn watch_deposits_task() {
+    ic_cdk::spawn(async {
+        let _guard = match crate::guard::TimerLogicGuard::new(WATCH_DEPOSITS_NAME.to_string()) {
+            Some(guard) => guard,
+            None => return,
+        };
+        watch_deposits().await;
+    });
+}
+
+/// Scans a bounded number of the watched addresses, the ones not scanned for the
+/// longest time first, and generates the tickets of their new deposits.
+async fn watch_deposits() {
+    if read_state(|s| s.chain_state == ChainState::Deactive) {
+        return;
+    }
+    let now = ic_cdk::api::time();
+    let addresses: Vec<String> = mutate_state(|s| {
+        s.deposit_watches
+            .retain(|_, w| w.registered_at.saturating_add(DEPOSIT_WATCH_EXPIRATION) >= now);
+        let mut due: Vec<(&String, &DepositWatch)> = s
+            .deposit_watches
+            .iter()
+            .filter(|(_, w)| w.last_scanned_at.saturating_add(MIN_SCAN_INTERVAL) <= now)
+            .collect();
+        due.sort_by_key(|(_, w)| w.last_scanned_at);
+        due.into_iter()
+            .take(MAX_ADDRESSES_PER_SCAN)
+            .map(|(address, _)| address.clone())
+            .collect()
+    });
+    for address in addresses {
+        mutate_state(|s| {
+            if let Some(w) = s.deposit_watches.get_mut(&address) {
+                w.last_scanned_at = now;
+            }
+        });
+        match query_address_transactions(&address).await {
+            Ok(txs) => {
+                for tx in txs {
+                    check_deposit(&address, tx).await;
+                }
+            }
+            Err(e) => {
+                log!(ERROR, "query transactions of {} error: {:?}", address, e);
+            }
+        }
+    }
+}
+
+/// Generates the ticket of the transaction if it sends a transfer inscription
+/// to the address.
+async fn check_deposit(address: &String, tx: TxInfo) {
+    if tx
+        .vout
+        .first()
+        .and_then(|out| out.scriptpubkey_address.as_ref())
+        != Some(address)
+    {
+        return;
+    }
+    let txid = match Txid::from_str(&tx.txid) {
+        Ok(txid) => txid,
+        Err(_) => return,
+    };
+    let (destination, ignored) = read_state(|s| {
+        let watch = s.deposit_watches.get(address);
+        (
+            watch
+                .and_then(|w| w.destination.clone())
+                .or_else(|| s.deposit_destinations.get(address).cloned()),
+            watch.is_some_and(|w| w.ignored_txids.contains(&tx.txid)),
+        )
+    });
+    let destination = match destination {
+        Some(destination) => destination,
+        None => return,
+    };
+    if ignored || read_state(|s| s.generate_ticket_status(txid)) != GenTicketStatus::Unknown {
+        return;
+    }
+    let transfer = match query_raw_transaction(&tx.txid).await {
+        Ok(deposit_tx) => transfer_inscription(&deposit_tx).await.map(|(t, _)| t),
+        Err(e) => Err(e),
+    };
+    let result = match transfer {
+        Ok(t) => {
+            let token_id = read_state(|s| {
+                s.tokens
+                    .values()
+                    .find(|token| token.name.eq_ignore_ascii_case(&t.tick))
+                    .map(|token| token.token_id.clone())
+            });
+            match token_id {
+                Some(token_id) => {
+                    generate_ticket(GenerateTicketArgs {
+                        txid: tx.txid.clone(),
+                        amount: t.amt,
+                        target_chain_id: destination.target_chain_id,
+                        token_id,
+                        receiver: destination.receiver,
+                    })
+                    .await
+                }
+                None => Err(GenerateTicketError::UnsupportedToken(t.tick)),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(_) => {
+            log!(
+                INFO,
+                "generate ticket of the deposit {} to {}",
+                tx.txid,
+                address
+            );
+        }
+        Err(GenerateTicketError::AlreadySubmitted | GenerateTicketError::AlreadyProcessed) => {}
+        // Checked again by the next scan.
+        Err(
+            e @ (GenerateTicketError::RpcError(_)
+            | GenerateTicketError::SendTicketErr(_)
+            | GenerateTicketError::TemporarilyUnavailable(_)),
+        ) => {
+            log!(
+                ERROR,
+                "check the deposit {} to {} error: {}",
+                tx.txid,
+                address,
+                e
+            );
+        }
+        Err(e) => {
+            log!(
+                WARNING,
+                "ignore the deposit {} to {}: {}",
+                tx.txid,
+                address,
+                e
+            );
+            mutate_state(|s| {
+                if let Some(w) = s.deposit_watches.get_mut(address) {
+                    w.ignored_txids.insert(tx.txid.clone());
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::InitArgs;
+
+    fn new_state() -> Brc20State {
+        Brc20State::init(InitArgs {
+            admins: vec![],
+            hub_principal: Principal::anonymous(),
+            network: omnity_types::Network::Testnet,
+            chain_id: "Bitcoin".to_string(),
+            indexer_principal: Principal::anonymous(),
+            fee_token: "ICP".to_string(),
+        })
+        .unwrap()
+    }
+
+    fn destination(receiver: &str) -> Destination {
+        Destination {
+            target_chain_id: "eICP".to_string(),
+            receiver: receiver.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate_receiver() {
+        assert!(validate_receiver("rrkah-fqaaa-aaaaa-aaaaq-cai").is_ok());
+        assert!(validate_receiver("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").is_ok());
+        assert!(validate_receiver(&"a".repeat(MAX_RECEIVER_LENGTH)).is_ok());
+        assert!(validate_receiver("").is_err());
+        assert!(validate_receiver(&"a".repeat(MAX_RECEIVER_LENGTH + 1)).is_err());
+        assert!(validate_receiver("rrkah fqaaa").is_err());
+        assert!(validate_receiver("rrkah\nfqaaa").is_err());
+        assert!(validate_receiver("réceiver").is_err());
+    }
+
+    #[test]
+    fn test_register_watch() {
+        let mut s = new_state();
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        for i in 0..MAX_DEPOSIT_WATCHES_PER_CALLER {
+            let d = destination(&format!("alice-{}", i));
+            assert!(register_watch(&mut s, format!("addr-{}", i), d, alice, 1).is_ok());
+        }
+        let d = destination("alice-new");
+        assert!(register_watch(&mut s, "addr-new".to_string(), d.clone(), alice, 1).is_err());
+        assert!(register_watch(&mut s, "addr-new".to_string(), d, bob, 1).is_ok());
+
+        // Renewing a watch doesn't take another one of the caller.
+        assert!(register_watch(
+            &mut s,
+            "addr-0".to_string(),
+            destination("alice-0"),
+            alice,
+            5
+        )
+        .is_ok());
+        let watch = s.deposit_watches.get("addr-0").unwrap();
+        assert_eq!(watch.registered_at, 5);
+        assert_eq!(watch.registered_by, Some(alice));
+        assert_eq!(watch.destination, Some(destination("alice-0")));
+        assert_eq!(s.deposit_watches.len(), MAX_DEPOSIT_WATCHES_PER_CALLER + 1);
+        // The destination is only kept once a deposit is seen.
+        assert!(s.deposit_destinations.is_empty());
+    }
+
+    #[test]
+    fn test_register_watch_global_limit() {
+        let mut s = new_state();
+        for i in 0..MAX_DEPOSIT_WATCHES {
+            s.deposit_watches
+                .insert(format!("addr-{}", i), DepositWatch::default());
+        }
+        let caller = Principal::from_slice(&[1]);
+        assert!(
+            register_watch(&mut s, "addr-new".to_string(), destination("r"), caller, 1).is_err()
+        );
+        assert!(register_watch(&mut s, "addr-0".to_string(), destination("r"), caller, 1).is_ok());
+    }
+}
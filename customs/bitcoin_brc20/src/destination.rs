@@ -0,0 +1,9 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+/// The destination of the BRC-20 tokens deposited to a derived deposit address.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Destination {
+    pub target_chain_id: String,
+    pub receiver: String,
+}
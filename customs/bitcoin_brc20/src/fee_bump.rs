@@ -0,0 +1,790 @@
+use std::str::FromStr;
+
+use bitcoin::{Address, Amount, PublicKey, Transaction, Txid};
+use ic_canister_log::log;
+use serde::{Deserialize, Serialize};
+
+use omnity_types::ic_log::{ERROR, INFO};
+use omnity_types::{Seq, Ticket};
+
+use crate::bitcoin_to_custom::query_transaction;
+use crate::constants::{
+    FEE_BUMP_CHECK_DELAY, INPUT_SIZE_VBYTES, MAX_UNLOCK_FEE_BUMPS, MIN_RELAY_FEE_PER_VBYTE,
+    OUTPUT_SIZE_VBYTES, TRANSFER_TX_VBYTES, UNLOCK_FEE_BUMP_DELAY,
+};
+use crate::custom_to_bitcoin::CustomToBitcoinError::{
+    ArgumentError, InsufficientFunds, SendTransactionFailed,
+};
+use crate::custom_to_bitcoin::{
+    create_fees, estimate_commit_size, find_commit_remain_fee, generate_brc20_transactions,
+    CustomToBitcoinResult, SendTicketResult,
+};
+use crate::management;
+use crate::ord::builder::fees::Fees;
+use crate::ord::builder::signer::MixSigner;
+use crate::ord::builder::spend_transaction::spend_utxos_with_change;
+use crate::ord::builder::Utxo;
+use crate::ord::mempool_rpc_types::TxInfo;
+use crate::ord::parser::POSTAGE;
+use crate::state::{deposit_addr, deposit_pubkey, mutate_state, read_state};
+
+/// The size of a transfer transaction with a fee input and a change output added.
+const CPFP_TRANSFER_TX_VBYTES: u64 = TRANSFER_TX_VBYTES + INPUT_SIZE_VBYTES + OUTPUT_SIZE_VBYTES;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum FeeBumpKind {
+    /// The commit, reveal and transfer transactions are replaced at a higher fee rate.
+    Rbf,
+    /// The transfer transaction is replaced by one with a fee input, paying for
+    /// its unconfirmed parents.
+    Cpfp,
+}
+
+/// The bitcoin canister doesn't tell if the mempool accepted a transaction,
+/// a replacement is checked against the mempool explorer afterwards.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FeeBumpStatus {
+    /// The replacement is sent, it's not seen in the mempool yet.
+    Sent,
+    #[default]
+    Accepted,
+    /// The replaced transactions are kept in the mempool.
+    Rejected,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeeBump {
+    pub kind: FeeBumpKind,
+    /// The fee rate in satoshi per vbyte after the bump.
+    pub fee_rate: u64,
+    pub replaced_txids: Vec<String>,
+    pub time_at: u64,
+    #[serde(default)]
+    pub status: FeeBumpStatus,
+    /// The unlock transactions before the bump, kept until the replacement is
+    /// accepted or rejected.
+    #[serde(default)]
+    pub replaced: Option<Box<SendTicketResult>>,
+}
+
+/// Returns true if the unlock transactions have waited long enough to be bumped.
+/// They are not bumped again before the last replacement is accepted or rejected.
+pub fn is_stuck(send_result: &SendTicketResult, now: u64) -> bool {
+    let checking = send_result
+        .fee_bumps
+        .last()
+        .is_some_and(|b| b.status == FeeBumpStatus::Sent);
+    !checking
+        && send_result.fee_bumps.len() < MAX_UNLOCK_FEE_BUMPS
+        && send_result.time_at + UNLOCK_FEE_BUMP_DELAY < now
+}
+
+/// The fee rate of a bump, at least a quarter above the old rate to pass the
+/// replacement rules.
+pub fn bumped_fee_rate(old_fee_rate: u64, estimate: u64) -> u64 {
+    estimate.max(old_fee_rate + old_fee_rate / 4 + 1)
+}
+
+/// Bumps the fee of the stuck unlock transactions of the ticket, the fee rate is
+/// the current estimate in satoshi per vbyte. Nothing is done if the transactions
+/// already pay the estimated rate.
+pub async fn bump_stuck_unlock(seq: Seq, send_result: SendTicketResult, estimate: u64) {
+    if send_result.success && estimate <= send_result.fee_rate {
+        return;
+    }
+    let ticket = match read_state(|s| s.tickets_queue.get(&seq)) {
+        Some(ticket) => ticket,
+        None => return,
+    };
+    let fee_rate = bumped_fee_rate(send_result.fee_rate, estimate);
+    let commit_txid = send_result.txs[0].txid().to_string();
+    let commit_confirmed = match query_transaction(&commit_txid).await {
+        Ok(tx) => tx.status.confirmed,
+        Err(e) => {
+            log!(
+                ERROR,
+                "query commit tx of unlock ticket {} error: {:?}",
+                seq,
+                e
+            );
+            return;
+        }
+    };
+    let bumped = if !commit_confirmed && can_replace_commit(&send_result) {
+        match replace_commit(&send_result, &ticket, fee_rate).await {
+            Ok(r) => Ok(r),
+            Err(e) => {
+                log!(
+                    ERROR,
+                    "replace commit of unlock ticket {} error: {}, try cpfp",
+                    seq,
+                    e
+                );
+                add_transfer_fee(&send_result, &ticket, fee_rate, commit_confirmed).await
+            }
+        }
+    } else {
+        add_transfer_fee(&send_result, &ticket, fee_rate, commit_confirmed).await
+    };
+    match bumped {
+        Ok(r) => {
+            let old_reveal_index = format!("{}:0", send_result.txs[1].txid());
+            let new_reveal_index = format!("{}:0", r.txs[1].txid());
+            log!(
+                INFO,
+                "bump unlock ticket {} fee: {:?}",
+                seq,
+                r.fee_bumps.last()
+            );
+            mutate_state(|s| {
+                s.reveal_utxo_index.remove(&old_reveal_index);
+                s.reveal_utxo_index.insert(new_reveal_index);
+                s.flight_unlock_ticket_map.insert(seq, r);
+            });
+        }
+        Err(e) => {
+            log!(ERROR, "bump unlock ticket {} fee error: {}", seq, e);
+        }
+    }
+}
+
+/// Returns true if the commit can be replaced, its leftover is taken out of
+/// the fee utxos then. It's false if the leftover is spent already or if a
+/// replacement of the commit was rejected before.
+/// The batched commits are never replaced.
+fn can_replace_commit(send_result: &SendTicketResult) -> bool {
+    if !send_result.batch.is_empty() || send_result.commit_inputs.is_empty() {
+        return false;
+    }
+    let rejected = send_result
+        .fee_bumps
+        .iter()
+        .any(|b| b.kind == FeeBumpKind::Rbf && b.status == FeeBumpStatus::Rejected);
+    if rejected {
+        return false;
+    }
+    match find_commit_remain_fee(&send_result.txs[0]) {
+        None => true,
+        Some(leftover) => {
+            mutate_state(
+                |s| match s.deposit_addr_utxo.iter().position(|u| *u == leftover) {
+                    Some(index) => {
+                        s.deposit_addr_utxo.remove(index);
+                        true
+                    }
+                    None => false,
+                },
+            )
+        }
+    }
+}
+
+fn restore_commit_leftover(commit: &Transaction) {
+    if let Some(leftover) = find_commit_remain_fee(commit) {
+        mutate_state(|s| s.deposit_addr_utxo.push(leftover));
+    }
+}
+
+/// Replaces the commit, reveal and transfer transactions by new ones at the fee
+/// rate, spending the inputs of the old commit. The new commit pays at least the
+/// fees of the replaced transactions, more fee utxos are spent if needed.
+async fn replace_commit(
+    send_result: &SendTicketResult,
+    ticket: &Ticket,
+    fee_rate: u64,
+) -> CustomToBitcoinResult<SendTicketResult> {
+    let old_commit = &send_result.txs[0];
+    let (commit_fee, reveal_fee, transfer_fee) = paid_fees(send_result);
+    let replaced_fee = commit_fee + reveal_fee + transfer_fee;
+    let mut vins = send_result.commit_inputs.clone();
+    let mut selected = vec![];
+    let fees = loop {
+        let fees = replacement_fees(vins.len() as u64, fee_rate, replaced_fee);
+        if utxos_amount(&vins) >= fees.sum() + POSTAGE {
+            break fees;
+        }
+        match mutate_state(|s| s.deposit_addr_utxo.pop()) {
+            Some(u) => {
+                selected.push(u.clone());
+                vins.push(u);
+            }
+            None => {
+                mutate_state(|s| s.deposit_addr_utxo.append(&mut selected));
+                restore_commit_leftover(old_commit);
+                return Err(InsufficientFunds);
+            }
+        }
+    };
+    let txs = match generate_brc20_transactions(vins.clone(), &fees, ticket).await {
+        Ok(txs) => txs,
+        Err(e) => {
+            mutate_state(|s| s.deposit_addr_utxo.append(&mut selected));
+            restore_commit_leftover(old_commit);
+            return Err(e);
+        }
+    };
+    if let Err(e) = management::send_transaction(&txs[0]).await {
+        mutate_state(|s| s.deposit_addr_utxo.append(&mut selected));
+        restore_commit_leftover(old_commit);
+        return Err(SendTransactionFailed(e.to_string()));
+    }
+    let now = ic_cdk::api::time();
+    let mut result = send_result.clone();
+    result.success = true;
+    result.err_step = None;
+    result.err_info = None;
+    for (index, tx) in txs.iter().enumerate().skip(1) {
+        if let Err(e) = management::send_transaction(tx).await {
+            result.success = false;
+            result.err_step = Some(index as u8);
+            result.err_info = Some(e);
+            break;
+        }
+    }
+    // The leftover of the new commit and the fee inputs of the old transfer are
+    // given back to the fee utxos once the replacement is accepted.
+    result.fee_bumps.push(FeeBump {
+        kind: FeeBumpKind::Rbf,
+        fee_rate,
+        replaced_txids: send_result
+            .txs
+            .iter()
+            .map(|tx| tx.txid().to_string())
+            .collect(),
+        time_at: now,
+        status: FeeBumpStatus::Sent,
+        replaced: Some(Box::new(send_result.clone())),
+    });
+    result.txs = txs;
+    result.commit_inputs = vins;
+    result.bump_inputs = vec![];
+    result.fee_rate = fee_rate;
+    result.time_at = now;
+    Ok(result)
+}
+
+/// Replaces the transfer transaction by one with fee inputs and a change output,
+/// paying the fee rate for itself and for its unconfirmed parents.
+async fn add_transfer_fee(
+    send_result: &SendTicketResult,
+    ticket: &Ticket,
+    fee_rate: u64,
+    commit_confirmed: bool,
+) -> CustomToBitcoinResult<SendTicketResult> {
+    let commit = &send_result.txs[0];
+    let reveal = &send_result.txs[1];
+    let old_transfer = send_result.txs.last().cloned().unwrap();
+    let recipient = Address::from_str(&ticket.receiver)
+        .map_err(|e| ArgumentError(e.to_string()))?
+        .assume_checked();
+    // The reveal is sent again if it failed to be sent at first.
+    if send_result.err_step == Some(1) {
+        management::send_transaction(reveal)
+            .await
+            .map_err(|e| SendTransactionFailed(e.to_string()))?;
+    }
+
+    // The unconfirmed parents are paid for by the new transfer.
+    let (commit_fee, reveal_fee, transfer_fee) = paid_fees(send_result);
+    let mut parents_vsize = reveal.vsize() as u64;
+    let mut parents_fee = reveal_fee;
+    if !commit_confirmed {
+        parents_vsize += commit.vsize() as u64;
+        parents_fee += commit_fee;
+    }
+
+    let reveal_utxo = Utxo {
+        id: reveal.txid(),
+        index: 0,
+        amount: reveal.output[0].value,
+    };
+    let mut fee_inputs = send_result.bump_inputs.clone();
+    let mut selected = vec![];
+    let required = |inputs: &Vec<Utxo>| {
+        cpfp_transfer_fee(
+            inputs.len() as u64,
+            fee_rate,
+            parents_vsize,
+            parents_fee,
+            transfer_fee,
+        )
+    };
+    let input_amount = |inputs: &Vec<Utxo>| utxos_amount(inputs) + reveal_utxo.amount.to_sat();
+    while fee_inputs.is_empty() || input_amount(&fee_inputs) < required(&fee_inputs) + 2 * POSTAGE {
+        match mutate_state(|s| s.deposit_addr_utxo.pop()) {
+            Some(u) => {
+                selected.push(u.clone());
+                fee_inputs.push(u);
+            }
+            None => {
+                mutate_state(|s| s.deposit_addr_utxo.append(&mut selected));
+                return Err(InsufficientFunds);
+            }
+        }
+    }
+    let change = input_amount(&fee_inputs) - required(&fee_inputs) - POSTAGE;
+    let mut inputs = vec![reveal_utxo];
+    inputs.extend(fee_inputs.iter().cloned());
+    let signer = MixSigner::new(
+        PublicKey::from_str(deposit_pubkey().as_str()).unwrap(),
+        deposit_addr(),
+    );
+    let transfer = match spend_utxos_with_change(
+        &signer,
+        recipient,
+        Amount::from_sat(POSTAGE),
+        inputs,
+        Amount::from_sat(change),
+    )
+    .await
+    {
+        Ok(tx) => tx,
+        Err(e) => {
+            mutate_state(|s| s.deposit_addr_utxo.append(&mut selected));
+            return Err(e);
+        }
+    };
+    if let Err(e) = management::send_transaction(&transfer).await {
+        mutate_state(|s| s.deposit_addr_utxo.append(&mut selected));
+        return Err(SendTransactionFailed(e.to_string()));
+    }
+
+    let now = ic_cdk::api::time();
+    let mut result = send_result.clone();
+    result.success = true;
+    result.err_step = None;
+    result.err_info = None;
+    result.fee_bumps.push(FeeBump {
+        kind: FeeBumpKind::Cpfp,
+        fee_rate,
+        replaced_txids: vec![old_transfer.txid().to_string()],
+        time_at: now,
+        status: FeeBumpStatus::Sent,
+        replaced: Some(Box::new(send_result.clone())),
+    });
+    *result.txs.last_mut().unwrap() = transfer;
+    result.bump_inputs = fee_inputs;
+    result.fee_rate = fee_rate;
+    result.time_at = now;
+    Ok(result)
+}
+
+/// Checks the last replacement of the unlock transactions against the mempool.
+/// It's accepted once the replacing transaction is seen. It's rejected if that
+/// isn't seen in time or if the replaced one is confirmed, the unlock goes on
+/// with the replaced transactions then.
+pub async fn check_fee_bump(seq: Seq, send_result: SendTicketResult, now: u64) -> SendTicketResult {
+    let bump = match send_result.fee_bumps.last() {
+        Some(b) if b.status == FeeBumpStatus::Sent => b,
+        _ => return send_result,
+    };
+    let replaced = match &bump.replaced {
+        Some(r) => r,
+        None => return send_result,
+    };
+    let new_txid = conflicting_tx(&bump.kind, &send_result).txid().to_string();
+    let old_txid = conflicting_tx(&bump.kind, replaced).txid().to_string();
+    let time_at = bump.time_at;
+    let accepted = query_transaction(&new_txid).await.is_ok();
+    let rejected = !accepted
+        && (time_at + FEE_BUMP_CHECK_DELAY < now
+            || query_transaction(&old_txid)
+                .await
+                .is_ok_and(|tx| tx.status.confirmed));
+    let result = if accepted {
+        accept_fee_bump(send_result)
+    } else if rejected {
+        log!(
+            ERROR,
+            "fee bump of unlock ticket {} rejected, {} is kept",
+            seq,
+            old_txid
+        );
+        reject_fee_bump(send_result)
+    } else {
+        return send_result;
+    };
+    mutate_state(|s| s.flight_unlock_ticket_map.insert(seq, result.clone()));
+    result
+}
+
+/// The transaction spending the same outputs as the one it replaces.
+fn conflicting_tx<'a>(kind: &FeeBumpKind, send_result: &'a SendTicketResult) -> &'a Transaction {
+    match kind {
+        FeeBumpKind::Rbf => &send_result.txs[0],
+        FeeBumpKind::Cpfp => send_result.txs.last().unwrap(),
+    }
+}
+
+fn accept_fee_bump(mut send_result: SendTicketResult) -> SendTicketResult {
+    let bump = send_result.fee_bumps.last_mut().unwrap();
+    bump.status = FeeBumpStatus::Accepted;
+    let replaced = bump.replaced.take();
+    if bump.kind == FeeBumpKind::Rbf {
+        // The old transfer is evicted with its parents, its fee inputs are free again.
+        let mut free = replaced.map(|r| r.bump_inputs).unwrap_or_default();
+        free.extend(find_commit_remain_fee(&send_result.txs[0]));
+        mutate_state(|s| s.deposit_addr_utxo.append(&mut free));
+    }
+    send_result
+}
+
+fn reject_fee_bump(mut send_result: SendTicketResult) -> SendTicketResult {
+    let mut bump = send_result.fee_bumps.pop().unwrap();
+    let mut replaced = *bump.replaced.take().unwrap();
+    // The fee utxos only spent by the replacement are free again.
+    let mut free: Vec<Utxo> = send_result
+        .commit_inputs
+        .iter()
+        .chain(send_result.bump_inputs.iter())
+        .filter(|u| !replaced.commit_inputs.contains(u) && !replaced.bump_inputs.contains(u))
+        .cloned()
+        .collect();
+    if bump.kind == FeeBumpKind::Rbf {
+        free.extend(find_commit_remain_fee(&replaced.txs[0]));
+    }
+    mutate_state(|s| {
+        s.deposit_addr_utxo.append(&mut free);
+        s.reveal_utxo_index
+            .remove(&format!("{}:0", send_result.txs[1].txid()));
+        s.reveal_utxo_index
+            .insert(format!("{}:0", replaced.txs[1].txid()));
+    });
+    bump.status = FeeBumpStatus::Rejected;
+    replaced.fee_bumps.push(bump);
+    replaced
+}
+
+/// The txids of the transfers which may unlock the ticket, the current transfer
+/// first, then the ones replaced by fee bumps.
+pub fn transfer_txids(send_result: &SendTicketResult) -> Vec<String> {
+    let mut txids = vec![send_result.txs.last().unwrap().txid().to_string()];
+    for bump in send_result.fee_bumps.iter().rev() {
+        // The commit and the reveal replaced with a transfer don't unlock the ticket.
+        if let Some(txid) = bump.replaced_txids.last() {
+            if !txids.contains(txid) {
+                txids.push(txid.clone());
+            }
+        }
+    }
+    txids
+}
+
+/// The fee utxos spent by the confirmed transfer are kept, the other ones are
+/// given back with the change of the transfer.
+pub fn release_bump_inputs(send_result: &mut SendTicketResult, txid: &str, transfer: &TxInfo) {
+    let (spent, mut free): (Vec<Utxo>, Vec<Utxo>) =
+        send_result.bump_inputs.drain(..).partition(|u| {
+            transfer
+                .vin
+                .iter()
+                .any(|i| i.txid == u.id.to_string() && i.vout == u.index)
+        });
+    send_result.bump_inputs = spent;
+    free.extend(transfer_change(txid, transfer));
+    mutate_state(|s| s.deposit_addr_utxo.append(&mut free));
+}
+
+/// The change of a bumped transfer, it's spendable once the transfer is confirmed.
+fn transfer_change(txid: &str, transfer: &TxInfo) -> Option<Utxo> {
+    let out = transfer.vout.get(1)?;
+    Some(Utxo {
+        id: Txid::from_str(txid).ok()?,
+        index: 1,
+        amount: Amount::from_sat(out.value),
+    })
+}
+
+/// The fee paid by the transaction, given the amount of its inputs.
+fn paid_fee(tx: &Transaction, input_amount: u64) -> u64 {
+    let output_amount: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+    input_amount.saturating_sub(output_amount)
+}
+
+fn utxos_amount(utxos: &[Utxo]) -> u64 {
+    utxos.iter().map(|u| u.amount.to_sat()).sum()
+}
+
+/// The fees paid by the commit, the reveal and the transfer transactions.
+/// The commit fee is zero if its inputs are unknown.
+pub fn paid_fees(send_result: &SendTicketResult) -> (u64, u64, u64) {
+    let commit = &send_result.txs[0];
+    let reveal = &send_result.txs[1];
+    let transfer = send_result.txs.last().unwrap();
+    let commit_fee = paid_fee(commit, utxos_amount(&send_result.commit_inputs));
+    let reveal_input = commit
+        .output
+        .get(reveal.input[0].previous_output.vout as usize)
+        .map_or(0, |o| o.value.to_sat());
+    let reveal_fee = paid_fee(reveal, reveal_input);
+    let transfer_input = reveal.output[0].value.to_sat() + utxos_amount(&send_result.bump_inputs);
+    let transfer_fee = paid_fee(transfer, transfer_input);
+    (commit_fee, reveal_fee, transfer_fee)
+}
+
+/// The fees of the transactions replacing a commit and its children, the new
+/// commit pays the fees of the replaced transactions plus the relay fee of its
+/// own size at least.
+pub fn replacement_fees(input_count: u64, fee_rate: u64, replaced_fee: u64) -> Fees {
+    let mut fees = create_fees(input_count, fee_rate);
+    let min_commit_fee = replaced_fee + MIN_RELAY_FEE_PER_VBYTE * estimate_commit_size(input_count);
+    fees.commit_fee = fees.commit_fee.max(Amount::from_sat(min_commit_fee));
+    fees
+}
+
+/// The fee of a transfer with fee inputs: the fee rate for itself plus what the
+/// unconfirmed parents miss to reach the fee rate, and at least the fee of the
+/// replaced transfer plus the relay fee of its own size.
+pub fn cpfp_transfer_fee(
+    fee_input_count: u64,
+    fee_rate: u64,
+    parents_vsize: u64,
+    parents_fee: u64,
+    replaced_fee: u64,
+) -> u64 {
+    let vsize = CPFP_TRANSFER_TX_VBYTES + fee_input_count.saturating_sub(1) * INPUT_SIZE_VBYTES;
+    let package_fee = fee_rate * vsize + (fee_rate * parents_vsize).saturating_sub(parents_fee);
+    package_fee.max(replaced_fee + MIN_RELAY_FEE_PER_VBYTE * vsize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{REVEAL_TX_VBYTES, TX_OVERHEAD_VBYTES};
+    use crate::service::InitArgs;
+    use crate::state::{replace_state, Brc20State};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{OutPoint, ScriptBuf, TxIn, TxOut};
+    use candid::Principal;
+
+    fn init_state() {
+        replace_state(
+            Brc20State::init(InitArgs {
+                admins: vec![],
+                hub_principal: Principal::anonymous(),
+                network: omnity_types::Network::Testnet,
+                chain_id: "Bitcoin".to_string(),
+                indexer_principal: Principal::anonymous(),
+                fee_token: "ICP".to_string(),
+            })
+            .unwrap(),
+        );
+    }
+
+    fn utxo(n: u8, amount: u64) -> Utxo {
+        Utxo {
+            id: Txid::from_str(&format!("{:064x}", n)).unwrap(),
+            index: 0,
+            amount: Amount::from_sat(amount),
+        }
+    }
+
+    fn tx(inputs: &[(Txid, u32)], outputs: &[u64]) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|(txid, vout)| TxIn {
+                    previous_output: OutPoint {
+                        txid: *txid,
+                        vout: *vout,
+                    },
+                    ..Default::default()
+                })
+                .collect(),
+            output: outputs
+                .iter()
+                .map(|value| TxOut {
+                    value: Amount::from_sat(*value),
+                    script_pubkey: ScriptBuf::new(),
+                })
+                .collect(),
+        }
+    }
+
+    /// A commit spending the inputs with a leftover of 5000, a reveal paying
+    /// 300 and a transfer paying 200.
+    fn unlock_result(commit_inputs: Vec<Utxo>) -> SendTicketResult {
+        let input_amount = utxos_amount(&commit_inputs);
+        let inputs: Vec<(Txid, u32)> = commit_inputs.iter().map(|u| (u.id, u.index)).collect();
+        let commit = tx(&inputs, &[1046, input_amount - 1046 - 1000 - 5000, 5000]);
+        let reveal = tx(&[(commit.txid(), 0)], &[746]);
+        let transfer = tx(&[(reveal.txid(), 0)], &[546]);
+        SendTicketResult {
+            txs: vec![commit, reveal, transfer],
+            success: true,
+            fee_rate: 5,
+            commit_inputs,
+            ..Default::default()
+        }
+    }
+
+    fn fee_bump(kind: FeeBumpKind, status: FeeBumpStatus) -> FeeBump {
+        FeeBump {
+            kind,
+            fee_rate: 10,
+            replaced_txids: vec![],
+            time_at: 0,
+            status,
+            replaced: None,
+        }
+    }
+
+    #[test]
+    fn test_bumped_fee_rate() {
+        assert_eq!(bumped_fee_rate(10, 5), 13);
+        assert_eq!(bumped_fee_rate(10, 13), 13);
+        assert_eq!(bumped_fee_rate(10, 20), 20);
+        assert_eq!(bumped_fee_rate(0, 0), 1);
+    }
+
+    #[test]
+    fn test_is_stuck() {
+        let mut result = SendTicketResult {
+            time_at: 100,
+            ..Default::default()
+        };
+        assert!(!is_stuck(&result, 100 + UNLOCK_FEE_BUMP_DELAY));
+        assert!(is_stuck(&result, 101 + UNLOCK_FEE_BUMP_DELAY));
+
+        result
+            .fee_bumps
+            .push(fee_bump(FeeBumpKind::Cpfp, FeeBumpStatus::Sent));
+        assert!(!is_stuck(&result, 101 + UNLOCK_FEE_BUMP_DELAY));
+        result.fee_bumps[0].status = FeeBumpStatus::Rejected;
+        assert!(is_stuck(&result, 101 + UNLOCK_FEE_BUMP_DELAY));
+
+        result.fee_bumps = (0..MAX_UNLOCK_FEE_BUMPS)
+            .map(|_| fee_bump(FeeBumpKind::Cpfp, FeeBumpStatus::Accepted))
+            .collect();
+        assert!(!is_stuck(&result, 101 + UNLOCK_FEE_BUMP_DELAY));
+    }
+
+    #[test]
+    fn test_cpfp_transfer_fee() {
+        let vsize = TRANSFER_TX_VBYTES + INPUT_SIZE_VBYTES + OUTPUT_SIZE_VBYTES;
+        // The parents miss 10 * 300 - 1000 satoshis.
+        assert_eq!(cpfp_transfer_fee(1, 10, 300, 1000, 200), 10 * vsize + 2000);
+        // Every fee input above the first one adds its size.
+        assert_eq!(
+            cpfp_transfer_fee(3, 10, 300, 1000, 200),
+            10 * (vsize + 2 * INPUT_SIZE_VBYTES) + 2000
+        );
+        // The parents paying the fee rate already are not paid for.
+        assert_eq!(cpfp_transfer_fee(1, 10, 300, 5000, 200), 10 * vsize);
+        // The replaced transfer is outbid by the relay fee.
+        assert_eq!(
+            cpfp_transfer_fee(1, 10, 300, 5000, 9000),
+            9000 + MIN_RELAY_FEE_PER_VBYTE * vsize
+        );
+    }
+
+    #[test]
+    fn test_replacement_fees() {
+        let commit_size = 2 * INPUT_SIZE_VBYTES + 2 * OUTPUT_SIZE_VBYTES + TX_OVERHEAD_VBYTES;
+        let fees = replacement_fees(2, 10, 0);
+        assert_eq!(fees.commit_fee.to_sat(), 10 * commit_size);
+        assert_eq!(fees.reveal_fee.to_sat(), 10 * REVEAL_TX_VBYTES);
+        assert_eq!(fees.spend_fee.to_sat(), 10 * TRANSFER_TX_VBYTES);
+
+        let fees = replacement_fees(2, 10, 5000);
+        assert_eq!(
+            fees.commit_fee.to_sat(),
+            5000 + MIN_RELAY_FEE_PER_VBYTE * commit_size
+        );
+        assert_eq!(fees.reveal_fee.to_sat(), 10 * REVEAL_TX_VBYTES);
+    }
+
+    #[test]
+    fn test_paid_fees() {
+        let mut result = unlock_result(vec![utxo(1, 10_000), utxo(2, 20_000)]);
+        assert_eq!(paid_fees(&result), (1000, 300, 200));
+
+        // A transfer with a fee input and a change output.
+        let reveal_txid = result.txs[1].txid();
+        let fee_input = utxo(3, 4000);
+        result.txs[2] = tx(&[(reveal_txid, 0), (fee_input.id, 0)], &[546, 3000]);
+        result.bump_inputs = vec![fee_input];
+        assert_eq!(paid_fees(&result), (1000, 300, 1200));
+
+        // The commit fee is unknown without the commit inputs.
+        result.commit_inputs = vec![];
+        assert_eq!(paid_fees(&result).0, 0);
+    }
+
+    #[test]
+    fn test_transfer_txids() {
+        let mut result = unlock_result(vec![utxo(1, 10_000)]);
+        let current = result.txs[2].txid().to_string();
+        let mut rbf = fee_bump(FeeBumpKind::Rbf, FeeBumpStatus::Accepted);
+        rbf.replaced_txids = vec!["c0".to_string(), "r0".to_string(), "t0".to_string()];
+        let mut cpfp = fee_bump(FeeBumpKind::Cpfp, FeeBumpStatus::Accepted);
+        cpfp.replaced_txids = vec!["t1".to_string()];
+        // A rejected bump replaced the current transfer.
+        let mut rejected = fee_bump(FeeBumpKind::Cpfp, FeeBumpStatus::Rejected);
+        rejected.replaced_txids = vec![current.clone()];
+        result.fee_bumps = vec![rbf, cpfp, rejected];
+        assert_eq!(
+            transfer_txids(&result),
+            vec![current, "t1".to_string(), "t0".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_accept_fee_bump() {
+        init_state();
+        let mut replaced = unlock_result(vec![utxo(1, 10_000)]);
+        replaced.bump_inputs = vec![utxo(2, 4000)];
+        let mut result = unlock_result(vec![utxo(1, 10_000), utxo(3, 3000)]);
+        let mut bump = fee_bump(FeeBumpKind::Rbf, FeeBumpStatus::Sent);
+        bump.replaced = Some(Box::new(replaced));
+        result.fee_bumps.push(bump);
+
+        let result = accept_fee_bump(result);
+        let bump = result.fee_bumps.last().unwrap();
+        assert_eq!(bump.status, FeeBumpStatus::Accepted);
+        assert!(bump.replaced.is_none());
+        // The fee input of the evicted transfer and the new leftover are free.
+        let leftover = find_commit_remain_fee(&result.txs[0]).unwrap();
+        assert_eq!(
+            read_state(|s| s.deposit_addr_utxo.clone()),
+            vec![utxo(2, 4000), leftover]
+        );
+    }
+
+    #[test]
+    fn test_reject_fee_bump() {
+        init_state();
+        let mut replaced = unlock_result(vec![utxo(1, 10_000)]);
+        replaced.bump_inputs = vec![utxo(2, 4000)];
+        replaced
+            .fee_bumps
+            .push(fee_bump(FeeBumpKind::Cpfp, FeeBumpStatus::Accepted));
+        let old_txids: Vec<Txid> = replaced.txs.iter().map(|tx| tx.txid()).collect();
+        let old_leftover = find_commit_remain_fee(&replaced.txs[0]).unwrap();
+        let mut result = unlock_result(vec![utxo(1, 10_000), utxo(3, 3000)]);
+        let mut bump = fee_bump(FeeBumpKind::Rbf, FeeBumpStatus::Sent);
+        bump.replaced = Some(Box::new(replaced));
+        result.fee_bumps.push(bump);
+
+        let result = reject_fee_bump(result);
+        let txids: Vec<Txid> = result.txs.iter().map(|tx| tx.txid()).collect();
+        assert_eq!(txids, old_txids);
+        assert_eq!(result.bump_inputs, vec![utxo(2, 4000)]);
+        assert_eq!(result.fee_bumps.len(), 2);
+        let bump = result.fee_bumps.last().unwrap();
+        assert_eq!(bump.status, FeeBumpStatus::Rejected);
+        assert!(bump.replaced.is_none());
+        assert!(!can_replace_commit(&result));
+        // The input only spent by the replacement and the old leftover are free.
+        assert_eq!(
+            read_state(|s| s.deposit_addr_utxo.clone()),
+            vec![utxo(3, 3000), old_leftover]
+        );
+        assert!(read_state(|s| s
+            .reveal_utxo_index
+            .contains(&format!("{}:0", old_txids[1]))));
+    }
+}
@@ -13,6 +13,7 @@ use omnity_types::ic_log::INFO;
 use omnity_types::{ChainState, Ticket, TicketType, TxAction, Memo};
 
 use crate::bitcoin_to_custom::check_transaction;
+use crate::destination::Destination;
 use crate::hub;
 use crate::state::{mutate_state, read_state};
 use crate::types::{GenTicketStatus, LockTicketRequest};
@@ -103,11 +104,13 @@ pub async fn generate_ticket(args: GenerateTicketArgs) -> Result<(), GenerateTic
         GenTicketStatus::Pending(_) | GenTicketStatus::Confirmed(_) => {
             Err(GenerateTicketError::AlreadySubmitted)
         }
-        GenTicketStatus::Finalized(_) => Err(GenerateTicketError::AlreadyProcessed),
+        GenTicketStatus::Finalized(_) | GenTicketStatus::Rejected(_) => {
+            Err(GenerateTicketError::AlreadyProcessed)
+        }
         GenTicketStatus::Unknown => Ok(()),
     })?;
     let (chain_id, hub_principal) = read_state(|s| (s.chain_id.clone(), s.hub_principal));
-    let transfer = check_transaction(args.clone()).await?;
+    let (transfer, deposit_addr, inscription) = check_transaction(args.clone()).await?;
 
     let ticket_amount: u128 = Decimal::from_str(&transfer.amt)
         .unwrap()
@@ -139,6 +142,10 @@ pub async fn generate_ticket(args: GenerateTicketArgs) -> Result<(), GenerateTic
     )
     .await
     .map_err(|err| GenerateTicketError::SendTicketErr(format!("{}", err)))?;
+    let destination = Destination {
+        target_chain_id: args.target_chain_id.clone(),
+        receiver: args.receiver.clone(),
+    };
     let request = LockTicketRequest {
         target_chain_id: args.target_chain_id,
         receiver: args.receiver,
@@ -147,8 +154,16 @@ pub async fn generate_ticket(args: GenerateTicketArgs) -> Result<(), GenerateTic
         amount: args.amount,
         txid,
         received_at: ic_cdk::api::time(),
+        deposit_addr: Some(deposit_addr.clone()),
+        inscription: Some(inscription.clone()),
     };
     mutate_state(|s| {
+        // The destination of a derived address is kept to sweep the deposit.
+        if s.deposit_addr.as_ref() != Some(&deposit_addr) {
+            s.deposit_destinations.insert(deposit_addr, destination);
+        }
+        s.indexed_inscriptions
+            .insert(inscription.inscription_id, request.txid);
         s.pending_lock_ticket_requests.insert(request.txid, request);
     });
     Ok(())
@@ -9,6 +9,19 @@ use omnity_types::TicketId;
 use omnity_types::Topic;
 use omnity_types::{self, ChainId, Seq, Ticket};
 
+pub async fn ack_tickets(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    call(hub_principal, "ack_tickets".into(), (None::<ChainId>, seq)).await
+}
+
+pub async fn ack_directives(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    call(
+        hub_principal,
+        "ack_directives".into(),
+        (None::<ChainId>, seq),
+    )
+    .await
+}
+
 pub async fn query_tickets(
     hub_principal: Principal,
     offset: u64,
@@ -61,10 +74,30 @@ pub async fn query_directives(
     .await
 }
 
+pub async fn send_ticket(hub_principal: Principal, ticket: Ticket) -> Result<(), CallError> {
+    call(hub_principal, "send_ticket".into(), (ticket,)).await
+}
+
 pub async fn pending_ticket(hub_principal: Principal, ticket: Ticket) -> Result<(), CallError> {
     call(hub_principal, "pending_ticket".into(), (ticket,)).await
 }
 
+/// Reports that the ticket can't be unlocked, the hub refunds the permanently
+/// failed ticket to its sender on the source chain.
+pub async fn report_ticket_failure(
+    hub_principal: Principal,
+    ticket_id: TicketId,
+    reason: String,
+    permanent: bool,
+) -> Result<(), CallError> {
+    call(
+        hub_principal,
+        "report_ticket_failure".into(),
+        (ticket_id, reason, permanent),
+    )
+    .await
+}
+
 pub async fn finalize_ticket(hub_principal: Principal, ticket_id: String) -> Result<(), CallError> {
     call(hub_principal, "finalize_ticket".into(), (ticket_id,)).await
 }
@@ -0,0 +1,487 @@
+//! Validates deposits against transaction data the customs checks itself.
+//!
+//! The raw transactions fetched over http are only accepted if they hash to the
+//! requested txid, so the provider can't alter their content. The txid doesn't
+//! cover the witness, so the tapscript holding the transfer inscription must be
+//! committed to by the taproot output its reveal transaction spends. The reveal
+//! transaction is proven to be in a block of the main chain by its merkle branch,
+//! checked against the block header of the bitcoin canister, so the inscriber is
+//! taken from block data. The deposit is then proven to be on chain by its
+//! output in the utxo set of the bitcoin canister, which follows the chain with
+//! full block validation.
+
+use std::collections::BTreeMap;
+
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+use bitcoin::taproot::{ControlBlock, TAPROOT_ANNEX_PREFIX};
+use bitcoin::{Address, Amount, Network, Transaction, TxMerkleNode};
+use candid::CandidType;
+use ic_btc_interface::{Txid, Utxo};
+use serde::{Deserialize, Serialize};
+
+use crate::bitcoin_to_custom::{query_merkle_proof, query_raw_transaction};
+use crate::call_error::CallError;
+use crate::generate_ticket::GenerateTicketError;
+use crate::management;
+use crate::ord::inscription::brc20::{Brc20, Brc20Transfer201};
+use crate::ord::parser::envelope::ParsedEnvelope;
+use crate::ord::parser::OrdParser;
+use crate::retry::call_rpc_with_retry;
+use crate::state::{bitcoin_network, read_state};
+
+/// Where the transfer inscription of a deposit comes from.
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InscriptionProvenance {
+    /// The id of the transfer inscription, `<reveal txid>i0`.
+    pub inscription_id: String,
+    /// The address the inscription is inscribed to, the holder of the balance
+    /// it transfers.
+    pub inscriber: String,
+    /// The value of the deposit output, the inscription is on its first sat.
+    pub deposit_value: u64,
+}
+
+/// Returns the transfer inscription sent by the deposit transaction and its
+/// provenance.
+///
+/// The inscription must be inscribed on the first sat of the first input of its
+/// reveal transaction and sent by the deposit straight from the first output of
+/// the reveal, so it was never transferred before and lands on the first sat of
+/// the deposit output. The reveal transaction must be confirmed.
+pub async fn transfer_inscription(
+    deposit_tx: &Transaction,
+) -> Result<(Brc20Transfer201, InscriptionProvenance), GenerateTicketError> {
+    let (reveal_txid, deposit_value) = deposit_of(deposit_tx)?;
+    let reveal_tx = call_rpc_with_retry(&reveal_txid, query_raw_transaction).await?;
+    let commit_txid = reveal_tx
+        .input
+        .first()
+        .ok_or(GenerateTicketError::NotBridgeTx)?
+        .previous_output
+        .txid
+        .to_string();
+    let commit_tx = call_rpc_with_retry(&commit_txid, query_raw_transaction).await?;
+    if !is_tapscript_committed(&reveal_tx, &commit_tx) {
+        return Err(GenerateTicketError::OrdTxError(format!(
+            "the inscription of {} is not committed to by the output it spends",
+            reveal_txid
+        )));
+    }
+    verify_inclusion(&reveal_tx).await?;
+    reveal_inscription(&reveal_tx, bitcoin_network(), deposit_value)
+}
+
+/// Returns true if the tapscript of the first input is committed to by the
+/// taproot output of the commit transaction it spends.
+fn is_tapscript_committed(reveal_tx: &Transaction, commit_tx: &Transaction) -> bool {
+    let Some(input) = reveal_tx.input.first() else {
+        return false;
+    };
+    if input.previous_output.txid != commit_tx.txid() {
+        return false;
+    }
+    let Some(tapscript) = input.witness.tapscript() else {
+        return false;
+    };
+    // The control block is the last element, or the one before the annex.
+    let mut elements = input.witness.iter().rev();
+    let control_block = match elements.next() {
+        Some(last) if input.witness.len() > 2 && last.first() == Some(&TAPROOT_ANNEX_PREFIX) => {
+            elements.next()
+        }
+        last => last,
+    };
+    let Some(control_block) = control_block.and_then(|c| ControlBlock::decode(c).ok()) else {
+        return false;
+    };
+    let Some(output_key) = commit_tx
+        .output
+        .get(input.previous_output.vout as usize)
+        .filter(|out| out.script_pubkey.is_p2tr())
+        .and_then(|out| XOnlyPublicKey::from_slice(&out.script_pubkey.as_bytes()[2..]).ok())
+    else {
+        return false;
+    };
+    control_block.verify_taproot_commitment(&Secp256k1::verification_only(), output_key, tapscript)
+}
+
+/// Checks the merkle branch served for the transaction against the header of
+/// its block in the bitcoin canister.
+async fn verify_inclusion(tx: &Transaction) -> Result<(), GenerateTicketError> {
+    let txid = tx.txid();
+    let proof = call_rpc_with_retry(&txid.to_string(), query_merkle_proof).await?;
+    let branch = proof
+        .merkle
+        .iter()
+        .map(|hash| hash.parse::<TxMerkleNode>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| GenerateTicketError::RpcError(format!("invalid merkle proof: {}", e)))?;
+    let header = management::get_block_header(read_state(|s| s.btc_network), proof.block_height)
+        .await
+        .map_err(|e| GenerateTicketError::TemporarilyUnavailable(e.to_string()))?;
+    if merkle_root(txid, &branch, proof.pos) != Some(header.merkle_root) {
+        return Err(GenerateTicketError::OrdTxError(format!(
+            "{} is not in the block {} of the bitcoin canister",
+            txid, proof.block_height
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the merkle root the branch of the transaction at the position leads
+/// to, none if the position doesn't fit the branch.
+fn merkle_root(txid: bitcoin::Txid, branch: &[TxMerkleNode], pos: u32) -> Option<TxMerkleNode> {
+    if branch.len() >= 32 || pos >> branch.len() != 0 {
+        return None;
+    }
+    let mut node = TxMerkleNode::from_raw_hash(txid.to_raw_hash());
+    for (depth, sibling) in branch.iter().enumerate() {
+        node = if (pos >> depth) & 1 == 0 {
+            merkle_parent(node, *sibling)
+        } else {
+            merkle_parent(*sibling, node)
+        };
+    }
+    Some(node)
+}
+
+fn merkle_parent(left: TxMerkleNode, right: TxMerkleNode) -> TxMerkleNode {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(left.as_byte_array());
+    engine.input(right.as_byte_array());
+    TxMerkleNode::from_raw_hash(sha256d::Hash::from_engine(engine))
+}
+
+/// Returns the reveal txid and the value of a deposit spending the first output
+/// of the reveal to its own first output.
+fn deposit_of(deposit_tx: &Transaction) -> Result<(String, u64), GenerateTicketError> {
+    let deposit_input = deposit_tx
+        .input
+        .first()
+        .ok_or(GenerateTicketError::NotBridgeTx)?;
+    let deposit_output = deposit_tx
+        .output
+        .first()
+        .ok_or(GenerateTicketError::NotBridgeTx)?;
+    if deposit_input.previous_output.vout != 0 || deposit_output.value == Amount::ZERO {
+        return Err(GenerateTicketError::NotBridgeTx);
+    }
+    Ok((
+        deposit_input.previous_output.txid.to_string(),
+        deposit_output.value.to_sat(),
+    ))
+}
+
+/// Parses the transfer inscription of the first input of the reveal transaction.
+fn reveal_inscription(
+    reveal_tx: &Transaction,
+    network: Network,
+    deposit_value: u64,
+) -> Result<(Brc20Transfer201, InscriptionProvenance), GenerateTicketError> {
+    let envelope = ParsedEnvelope::from_transaction_input(reveal_tx, 0)
+        .ok_or(GenerateTicketError::NotBridgeTx)?;
+    if !envelope.is_on_first_sat() {
+        return Err(GenerateTicketError::OrdTxError(format!(
+            "the inscription of {} is not on the first sat of its output",
+            reveal_tx.txid()
+        )));
+    }
+    let (inscription_id, parsed_inscription) = OrdParser::parse_one(reveal_tx, 0)
+        .map_err(|e| GenerateTicketError::OrdTxError(e.to_string()))?;
+    let transfer = match Brc20::try_from(parsed_inscription)
+        .map_err(|e| GenerateTicketError::OrdTxError(e.to_string()))?
+    {
+        Brc20::TransferBrc201(t) => t,
+        _ => return Err(GenerateTicketError::NotBridgeTx),
+    };
+    let inscriber = reveal_tx
+        .output
+        .first()
+        .ok_or(GenerateTicketError::NotBridgeTx)
+        .and_then(|out| {
+            Address::from_script(&out.script_pubkey, network)
+                .map_err(|e| GenerateTicketError::OrdTxError(e.to_string()))
+        })?;
+    Ok((
+        transfer,
+        InscriptionProvenance {
+            inscription_id: inscription_id.to_string(),
+            inscriber: inscriber.to_string(),
+            deposit_value,
+        },
+    ))
+}
+
+/// The confirmed utxos of the deposit addresses, fetched at most once per
+/// address for a round of finalizations.
+#[derive(Default)]
+pub struct DepositUtxos {
+    utxos: BTreeMap<String, Vec<Utxo>>,
+}
+
+impl DepositUtxos {
+    /// Returns true if the first output of the deposit is in the utxo set of the
+    /// bitcoin canister with enough confirmations, with the value the inscription
+    /// was validated with.
+    pub async fn is_deposit_confirmed(
+        &mut self,
+        txid: Txid,
+        deposit_addr: &str,
+        provenance: Option<&InscriptionProvenance>,
+    ) -> Result<bool, CallError> {
+        if !self.utxos.contains_key(deposit_addr) {
+            let (btc_network, min_confirmations) =
+                read_state(|s| (s.btc_network, s.min_confirmations));
+            let response =
+                management::get_utxos(btc_network, deposit_addr, min_confirmations as u32).await?;
+            self.utxos.insert(deposit_addr.to_string(), response.utxos);
+        }
+        Ok(is_deposit_in(&self.utxos[deposit_addr], txid, provenance))
+    }
+}
+
+fn is_deposit_in(utxos: &[Utxo], txid: Txid, provenance: Option<&InscriptionProvenance>) -> bool {
+    utxos.iter().any(|u| {
+        u.outpoint.txid == txid
+            && u.outpoint.vout == 0
+            && provenance.map_or(true, |p| p.deposit_value == u.value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ord::parser::push_bytes::bytes_to_push_bytes;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::opcodes::all::{OP_CHECKSIG, OP_ENDIF, OP_IF, OP_PUSHNUM_1};
+    use bitcoin::opcodes::OP_FALSE;
+    use bitcoin::script::Builder;
+    use bitcoin::secp256k1::Keypair;
+    use bitcoin::taproot::{LeafVersion, TaprootBuilder};
+    use bitcoin::transaction::Version;
+    use bitcoin::{OutPoint, ScriptBuf, TxIn, TxOut, Witness};
+    use ic_btc_interface::OutPoint as BtcOutPoint;
+    use std::str::FromStr;
+
+    const INSCRIBER: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+    const BODY: &str = r#"{"p":"brc-20","op":"transfer","tick":"ordi","amt":"10"}"#;
+
+    enum Field<'a> {
+        Push(&'a [u8], &'a [u8]),
+        PushNum(&'a [u8]),
+    }
+
+    fn push(builder: Builder, bytes: &[u8]) -> Builder {
+        builder.push_slice(bytes_to_push_bytes(bytes).unwrap().as_push_bytes())
+    }
+
+    /// Returns the commit transaction and the reveal transaction spending it
+    /// through the tapscript of the inscription.
+    fn inscribe(fields: &[Field]) -> (Transaction, Transaction) {
+        let mut builder = push(Builder::new(), &[7; 32])
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_FALSE)
+            .push_opcode(OP_IF);
+        builder = push(builder, b"ord");
+        for field in fields {
+            builder = match field {
+                Field::Push(tag, value) => push(push(builder, tag), value),
+                Field::PushNum(value) => push(builder.push_opcode(OP_PUSHNUM_1), value),
+            };
+        }
+        builder = push(push(builder, &[]), BODY.as_bytes()).push_opcode(OP_ENDIF);
+        let tapscript = builder.into_script();
+        let secp = Secp256k1::new();
+        let (internal_key, _) = Keypair::from_seckey_slice(&secp, &[3; 32])
+            .unwrap()
+            .x_only_public_key();
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, tapscript.clone())
+            .unwrap()
+            .finalize(&secp, internal_key)
+            .unwrap();
+        let control_block = spend_info
+            .control_block(&(tapscript.clone(), LeafVersion::TapScript))
+            .unwrap();
+        let commit = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new_p2tr_tweaked(spend_info.output_key()),
+            }],
+        };
+        let reveal = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: commit.txid(),
+                    vout: 0,
+                },
+                witness: Witness::from_slice(&[
+                    vec![1; 64],
+                    tapscript.into_bytes(),
+                    control_block.serialize(),
+                ]),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(546),
+                script_pubkey: Address::from_str(INSCRIBER)
+                    .unwrap()
+                    .assume_checked()
+                    .script_pubkey(),
+            }],
+        };
+        (commit, reveal)
+    }
+
+    fn reveal_tx(fields: &[Field]) -> Transaction {
+        inscribe(fields).1
+    }
+
+    fn content_type<'a>() -> Field<'a> {
+        Field::Push(&[1], b"text/plain;charset=utf-8")
+    }
+
+    fn deposit_tx(reveal: &Transaction, vout: u32, value: u64) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: reveal.txid(),
+                    vout,
+                },
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_is_on_first_sat() {
+        let on_first_sat = |fields: &[Field]| {
+            ParsedEnvelope::from_transaction_input(&reveal_tx(fields), 0)
+                .unwrap()
+                .is_on_first_sat()
+        };
+        assert!(on_first_sat(&[content_type()]));
+        assert!(on_first_sat(&[content_type(), Field::Push(&[2], &[0])]));
+        // A pointer moves the inscription off the first sat.
+        assert!(!on_first_sat(&[content_type(), Field::Push(&[2], &[1])]));
+        // A pushnum tag curses the inscription.
+        assert!(!on_first_sat(&[Field::PushNum(
+            b"text/plain;charset=utf-8"
+        )]));
+        assert!(!on_first_sat(&[content_type(), content_type()]));
+        assert!(!on_first_sat(&[content_type(), Field::Push(&[4], b"x")]));
+    }
+
+    #[test]
+    fn test_deposit_of() {
+        let reveal = reveal_tx(&[content_type()]);
+        assert_eq!(
+            deposit_of(&deposit_tx(&reveal, 0, 546)).unwrap(),
+            (reveal.txid().to_string(), 546)
+        );
+        assert!(deposit_of(&deposit_tx(&reveal, 1, 546)).is_err());
+        assert!(deposit_of(&deposit_tx(&reveal, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_reveal_inscription() {
+        let reveal = reveal_tx(&[content_type()]);
+        let (transfer, provenance) = reveal_inscription(&reveal, Network::Testnet, 546).unwrap();
+        assert_eq!(transfer.tick, "ordi");
+        assert_eq!(transfer.amt, "10");
+        assert_eq!(
+            provenance,
+            InscriptionProvenance {
+                inscription_id: format!("{}i0", reveal.txid()),
+                inscriber: INSCRIBER.to_string(),
+                deposit_value: 546,
+            }
+        );
+
+        let moved = reveal_tx(&[content_type(), Field::Push(&[2], &[1])]);
+        assert!(matches!(
+            reveal_inscription(&moved, Network::Testnet, 546),
+            Err(GenerateTicketError::OrdTxError(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_tapscript_committed() {
+        let (commit, reveal) = inscribe(&[content_type()]);
+        assert!(is_tapscript_committed(&reveal, &commit));
+
+        // The txid doesn't cover a witness swapped for another inscription.
+        let (_, other) = inscribe(&[content_type(), Field::Push(&[2], &[0])]);
+        let mut forged = reveal.clone();
+        forged.input[0].witness = other.input[0].witness.clone();
+        assert_eq!(forged.txid(), reveal.txid());
+        assert!(!is_tapscript_committed(&forged, &commit));
+
+        let (other_commit, _) = inscribe(&[content_type(), Field::Push(&[2], &[0])]);
+        assert!(!is_tapscript_committed(&reveal, &other_commit));
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let leaves = (1..=5u8)
+            .map(|n| TxMerkleNode::from_byte_array([n; 32]))
+            .collect::<Vec<_>>();
+        let root = bitcoin::merkle_tree::calculate_root(leaves.iter().copied()).unwrap();
+        let branch = |mut pos: usize| {
+            let mut level = leaves.clone();
+            let mut branch = vec![];
+            while level.len() > 1 {
+                if level.len() % 2 == 1 {
+                    level.push(*level.last().unwrap());
+                }
+                branch.push(level[pos ^ 1]);
+                level = level
+                    .chunks(2)
+                    .map(|pair| merkle_parent(pair[0], pair[1]))
+                    .collect();
+                pos /= 2;
+            }
+            branch
+        };
+        for (pos, leaf) in leaves.iter().enumerate() {
+            let txid = bitcoin::Txid::from_raw_hash(leaf.to_raw_hash());
+            assert_eq!(merkle_root(txid, &branch(pos), pos as u32), Some(root));
+        }
+        let txid = bitcoin::Txid::from_raw_hash(leaves[2].to_raw_hash());
+        assert_ne!(merkle_root(txid, &branch(2), 3), Some(root));
+        assert_eq!(merkle_root(txid, &branch(2), 8), None);
+    }
+
+    #[test]
+    fn test_is_deposit_in() {
+        let txid = Txid::from_str(&"11".repeat(32)).unwrap();
+        let utxo = |vout, value| Utxo {
+            outpoint: BtcOutPoint { txid, vout },
+            value,
+            height: 100,
+        };
+        let provenance = InscriptionProvenance {
+            inscription_id: String::new(),
+            inscriber: String::new(),
+            deposit_value: 546,
+        };
+        assert!(is_deposit_in(&[utxo(0, 546)], txid, Some(&provenance)));
+        assert!(is_deposit_in(&[utxo(0, 1000)], txid, None));
+        assert!(!is_deposit_in(&[utxo(0, 1000)], txid, Some(&provenance)));
+        assert!(!is_deposit_in(&[utxo(1, 546)], txid, Some(&provenance)));
+        let other = Txid::from_str(&"22".repeat(32)).unwrap();
+        assert!(!is_deposit_in(&[utxo(0, 546)], other, Some(&provenance)));
+    }
+}
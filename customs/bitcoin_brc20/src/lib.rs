@@ -3,10 +3,15 @@ mod bitcoin;
 mod bitcoin_to_custom;
 mod call_error;
 mod custom_to_bitcoin;
+mod deposit_sweep;
+mod deposit_watcher;
+mod destination;
+mod fee_bump;
 mod generate_ticket;
 mod guard;
 mod hub;
 mod hub_to_custom;
+mod inscription_index;
 mod management;
 mod ord;
 //mod psbt;
@@ -15,6 +20,7 @@ mod stable_memory;
 pub(crate) mod state;
 mod tasks;
 mod types;
+mod unlock_retry;
 
 pub mod constants {
     use crate::ord::builder::fees::Fees;
@@ -49,6 +55,26 @@ pub mod constants {
     pub const TRANSFER_TX_VBYTES: u64 = 120;
     pub const FIXED_COMMIT_TX_VBYTES: u64 =
         2 * OUTPUT_SIZE_VBYTES + TX_OVERHEAD_VBYTES + REVEAL_TX_VBYTES + TRANSFER_TX_VBYTES;
+    pub const MAX_UNLOCK_BATCH_SIZE: u64 = 20;
+    pub const MAX_UNLOCK_RETRY_ATTEMPTS: u32 = 8;
+    pub const MAX_UNLOCK_ATTEMPT_HISTORY: usize = 20;
+    pub const UNLOCK_RETRY_BASE_DELAY: u64 = MIN_NANOS;
+    pub const UNLOCK_RETRY_MAX_DELAY: u64 = 60 * MIN_NANOS;
+    pub const UNLOCK_FEE_BUMP_DELAY: u64 = 120 * MIN_NANOS;
+    pub const MAX_UNLOCK_FEE_BUMPS: usize = 5;
+    pub const FEE_BUMP_CHECK_DELAY: u64 = 10 * MIN_NANOS;
+    pub const MIN_RELAY_FEE_PER_VBYTE: u64 = 1;
+    pub const WATCH_DEPOSITS_NAME: &str = "WATCH_DEPOSITS";
+    pub const WATCH_DEPOSITS_INTERVAL: u64 = 120;
+    pub const SWEEP_DEPOSITS_NAME: &str = "SWEEP_DEPOSITS";
+    pub const SWEEP_DEPOSITS_INTERVAL: u64 = 600;
+    pub const MAX_DEPOSIT_WATCHES: usize = 10_000;
+    pub const MAX_DEPOSIT_WATCHES_PER_CALLER: usize = 10;
+    pub const MAX_RECEIVER_LENGTH: usize = 128;
+    pub const DEPOSIT_WATCH_EXPIRATION: u64 = 7 * 24 * 60 * MIN_NANOS;
+    pub const MAX_ADDRESSES_PER_SCAN: usize = 10;
+    pub const MIN_SCAN_INTERVAL: u64 = 10 * MIN_NANOS;
+    pub const MAX_SWEEPS_PER_ROUND: usize = 5;
 }
 
 pub mod retry {
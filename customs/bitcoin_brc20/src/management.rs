@@ -1,6 +1,10 @@
+use bitcoin::block::Header;
 use bitcoin::Transaction;
-use candid::{CandidType, Principal};
-use ic_btc_interface::{GetCurrentFeePercentilesRequest, MillisatoshiPerByte, Network};
+use candid::{CandidType, Deserialize, Principal};
+use ic_btc_interface::{
+    GetCurrentFeePercentilesRequest, GetUtxosRequest, GetUtxosResponse, MillisatoshiPerByte,
+    Network, NetworkInRequest, UtxosFilterInRequest,
+};
 use ic_cdk::api::call::CallResult;
 use ic_ic00_types::{
     DerivationPath, ECDSAPublicKeyArgs, ECDSAPublicKeyResponse, EcdsaCurve, EcdsaKeyId,
@@ -42,6 +46,99 @@ where
     }
 }
 
+/// Fetches the full list of UTXOs for the specified address.
+pub async fn get_utxos(
+    network: Network,
+    address: &str,
+    min_confirmations: u32,
+) -> Result<GetUtxosResponse, CallError> {
+    // NB. The minimum number of cycles that need to be sent with the call is 10B (4B) for
+    // Bitcoin mainnet (Bitcoin testnet):
+    // https://internetcomputer.org/docs/current/developer-docs/integrations/bitcoin/bitcoin-how-it-works#api-fees--pricing
+    let get_utxos_cost_cycles = match network {
+        Network::Mainnet => 10_000_000_000,
+        Network::Testnet | Network::Regtest => 4_000_000_000,
+    };
+
+    let mut response: GetUtxosResponse = call(
+        "bitcoin_get_utxos",
+        get_utxos_cost_cycles,
+        &GetUtxosRequest {
+            address: address.to_string(),
+            network: network.into(),
+            filter: Some(UtxosFilterInRequest::MinConfirmations(min_confirmations)),
+        },
+    )
+    .await?;
+
+    let mut utxos = std::mem::take(&mut response.utxos);
+
+    // Continue fetching until there are no more pages.
+    while let Some(page) = response.next_page {
+        response = call(
+            "bitcoin_get_utxos",
+            get_utxos_cost_cycles,
+            &GetUtxosRequest {
+                address: address.to_string(),
+                network: network.into(),
+                filter: Some(UtxosFilterInRequest::Page(page)),
+            },
+        )
+        .await?;
+
+        utxos.append(&mut response.utxos);
+    }
+
+    response.utxos = utxos;
+
+    Ok(response)
+}
+
+#[derive(CandidType, Debug)]
+struct GetBlockHeadersRequest {
+    start_height: u32,
+    end_height: Option<u32>,
+    network: NetworkInRequest,
+}
+
+#[derive(CandidType, Debug, Deserialize)]
+struct GetBlockHeadersResponse {
+    tip_height: u32,
+    block_headers: Vec<Vec<u8>>,
+}
+
+/// Returns the header of the block at the height of the main chain followed by
+/// the bitcoin canister.
+pub async fn get_block_header(network: Network, height: u32) -> Result<Header, CallError> {
+    // The fees of a single header are charged like the fees of `bitcoin_get_utxos`.
+    let cost_cycles = match network {
+        Network::Mainnet => 10_000_000_000,
+        Network::Testnet | Network::Regtest => 4_000_000_000,
+    };
+
+    let response: GetBlockHeadersResponse = call(
+        "bitcoin_get_block_headers",
+        cost_cycles,
+        &GetBlockHeadersRequest {
+            start_height: height,
+            end_height: Some(height),
+            network: network.into(),
+        },
+    )
+    .await?;
+    response
+        .block_headers
+        .first()
+        .and_then(|header| bitcoin::consensus::deserialize(header).ok())
+        .ok_or(CallError {
+            method: "bitcoin_get_block_headers".to_string(),
+            reason: Reason::CanisterError(format!(
+                "no block header at {}, the tip is at {}",
+                height, response.tip_height
+            )),
+        })
+}
+
 /// Returns the current fee percentiles on the bitcoin network.
 pub async fn get_current_fees(network: Network) -> Result<Vec<MillisatoshiPerByte>, CallError> {
     let cost_cycles = match network {
@@ -118,6 +118,22 @@ impl OrdTransactionBuilder {
     pub async fn build_reveal_transaction(
         &mut self,
         args: RevealTransactionArgs,
+    ) -> OrdResult<Transaction> {
+        let taproot_payload = match self.taproot_payload.clone() {
+            Some(taproot_payload) => taproot_payload,
+            None => {
+                panic!("taproot error");
+            }
+        };
+        self.build_reveal_transaction_with_payload(args, &taproot_payload)
+            .await
+    }
+
+    /// Create the reveal transaction spending the given taproot output of the commit transaction
+    pub async fn build_reveal_transaction_with_payload(
+        &mut self,
+        args: RevealTransactionArgs,
+        taproot_payload: &TaprootPayload,
     ) -> OrdResult<Transaction> {
         // previous output
         let previous_output = OutPoint {
@@ -147,18 +163,8 @@ impl OrdTransactionBuilder {
             output: tx_out,
         };
 
-        let tx = match self.taproot_payload.as_ref() {
-            Some(taproot_payload) => self.signer.sign_reveal_transaction_schnorr(
-                taproot_payload,
-                &args.redeem_script,
-                unsigned_tx,
-            ),
-            None => {
-                panic!("taproot error");
-            }
-        }?;
-
-        Ok(tx)
+        self.signer
+            .sign_reveal_transaction_schnorr(taproot_payload, &args.redeem_script, unsigned_tx)
     }
 
     /// Generate redeem script from script pubkey and inscription
@@ -275,6 +281,130 @@ impl OrdTransactionBuilder {
             leftover_amount: Amount::from_sat(leftover_amount),
         })
     }
+
+    /// Creates the commit transaction of several inscriptions with one taproot output
+    /// per inscription, `fees.reveal_fee` and `fees.spend_fee` are paid for each inscription.
+    pub async fn build_batch_commit_transaction_with_fixed_fees<T>(
+        &mut self,
+        network: Network,
+        args: CreateBatchCommitTransactionArgs<T>,
+    ) -> OrdResult<CreateBatchCommitTransaction>
+    where
+        T: Inscription,
+    {
+        let secp_ctx = secp256k1::Secp256k1::new();
+
+        let input_amount = args
+            .inputs
+            .iter()
+            .map(|input| input.amount.to_sat())
+            .sum::<u64>();
+        let reveal_balance = POSTAGE + args.fees.reveal_fee.to_sat() + args.fees.spend_fee.to_sat();
+        let required =
+            args.fees.commit_fee.to_sat() + reveal_balance * args.inscriptions.len() as u64;
+        let leftover_amount = input_amount
+            .checked_sub(required)
+            .ok_or(OrdError::InsufficientBalance {
+                available: input_amount,
+                required,
+            })?;
+
+        let mut reveals = vec![];
+        let mut tx_out = vec![];
+        for inscription in args.inscriptions.iter() {
+            // every inscription is locked by its own P2TR keys
+            let p2tr_keys = generate_keypair(&secp_ctx)
+                .await
+                .map_err(|e| OrdError::ManagementError(format!("code: {:?}, msg:{}", e.0, e.1)))?;
+            let redeem_script_pubkey = RedeemScriptPubkey::XPublickey(p2tr_keys.1);
+            let redeem_script = self.generate_redeem_script(inscription, redeem_script_pubkey)?;
+            let taproot_payload = TaprootPayload::build(
+                &secp_ctx,
+                p2tr_keys.0,
+                p2tr_keys.1,
+                &redeem_script,
+                reveal_balance,
+                network,
+            )?;
+            tx_out.push(TxOut {
+                value: Amount::from_sat(reveal_balance),
+                script_pubkey: taproot_payload.address.script_pubkey(),
+            });
+            reveals.push(BatchReveal {
+                redeem_script,
+                reveal_balance: Amount::from_sat(reveal_balance),
+                taproot_payload,
+            });
+        }
+        if leftover_amount > 0 {
+            tx_out.push(TxOut {
+                value: Amount::from_sat(leftover_amount),
+                script_pubkey: args.txin_script_pubkey.clone(),
+            });
+        }
+
+        let tx_in = args
+            .inputs
+            .iter()
+            .map(|input| TxIn {
+                previous_output: OutPoint {
+                    txid: input.id,
+                    vout: input.index,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(0xfffffff0),
+                witness: Witness::new(),
+            })
+            .collect();
+
+        let unsigned_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: tx_in,
+            output: tx_out,
+        };
+
+        Ok(CreateBatchCommitTransaction {
+            unsigned_tx,
+            reveals,
+            leftover_amount: Amount::from_sat(leftover_amount),
+        })
+    }
+}
+
+/// Arguments for creating the commit transaction of several inscriptions
+pub struct CreateBatchCommitTransactionArgs<T>
+where
+    T: Inscription,
+{
+    /// UTXOs to be used as inputs of the transaction
+    pub inputs: Vec<Utxo>,
+    /// Inscriptions to write, one commit output each
+    pub inscriptions: Vec<T>,
+    pub fees: Fees,
+    /// Script pubkey of the inputs
+    pub txin_script_pubkey: ScriptBuf,
+}
+
+/// The commit transaction of several inscriptions
+#[derive(Debug, Clone)]
+pub struct CreateBatchCommitTransaction {
+    /// The unsigned commit transaction
+    pub unsigned_tx: Transaction,
+    /// The reveals of the inscriptions, in the order of the commit outputs
+    pub reveals: Vec<BatchReveal>,
+    /// Leftover amount sent back to the inputs owner
+    pub leftover_amount: Amount,
+}
+
+/// What the reveal transaction of an inscription in a batch needs
+#[derive(Debug, Clone)]
+pub struct BatchReveal {
+    /// The redeem script to be used in the reveal transaction
+    pub redeem_script: ScriptBuf,
+    /// Balance of the commit output
+    pub reveal_balance: Amount,
+    pub taproot_payload: TaprootPayload,
 }
 
 #[derive(Debug)]
@@ -7,11 +7,14 @@ use bitcoin::{Address, PublicKey};
 use ic_canister_log::log;
 use ic_ic00_types::DerivationPath;
 use omnity_types::ic_log::ERROR;
+use serde_bytes::ByteBuf;
 
 #[derive(Clone)]
 pub struct MixSigner {
     pub pubkey: PublicKey,
     pub signer_addr: Address,
+    /// The derivation path of the key, empty for the main deposit address.
+    pub derivation_path: Vec<ByteBuf>,
 }
 
 impl MixSigner {
@@ -19,19 +22,29 @@ impl MixSigner {
         Self {
             pubkey: public_key,
             signer_addr: addr,
+            derivation_path: vec![],
+        }
+    }
+
+    /// The signer of a derived deposit address.
+    pub fn derived(public_key: PublicKey, addr: Address, derivation_path: Vec<ByteBuf>) -> Self {
+        Self {
+            pubkey: public_key,
+            signer_addr: addr,
+            derivation_path,
         }
     }
 
     pub async fn sign_with_ecdsa(&self, message: Message) -> OrdResult<Signature> {
         let key_name = read_state(|s| s.ecdsa_key_name.clone());
         let sighash = *message.as_ref();
-        let sec1_signature =
-            management::sign_with_ecdsa(key_name, DerivationPath::new(vec![]), sighash)
-                .await
-                .map_err(|e| {
-                    log!(ERROR, "call management signature error: {:?}", e);
-                    OrdError::UnexpectedSignature
-                })?;
+        let derivation_path = DerivationPath::new(self.derivation_path.clone());
+        let sec1_signature = management::sign_with_ecdsa(key_name, derivation_path, sighash)
+            .await
+            .map_err(|e| {
+                log!(ERROR, "call management signature error: {:?}", e);
+                OrdError::UnexpectedSignature
+            })?;
         Signature::from_compact(sec1_signature.as_slice()).map_err(OrdError::Signature)
     }
 }
@@ -49,6 +49,48 @@ pub async fn spend_utxo_transaction(
     Ok(tx)
 }
 
+/// Spends the utxos to the recipient with a change output, the inputs are all
+/// owned by the signer and the first input is sent to the recipient.
+pub async fn spend_utxos_with_change(
+    signer: &MixSigner,
+    recipient: Address,
+    utxo_value: Amount,
+    inputs: Vec<Utxo>,
+    change_value: Amount,
+) -> Result<Transaction, CustomToBitcoinError> {
+    let tx_out = vec![
+        TxOut {
+            value: utxo_value,
+            script_pubkey: recipient.script_pubkey(),
+        },
+        TxOut {
+            value: change_value,
+            script_pubkey: signer.signer_addr.script_pubkey(),
+        },
+    ];
+
+    let tx_in = inputs
+        .iter()
+        .map(|input| TxIn {
+            previous_output: OutPoint {
+                txid: input.id,
+                vout: input.index,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::from_consensus(0xfffffff0),
+            witness: Witness::new(),
+        })
+        .collect();
+
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: tx_in,
+        output: tx_out,
+    };
+    sign_transaction(signer, tx, inputs, &signer.signer_addr.script_pubkey()).await
+}
+
 async fn sign_transaction(
     signer: &MixSigner,
     unsigned_tx: Transaction,
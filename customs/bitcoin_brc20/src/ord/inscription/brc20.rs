@@ -140,11 +140,11 @@ pub struct Brc20Transfer201 {
     pub tick: String,
     /// Amount to transfer (required): States the amount of the brc-20 to transfer.
     pub amt: String,
-    #[serde(rename = "ref", skip_serializing)]
+    #[serde(rename = "ref", default, skip_serializing)]
     pub refx: String,
-    #[serde(skip_serializing)]
+    #[serde(default, skip_serializing)]
     pub chain: String,
-    #[serde(skip_serializing)]
+    #[serde(default, skip_serializing)]
     pub ext: String,
 }
 
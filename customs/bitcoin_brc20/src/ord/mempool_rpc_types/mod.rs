@@ -6,6 +6,8 @@ use std::str::FromStr;
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct TxInfo {
+    #[serde(default)]
+    pub txid: String,
     pub version: i32,
     pub locktime: u32,
     pub vin: Vec<Vin>,
@@ -53,6 +55,15 @@ pub struct Vout {
     pub value: u64,
 }
 
+/// The merkle branch of a transaction in its block, the hashes are in the
+/// display order.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MerkleProof {
+    pub block_height: u32,
+    pub merkle: Vec<String>,
+    pub pos: u32,
+}
+
 impl TryFrom<TxInfo> for Transaction {
     type Error = anyhow::Error;
 
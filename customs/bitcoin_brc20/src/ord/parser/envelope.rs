@@ -45,6 +45,22 @@ impl ParsedEnvelope {
             })
         })
     }
+
+    /// Returns true if the inscription is neither cursed nor unbound and no pointer
+    /// moves it off the first sat of its input, so that an inscription of the first
+    /// input lands on the first sat of the first output.
+    pub(crate) fn is_on_first_sat(&self) -> bool {
+        !self.pushnum
+            && !self.stutter
+            && !self.payload.duplicate_field
+            && !self.payload.incomplete_field
+            && !self.payload.unrecognized_even_field
+            && self
+                .payload
+                .pointer
+                .as_ref()
+                .map_or(true, |pointer| pointer.iter().all(|b| *b == 0))
+    }
 }
 
 impl RawEnvelope {
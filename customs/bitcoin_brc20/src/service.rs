@@ -1,11 +1,17 @@
 use crate::bitcoin_to_custom::finalize_lock;
+use crate::constants::MAX_UNLOCK_BATCH_SIZE;
 use crate::generate_ticket::{GenerateTicketArgs, GenerateTicketError};
+use crate::hub_to_custom::{fetch_hub_directive_task, fetch_hub_ticket_task};
+use crate::inscription_index::DepositUtxos;
 use crate::ord::builder::Utxo;
 use crate::state::{
     init_ecdsa_public_key, mutate_state, read_state, replace_state, Brc20State, StateProfile,
 };
 use crate::tasks::start_tasks;
-use crate::types::{LockTicketRequest, ReleaseTokenStatus, TokenResp, UtxoArgs};
+use crate::types::{
+    GetBtcAddressArgs, LockTicketRequest, ReleaseTokenStatus, TokenResp, UtxoArgs,
+};
+use crate::unlock_retry::FailedUnlockTicket;
 use candid::{CandidType, Deserialize, Principal};
 use ic_btc_interface::Txid;
 use ic_canisters_http_types::{HttpRequest, HttpResponse};
@@ -45,6 +51,13 @@ pub async fn generate_ticket(req: GenerateTicketArgs) -> Result<(), GenerateTick
     crate::generate_ticket::generate_ticket(req).await
 }
 
+/// Returns the deposit address of the target chain and receiver, a transfer
+/// inscription sent to it is bridged without naming the destination.
+#[update]
+pub async fn get_btc_address(args: GetBtcAddressArgs) -> Result<String, String> {
+    crate::deposit_watcher::get_btc_address(args).await
+}
+
 #[query]
 fn get_platform_fee(target_chain: ChainId) -> (Option<u128>, Option<String>) {
     read_state(|s| {
@@ -83,6 +96,15 @@ pub fn set_fee_collector(addr: String) {
     mutate_state(|s|s.fee_collector = addr);
 }
 
+#[update(guard = "is_admin")]
+pub fn set_unlock_batch_size(size: u64) -> Result<(), String> {
+    if size > MAX_UNLOCK_BATCH_SIZE {
+        return Err(format!("the batch size is limited to {}", MAX_UNLOCK_BATCH_SIZE));
+    }
+    mutate_state(|s| s.unlock_batch_size = size);
+    Ok(())
+}
+
 #[query]
 fn release_token_status(ticket_id: String) -> ReleaseTokenStatus {
     read_state(|s| s.unlock_tx_status(&ticket_id))
@@ -117,7 +139,7 @@ pub async fn finalize_lock_request(txid: String) {
     let txid = Txid::from_str(txid.as_str()).unwrap();
     let deposit = read_state(|s| s.deposit_addr.clone().unwrap());
     let req = read_state(|s| s.pending_lock_ticket_requests.get(&txid).cloned().unwrap());
-    finalize_lock(txid, req, deposit).await;
+    finalize_lock(txid, req, deposit, &mut DepositUtxos::default()).await;
 }
 
 #[query(hidden = true)]
@@ -129,14 +151,39 @@ fn transform(raw: TransformArgs) -> http_request::HttpResponse {
     }
 }
 
+#[query(guard = "is_admin")]
+pub fn failed_unlock_tickets() -> Vec<(Seq, FailedUnlockTicket)> {
+    read_state(|s| {
+        s.failed_unlock_tickets
+            .iter()
+            .map(|(seq, f)| (*seq, f.clone()))
+            .collect()
+    })
+}
+
+/// Retries a failed unlock ticket, the fee rate is in satoshi per vbyte.
 #[update(guard = "is_admin")]
-pub async fn resend_unlock_ticket(seq: Seq, fee_rate: u64) -> String {
-    let r = crate::custom_to_bitcoin::submit_unlock_ticket(seq, fee_rate)
-        .await
-        .unwrap()
-        .unwrap();
-    mutate_state(|s| s.flight_unlock_ticket_map.insert(seq, r.clone()));
-    serde_json::to_string(&r).unwrap()
+pub async fn retry_unlock_ticket(seq: Seq, fee_rate: Option<u64>) -> Result<(), String> {
+    crate::unlock_retry::retry_unlock_ticket(seq, fee_rate).await
+}
+
+/// Refunds a failed unlock ticket to its sender on the source chain,
+/// returns the refund ticket id.
+#[update(guard = "is_admin")]
+pub async fn refund_unlock_ticket(seq: Seq) -> Result<String, String> {
+    crate::unlock_retry::refund_unlock_ticket(seq).await
+}
+
+/// Notified by the hub once the customs subscribed the push mode, the tickets
+/// are pulled immediately instead of waiting for the next timer.
+#[update(guard = "is_hub")]
+pub fn on_new_tickets(_from_seq: Seq) {
+    fetch_hub_ticket_task();
+}
+
+#[update(guard = "is_hub")]
+pub fn on_new_directives(_from_seq: Seq) {
+    fetch_hub_directive_task();
 }
 
 #[query]
@@ -149,6 +196,11 @@ fn query_finalized_lock_tickets(txid: Txid) -> Option<LockTicketRequest> {
     read_state(|s| s.finalized_lock_ticket_requests.get(&txid).cloned())
 }
 
+#[query(guard = "is_admin")]
+fn query_rejected_lock_tickets(txid: Txid) -> Option<LockTicketRequest> {
+    read_state(|s| s.rejected_lock_ticket_requests.get(&txid).cloned())
+}
+
 #[derive(CandidType, Deserialize)]
 pub struct InitArgs {
     pub admins: Vec<Principal>,
@@ -159,6 +211,13 @@ pub struct InitArgs {
     pub fee_token: String,
 }
 
+fn is_hub() -> Result<(), String> {
+    match read_state(|s| s.hub_principal == ic_cdk::caller()) {
+        true => Ok(()),
+        false => Err("caller is not hub".to_string()),
+    }
+}
+
 fn is_admin() -> Result<(), String> {
     let c = ic_cdk::caller();
     match ic_cdk::api::is_controller(&c) || read_state(|s| s.admins.contains(&c)) {
@@ -15,14 +15,17 @@ use serde::{Deserialize, Serialize};
 use omnity_types::ic_log::ERROR;
 use omnity_types::{Chain, ChainId, ChainState, Directive, Seq, Ticket, TicketId, Token, TokenId};
 
-use crate::bitcoin::{main_bitcoin_address, ECDSAPublicKey};
+use crate::bitcoin::{destination_to_bitcoin_address, main_bitcoin_address, ECDSAPublicKey};
 use crate::constants::{MIN_NANOS, SEC_NANOS};
 use crate::custom_to_bitcoin::SendTicketResult;
+use crate::deposit_watcher::DepositWatch;
+use crate::destination::Destination;
 use crate::ord::builder::{Utxo};
 use crate::service::InitArgs;
 use crate::stable_memory;
 use crate::stable_memory::Memory;
 use crate::types::{GenTicketStatus, LockTicketRequest, ReleaseTokenStatus};
+use crate::unlock_retry::FailedUnlockTicket;
 
 thread_local! {
     static STATE: RefCell<Option<Brc20State>> = const {RefCell::new(None)};
@@ -58,6 +61,10 @@ pub struct Brc20State {
     //lock tickets storage
     pub pending_lock_ticket_requests: BTreeMap<Txid, LockTicketRequest>,
     pub finalized_lock_ticket_requests: BTreeMap<Txid, LockTicketRequest>,
+    /// The deposits whose transfer the indexer reports from another address than
+    /// the inscriber, they are never finalized.
+    #[serde(default)]
+    pub rejected_lock_ticket_requests: BTreeMap<Txid, LockTicketRequest>,
 
     #[serde(skip, default = "crate::stable_memory::init_directives_queue")]
     pub directives_queue: StableBTreeMap<u64, Directive, Memory>,
@@ -68,6 +75,35 @@ pub struct Brc20State {
     pub fee_collector: String,
     pub fee_token_factor: Option<u128>,
     pub target_chain_factor: BTreeMap<ChainId, u128>,
+    /// The max number of unlock tickets sharing one commit transaction,
+    /// the tickets are unlocked one by one if it's not above 1.
+    #[serde(default)]
+    pub unlock_batch_size: u64,
+    /// The unlock tickets failed to be sent, waiting for a retry or a refund.
+    #[serde(default)]
+    pub failed_unlock_tickets: BTreeMap<Seq, FailedUnlockTicket>,
+    /// The refunded unlock tickets, mapped to the id of their refund ticket.
+    #[serde(default)]
+    pub refunded_unlock_tickets: BTreeMap<Seq, TicketId>,
+    /// The destinations of the derived deposit addresses, by address. A destination
+    /// is only kept once a deposit to its address is seen.
+    #[serde(default)]
+    pub deposit_destinations: BTreeMap<String, Destination>,
+    /// The derived deposit addresses scanned for new deposits.
+    #[serde(default)]
+    pub deposit_watches: BTreeMap<String, DepositWatch>,
+    /// The finalized deposits to derived addresses, waiting to be swept to the
+    /// main deposit address.
+    #[serde(default)]
+    pub pending_sweeps: BTreeMap<Txid, LockTicketRequest>,
+    #[serde(default)]
+    pub flight_sweeps: BTreeMap<Txid, SendTicketResult>,
+    /// The sweeps whose transfer the indexer rejected, left to the admin.
+    #[serde(default)]
+    pub failed_sweeps: BTreeMap<Txid, SendTicketResult>,
+    /// The ids of the deposited transfer inscriptions, mapped to their deposit.
+    #[serde(default)]
+    pub indexed_inscriptions: BTreeMap<String, Txid>,
 }
 
 #[derive(Serialize, Deserialize, CandidType, Clone)]
@@ -93,6 +129,7 @@ pub struct StateProfile {
     pub fee_collector: String,
     pub fee_token_factor: Option<u128>,
     pub target_chain_factor: BTreeMap<ChainId, u128>,
+    pub unlock_batch_size: u64,
 }
 
 impl From<&Brc20State> for StateProfile {
@@ -119,6 +156,7 @@ impl From<&Brc20State> for StateProfile {
             fee_collector: value.fee_collector.clone(),
             fee_token_factor: value.fee_token_factor,
             target_chain_factor: value.target_chain_factor.clone(),
+            unlock_batch_size: value.unlock_batch_size,
         }
     }
 }
@@ -151,6 +189,7 @@ impl Brc20State {
             is_timer_running: Default::default(),
             pending_lock_ticket_requests: Default::default(),
             finalized_lock_ticket_requests: Default::default(),
+            rejected_lock_ticket_requests: Default::default(),
             btc_network,
             indexer_principal: args.indexer_principal,
             deposit_addr_utxo: vec![],
@@ -161,6 +200,15 @@ impl Brc20State {
             ticket_id_seq_indexer: Default::default(),
             target_chain_factor: Default::default(),
             fee_token: args.fee_token,
+            unlock_batch_size: 0,
+            failed_unlock_tickets: Default::default(),
+            refunded_unlock_tickets: Default::default(),
+            deposit_destinations: Default::default(),
+            deposit_watches: Default::default(),
+            pending_sweeps: Default::default(),
+            flight_sweeps: Default::default(),
+            failed_sweeps: Default::default(),
+            indexed_inscriptions: Default::default(),
         };
         Ok(ret)
     }
@@ -222,10 +270,24 @@ impl Brc20State {
         (fee, fee.map(|_|self.fee_collector.clone()))
     }
 
+    /// Returns the deposit address of the destination.
+    ///
+    /// PRECONDITION: the ECDSA public key is initialized.
+    pub fn destination_address(&self, destination: &Destination) -> String {
+        let ecdsa_public_key = self
+            .ecdsa_public_key
+            .as_ref()
+            .expect("bug: the ECDSA public key must be initialized");
+        destination_to_bitcoin_address(ecdsa_public_key, destination).display(self.btc_network)
+    }
+
     pub fn generate_ticket_status(&self, tx_id: Txid) -> GenTicketStatus {
         if let Some(req) = self.pending_lock_ticket_requests.get(&tx_id) {
             return GenTicketStatus::Pending(req.clone());
         }
+        if let Some(req) = self.rejected_lock_ticket_requests.get(&tx_id) {
+            return GenTicketStatus::Rejected(req.clone());
+        }
 
         match self
             .finalized_lock_ticket_requests
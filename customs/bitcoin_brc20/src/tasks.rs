@@ -5,6 +5,8 @@ use ic_cdk_timers::set_timer_interval;
 
 use crate::constants::*;
 use crate::custom_to_bitcoin::{finalize_unlock_tickets_task, submit_unlock_tickets_task};
+use crate::deposit_sweep::sweep_deposits_task;
+use crate::deposit_watcher::watch_deposits_task;
 use crate::hub_to_custom::{fetch_hub_directive_task, fetch_hub_ticket_task};
 
 pub fn start_tasks() {
@@ -28,4 +30,12 @@ pub fn start_tasks() {
         Duration::from_secs(SUBMIT_UNLOCK_TICKETS_INTERVAL),
         submit_unlock_tickets_task,
     );
+    set_timer_interval(
+        Duration::from_secs(WATCH_DEPOSITS_INTERVAL),
+        watch_deposits_task,
+    );
+    set_timer_interval(
+        Duration::from_secs(SWEEP_DEPOSITS_INTERVAL),
+        sweep_deposits_task,
+    );
 }
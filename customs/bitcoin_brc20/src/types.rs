@@ -4,6 +4,7 @@ use ic_btc_interface::Txid;
 use serde::Serialize;
 use std::str::FromStr;
 
+use crate::inscription_index::InscriptionProvenance;
 use crate::ord::builder::fees::Fees;
 use crate::ord::builder::Utxo;
 use omnity_types::brc20::QueryBrc20TransferArgs;
@@ -56,6 +57,8 @@ pub enum GenTicketStatus {
     Pending(LockTicketRequest),
     Confirmed(LockTicketRequest),
     Finalized(LockTicketRequest),
+    /// The indexer disagrees with the inscription of the deposit.
+    Rejected(LockTicketRequest),
 }
 
 #[derive(candid::CandidType, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -67,6 +70,19 @@ pub struct LockTicketRequest {
     pub amount: String,
     pub txid: Txid,
     pub received_at: u64,
+    /// The address the tokens are deposited to, the main deposit address if none.
+    #[serde(default)]
+    pub deposit_addr: Option<String>,
+    /// The provenance of the transfer inscription, none for the requests
+    /// received before it was tracked.
+    #[serde(default)]
+    pub inscription: Option<InscriptionProvenance>,
+}
+
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetBtcAddressArgs {
+    pub target_chain_id: String,
+    pub receiver: String,
 }
 
 /// Unspent transaction output to be used as input of a transaction
@@ -0,0 +1,295 @@
+use candid::CandidType;
+use ic_canister_log::log;
+use serde::{Deserialize, Serialize};
+
+use omnity_types::ic_log::{ERROR, INFO};
+use omnity_types::{Seq, TicketId};
+
+use crate::constants::{
+    MAX_UNLOCK_ATTEMPT_HISTORY, MAX_UNLOCK_RETRY_ATTEMPTS, SUBMIT_UNLOCK_TICKETS_NAME,
+    UNLOCK_RETRY_BASE_DELAY, UNLOCK_RETRY_MAX_DELAY,
+};
+use crate::custom_to_bitcoin::{
+    estimate_fee_per_vbyte, process_unlock_ticket, CustomToBitcoinError,
+};
+use crate::deposit_sweep::is_unlock_held;
+use crate::guard::TimerLogicGuard;
+use crate::hub;
+use crate::state::{mutate_state, read_state, Brc20State};
+
+#[derive(CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnlockErrorKind {
+    /// The unlock may succeed later, it's retried with a backoff.
+    Transient,
+    /// The unlock fails the same way on every retry, it waits for the admin
+    /// to retry or refund it.
+    Permanent,
+}
+
+impl CustomToBitcoinError {
+    pub fn kind(&self) -> UnlockErrorKind {
+        match self {
+            CustomToBitcoinError::SignFailed(_)
+            | CustomToBitcoinError::SendTransactionFailed(_)
+            | CustomToBitcoinError::InsufficientFunds
+            | CustomToBitcoinError::ManagementCallFailed(_)
+            | CustomToBitcoinError::InsufficientBalance { .. } => UnlockErrorKind::Transient,
+            CustomToBitcoinError::BuildTransactionFailed(_)
+            | CustomToBitcoinError::ArgumentError(_) => UnlockErrorKind::Permanent,
+        }
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct UnlockAttempt {
+    pub time_at: u64,
+    pub error: String,
+    pub kind: UnlockErrorKind,
+}
+
+/// An unlock ticket that failed to be sent to bitcoin.
+#[derive(CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct FailedUnlockTicket {
+    pub ticket_id: TicketId,
+    /// The number of the failed attempts, the history keeps the latest ones.
+    pub attempt_count: u32,
+    pub attempts: Vec<UnlockAttempt>,
+    /// The time of the next automatic retry, none if it waits for the admin.
+    pub next_retry_at: Option<u64>,
+}
+
+impl FailedUnlockTicket {
+    pub fn is_due(&self, now: u64) -> bool {
+        self.next_retry_at.is_some_and(|t| t <= now)
+    }
+}
+
+/// The delay before the next retry, doubled after every failed attempt.
+pub fn retry_delay(attempt_count: u32) -> u64 {
+    let factor = 1u64 << attempt_count.saturating_sub(1).min(32);
+    UNLOCK_RETRY_BASE_DELAY
+        .saturating_mul(factor)
+        .min(UNLOCK_RETRY_MAX_DELAY)
+}
+
+/// Moves the ticket to the failed unlock tickets, or records the attempt if
+/// it's already there.
+pub fn record_unlock_failure(seq: Seq, err: &CustomToBitcoinError) {
+    let now = ic_cdk::api::time();
+    mutate_state(|s| record_failure(s, seq, err, now));
+}
+
+fn record_failure(s: &mut Brc20State, seq: Seq, err: &CustomToBitcoinError, now: u64) {
+    let ticket_id = s
+        .tickets_queue
+        .get(&seq)
+        .map(|t| t.ticket_id)
+        .unwrap_or_default();
+    let failed = s
+        .failed_unlock_tickets
+        .entry(seq)
+        .or_insert(FailedUnlockTicket {
+            ticket_id,
+            attempt_count: 0,
+            attempts: vec![],
+            next_retry_at: None,
+        });
+    failed.attempt_count += 1;
+    failed.attempts.push(UnlockAttempt {
+        time_at: now,
+        error: err.to_string(),
+        kind: err.kind(),
+    });
+    if failed.attempts.len() > MAX_UNLOCK_ATTEMPT_HISTORY {
+        failed.attempts.remove(0);
+    }
+    failed.next_retry_at = match err.kind() {
+        UnlockErrorKind::Transient if failed.attempt_count < MAX_UNLOCK_RETRY_ATTEMPTS => {
+            Some(now + retry_delay(failed.attempt_count))
+        }
+        _ => None,
+    };
+}
+
+/// Retries the failed unlock tickets whose backoff has elapsed.
+pub async fn retry_failed_unlock_tickets() {
+    let now = ic_cdk::api::time();
+    let due: Vec<Seq> = read_state(|s| {
+        s.failed_unlock_tickets
+            .iter()
+            .filter(|(seq, f)| f.is_due(now) && !is_unlock_held(s, **seq))
+            .map(|(seq, _)| *seq)
+            .collect()
+    });
+    if due.is_empty() {
+        return;
+    }
+    let fee_rate = estimate_fee_per_vbyte().await / 1000;
+    for seq in due {
+        match process_unlock_ticket(seq, fee_rate).await {
+            Ok(_) => {
+                mutate_state(|s| s.failed_unlock_tickets.remove(&seq));
+                log!(INFO, "retry unlock ticket {} success", seq);
+            }
+            // The fee utxos are used up, the later tickets would fail the same way.
+            Err(CustomToBitcoinError::InsufficientFunds) => break,
+            Err(e) => record_unlock_failure(seq, &e),
+        }
+    }
+}
+
+/// Retries a failed unlock ticket now, at the given fee rate in satoshi per vbyte
+/// or at the estimated one.
+pub async fn retry_unlock_ticket(seq: Seq, fee_rate: Option<u64>) -> Result<(), String> {
+    if read_state(|s| !s.failed_unlock_tickets.contains_key(&seq)) {
+        return Err(format!("the unlock ticket {} hasn't failed", seq));
+    }
+    let _guard = match TimerLogicGuard::new(SUBMIT_UNLOCK_TICKETS_NAME.to_string()) {
+        Some(guard) => guard,
+        None => return Err("the unlock tickets are being submitted, try again later".into()),
+    };
+    let fee_rate = match fee_rate {
+        Some(fee_rate) => fee_rate,
+        None => estimate_fee_per_vbyte().await / 1000,
+    };
+    match process_unlock_ticket(seq, fee_rate).await {
+        Ok(_) => {
+            mutate_state(|s| s.failed_unlock_tickets.remove(&seq));
+            Ok(())
+        }
+        Err(e) => {
+            if !matches!(e, CustomToBitcoinError::InsufficientFunds) {
+                record_unlock_failure(seq, &e);
+            }
+            Err(e.to_string())
+        }
+    }
+}
+
+/// Gives up unlocking a failed ticket, the hub refunds it to the sender on the
+/// source chain. Returns the id of the refund ticket.
+pub async fn refund_unlock_ticket(seq: Seq) -> Result<TicketId, String> {
+    // The ticket can't be retried by the timer while it's being refunded.
+    let _guard = match TimerLogicGuard::new(SUBMIT_UNLOCK_TICKETS_NAME.to_string()) {
+        Some(guard) => guard,
+        None => return Err("the unlock tickets are being submitted, try again later".into()),
+    };
+    let failed = read_state(|s| s.failed_unlock_tickets.get(&seq).cloned())
+        .ok_or(format!("the unlock ticket {} hasn't failed", seq))?;
+    let reason = failed
+        .attempts
+        .last()
+        .map(|a| a.error.clone())
+        .unwrap_or_default();
+    let hub_principal = read_state(|s| s.hub_principal);
+    match hub::report_ticket_failure(hub_principal, failed.ticket_id.clone(), reason, true).await {
+        Ok(()) => {
+            // The id of the refund ticket created by the hub.
+            let refund_ticket_id = format!("{}_refund", failed.ticket_id);
+            mutate_state(|s| {
+                s.failed_unlock_tickets.remove(&seq);
+                s.refunded_unlock_tickets
+                    .insert(seq, refund_ticket_id.clone());
+            });
+            log!(
+                INFO,
+                "refund unlock ticket {} with ticket {}",
+                seq,
+                refund_ticket_id
+            );
+            Ok(refund_ticket_id)
+        }
+        Err(e) => {
+            log!(ERROR, "refund unlock ticket {} error: {}", seq, e);
+            Err(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::InitArgs;
+    use candid::Principal;
+
+    fn state() -> Brc20State {
+        Brc20State::init(InitArgs {
+            admins: vec![],
+            hub_principal: Principal::anonymous(),
+            network: omnity_types::Network::Testnet,
+            chain_id: "Bitcoin".to_string(),
+            indexer_principal: Principal::anonymous(),
+            fee_token: "ICP".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), UNLOCK_RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(1), UNLOCK_RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(2), 2 * UNLOCK_RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(4), 8 * UNLOCK_RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(20), UNLOCK_RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(u32::MAX), UNLOCK_RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn test_error_kind() {
+        for err in [
+            CustomToBitcoinError::SignFailed("".into()),
+            CustomToBitcoinError::SendTransactionFailed("".into()),
+            CustomToBitcoinError::InsufficientFunds,
+            CustomToBitcoinError::ManagementCallFailed("".into()),
+            CustomToBitcoinError::InsufficientBalance {
+                required: 2,
+                available: 1,
+            },
+        ] {
+            assert_eq!(err.kind(), UnlockErrorKind::Transient, "{}", err);
+        }
+        for err in [
+            CustomToBitcoinError::BuildTransactionFailed("".into()),
+            CustomToBitcoinError::ArgumentError("".into()),
+        ] {
+            assert_eq!(err.kind(), UnlockErrorKind::Permanent, "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_record_failure_backoff() {
+        let mut s = state();
+        let transient = CustomToBitcoinError::InsufficientBalance {
+            required: 2,
+            available: 1,
+        };
+
+        record_failure(&mut s, 7, &transient, 100);
+        let failed = s.failed_unlock_tickets.get(&7).unwrap();
+        assert_eq!(failed.attempt_count, 1);
+        assert_eq!(failed.next_retry_at, Some(100 + UNLOCK_RETRY_BASE_DELAY));
+        assert!(!failed.is_due(100));
+        assert!(failed.is_due(100 + UNLOCK_RETRY_BASE_DELAY));
+
+        record_failure(&mut s, 7, &transient, 200);
+        let failed = s.failed_unlock_tickets.get(&7).unwrap();
+        assert_eq!(failed.attempt_count, 2);
+        assert_eq!(
+            failed.next_retry_at,
+            Some(200 + 2 * UNLOCK_RETRY_BASE_DELAY)
+        );
+
+        // The automatic retries stop after the max attempts.
+        for _ in 2..MAX_UNLOCK_RETRY_ATTEMPTS {
+            record_failure(&mut s, 7, &transient, 300);
+        }
+        let failed = s.failed_unlock_tickets.get(&7).unwrap();
+        assert_eq!(failed.attempt_count, MAX_UNLOCK_RETRY_ATTEMPTS);
+        assert_eq!(failed.next_retry_at, None);
+
+        // A permanent failure waits for the admin.
+        let permanent = CustomToBitcoinError::ArgumentError("bad receiver".into());
+        record_failure(&mut s, 8, &permanent, 100);
+        assert_eq!(s.failed_unlock_tickets.get(&8).unwrap().next_retry_at, None);
+    }
+}
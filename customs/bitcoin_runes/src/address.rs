@@ -42,6 +42,29 @@ pub enum BitcoinAddress {
     OpReturn(Vec<u8>),
 }
 
+/// The type of the deposit addresses derived for the destinations.
+#[derive(
+    candid::CandidType,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+)]
+pub enum AddressType {
+    /// Pay to witness public key hash address, controlled by the threshold ECDSA key.
+    #[default]
+    P2wpkh,
+    /// Pay to taproot address, controlled by the threshold Schnorr (BIP-340) key
+    /// and spent through the key path.
+    P2tr,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum WitnessVersion {
     V0 = 0,
@@ -105,6 +128,7 @@ pub fn main_destination(main_chain_id: String, token: String) -> Destination {
         target_chain_id: main_chain_id,
         receiver: ic_cdk::id().to_string(),
         token: Some(token + MAIN_DEST_TOKEN_SUFFIX),
+        address_type: None,
     }
 }
 
@@ -164,6 +188,47 @@ pub fn destination_to_bitcoin_address(
     BitcoinAddress::P2wpkhV0(crate::tx::hash160(&pk))
 }
 
+/// Derives a taproot address for the specified destination and converts it into
+/// bech32m textual representation.
+pub fn destination_to_p2tr_address(
+    network: Network,
+    schnorr_public_key: &ECDSAPublicKey,
+    destination: &Destination,
+) -> String {
+    network_and_public_key_to_p2tr(
+        network,
+        &derive_public_key(schnorr_public_key, destination).public_key,
+    )
+}
+
+/// Constructs the taproot address corresponding to the specified destination.
+pub fn destination_to_p2tr_bitcoin_address(
+    schnorr_public_key: &ECDSAPublicKey,
+    destination: &Destination,
+) -> BitcoinAddress {
+    let pk = derive_public_key(schnorr_public_key, destination).public_key;
+    BitcoinAddress::P2trV1(taproot_output_key(&pk))
+}
+
+/// Returns the x-only output key of a key path only taproot output, i.e. the
+/// internal key tweaked without a script tree as described in
+/// [BIP-0341](https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#constructing-and-spending-taproot-outputs).
+///
+/// # Panics
+///
+/// This function panics if the public key in not compressed.
+pub fn taproot_output_key(public_key: &[u8]) -> [u8; 32] {
+    use bitcoin::key::{TapTweak, UntweakedPublicKey};
+    use bitcoin::secp256k1::Secp256k1;
+
+    assert_eq!(public_key.len(), 33);
+    assert!(public_key[0] == 0x02 || public_key[0] == 0x03);
+    let internal_key = UntweakedPublicKey::from_slice(&public_key[1..])
+        .expect("bug: failed to parse a valid x-only public key");
+    let (output_key, _parity) = internal_key.tap_tweak(&Secp256k1::verification_only(), None);
+    output_key.to_inner().serialize()
+}
+
 fn encode_bech32(network: Network, hash: &[u8], version: WitnessVersion) -> String {
     use bech32::u5;
 
@@ -208,6 +273,15 @@ pub fn network_and_public_key_to_p2wpkh(network: Network, public_key: &[u8]) ->
     encode_bech32(network, &crate::tx::hash160(public_key), WitnessVersion::V0)
 }
 
+/// Calculates the p2tr address as described in [BIP-0086](https://github.com/bitcoin/bips/blob/master/bip-0086.mediawiki).
+///
+/// # Panics
+///
+/// This function panics if the public key in not compressed.
+pub fn network_and_public_key_to_p2tr(network: Network, public_key: &[u8]) -> String {
+    encode_bech32(network, &taproot_output_key(public_key), WitnessVersion::V1)
+}
+
 /// Returns the human-readable part of a bech32 address
 pub fn hrp(network: Network) -> &'static str {
     match network {
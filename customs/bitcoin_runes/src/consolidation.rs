@@ -0,0 +1,437 @@
+use crate::address::{self, BitcoinAddress};
+use crate::fee_policy::FeeOperation;
+use crate::state::{
+    audit, mutate_state, read_state, BtcChangeOutput, CustomsState, RunesChangeOutput,
+    RunesUtxo, SubmittedBtcTransactionV2, SubmittedTxKind, BTC_TOKEN,
+};
+use crate::{
+    estimate_fee_per_vbyte, fake_sign, filter_output_destinations, greedy, management,
+    sign_transaction, tx, tx_vsize_estimate, undo_sign_request, updates, BuildTxError,
+    SignTxRequest, MIN_OUTPUT_AMOUNT,
+};
+use ic_btc_interface::Utxo;
+use ic_canister_log::log;
+use omnity_types::ic_log::{CRITICAL, ERROR, INFO};
+use omnity_types::rune_id::RuneId;
+use scopeguard::{guard, ScopeGuard};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The minimum number of available UTXOs of a rune (or BTC) before we consolidate them.
+pub const MIN_CONSOLIDATION_UTXOS: usize = 50;
+
+/// The maximum number of inputs of a consolidation transaction.
+pub const MAX_CONSOLIDATION_INPUTS: usize = 100;
+
+pub fn consolidate_utxos_task() {
+    ic_cdk::spawn(async {
+        let _guard = match crate::guard::TimerLogicGuard::new() {
+            Some(guard) => guard,
+            None => return,
+        };
+        consolidate_utxos().await;
+    });
+}
+
+/// Sweeps the small UTXOs of the most fragmented rune, together with the small BTC
+/// UTXOs, into one runes output and one BTC output when the fee rate is low enough.
+async fn consolidate_utxos() {
+    let max_fee_per_vbyte = match read_state(|s| s.consolidation_max_fee_per_vbyte) {
+        Some(max_fee) => max_fee,
+        None => return,
+    };
+
+    // Only one consolidation at a time, so that the customs always keeps
+    // enough liquid UTXOs for the release requests.
+    if read_state(|s| s.has_pending_consolidation()) {
+        return;
+    }
+
+    let fee_per_vbyte = match estimate_fee_per_vbyte(FeeOperation::Consolidation).await {
+        Some(fee) => fee,
+        None => return,
+    };
+    if fee_per_vbyte > max_fee_per_vbyte {
+        log!(
+            INFO,
+            "[consolidate_utxos]: fee rate {} is above the consolidation ceiling {}",
+            fee_per_vbyte,
+            max_fee_per_vbyte
+        );
+        return;
+    }
+
+    let main_chain_id = read_state(|s| s.chain_id.clone());
+    let ecdsa_public_key = updates::get_btc_address::init_ecdsa_public_key().await;
+    let btc_main_address = address::main_bitcoin_address(
+        &ecdsa_public_key,
+        main_chain_id.clone(),
+        String::from(BTC_TOKEN),
+    );
+
+    let maybe_sign_request = mutate_state(|s| {
+        let (rune_id, runes_utxos) = select_runes_utxos(s)?;
+        let btc_utxos = select_btc_utxos(s, &runes_utxos, fee_per_vbyte);
+
+        let mut runes_utxos: BTreeSet<_> = runes_utxos.into_iter().collect();
+        let mut btc_utxos: BTreeSet<_> = btc_utxos.into_iter().collect();
+        match build_consolidation_transaction(
+            &mut runes_utxos,
+            &mut btc_utxos,
+            address::main_bitcoin_address(&ecdsa_public_key, main_chain_id, rune_id.to_string()),
+            btc_main_address,
+            fee_per_vbyte,
+        ) {
+            Ok((unsigned_tx, runes_change_outputs, btc_change_output, runes_utxos, btc_utxos)) => {
+                Some(SignTxRequest {
+                    network: s.btc_network,
+                    outpoint_destination: filter_output_destinations(s, &unsigned_tx),
+                    unsigned_tx,
+                    runes_change_outputs,
+                    btc_change_output,
+                    requests: vec![],
+                    runes_utxos,
+                    btc_utxos,
+                })
+            }
+            Err(err) => {
+                log!(
+                    ERROR,
+                    "[consolidate_utxos]: failed to build the consolidation transaction of {}: {:?}",
+                    rune_id,
+                    err
+                );
+                s.available_runes_utxos.append(&mut runes_utxos);
+                s.available_fee_utxos.append(&mut btc_utxos);
+                None
+            }
+        }
+    });
+
+    let req = match maybe_sign_request {
+        Some(req) => req,
+        None => return,
+    };
+
+    // This guard ensures that we return the UTXOs back to the state
+    // if the signing or sending a transaction fails or panics.
+    let utxos_guard = guard(
+        (req.runes_utxos, req.btc_utxos),
+        |(runes_utxos, btc_utxos)| {
+            undo_sign_request(vec![], runes_utxos, btc_utxos);
+        },
+    );
+
+    let txid = req.unsigned_tx.txid();
+    let signed_tx = match sign_transaction(&req.outpoint_destination, req.unsigned_tx).await {
+        Ok(signed_tx) => signed_tx,
+        Err(err) => {
+            log!(
+                ERROR,
+                "[consolidate_utxos]: failed to sign the consolidation transaction: {}",
+                err
+            );
+            return;
+        }
+    };
+
+    if let Err(err) = management::send_transaction(&signed_tx, req.network).await {
+        log!(
+            CRITICAL,
+            "[consolidate_utxos]: failed to send the consolidation transaction: {}",
+            err
+        );
+        return;
+    }
+
+    log!(
+        INFO,
+        "[consolidate_utxos]: sent the consolidation transaction {} with {} inputs",
+        &txid,
+        signed_tx.inputs.len()
+    );
+
+    let (runes_utxos, btc_utxos) = ScopeGuard::into_inner(utxos_guard);
+    let runes_change_output = req
+        .runes_change_outputs
+        .into_iter()
+        .next()
+        .expect("bug: the consolidation transaction must have a runes output");
+    mutate_state(|s| {
+        audit::sent_transaction(
+            s,
+            SubmittedBtcTransactionV2 {
+                rune_id: runes_change_output.rune_id,
+                requests: vec![],
+                txid,
+                runes_utxos,
+                btc_utxos,
+                submitted_at: ic_cdk::api::time(),
+                runes_change_output,
+                btc_change_output: req.btc_change_output,
+                fee_per_vbyte: Some(fee_per_vbyte),
+                extra_runes_change_outputs: vec![],
+                kind: SubmittedTxKind::Consolidation,
+            },
+        );
+    });
+}
+
+/// Takes the smallest UTXOs of the rune that has the most available UTXOs, the
+/// largest UTXO is left for the release requests. The runes with pending requests
+/// are not consolidated.
+fn select_runes_utxos(s: &mut CustomsState) -> Option<(RuneId, Vec<RunesUtxo>)> {
+    let mut utxos_count: BTreeMap<RuneId, usize> = BTreeMap::new();
+    for utxo in s.available_runes_utxos.iter() {
+        *utxos_count.entry(utxo.runes.rune_id).or_default() += 1;
+    }
+
+    let (rune_id, _) = utxos_count
+        .into_iter()
+        .filter(|(rune_id, count)| {
+            *count >= MIN_CONSOLIDATION_UTXOS
+                && s
+                    .pending_rune_tx_requests
+                    .get(rune_id)
+                    .map_or(true, |requests| requests.is_empty())
+        })
+        .max_by_key(|(_, count)| *count)?;
+
+    let mut utxos: Vec<RunesUtxo> = s
+        .available_runes_utxos
+        .iter()
+        .filter(|utxo| utxo.runes.rune_id == rune_id)
+        .cloned()
+        .collect();
+    utxos.sort_by_key(|utxo| utxo.runes.amount);
+    utxos.truncate(MAX_CONSOLIDATION_INPUTS.min(utxos.len() - 1));
+
+    for utxo in utxos.iter() {
+        s.available_runes_utxos.remove(utxo);
+    }
+    Some((rune_id, utxos))
+}
+
+/// Takes the smallest BTC UTXOs if they are fragmented, and makes sure that the
+/// selected UTXOs pay the fee of the consolidation transaction.
+fn select_btc_utxos(
+    s: &mut CustomsState,
+    runes_utxos: &[RunesUtxo],
+    fee_per_vbyte: u64,
+) -> Vec<Utxo> {
+    let mut utxos = vec![];
+    if s.available_fee_utxos.len() >= MIN_CONSOLIDATION_UTXOS {
+        utxos = s.available_fee_utxos.iter().cloned().collect();
+        utxos.sort_by_key(|utxo| utxo.value);
+        let max_inputs = MAX_CONSOLIDATION_INPUTS.saturating_sub(runes_utxos.len());
+        utxos.truncate(max_inputs.min(utxos.len() - 1));
+        for utxo in utxos.iter() {
+            s.available_fee_utxos.remove(utxo);
+        }
+    }
+
+    // Assume one additional input for the fee, and the runes output plus the btc change output.
+    let inputs_count = (runes_utxos.len() + utxos.len() + 1) as u64;
+    let fee = tx_vsize_estimate(inputs_count, 2) * fee_per_vbyte / 1000;
+    let required = fee + MIN_OUTPUT_AMOUNT * 2;
+    let selected = runes_utxos.iter().map(|utxo| utxo.raw.value).sum::<u64>()
+        + utxos.iter().map(|utxo| utxo.value).sum::<u64>();
+    if selected < required {
+        utxos.append(&mut greedy(
+            required - selected,
+            &mut s.available_fee_utxos,
+            |utxo| utxo.value,
+        ));
+    }
+    utxos
+}
+
+/// Builds a transaction that spends all the given UTXOs, the runes go to the
+/// first output at the runes main address, as the transaction has no runestone,
+/// and the remaining BTC go to the second output at the BTC main address.
+///
+/// The given UTXOs are put back into the sets if we fail to build the transaction.
+pub fn build_consolidation_transaction(
+    runes_utxos: &mut BTreeSet<RunesUtxo>,
+    btc_utxos: &mut BTreeSet<Utxo>,
+    runes_main_address: BitcoinAddress,
+    btc_main_address: BitcoinAddress,
+    fee_per_vbyte: u64,
+) -> Result<
+    (
+        tx::UnsignedTransaction,
+        Vec<RunesChangeOutput>,
+        BtcChangeOutput,
+        Vec<RunesUtxo>,
+        Vec<Utxo>,
+    ),
+    BuildTxError,
+> {
+    /// Having a sequence number lower than (0xffffffff - 1) signals the use of replacement by fee.
+    const SEQUENCE_RBF_ENABLED: u32 = 0xfffffffd;
+
+    let rune_id = match runes_utxos.first() {
+        Some(utxo) => utxo.runes.rune_id,
+        None => return Err(BuildTxError::NotEnoughFunds),
+    };
+    assert!(
+        runes_utxos.iter().all(|utxo| utxo.runes.rune_id == rune_id),
+        "bug: a consolidation transaction only sweeps the UTXOs of one rune"
+    );
+
+    let runes_utxos_guard = guard(std::mem::take(runes_utxos), |utxos| {
+        *runes_utxos = utxos;
+    });
+    let btc_utxos_guard = guard(std::mem::take(btc_utxos), |utxos| {
+        *btc_utxos = utxos;
+    });
+
+    let inputs = runes_utxos_guard
+        .iter()
+        .map(|utxo| &utxo.raw)
+        .chain(btc_utxos_guard.iter())
+        .map(|utxo| tx::UnsignedInput {
+            previous_output: utxo.outpoint.clone(),
+            value: utxo.value,
+            sequence: SEQUENCE_RBF_ENABLED,
+        })
+        .collect::<Vec<_>>();
+    let input_btc_amount = inputs.iter().map(|input| input.value).sum::<u64>();
+
+    let mut unsigned_tx = tx::UnsignedTransaction {
+        inputs,
+        outputs: vec![
+            tx::TxOut {
+                address: runes_main_address,
+                value: MIN_OUTPUT_AMOUNT,
+            },
+            tx::TxOut {
+                address: btc_main_address,
+                value: 0,
+            },
+        ],
+        lock_time: 0,
+    };
+
+    let fee = fake_sign(&unsigned_tx).vsize() as u64 * fee_per_vbyte / 1000;
+    let btc_consumed = fee + MIN_OUTPUT_AMOUNT;
+    if input_btc_amount < btc_consumed + MIN_OUTPUT_AMOUNT {
+        log!(
+            CRITICAL,
+            "[build_consolidation_transaction]: input btc amount: {} is not enough for btc consumed: {}",
+            input_btc_amount,
+            btc_consumed,
+        );
+        return Err(BuildTxError::NotEnoughGas);
+    }
+
+    let btc_change_amount = input_btc_amount - btc_consumed;
+    unsigned_tx.outputs[1].value = btc_change_amount;
+
+    let runes_utxos = ScopeGuard::into_inner(runes_utxos_guard);
+    let btc_utxos = ScopeGuard::into_inner(btc_utxos_guard);
+    let runes_change_output = RunesChangeOutput {
+        rune_id,
+        vout: 0,
+        value: runes_utxos.iter().map(|utxo| utxo.runes.amount).sum(),
+    };
+    let btc_change_output = BtcChangeOutput {
+        vout: 1,
+        value: btc_change_amount,
+    };
+
+    Ok((
+        unsigned_tx,
+        vec![runes_change_output],
+        btc_change_output,
+        runes_utxos.into_iter().collect(),
+        btc_utxos.into_iter().collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::RunesBalance;
+    use ic_btc_interface::OutPoint;
+
+    const RUNE: RuneId = RuneId { block: 840000, tx: 3 };
+
+    fn utxo(id: u8, value: u64) -> Utxo {
+        Utxo {
+            outpoint: OutPoint {
+                txid: [id; 32].into(),
+                vout: 1,
+            },
+            value,
+            height: 0,
+        }
+    }
+
+    fn runes_utxo(id: u8, amount: u128) -> RunesUtxo {
+        RunesUtxo {
+            raw: utxo(id, MIN_OUTPUT_AMOUNT),
+            runes: RunesBalance {
+                rune_id: RUNE,
+                vout: 1,
+                amount,
+            },
+        }
+    }
+
+    #[test]
+    fn test_build_consolidation_transaction() {
+        let mut runes_utxos: BTreeSet<_> = (1..=10).map(|i| runes_utxo(i, i as u128)).collect();
+        let mut btc_utxos: BTreeSet<_> = (11..=20).map(|i| utxo(i, 10_000)).collect();
+
+        let (unsigned_tx, runes_changes, btc_change, used_runes_utxos, used_btc_utxos) =
+            build_consolidation_transaction(
+                &mut runes_utxos,
+                &mut btc_utxos,
+                BitcoinAddress::P2wpkhV0([1; 20]),
+                BitcoinAddress::P2wpkhV0([2; 20]),
+                10_000,
+            )
+            .unwrap();
+
+        assert!(runes_utxos.is_empty());
+        assert!(btc_utxos.is_empty());
+        assert_eq!(used_runes_utxos.len(), 10);
+        assert_eq!(used_btc_utxos.len(), 10);
+        assert_eq!(unsigned_tx.inputs.len(), 20);
+        assert_eq!(unsigned_tx.outputs.len(), 2);
+        assert_eq!(
+            runes_changes,
+            vec![RunesChangeOutput {
+                rune_id: RUNE,
+                vout: 0,
+                value: 55,
+            }]
+        );
+
+        let fee = fake_sign(&unsigned_tx).vsize() as u64 * 10;
+        assert_eq!(btc_change.vout, 1);
+        assert_eq!(
+            btc_change.value,
+            10 * MIN_OUTPUT_AMOUNT + 100_000 - fee - MIN_OUTPUT_AMOUNT
+        );
+        assert_eq!(unsigned_tx.outputs[1].value, btc_change.value);
+    }
+
+    #[test]
+    fn test_build_consolidation_transaction_not_enough_gas() {
+        let mut runes_utxos: BTreeSet<_> = (1..=10).map(|i| runes_utxo(i, i as u128)).collect();
+        let mut btc_utxos = BTreeSet::new();
+
+        let result = build_consolidation_transaction(
+            &mut runes_utxos,
+            &mut btc_utxos,
+            BitcoinAddress::P2wpkhV0([1; 20]),
+            BitcoinAddress::P2wpkhV0([2; 20]),
+            100_000,
+        );
+
+        assert_eq!(result.err(), Some(BuildTxError::NotEnoughGas));
+        // The UTXOs are put back.
+        assert_eq!(runes_utxos.len(), 10);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::address::BitcoinAddress;
+use crate::destination::Destination;
+use crate::state::{
+    audit, mutate_state, read_state, BtcChangeOutput, CpfpTransaction, SubmittedBtcTransactionV2,
+    SubmittedTxKind,
+};
+use crate::{
+    fake_sign, management, sign_transaction, tx, tx_vsize_estimate, BuildTxError,
+    MIN_OUTPUT_AMOUNT, MIN_RELAY_FEE_PER_VBYTE,
+};
+use ic_btc_interface::{Network, OutPoint, Txid};
+use ic_canister_log::log;
+use omnity_types::ic_log::{ERROR, INFO};
+use omnity_types::TxAction;
+use std::collections::BTreeMap;
+
+/// Returns the estimated vsize of the submitted transaction and the fee it pays.
+pub fn parent_vsize_and_fee(parent: &SubmittedBtcTransactionV2) -> (u64, u64) {
+    // The outputs with MIN_OUTPUT_AMOUNT, excluding the OP_RETURN and the btc change output.
+    let min_outputs = match parent.kind {
+        SubmittedTxKind::Release => {
+            parent.runes_change_outputs().count()
+                + parent
+                    .requests
+                    .iter()
+                    .filter(|req| {
+                        req.action != TxAction::Mint
+                            && !matches!(req.address, BitcoinAddress::OpReturn(_))
+                    })
+                    .count()
+        }
+        SubmittedTxKind::Consolidation => 1,
+    };
+    let outputs = match parent.kind {
+        SubmittedTxKind::Release => min_outputs + 2,
+        SubmittedTxKind::Consolidation => min_outputs + 1,
+    };
+    let inputs = parent.runes_utxos.len() + parent.btc_utxos.len();
+
+    let inputs_value = parent
+        .runes_utxos
+        .iter()
+        .map(|utxo| utxo.raw.value)
+        .chain(parent.btc_utxos.iter().map(|utxo| utxo.value))
+        .sum::<u64>();
+    let outputs_value = parent.btc_change_output.value + MIN_OUTPUT_AMOUNT * min_outputs as u64;
+
+    (
+        tx_vsize_estimate(inputs as u64, outputs as u64),
+        inputs_value.saturating_sub(outputs_value),
+    )
+}
+
+/// Returns the fee the transaction pays.
+pub fn unsigned_tx_fee(unsigned_tx: &tx::UnsignedTransaction) -> u64 {
+    let inputs_value = unsigned_tx.inputs.iter().map(|input| input.value).sum::<u64>();
+    let outputs_value = unsigned_tx.outputs.iter().map(|output| output.value).sum::<u64>();
+    inputs_value.saturating_sub(outputs_value)
+}
+
+/// Returns the fee the child transaction pays, it spends the BTC change output
+/// of the parent into a single BTC change output.
+pub fn child_fee(parent: &SubmittedBtcTransactionV2, child: &CpfpTransaction) -> u64 {
+    parent
+        .btc_change_output
+        .value
+        .saturating_sub(child.btc_change_output.value)
+}
+
+/// Returns the minimum fee a transaction of `replacement_vsize` replacing the
+/// parent pays: the replacement evicts the child too, so it pays for the fees of
+/// both of them plus the relay fee of its own size (BIP-125 rule 3).
+pub fn min_replacement_fee(
+    parent: &SubmittedBtcTransactionV2,
+    child: Option<&CpfpTransaction>,
+    replacement_vsize: u64,
+) -> u64 {
+    let (_, parent_fee) = parent_vsize_and_fee(parent);
+    parent_fee
+        + child.map_or(0, |child| child_fee(parent, child))
+        + replacement_vsize * MIN_RELAY_FEE_PER_VBYTE / 1000
+}
+
+/// Returns the minimum fee per vbyte (in millisatoshi) of a transaction
+/// replacing the parent, assuming it has the size of the parent.
+pub fn min_replacement_fee_per_vbyte(
+    parent: &SubmittedBtcTransactionV2,
+    child: Option<&CpfpTransaction>,
+) -> u64 {
+    let (parent_vsize, _) = parent_vsize_and_fee(parent);
+    let min_fee = min_replacement_fee(parent, child, parent_vsize);
+    (min_fee * 1000).div_ceil(parent_vsize.max(1))
+}
+
+/// Builds a child transaction that spends the BTC change output of the stuck
+/// transaction, so that the parent and the child together pay `fee_per_vbyte`.
+///
+/// Returns the child transaction, its BTC change output and the fee it pays.
+pub fn build_cpfp_transaction(
+    parent: &SubmittedBtcTransactionV2,
+    btc_main_address: BitcoinAddress,
+    fee_per_vbyte: u64,
+) -> Result<(tx::UnsignedTransaction, BtcChangeOutput, u64), BuildTxError> {
+    /// Having a sequence number lower than (0xffffffff - 1) signals the use of replacement by fee,
+    /// so that a later child with a higher fee can replace this one.
+    const SEQUENCE_RBF_ENABLED: u32 = 0xfffffffd;
+
+    let input_value = parent.btc_change_output.value;
+    let mut unsigned_tx = tx::UnsignedTransaction {
+        inputs: vec![tx::UnsignedInput {
+            previous_output: OutPoint {
+                txid: parent.txid,
+                vout: parent.btc_change_output.vout,
+            },
+            value: input_value,
+            sequence: SEQUENCE_RBF_ENABLED,
+        }],
+        outputs: vec![tx::TxOut {
+            address: btc_main_address,
+            value: 0,
+        }],
+        lock_time: 0,
+    };
+
+    let child_vsize = fake_sign(&unsigned_tx).vsize() as u64;
+    let (parent_vsize, parent_fee) = parent_vsize_and_fee(parent);
+    let package_fee = (parent_vsize + child_vsize) * fee_per_vbyte / 1000;
+    // The child pays at least for itself.
+    let fee = package_fee
+        .saturating_sub(parent_fee)
+        .max(child_vsize * fee_per_vbyte / 1000);
+
+    if input_value < fee + MIN_OUTPUT_AMOUNT {
+        return Err(BuildTxError::NotEnoughGas);
+    }
+
+    let btc_change_amount = input_value - fee;
+    unsigned_tx.outputs[0].value = btc_change_amount;
+    Ok((
+        unsigned_tx,
+        BtcChangeOutput {
+            vout: 0,
+            value: btc_change_amount,
+        },
+        fee,
+    ))
+}
+
+/// Signs and sends the child transaction, and records it in the event log.
+pub async fn send_cpfp_transaction(
+    parent_txid: Txid,
+    unsigned_tx: tx::UnsignedTransaction,
+    btc_change_output: BtcChangeOutput,
+    fee_per_vbyte: u64,
+    btc_network: Network,
+    main_btc_destination: Destination,
+) {
+    let txid = unsigned_tx.txid();
+    // The only input is the BTC change output of the parent.
+    let outpoint_destination: BTreeMap<OutPoint, Destination> = unsigned_tx
+        .inputs
+        .iter()
+        .map(|input| (input.previous_output.clone(), main_btc_destination.clone()))
+        .collect();
+
+    // The parent may have been finalized or replaced in the meantime, the child
+    // must not be sent then as it could not be recorded.
+    let parent_known = || read_state(|s| s.has_submitted_transaction(&parent_txid));
+    if !parent_known() {
+        log!(
+            INFO,
+            "[send_cpfp_transaction]: transaction {} is not pending anymore",
+            &parent_txid
+        );
+        return;
+    }
+
+    let signed_tx = match sign_transaction(&outpoint_destination, unsigned_tx).await {
+        Ok(tx) => tx,
+        Err(err) => {
+            log!(
+                ERROR,
+                "[send_cpfp_transaction]: failed to sign the child transaction of {}: {}",
+                &parent_txid,
+                err
+            );
+            return;
+        }
+    };
+
+    if !parent_known() {
+        log!(
+            INFO,
+            "[send_cpfp_transaction]: transaction {} is not pending anymore",
+            &parent_txid
+        );
+        return;
+    }
+
+    match management::send_transaction(&signed_tx, btc_network).await {
+        Ok(()) => {
+            log!(INFO,
+                "[send_cpfp_transaction]: sent child transaction {} to bump the fee of stuck transaction {}. TX bytes: {}",
+                &txid,
+                &parent_txid,
+                hex::encode(tx::encode_into(&signed_tx, Vec::new()))
+            );
+            mutate_state(|s| {
+                if !s.has_submitted_transaction(&parent_txid) {
+                    log!(
+                        ERROR,
+                        "[send_cpfp_transaction]: transaction {} was finalized or replaced while sending its child {}",
+                        &parent_txid,
+                        &txid
+                    );
+                    return;
+                }
+                audit::sent_cpfp_transaction(
+                    s,
+                    CpfpTransaction {
+                        txid,
+                        parent_txid,
+                        btc_change_output,
+                        fee_per_vbyte,
+                        submitted_at: ic_cdk::api::time(),
+                    },
+                );
+            });
+        }
+        Err(err) => {
+            log!(ERROR, "[send_cpfp_transaction]: failed to send child transaction bytes {} of stuck transaction {}: {}",
+                hex::encode(tx::encode_into(&signed_tx, Vec::new())),
+                &parent_txid,
+                err,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{RunesBalance, RunesChangeOutput, RunesUtxo};
+    use ic_btc_interface::Utxo;
+    use omnity_types::rune_id::RuneId;
+
+    const RUNE: RuneId = RuneId { block: 840000, tx: 3 };
+
+    fn parent(btc_change: u64) -> SubmittedBtcTransactionV2 {
+        let utxo = |id: u8, value: u64| Utxo {
+            outpoint: OutPoint {
+                txid: [id; 32].into(),
+                vout: 1,
+            },
+            value,
+            height: 0,
+        };
+        SubmittedBtcTransactionV2 {
+            rune_id: RUNE,
+            requests: vec![],
+            txid: [9; 32].into(),
+            runes_utxos: vec![RunesUtxo {
+                raw: utxo(1, MIN_OUTPUT_AMOUNT),
+                runes: RunesBalance {
+                    rune_id: RUNE,
+                    vout: 1,
+                    amount: 100,
+                },
+            }],
+            btc_utxos: vec![utxo(2, 20_000)],
+            submitted_at: 0,
+            runes_change_output: RunesChangeOutput {
+                rune_id: RUNE,
+                vout: 0,
+                value: 100,
+            },
+            btc_change_output: BtcChangeOutput {
+                vout: 1,
+                value: btc_change,
+            },
+            fee_per_vbyte: Some(1_000),
+            extra_runes_change_outputs: vec![],
+            kind: SubmittedTxKind::Consolidation,
+        }
+    }
+
+    #[test]
+    fn test_parent_vsize_and_fee() {
+        let (vsize, fee) = parent_vsize_and_fee(&parent(19_800));
+        assert_eq!(vsize, tx_vsize_estimate(2, 2));
+        assert_eq!(fee, MIN_OUTPUT_AMOUNT + 20_000 - 19_800 - MIN_OUTPUT_AMOUNT);
+    }
+
+    #[test]
+    fn test_build_cpfp_transaction() {
+        let parent = parent(19_800);
+        let (unsigned_tx, btc_change, fee) =
+            build_cpfp_transaction(&parent, BitcoinAddress::P2wpkhV0([1; 20]), 10_000).unwrap();
+
+        assert_eq!(unsigned_tx.inputs.len(), 1);
+        assert_eq!(unsigned_tx.inputs[0].previous_output.txid, parent.txid);
+        assert_eq!(unsigned_tx.inputs[0].previous_output.vout, 1);
+
+        let (parent_vsize, parent_fee) = parent_vsize_and_fee(&parent);
+        let child_vsize = fake_sign(&unsigned_tx).vsize() as u64;
+        assert_eq!(fee, (parent_vsize + child_vsize) * 10 - parent_fee);
+        assert_eq!(btc_change.value, 19_800 - fee);
+        assert_eq!(unsigned_tx.outputs[0].value, btc_change.value);
+
+        assert_eq!(
+            build_cpfp_transaction(&parent, BitcoinAddress::P2wpkhV0([1; 20]), 1_000_000).err(),
+            Some(BuildTxError::NotEnoughGas)
+        );
+    }
+
+    #[test]
+    fn test_min_replacement_fee() {
+        let parent = parent(19_800);
+        let (parent_vsize, parent_fee) = parent_vsize_and_fee(&parent);
+        assert_eq!(
+            min_replacement_fee(&parent, None, 100),
+            parent_fee + 100 * MIN_RELAY_FEE_PER_VBYTE / 1000
+        );
+
+        // The replacement evicts the child, it pays for its fee too.
+        let child = CpfpTransaction {
+            txid: [10; 32].into(),
+            parent_txid: parent.txid,
+            btc_change_output: BtcChangeOutput {
+                vout: 0,
+                value: 18_000,
+            },
+            fee_per_vbyte: 10_000,
+            submitted_at: 0,
+        };
+        assert_eq!(child_fee(&parent, &child), 1_800);
+        let min_fee = min_replacement_fee(&parent, Some(&child), parent_vsize);
+        assert_eq!(
+            min_fee,
+            parent_fee + 1_800 + parent_vsize * MIN_RELAY_FEE_PER_VBYTE / 1000
+        );
+        let fee_per_vbyte = min_replacement_fee_per_vbyte(&parent, Some(&child));
+        assert!(parent_vsize * fee_per_vbyte / 1000 >= min_fee);
+        assert!(parent_vsize * (fee_per_vbyte - 1) / 1000 <= min_fee);
+    }
+}
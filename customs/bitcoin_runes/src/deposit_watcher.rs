@@ -0,0 +1,484 @@
+use crate::destination::Destination;
+use crate::management::{self, CallSource};
+use crate::runes_etching::mint::{get_rune_balances_for_outputs, OutputRuneBalance};
+use crate::runestone::OutputAllocation;
+use crate::state::{audit, mutate_state, read_state, CustomsState, GenTicketStatus};
+use crate::updates::generate_ticket::{
+    fetch_new_utxos, generate_ticket, GenerateTicketArgs, GenerateTicketError,
+};
+use crate::updates::get_btc_address::{
+    destination_to_deposit_address_from_state, init_deposit_public_key, init_ecdsa_public_key,
+};
+use ic_btc_interface::{OutPoint, Txid};
+use ic_canister_log::log;
+use omnity_types::ic_log::{ERROR, INFO, WARNING};
+use omnity_types::rune_id::RuneId;
+use omnity_types::ChainState;
+use std::collections::BTreeMap;
+
+/// The maximum number of destinations registered for the deposit auto-detection.
+pub const MAX_DEPOSIT_WATCHES: usize = 10_000;
+
+/// The maximum number of destinations registered by one principal.
+pub const MAX_DEPOSIT_WATCHES_PER_CALLER: usize = 10;
+
+/// The time after which the customs stops scanning a registered destination.
+pub const DEPOSIT_WATCH_EXPIRATION_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+/// The maximum number of destinations scanned by one run of the watcher.
+pub const MAX_DESTINATIONS_PER_SCAN: usize = 10;
+
+/// The minimum time between two scans of the same destination.
+pub const MIN_SCAN_INTERVAL_NANOS: u64 = 10 * 60 * 1_000_000_000;
+
+pub fn watch_deposits_task() {
+    ic_cdk::spawn(async {
+        let _guard = match crate::guard::DepositWatcherGuard::new() {
+            Some(guard) => guard,
+            None => return,
+        };
+        watch_deposits().await;
+    });
+}
+
+/// Returns the registered destinations that expired at the specified time.
+pub fn expired_watches(s: &CustomsState, now: u64) -> Vec<Destination> {
+    s.deposit_watches
+        .iter()
+        .filter(|(_, watch)| {
+            watch.registered_at.saturating_add(DEPOSIT_WATCH_EXPIRATION_NANOS) < now
+        })
+        .map(|(destination, _)| destination.clone())
+        .collect()
+}
+
+/// Returns the destinations to scan at the specified time, the ones that were
+/// not scanned for the longest time first.
+pub fn destinations_to_scan(s: &CustomsState, now: u64) -> Vec<Destination> {
+    let mut candidates: Vec<_> = s
+        .deposit_watches
+        .iter()
+        .filter(|(_, watch)| {
+            watch.last_scanned_at.saturating_add(MIN_SCAN_INTERVAL_NANOS) <= now
+        })
+        .collect();
+    candidates.sort_by_key(|(_, watch)| watch.last_scanned_at);
+    candidates
+        .into_iter()
+        .take(MAX_DESTINATIONS_PER_SCAN)
+        .map(|(destination, _)| destination.clone())
+        .collect()
+}
+
+/// Scans a bounded number of registered destinations for new UTXOs and creates
+/// the generate ticket requests of the deposits.
+async fn watch_deposits() {
+    if read_state(|s| s.chain_state == ChainState::Deactive) {
+        return;
+    }
+
+    crate::updates::deposit_psbt::retry_pending_deposit_psbts().await;
+
+    let now = ic_cdk::api::time();
+    mutate_state(|s| {
+        for destination in expired_watches(s, now) {
+            audit::remove_deposit_watch(s, destination);
+        }
+    });
+
+    let destinations = mutate_state(|s| {
+        let destinations = destinations_to_scan(s, now);
+        for destination in destinations.iter() {
+            if let Some(watch) = s.deposit_watches.get_mut(destination) {
+                watch.last_scanned_at = now;
+            }
+        }
+        destinations
+    });
+    if destinations.is_empty() {
+        return;
+    }
+
+    init_ecdsa_public_key().await;
+    for destination in destinations {
+        scan_destination(destination).await;
+    }
+}
+
+async fn scan_destination(destination: Destination) {
+    init_deposit_public_key(destination.address_type).await;
+    let (btc_network, address) = read_state(|s| {
+        (
+            s.btc_network,
+            destination_to_deposit_address_from_state(s, &destination),
+        )
+    });
+
+    let utxos = match management::get_utxos(btc_network, &address, 1, CallSource::Custom).await {
+        Ok(response) => response.utxos,
+        Err(err) => {
+            log!(
+                ERROR,
+                "[watch_deposits]: failed to get the utxos of {}: {}",
+                address,
+                err
+            );
+            return;
+        }
+    };
+
+    // The new deposit outputs, grouped by the deposit transaction.
+    let new_deposits = read_state(|s| {
+        let watch = match s.deposit_watches.get(&destination) {
+            Some(watch) => watch,
+            None => return None,
+        };
+        let mut deposits: BTreeMap<Txid, Vec<OutPoint>> = BTreeMap::new();
+        for utxo in utxos {
+            if s.outpoint_destination.contains_key(&utxo.outpoint)
+                || watch.skipped_outpoints.contains(&utxo.outpoint)
+                || !matches!(
+                    s.generate_ticket_status(utxo.outpoint.txid),
+                    GenTicketStatus::Unknown
+                )
+            {
+                continue;
+            }
+            deposits
+                .entry(utxo.outpoint.txid)
+                .or_default()
+                .push(utxo.outpoint);
+        }
+        Some((watch.rune_id, deposits))
+    });
+    let (rune_id, deposits) = match new_deposits {
+        Some(new_deposits) => new_deposits,
+        None => return,
+    };
+
+    for (txid, outpoints) in deposits {
+        detect_deposit(&destination, rune_id, &address, txid, outpoints).await;
+    }
+}
+
+/// Creates the generate ticket request of the deposit transaction, or skips its
+/// outputs if the request can not be created.
+async fn detect_deposit(
+    destination: &Destination,
+    rune_id: RuneId,
+    address: &String,
+    txid: Txid,
+    outpoints: Vec<OutPoint>,
+) {
+    let skip = |reason: String| {
+        log!(
+            WARNING,
+            "[watch_deposits]: skipped the deposit {} to {}: {}",
+            txid,
+            address,
+            reason
+        );
+        mutate_state(|s| {
+            if let Some(watch) = s.deposit_watches.get_mut(destination) {
+                watch.skipped_outpoints.extend(outpoints.iter().cloned());
+            }
+        });
+    };
+
+    let (new_utxos, allocations) = match fetch_new_utxos(txid, address).await {
+        Ok((new_utxos, _, allocations)) => (new_utxos, allocations),
+        Err(GenerateTicketError::RpcError(err)) => {
+            log!(
+                ERROR,
+                "[watch_deposits]: failed to fetch the deposit {}: {}",
+                txid,
+                err
+            );
+            return;
+        }
+        Err(err) => return skip(format!("{:?}", err)),
+    };
+
+    // The amount is the balance the runes indexer reports, as the rune balances
+    // of the inputs are unknown to the customs. The oracle confirms it later.
+    let new_outpoints: Vec<OutPoint> = new_utxos.iter().map(|utxo| utxo.outpoint).collect();
+    let balances = match get_rune_balances_for_outputs(&new_outpoints).await {
+        Ok(balances) => balances,
+        Err(err) => {
+            log!(
+                ERROR,
+                "[watch_deposits]: failed to get the rune balances of {}: {}",
+                txid,
+                err
+            );
+            return;
+        }
+    };
+    let amount = match deposit_amount(&rune_id, &new_outpoints, &balances, &allocations) {
+        // The deposit is detected again once it is indexed.
+        Ok(None) => return,
+        Ok(Some(0)) => return skip(format!("no {} is transferred", rune_id)),
+        Ok(Some(amount)) => amount,
+        Err(reason) => return skip(reason),
+    };
+
+    let args = GenerateTicketArgs {
+        target_chain_id: destination.target_chain_id.clone(),
+        receiver: destination.receiver.clone(),
+        rune_id: rune_id.to_string(),
+        amount,
+        txid: txid.to_string(),
+        address_type: destination.address_type,
+    };
+    match generate_ticket(args, None).await {
+        Ok(()) => log!(
+            INFO,
+            "[watch_deposits]: detected the deposit {} of {} {} to {}",
+            txid,
+            amount,
+            rune_id,
+            address
+        ),
+        Err(
+            err @ (GenerateTicketError::TemporarilyUnavailable(_)
+            | GenerateTicketError::RpcError(_)
+            | GenerateTicketError::SendTicketErr(_)),
+        ) => log!(
+            ERROR,
+            "[watch_deposits]: failed to generate the ticket of {}: {:?}",
+            txid,
+            err
+        ),
+        Err(err) => skip(format!("{:?}", err)),
+    }
+}
+
+/// Returns the amount of the rune the indexer reports in the deposit outputs,
+/// `None` until every output is indexed. The amount of each output must be
+/// allowed by the runestone of the deposit.
+fn deposit_amount(
+    rune_id: &RuneId,
+    outpoints: &[OutPoint],
+    balances: &[Option<Vec<OutputRuneBalance>>],
+    allocations: &[OutputAllocation],
+) -> Result<Option<u128>, String> {
+    if balances.len() != outpoints.len() {
+        return Err("the indexer returned the balances of other outputs".to_string());
+    }
+    let mut total = 0u128;
+    for (outpoint, balances) in outpoints.iter().zip(balances) {
+        let Some(balances) = balances else {
+            return Ok(None);
+        };
+        let amount = balances
+            .iter()
+            .filter(|balance| balance.rune_id == rune_id.to_string())
+            .fold(0u128, |total, balance| total.saturating_add(balance.amount));
+        let allowed = allocations
+            .iter()
+            .find(|allocation| allocation.vout == outpoint.vout)
+            .is_some_and(|allocation| allocation.can_receive(rune_id, amount));
+        if !allowed {
+            return Err(format!(
+                "the runestone does not allow the output {} to receive {} {}",
+                outpoint.vout, amount, rune_id
+            ));
+        }
+        total = total.saturating_add(amount);
+    }
+    Ok(Some(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::init::{BtcNetwork, InitArgs};
+    use crate::state::GenTicketRequestV2;
+    use candid::Principal;
+    use ic_base_types::CanisterId;
+    use ic_btc_interface::Utxo;
+
+    fn state() -> CustomsState {
+        CustomsState::from(InitArgs {
+            btc_network: BtcNetwork::Regtest,
+            ecdsa_key_name: "some_key".to_string(),
+            max_time_in_queue_nanos: 0,
+            min_confirmations: None,
+            chain_state: ChainState::Active,
+            hub_principal: CanisterId::from(0).into(),
+            runes_oracle_principal: CanisterId::from(0).into(),
+            chain_id: "Bitcoin".into(),
+        })
+    }
+
+    fn destination(receiver: &str) -> Destination {
+        Destination {
+            target_chain_id: "eICP".into(),
+            receiver: receiver.into(),
+            token: None,
+            address_type: None,
+        }
+    }
+
+    #[test]
+    fn test_destinations_to_scan() {
+        let mut s = state();
+        let rune_id = RuneId { block: 840000, tx: 3 };
+        for i in 0..MAX_DESTINATIONS_PER_SCAN + 2 {
+            s.register_deposit_watch(destination(&i.to_string()), rune_id, 0, None);
+        }
+        // the destination "0" was scanned recently, "1" long ago.
+        let now = MIN_SCAN_INTERVAL_NANOS * 2;
+        s.deposit_watches
+            .get_mut(&destination("0"))
+            .unwrap()
+            .last_scanned_at = now - 1;
+        s.deposit_watches
+            .get_mut(&destination("1"))
+            .unwrap()
+            .last_scanned_at = 1;
+
+        let destinations = destinations_to_scan(&s, now);
+        assert_eq!(destinations.len(), MAX_DESTINATIONS_PER_SCAN);
+        assert!(!destinations.contains(&destination("0")));
+        assert_eq!(destinations.last(), Some(&destination("1")));
+    }
+
+    #[test]
+    fn test_expired_watches() {
+        let mut s = state();
+        let rune_id = RuneId { block: 840000, tx: 3 };
+        s.register_deposit_watch(destination("a"), rune_id, 0, None);
+        s.register_deposit_watch(
+            destination("b"),
+            rune_id,
+            DEPOSIT_WATCH_EXPIRATION_NANOS,
+            None,
+        );
+
+        assert!(expired_watches(&s, DEPOSIT_WATCH_EXPIRATION_NANOS).is_empty());
+        assert_eq!(
+            expired_watches(&s, DEPOSIT_WATCH_EXPIRATION_NANOS + 1),
+            vec![destination("a")]
+        );
+    }
+
+    #[test]
+    fn test_deposit_watches_of() {
+        let mut s = state();
+        let rune_id = RuneId { block: 840000, tx: 3 };
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        s.register_deposit_watch(destination("a"), rune_id, 0, Some(alice));
+        s.register_deposit_watch(destination("b"), rune_id, 0, Some(alice));
+        s.register_deposit_watch(destination("c"), rune_id, 0, Some(bob));
+        s.register_deposit_watch(destination("d"), rune_id, 0, None);
+
+        assert_eq!(s.deposit_watches_of(&alice), 2);
+        assert_eq!(s.deposit_watches_of(&bob), 1);
+
+        // the watch belongs to the principal that registered it last.
+        s.register_deposit_watch(destination("b"), rune_id, 1, Some(bob));
+        assert_eq!(s.deposit_watches_of(&alice), 1);
+        assert_eq!(s.deposit_watches_of(&bob), 2);
+    }
+
+    #[test]
+    fn test_deposit_amount() {
+        let rune_id = RuneId { block: 840000, tx: 3 };
+        let outpoint = |vout| OutPoint {
+            txid: [1; 32].into(),
+            vout,
+        };
+        let balance = |rune_id: &RuneId, amount| OutputRuneBalance {
+            confirmations: 1,
+            rune_id: rune_id.to_string(),
+            amount,
+        };
+        let other = RuneId { block: 840001, tx: 1 };
+        // a transfer of all the runes to the default output
+        let send_all = vec![OutputAllocation {
+            vout: 1,
+            default_output: true,
+            edicts: vec![],
+        }];
+        let balances = vec![Some(vec![balance(&rune_id, 700), balance(&other, 5)])];
+        assert_eq!(
+            deposit_amount(&rune_id, &[outpoint(1)], &balances, &send_all),
+            Ok(Some(700))
+        );
+        assert_eq!(
+            deposit_amount(&rune_id, &[outpoint(1)], &[None], &send_all),
+            Ok(None)
+        );
+        assert_eq!(
+            deposit_amount(&rune_id, &[outpoint(1)], &[Some(vec![])], &send_all),
+            Ok(Some(0))
+        );
+
+        // the edicts bound the amount of each output
+        let edicts = vec![
+            OutputAllocation {
+                vout: 1,
+                default_output: false,
+                edicts: vec![(rune_id, Some(500))],
+            },
+            OutputAllocation {
+                vout: 2,
+                default_output: false,
+                edicts: vec![(rune_id, Some(300))],
+            },
+        ];
+        let outpoints = [outpoint(1), outpoint(2)];
+        let balances = vec![
+            Some(vec![balance(&rune_id, 500)]),
+            Some(vec![balance(&rune_id, 200)]),
+        ];
+        assert_eq!(
+            deposit_amount(&rune_id, &outpoints, &balances, &edicts),
+            Ok(Some(700))
+        );
+        let balances = vec![
+            Some(vec![balance(&rune_id, 600)]),
+            Some(vec![balance(&rune_id, 200)]),
+        ];
+        assert!(deposit_amount(&rune_id, &outpoints, &balances, &edicts).is_err());
+        assert!(deposit_amount(&rune_id, &outpoints, &balances[..1], &edicts).is_err());
+    }
+
+    #[test]
+    fn test_skip_watched_deposit() {
+        let mut s = state();
+        let rune_id = RuneId { block: 840000, tx: 3 };
+        s.register_deposit_watch(destination("a"), rune_id, 0, None);
+        s.register_deposit_watch(destination("b"), rune_id, 0, None);
+        let outpoint = OutPoint {
+            txid: [1; 32].into(),
+            vout: 1,
+        };
+        let request = GenTicketRequestV2 {
+            address: "address".into(),
+            target_chain_id: "eICP".into(),
+            receiver: "a".into(),
+            token_id: "Bitcoin-runes-TEST".into(),
+            rune_id,
+            amount: 700,
+            txid: outpoint.txid,
+            new_utxos: vec![Utxo {
+                outpoint,
+                value: 546,
+                height: 0,
+            }],
+            received_at: 0,
+            allocations: None,
+            address_type: None,
+        };
+        s.skip_watched_deposit(&request);
+        assert!(s.deposit_watches[&destination("a")]
+            .skipped_outpoints
+            .contains(&outpoint));
+        assert!(s.deposit_watches[&destination("b")]
+            .skipped_outpoints
+            .is_empty());
+    }
+}
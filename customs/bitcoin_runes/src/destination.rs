@@ -1,3 +1,4 @@
+use crate::address::AddressType;
 use candid::{CandidType, Deserialize};
 use serde::Serialize;
 
@@ -6,6 +7,9 @@ pub struct Destination {
     pub target_chain_id: String,
     pub receiver: String,
     pub token: Option<String>,
+    /// The type of the address derived for the destination, None for P2WPKH.
+    #[serde(default)]
+    pub address_type: Option<AddressType>,
 }
 
 impl Destination {
@@ -13,4 +17,9 @@ impl Destination {
     pub fn effective_token(&self) -> String {
         self.token.clone().unwrap_or(String::new())
     }
+
+    #[inline]
+    pub fn effective_address_type(&self) -> AddressType {
+        self.address_type.unwrap_or_default()
+    }
 }
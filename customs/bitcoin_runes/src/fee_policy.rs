@@ -0,0 +1,364 @@
+//! The policy choosing the fee rates of the transactions the customs sends.
+use crate::state::{mutate_state, read_state, BitcoinFeeRate, CustomsState};
+use crate::{management, FEE_ESTIMATE_DELAY, MIN_RELAY_FEE_PER_VBYTE};
+use candid::{CandidType, Deserialize};
+use ic_btc_interface::{MillisatoshiPerByte, Network};
+use ic_canister_log::log;
+use omnity_types::ic_log::{ERROR, INFO};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The number of the chosen fee rates the customs remembers.
+pub const MAX_FEE_RATE_HISTORY: usize = 100;
+
+/// The mempool is considered congested if the 90th percentile of the fees
+/// is at least this many times the median fee.
+pub const CONGESTION_FACTOR: u64 = 2;
+
+/// The default fee rate in satoshi per vbyte of the fees collected in advance
+/// if the rate is unknown.
+const DEFAULT_ADVANCE_FEE_RATE: u64 = 5;
+
+/// The margin in satoshi per vbyte added to the fee rate of the fees collected
+/// in advance, the transactions are sent later at the rate of that time.
+const ADVANCE_FEE_RATE_MARGIN: u64 = 2;
+
+/// The fee percentiles older than this many refresh periods are stale.
+const MAX_FEE_PERCENTILES_AGE_PERIODS: u64 = 3;
+
+/// The kinds of the transactions whose fee rate is chosen by the policy.
+#[derive(
+    CandidType, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum FeeOperation {
+    Release,
+    Consolidation,
+    Etching,
+    Replacement,
+}
+
+impl FeeOperation {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FeeOperation::Release => "release",
+            FeeOperation::Consolidation => "consolidation",
+            FeeOperation::Etching => "etching",
+            FeeOperation::Replacement => "replacement",
+        }
+    }
+}
+
+#[derive(CandidType, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum FeeTarget {
+    Low,
+    Medium,
+    #[default]
+    High,
+}
+
+#[derive(CandidType, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum FeeRateMode {
+    /// Uses the fee rates set by the controller with `set_tx_fee_per_vbyte`.
+    #[default]
+    Manual,
+    /// Uses the percentiles of the fees of the recent transactions, moving to a
+    /// higher percentile when the mempool is congested.
+    MempoolAware,
+}
+
+/// The fee rate policy of an operation, the rates are in millisatoshi per vbyte.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OperationFeePolicy {
+    pub target: FeeTarget,
+    pub min_fee_per_vbyte: Option<u64>,
+    pub max_fee_per_vbyte: Option<u64>,
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FeeRatePolicy {
+    pub mode: FeeRateMode,
+    /// The operations without a policy target the high fee rate without clamps.
+    pub operations: BTreeMap<FeeOperation, OperationFeePolicy>,
+}
+
+impl FeeRatePolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        for (operation, policy) in &self.operations {
+            if let (Some(min), Some(max)) = (policy.min_fee_per_vbyte, policy.max_fee_per_vbyte) {
+                if min > max {
+                    return Err(format!(
+                        "the min fee rate {} of {:?} is above the max fee rate {}",
+                        min, operation, max
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn operation(&self, operation: FeeOperation) -> OperationFeePolicy {
+        self.operations.get(&operation).cloned().unwrap_or_default()
+    }
+}
+
+/// A fee rate chosen by the policy.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FeeRateRecord {
+    pub operation: FeeOperation,
+    /// The fee rate in millisatoshi per vbyte.
+    pub fee_per_vbyte: u64,
+    pub congested: bool,
+    pub timestamp: u64,
+}
+
+/// Returns true if the fee percentiles show a congested mempool.
+pub fn is_congested(percentiles: &[MillisatoshiPerByte]) -> bool {
+    percentiles.len() >= 100 && percentiles[90] >= percentiles[50] * CONGESTION_FACTOR
+}
+
+fn percentile(target: FeeTarget, congested: bool) -> usize {
+    match (target, congested) {
+        (FeeTarget::Low, false) => 25,
+        (FeeTarget::Medium, false) | (FeeTarget::Low, true) => 50,
+        (FeeTarget::High, false) | (FeeTarget::Medium, true) => 75,
+        (FeeTarget::High, true) => 90,
+    }
+}
+
+/// Returns the fee percentiles if they were refreshed recently, or no percentiles
+/// if they are unknown or stale.
+pub fn fresh_fee_percentiles(state: &CustomsState, now: u64) -> &[MillisatoshiPerByte] {
+    let max_age = FEE_ESTIMATE_DELAY.as_nanos() as u64 * MAX_FEE_PERCENTILES_AGE_PERIODS;
+    match state.last_fee_per_vbyte_updated_at {
+        Some(updated_at) if updated_at.saturating_add(max_age) >= now => &state.last_fee_per_vbyte,
+        _ => &[],
+    }
+}
+
+/// Chooses the fee rate in millisatoshi per vbyte of the operation, at least the
+/// minimum relay fee. The mempool-aware mode falls back to the manual rates if the
+/// percentiles are unknown.
+pub fn choose_fee_per_vbyte(
+    policy: &FeeRatePolicy,
+    manual_rates: &BitcoinFeeRate,
+    percentiles: &[MillisatoshiPerByte],
+    operation: FeeOperation,
+) -> (MillisatoshiPerByte, bool) {
+    let operation_policy = policy.operation(operation);
+    let (fee, congested) = match policy.mode {
+        FeeRateMode::MempoolAware if percentiles.len() >= 100 => {
+            let congested = is_congested(percentiles);
+            (
+                percentiles[percentile(operation_policy.target, congested)],
+                congested,
+            )
+        }
+        _ => {
+            let rate = match operation_policy.target {
+                FeeTarget::Low => manual_rates.low,
+                FeeTarget::Medium => manual_rates.medium,
+                FeeTarget::High => manual_rates.high,
+            };
+            (rate * 1000, false)
+        }
+    };
+    let fee = match operation_policy.min_fee_per_vbyte {
+        Some(min) => fee.max(min),
+        None => fee,
+    };
+    let fee = match operation_policy.max_fee_per_vbyte {
+        Some(max) => fee.min(max),
+        None => fee,
+    };
+    (fee.max(MIN_RELAY_FEE_PER_VBYTE), congested)
+}
+
+/// Chooses the fee rate of the operation and records it in the history.
+pub fn fee_per_vbyte(state: &mut CustomsState, operation: FeeOperation) -> MillisatoshiPerByte {
+    let now = ic_cdk::api::time();
+    let (fee, congested) = choose_fee_per_vbyte(
+        &state.fee_rate_policy,
+        &state.bitcoin_fee_rate,
+        fresh_fee_percentiles(state, now),
+        operation,
+    );
+    state.record_fee_rate(FeeRateRecord {
+        operation,
+        fee_per_vbyte: fee,
+        congested,
+        timestamp: now,
+    });
+    fee
+}
+
+/// Returns the fee rate in satoshi per vbyte of the operation with a margin,
+/// used for the etchings and the mints whose fees are collected in advance.
+pub fn advance_fee_rate(operation: FeeOperation) -> u64 {
+    let fee = mutate_state(|s| fee_per_vbyte(s, operation)) / 1000;
+    if fee == 0 {
+        DEFAULT_ADVANCE_FEE_RATE
+    } else {
+        fee + ADVANCE_FEE_RATE_MARGIN
+    }
+}
+
+/// Fetches the fee percentiles used by the mempool-aware mode.
+pub async fn refresh_fee_percentiles() {
+    let (btc_network, mode) = read_state(|s| (s.btc_network, s.fee_rate_policy.mode));
+    if btc_network == Network::Regtest || mode != FeeRateMode::MempoolAware {
+        return;
+    }
+    match management::get_current_fees(btc_network).await {
+        Ok(fees) => {
+            if fees.len() >= 100 {
+                log!(
+                    INFO,
+                    "[refresh_fee_percentiles]: median fee {}, congested: {}",
+                    fees[50],
+                    is_congested(&fees)
+                );
+                mutate_state(|s| {
+                    s.last_fee_per_vbyte = fees;
+                    s.last_fee_per_vbyte_updated_at = Some(ic_cdk::api::time());
+                });
+            } else {
+                log!(
+                    ERROR,
+                    "[refresh_fee_percentiles]: not enough data points ({}) to compute the fee",
+                    fees.len()
+                );
+            }
+        }
+        Err(err) => {
+            log!(
+                ERROR,
+                "[refresh_fee_percentiles]: failed to get fee percentiles: {}",
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::init::{BtcNetwork, InitArgs};
+    use ic_base_types::CanisterId;
+    use omnity_types::ChainState;
+
+    fn manual_rates() -> BitcoinFeeRate {
+        BitcoinFeeRate {
+            low: 5,
+            medium: 10,
+            high: 20,
+        }
+    }
+
+    #[test]
+    fn test_manual_mode_with_clamps() {
+        let mut policy = FeeRatePolicy::default();
+        assert_eq!(
+            choose_fee_per_vbyte(&policy, &manual_rates(), &[], FeeOperation::Release),
+            (20_000, false)
+        );
+
+        policy.operations.insert(
+            FeeOperation::Consolidation,
+            OperationFeePolicy {
+                target: FeeTarget::Low,
+                min_fee_per_vbyte: Some(8_000),
+                max_fee_per_vbyte: None,
+            },
+        );
+        policy.operations.insert(
+            FeeOperation::Replacement,
+            OperationFeePolicy {
+                target: FeeTarget::High,
+                min_fee_per_vbyte: None,
+                max_fee_per_vbyte: Some(15_000),
+            },
+        );
+        assert_eq!(
+            choose_fee_per_vbyte(&policy, &manual_rates(), &[], FeeOperation::Consolidation),
+            (8_000, false)
+        );
+        assert_eq!(
+            choose_fee_per_vbyte(&policy, &manual_rates(), &[], FeeOperation::Replacement),
+            (15_000, false)
+        );
+    }
+
+    #[test]
+    fn test_mempool_aware_mode() {
+        let policy = FeeRatePolicy {
+            mode: FeeRateMode::MempoolAware,
+            operations: BTreeMap::new(),
+        };
+        // Falls back to the manual rates without the percentiles.
+        assert_eq!(
+            choose_fee_per_vbyte(&policy, &manual_rates(), &[1; 10], FeeOperation::Etching),
+            (20_000, false)
+        );
+
+        let calm: Vec<u64> = (0..100).map(|i| 10_000 + i * 10).collect();
+        assert!(!is_congested(&calm));
+        assert_eq!(
+            choose_fee_per_vbyte(&policy, &manual_rates(), &calm, FeeOperation::Release),
+            (calm[75], false)
+        );
+
+        let congested: Vec<u64> = (0..100).map(|i| 1_000 * (i + 1)).collect();
+        assert!(is_congested(&congested));
+        assert_eq!(
+            choose_fee_per_vbyte(&policy, &manual_rates(), &congested, FeeOperation::Release),
+            (congested[90], true)
+        );
+    }
+
+    #[test]
+    fn test_min_relay_fee_floor() {
+        let policy = FeeRatePolicy::default();
+        let rates = BitcoinFeeRate::default();
+        assert_eq!(
+            choose_fee_per_vbyte(&policy, &rates, &[], FeeOperation::Release),
+            (MIN_RELAY_FEE_PER_VBYTE, false)
+        );
+    }
+
+    #[test]
+    fn test_fresh_fee_percentiles() {
+        let mut state = CustomsState::from(InitArgs {
+            btc_network: BtcNetwork::Regtest,
+            ecdsa_key_name: "some_key".to_string(),
+            max_time_in_queue_nanos: 0,
+            min_confirmations: None,
+            chain_state: ChainState::Active,
+            hub_principal: CanisterId::from(0).into(),
+            runes_oracle_principal: CanisterId::from(0).into(),
+            chain_id: "Bitcoin".into(),
+        });
+        // The initial percentiles are unknown.
+        assert!(fresh_fee_percentiles(&state, 0).is_empty());
+
+        let max_age = FEE_ESTIMATE_DELAY.as_nanos() as u64 * MAX_FEE_PERCENTILES_AGE_PERIODS;
+        state.last_fee_per_vbyte = vec![2_000; 100];
+        state.last_fee_per_vbyte_updated_at = Some(100);
+        assert_eq!(fresh_fee_percentiles(&state, 100 + max_age).len(), 100);
+        assert!(fresh_fee_percentiles(&state, 101 + max_age).is_empty());
+    }
+
+    #[test]
+    fn test_validate_policy() {
+        let mut policy = FeeRatePolicy::default();
+        assert!(policy.validate().is_ok());
+        policy.operations.insert(
+            FeeOperation::Release,
+            OperationFeePolicy {
+                target: FeeTarget::Medium,
+                min_fee_per_vbyte: Some(10_000),
+                max_fee_per_vbyte: Some(5_000),
+            },
+        );
+        assert!(policy.validate().is_err());
+    }
+}
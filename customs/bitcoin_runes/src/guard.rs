@@ -1,4 +1,5 @@
 use crate::state::{mutate_state, CustomsState};
+use candid::Principal;
 use std::marker::PhantomData;
 
 const MAX_CONCURRENT: u64 = 100;
@@ -166,6 +167,70 @@ impl Drop for crate::guard::ProcessEtchingMsgGuard {
     }
 }
 
+#[must_use]
+pub struct DepositWatcherGuard(());
+
+impl DepositWatcherGuard {
+    pub fn new() -> Option<Self> {
+        mutate_state(|s| {
+            if s.is_watching_deposits {
+                return None;
+            }
+            s.is_watching_deposits = true;
+            Some(DepositWatcherGuard(()))
+        })
+    }
+}
+
+impl Drop for DepositWatcherGuard {
+    fn drop(&mut self) {
+        mutate_state(|s| {
+            s.is_watching_deposits = false;
+        });
+    }
+}
+
+/// Marks the etching whose commit transaction is being sent, so that it can't be cancelled.
+#[must_use]
+pub struct CommittingEtchingGuard(());
+
+impl CommittingEtchingGuard {
+    pub fn new(etching_key: String) -> Self {
+        mutate_state(|s| s.committing_etching = Some(etching_key));
+        CommittingEtchingGuard(())
+    }
+}
+
+impl Drop for CommittingEtchingGuard {
+    fn drop(&mut self) {
+        mutate_state(|s| {
+            s.committing_etching = None;
+        });
+    }
+}
+
+/// Reserves the key of a mint request until the request is stored.
+#[must_use]
+pub struct MintKeyGuard(String);
+
+impl MintKeyGuard {
+    pub fn new(caller: &Principal) -> Self {
+        MintKeyGuard(mutate_state(|s| s.reserve_mint_key(caller)))
+    }
+
+    pub fn key(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for MintKeyGuard {
+    fn drop(&mut self) {
+        mutate_state(|s| {
+            s.reserved_mint_keys.remove(&self.0);
+        });
+    }
+}
+
 pub fn generate_ticket_guard() -> Result<Guard<GenerateTicketUpdates>, GuardError> {
     Guard::new()
 }
@@ -176,11 +241,12 @@ pub fn release_token_guard() -> Result<Guard<ReleaseTokenUpdates>, GuardError> {
 
 #[cfg(test)]
 mod tests {
-    use super::TimerLogicGuard;
+    use super::{CommittingEtchingGuard, MintKeyGuard, TimerLogicGuard};
     use crate::{
         lifecycle::init::{init, BtcNetwork, InitArgs},
         state::read_state,
     };
+    use candid::Principal;
     use ic_base_types::CanisterId;
     use omnity_types::ChainState;
 
@@ -209,4 +275,34 @@ mod tests {
         drop(guard);
         assert!(!read_state(|s| s.is_timer_running));
     }
+
+    #[test]
+    fn guard_mint_key() {
+        init(test_state_args());
+        let caller = Principal::from_slice(&[1]);
+
+        let first = MintKeyGuard::new(&caller);
+        let second = MintKeyGuard::new(&caller);
+        assert_ne!(first.key(), second.key());
+        assert!(read_state(|s| s.reserved_mint_keys.contains(first.key())));
+
+        let key = first.key().to_string();
+        drop(first);
+        assert!(!read_state(|s| s.reserved_mint_keys.contains(&key)));
+        assert!(read_state(|s| s.reserved_mint_keys.contains(second.key())));
+    }
+
+    #[test]
+    fn guard_committing_etching() {
+        init(test_state_args());
+
+        let guard = CommittingEtchingGuard::new("Bitcoin-runes-A".to_string());
+        assert_eq!(
+            read_state(|s| s.committing_etching.clone()),
+            Some("Bitcoin-runes-A".to_string())
+        );
+
+        drop(guard);
+        assert_eq!(read_state(|s| s.committing_etching.clone()), None);
+    }
 }
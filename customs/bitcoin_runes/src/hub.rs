@@ -2,12 +2,25 @@ use crate::call_error::{CallError, Reason};
 use candid::utils::ArgumentEncoder;
 use candid::CandidType;
 use candid::Principal;
-use omnity_types::hub_types::Proposal;
+use omnity_types::hub_types::{Proposal, TicketStatus};
 use omnity_types::Directive;
 use omnity_types::TicketId;
 use omnity_types::Topic;
 use omnity_types::{self, ChainId, Seq, Ticket};
 
+pub async fn ack_tickets(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    call(hub_principal, "ack_tickets".into(), (None::<ChainId>, seq)).await
+}
+
+pub async fn ack_directives(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    call(
+        hub_principal,
+        "ack_directives".into(),
+        (None::<ChainId>, seq),
+    )
+    .await
+}
+
 pub async fn query_tickets(
     hub_principal: Principal,
     offset: u64,
@@ -67,6 +80,29 @@ pub async fn finalize_ticket(hub_principal: Principal, ticket_id: String) -> Res
     call(hub_principal, "finalize_ticket".into(), (ticket_id,)).await
 }
 
+/// Returns the status of the ticket, `None` if the hub does not know the ticket.
+pub async fn get_ticket_status(
+    hub_principal: Principal,
+    ticket_id: TicketId,
+) -> Result<Option<TicketStatus>, CallError> {
+    let method = "get_ticket_status";
+    let resp: (Result<TicketStatus, omnity_types::Error>,) =
+        ic_cdk::api::call::call(hub_principal, method, (ticket_id,))
+            .await
+            .map_err(|(code, message)| CallError {
+                method: method.to_string(),
+                reason: Reason::from_reject(code, message),
+            })?;
+    match resp.0 {
+        Ok(status) => Ok(Some(status)),
+        Err(omnity_types::Error::NotFoundTicketId(_)) => Ok(None),
+        Err(err) => Err(CallError {
+            method: method.to_string(),
+            reason: Reason::CanisterError(err.to_string()),
+        }),
+    }
+}
+
 async fn call<T: ArgumentEncoder, R>(
     hub_principal: Principal,
     method: String,
@@ -1,5 +1,7 @@
 use crate::address::{main_bitcoin_address, main_destination, BitcoinAddress};
+use crate::fee_policy::{FeeOperation, FeeRatePolicy, FeeRateRecord};
 use crate::queries::RedeemFee;
+use crate::runes_etching::mint::{handle_mint_result_task, MINT_KEY_PREFIX};
 use crate::runes_etching::sync::handle_etching_result_task;
 use crate::runestone::{Edict, Runestone};
 use crate::state::{audit, mutate_state, BtcChangeOutput, EtchingAccountInfo, BitcoinFeeRate};
@@ -17,7 +19,7 @@ use serde::Serialize;
 use serde_bytes::ByteBuf;
 use state::{
     read_state, GenTicketRequestV2, RuneTxRequest, RunesBalance, RunesChangeOutput, RunesUtxo,
-    SubmittedBtcTransactionV2, BTC_TOKEN,
+    SubmittedBtcTransactionV2, SubmittedTxKind, BTC_TOKEN,
 };
 use std::collections::{BTreeMap, BTreeSet};
 use std::iter::Sum;
@@ -25,14 +27,17 @@ use std::str::FromStr;
 use std::time::Duration;
 use updates::rune_tx::{generate_rune_tx_request, GenRuneTxReqError, RuneTxArgs};
 use crate::call_error::{CallError, Reason};
-use crate::runes_etching::fee_calculator::transfer_etching_fees;
 use crate::runes_etching::InternalEtchingArgs;
 use crate::runes_etching::transactions::etching_rune_v2;
-use crate::runes_etching::transactions::EtchingStatus::{SendCommitFailed, SendCommitSuccess};
+use crate::runes_etching::transactions::EtchingStatus;
 
 pub mod address;
 pub mod call_error;
+pub mod consolidation;
+pub mod cpfp;
+pub mod deposit_watcher;
 pub mod destination;
+pub mod fee_policy;
 pub mod guard;
 pub mod hub;
 pub mod lifecycle;
@@ -61,6 +66,8 @@ pub const INTERVAL_COMMIT_ETCHING: Duration = Duration::from_secs(120);
 pub const INTERVAL_QUERY_DIRECTIVES: Duration = Duration::from_secs(60);
 pub const FEE_ESTIMATE_DELAY: Duration = Duration::from_secs(60 * 60);
 pub const INTERVAL_HANDLE_ETCHING: Duration = Duration::from_secs(5 * 60);
+pub const INTERVAL_CONSOLIDATION: Duration = Duration::from_secs(60 * 60);
+pub const INTERVAL_DEPOSIT_WATCHER: Duration = Duration::from_secs(5 * 60);
 /// The minimum fee increment for transaction resubmission.
 /// See https://en.bitcoin.it/wiki/Miner_fees#Relaying for more detail.
 pub const MIN_RELAY_FEE_PER_VBYTE: MillisatoshiPerByte = 1_000;
@@ -73,6 +80,9 @@ pub const MIN_RESUBMISSION_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
 /// when building transactions.
 pub const UTXOS_COUNT_THRESHOLD: usize = 1_000;
 
+/// The value of the outputs that carry runes or change, it's above the dust limit.
+const MIN_OUTPUT_AMOUNT: u64 = 546;
+
 #[derive(Clone, serde::Serialize, Deserialize, Debug)]
 pub enum Priority {
     P0,
@@ -129,6 +139,11 @@ pub struct CustomsInfo {
 
     pub bitcoin_fee_rate: BitcoinFeeRate,
 
+    pub fee_rate_policy: FeeRatePolicy,
+
+    /// The recent fee rates chosen by the policy, the oldest first.
+    pub fee_rate_history: Vec<FeeRateRecord>,
+
     pub max_time_in_queue_nanos: u64,
 
     pub generate_ticket_counter: u64,
@@ -160,7 +175,7 @@ pub struct TokenResp {
 struct SignTxRequest {
     network: Network,
     unsigned_tx: tx::UnsignedTransaction,
-    runes_change_output: RunesChangeOutput,
+    runes_change_outputs: Vec<RunesChangeOutput>,
     btc_change_output: BtcChangeOutput,
     outpoint_destination: BTreeMap<OutPoint, Destination>,
     /// The original requests that we keep around to place back to the queue
@@ -234,10 +249,9 @@ async fn fetch_main_utxos(
     result
 }
 
-/// Returns an estimate for transaction fees in millisatoshi per vbyte. Returns
-/// None if the bitcoin canister is unavailable or does not have enough data for
-/// an estimate yet.
-pub async fn estimate_fee_per_vbyte() -> Option<MillisatoshiPerByte> {
+/// Returns the fee rate in millisatoshi per vbyte chosen by the fee rate policy
+/// for the operation. Returns None if the fee rate is not available.
+pub async fn estimate_fee_per_vbyte(operation: FeeOperation) -> Option<MillisatoshiPerByte> {
     /// The default fee we use on regtest networks if there are not enough data
     /// to compute the median fee.
     const DEFAULT_FEE: MillisatoshiPerByte = 5_000;
@@ -245,36 +259,7 @@ pub async fn estimate_fee_per_vbyte() -> Option<MillisatoshiPerByte> {
     if btc_network == Network::Regtest {
         return Some(DEFAULT_FEE);
     }
-    let fee_rate = read_state(|s|s.bitcoin_fee_rate.high);
-    return Some(fee_rate*1000);
-   /* match management::get_current_fees(btc_network).await {
-        Ok(fees) => {
-            if btc_network == Network::Regtest {
-                return Some(DEFAULT_FEE);
-            }
-            if fees.len() >= 100 {
-                state::mutate_state(|s| {
-                    s.last_fee_per_vbyte = fees.clone();
-                });
-                Some(fees[50])
-            } else {
-                log!(
-                    ERROR,
-                    "[estimate_fee_per_vbyte]: not enough data points ({}) to compute the fee",
-                    fees.len()
-                );
-                None
-            }
-        }
-        Err(err) => {
-            log!(
-                ERROR,
-                "[estimate_fee_per_vbyte]: failed to get median fee per vbyte: {}",
-                err
-            );
-            None
-        }
-    }*/
+    Some(mutate_state(|s| fee_policy::fee_per_vbyte(s, operation)))
 }
 
 async fn process_tickets() {
@@ -332,6 +317,11 @@ async fn process_tickets() {
                 next_seq = seq + 1;
             }
             mutate_state(|s| audit::update_next_ticket_seq(s, next_seq));
+            if next_seq > offset {
+                if let Err(err) = hub::ack_tickets(hub_principal, next_seq - 1).await {
+                    log!(ERROR, "[process_tickets] failed to ack tickets: {}", err);
+                }
+            }
         }
     }
 }
@@ -346,75 +336,80 @@ async fn process_directive() {
                 err
             );
         }
-        Ok(directives) => mutate_state(|s| {
-            for (_, directive) in &directives {
-                match directive {
-                    Directive::AddChain(chain) | Directive::UpdateChain(chain) => {
-                        audit::add_chain(s, chain.clone())
-                    }
-                    Directive::AddToken(token) | Directive::UpdateToken(token) => {
-                        if let Some(rune_id) = token.metadata.clone().get("rune_id") {
-                            match RuneId::from_str(rune_id) {
-                                Err(err) => {
-                                    log!(
-                                        CRITICAL,
-                                        "[process_directive] failed to parse rune id: {}",
-                                        err
-                                    );
+        Ok(directives) => {
+            let next_seq = directives.last().map_or(offset, |(seq, _)| seq + 1);
+            mutate_state(|s| {
+                for (_, directive) in &directives {
+                    match directive {
+                        Directive::AddChain(chain) | Directive::UpdateChain(chain) => {
+                            audit::add_chain(s, chain.clone())
+                        }
+                        Directive::AddToken(token) | Directive::UpdateToken(token) => {
+                            if let Some(rune_id) = token.metadata.clone().get("rune_id") {
+                                match RuneId::from_str(rune_id) {
+                                    Err(err) => {
+                                        log!(
+                                            CRITICAL,
+                                            "[process_directive] failed to parse rune id: {}",
+                                            err
+                                        );
+                                    }
+                                    Ok(rune_id) => audit::add_token(s, rune_id, token.clone()),
                                 }
-                                Ok(rune_id) => audit::add_token(s, rune_id, token.clone()),
+                            } else {
+                                log!(
+                                    INFO,
+                                    "[process_directive] token {} not found rune_id in metadata",
+                                    token.token_id
+                                );
                             }
-                        } else {
+                        }
+                        Directive::ToggleChainState(toggle) => {
+                            audit::toggle_chain_state(s, toggle.clone())
+                        }
+                        Directive::UpdateFee(fee) => {
+                            audit::update_fee(s, fee.clone());
                             log!(
                                 INFO,
-                                "[process_directive] token {} not found rune_id in metadata",
-                                token.token_id
+                                "[process directives] success to update fee, fee: {}",
+                                fee
                             );
                         }
                     }
-                    Directive::ToggleChainState(toggle) => {
-                        audit::toggle_chain_state(s, toggle.clone())
-                    }
-                    Directive::UpdateFee(fee) => {
-                        audit::update_fee(s, fee.clone());
-                        log!(
-                            INFO,
-                            "[process directives] success to update fee, fee: {}",
-                            fee
-                        );
-                    }
+                }
+                audit::update_next_directive_seq(s, next_seq);
+            });
+            if next_seq > offset {
+                if let Err(err) = hub::ack_directives(hub_principal, next_seq - 1).await {
+                    log!(ERROR, "[process_directive] failed to ack directives: {}", err);
                 }
             }
-            let next_seq = directives.last().map_or(offset, |(seq, _)| seq + 1);
-            audit::update_next_directive_seq(s, next_seq);
-        }),
+        }
     }
 }
 
 /// Constructs and sends out signed bitcoin transactions for pending retrieve
-/// requests.
+/// requests, the requests of several runes are packed into one transaction.
 async fn submit_rune_txs() {
 
-    let fee_millisatoshi_per_vbyte = match estimate_fee_per_vbyte().await {
+    let fee_millisatoshi_per_vbyte = match estimate_fee_per_vbyte(FeeOperation::Release).await {
         Some(fee) => fee,
         None => return,
     };
 
-    let runes_list = read_state(|s| {
+    // We make requests if we have old requests in the queue or if have enough
+    // requests to fill a batch.
+    let mut runes_list = read_state(|s| {
         s.pending_rune_tx_requests
-            .iter()
-            .map(|(rune_id, _)| rune_id.clone())
+            .keys()
+            .filter(|rune_id| {
+                s.can_form_a_batch(**rune_id, MIN_PENDING_REQUESTS, ic_cdk::api::time())
+            })
+            .cloned()
             .collect::<Vec<RuneId>>()
     });
-    for rune_id in runes_list {
-        // We make requests if we have old requests in the queue or if have enough
-        // requests to fill a batch.
-        if !state::read_state(|s| {
-            s.can_form_a_batch(rune_id, MIN_PENDING_REQUESTS, ic_cdk::api::time())
-        }) {
-            continue;
-        }
 
+    while !runes_list.is_empty() {
         let main_chain_id = read_state(|s| s.chain_id.clone());
         let ecdsa_public_key = updates::get_btc_address::init_ecdsa_public_key().await;
         let btc_main_address = address::main_bitcoin_address(
@@ -424,18 +419,20 @@ async fn submit_rune_txs() {
         );
 
         // Each runes tokens use isolated main addresses
-        let runes_main_address =
-            address::main_bitcoin_address(&ecdsa_public_key, main_chain_id, rune_id.to_string());
+        let runes_main_address = |rune_id: &RuneId| {
+            address::main_bitcoin_address(&ecdsa_public_key, main_chain_id.clone(), rune_id.to_string())
+        };
 
         let maybe_sign_request = state::mutate_state(|s| {
-            let batch = s.build_batch(rune_id, MAX_REQUESTS_PER_BATCH);
+            let batch = s.build_batch(&runes_list, MAX_REQUESTS_PER_BATCH);
 
             if batch.is_empty() {
                 return None;
             }
+            // The runes in this batch are not batched again in this round.
+            runes_list.retain(|rune_id| !batch.iter().any(|req| req.rune_id.eq(rune_id)));
 
             match build_unsigned_transaction(
-                rune_id,
                 &mut s.available_runes_utxos,
                 &mut s.available_fee_utxos,
                 runes_main_address,
@@ -446,7 +443,7 @@ async fn submit_rune_txs() {
             ) {
                 Ok((
                     unsigned_tx,
-                    runes_change_output,
+                    runes_change_outputs,
                     btc_change_output,
                     runes_utxos,
                     btc_utxos,
@@ -458,8 +455,8 @@ async fn submit_rune_txs() {
                         );
                     }
 
-                    Some(SignTxRequest {
-                        runes_change_output,
+                    Some(Some(SignTxRequest {
+                        runes_change_outputs,
                         btc_change_output,
                         outpoint_destination: filter_output_destinations(s, &unsigned_tx),
                         network: s.btc_network,
@@ -467,7 +464,7 @@ async fn submit_rune_txs() {
                         requests: batch,
                         runes_utxos,
                         btc_utxos,
-                    })
+                    }))
                 }
                 Err(err) => {
                     log!(CRITICAL,
@@ -477,96 +474,115 @@ async fn submit_rune_txs() {
                     );
 
                     s.push_from_in_flight_to_pending_requests(batch);
-                    None
+                    Some(None)
                 }
             }
         });
 
-        if let Some(req) = maybe_sign_request {
-            log!(
-                INFO,
-                "[submit_pending_requests]: signing a new transaction: {}",
-                hex::encode(tx::encode_into(&req.unsigned_tx, Vec::new()))
-            );
+        let req = match maybe_sign_request {
+            Some(Some(req)) => req,
+            Some(None) => continue,
+            None => break,
+        };
 
-            // This guard ensures that we return pending requests and UTXOs back to
-            // the state if the signing or sending a transaction fails or panics.
-            let requests_guard = guard(
-                (req.requests, req.runes_utxos, req.btc_utxos),
-                |(reqs, runes_utxos, btc_utxos)| {
-                    undo_sign_request(reqs, runes_utxos, btc_utxos);
-                },
-            );
+        log!(
+            INFO,
+            "[submit_pending_requests]: signing a new transaction: {}",
+            hex::encode(tx::encode_into(&req.unsigned_tx, Vec::new()))
+        );
 
-            let txid = req.unsigned_tx.txid();
+        // This guard ensures that we return pending requests and UTXOs back to
+        // the state if the signing or sending a transaction fails or panics.
+        let requests_guard = guard(
+            (req.requests, req.runes_utxos, req.btc_utxos),
+            |(reqs, runes_utxos, btc_utxos)| {
+                undo_sign_request(reqs, runes_utxos, btc_utxos);
+            },
+        );
 
-            match sign_transaction(&req.outpoint_destination, req.unsigned_tx).await {
-                Ok(signed_tx) => {
-                    state::mutate_state(|s| {
-                        for release_req in requests_guard.0.iter() {
-                            s.push_in_flight_request(
-                                release_req.ticket_id.clone(),
-                                state::InFlightStatus::Sending { txid },
-                            );
-                        }
-                    });
+        let txid = req.unsigned_tx.txid();
 
-                    log!(
-                        INFO,
-                        "[submit_pending_requests]: sending a signed transaction {}",
-                        hex::encode(tx::encode_into(&signed_tx, Vec::new()))
-                    );
-                    match management::send_transaction(&signed_tx, req.network).await {
-                        Ok(()) => {
-                            log!(
-                                INFO,
-                                "[submit_pending_requests]: successfully sent transaction {}",
-                                &txid,
-                            );
+        match sign_transaction(&req.outpoint_destination, req.unsigned_tx).await {
+            Ok(signed_tx) => {
+                state::mutate_state(|s| {
+                    for release_req in requests_guard.0.iter() {
+                        s.push_in_flight_request(
+                            release_req.ticket_id.clone(),
+                            state::InFlightStatus::Sending { txid },
+                        );
+                    }
+                });
 
-                            // Defuse the guard because we sent the transaction
-                            // successfully.
-                            let (requests, runes_utxos, btc_utxos) =
-                                ScopeGuard::into_inner(requests_guard);
-
-                            state::mutate_state(|s| {
-                                state::audit::sent_transaction(
-                                    s,
-                                    SubmittedBtcTransactionV2 {
-                                        rune_id: rune_id.clone(),
-                                        requests,
-                                        txid,
-                                        runes_utxos,
-                                        btc_utxos,
-                                        runes_change_output: req.runes_change_output,
-                                        btc_change_output: req.btc_change_output,
-                                        submitted_at: ic_cdk::api::time(),
-                                        fee_per_vbyte: Some(fee_millisatoshi_per_vbyte),
-                                    },
-                                );
-                            });
-                        }
-                        Err(err) => {
-                            log!(
-                                CRITICAL,
-                                "[submit_pending_requests]: failed to send a bitcoin transaction: {}",
-                                err
+                log!(
+                    INFO,
+                    "[submit_pending_requests]: sending a signed transaction {}",
+                    hex::encode(tx::encode_into(&signed_tx, Vec::new()))
+                );
+                match management::send_transaction(&signed_tx, req.network).await {
+                    Ok(()) => {
+                        log!(
+                            INFO,
+                            "[submit_pending_requests]: successfully sent transaction {}",
+                            &txid,
+                        );
+
+                        // Defuse the guard because we sent the transaction
+                        // successfully.
+                        let (requests, runes_utxos, btc_utxos) =
+                            ScopeGuard::into_inner(requests_guard);
+                        let (runes_change_output, extra_runes_change_outputs) =
+                            split_runes_change_outputs(req.runes_change_outputs);
+
+                        state::mutate_state(|s| {
+                            state::audit::sent_transaction(
+                                s,
+                                SubmittedBtcTransactionV2 {
+                                    rune_id: runes_change_output.rune_id,
+                                    requests,
+                                    txid,
+                                    runes_utxos,
+                                    btc_utxos,
+                                    runes_change_output,
+                                    btc_change_output: req.btc_change_output,
+                                    submitted_at: ic_cdk::api::time(),
+                                    fee_per_vbyte: Some(fee_millisatoshi_per_vbyte),
+                                    extra_runes_change_outputs,
+                                    kind: SubmittedTxKind::Release,
+                                },
                             );
-                        }
+                        });
+                    }
+                    Err(err) => {
+                        log!(
+                            CRITICAL,
+                            "[submit_pending_requests]: failed to send a bitcoin transaction: {}",
+                            err
+                        );
                     }
                 }
-                Err(err) => {
-                    log!(
-                        ERROR,
-                        "[submit_pending_requests]: failed to sign a BTC transaction: {}",
-                        err
-                    );
-                }
+            }
+            Err(err) => {
+                log!(
+                    ERROR,
+                    "[submit_pending_requests]: failed to sign a BTC transaction: {}",
+                    err
+                );
             }
         }
     }
 }
 
+/// Splits the runes change outputs of a transaction into the first one and the others.
+fn split_runes_change_outputs(
+    outputs: Vec<RunesChangeOutput>,
+) -> (RunesChangeOutput, Vec<RunesChangeOutput>) {
+    let mut outputs = outputs.into_iter();
+    let first = outputs
+        .next()
+        .expect("bug: a transaction must have at least one runes change output");
+    (first, outputs.collect())
+}
+
 fn finalization_time_estimate(min_confirmations: u32, network: Network) -> Duration {
     Duration::from_nanos(
         min_confirmations as u64
@@ -579,18 +595,25 @@ fn finalization_time_estimate(min_confirmations: u32, network: Network) -> Durat
 }
 
 /// Returns finalized transactions from the list of `candidates` according to the
-/// list of newly received UTXOs for the main customs account.
+/// list of newly received UTXOs for the main customs account. A transaction with a
+/// child transaction is finalized as well if the change of the child is received.
 fn finalized_txs(
     candidates: &[state::SubmittedBtcTransactionV2],
+    cpfp_transactions: &BTreeMap<Txid, state::CpfpTransaction>,
     new_utxos: &[Utxo],
 ) -> Vec<state::SubmittedBtcTransactionV2> {
     candidates
         .iter()
         .filter_map(|tx| {
+            let child = cpfp_transactions.get(&tx.txid);
             new_utxos
                 .iter()
                 .any(|utxo| {
-                    utxo.outpoint.vout == tx.btc_change_output.vout && utxo.outpoint.txid == tx.txid
+                    (utxo.outpoint.vout == tx.btc_change_output.vout && utxo.outpoint.txid == tx.txid)
+                        || child.map_or(false, |child| {
+                            utxo.outpoint.vout == child.btc_change_output.vout
+                                && utxo.outpoint.txid == child.txid
+                        })
                 })
                 .then_some(tx.clone())
         })
@@ -628,13 +651,16 @@ async fn finalize_rune_txs() {
 
     let main_runes_addresses: Vec<(Destination, BitcoinAddress)> = maybe_finalized_transactions
         .iter()
-        .map(|(_, tx)| {
+        .flat_map(|(_, tx)| tx.rune_ids())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|rune_id| {
             (
-                main_destination(main_chain_id.clone(), tx.rune_id.to_string()),
+                main_destination(main_chain_id.clone(), rune_id.to_string()),
                 address::main_bitcoin_address(
                     &ecdsa_public_key,
                     main_chain_id.clone(),
-                    tx.rune_id.to_string(),
+                    rune_id.to_string(),
                 ),
             )
         })
@@ -659,12 +685,16 @@ async fn finalize_rune_txs() {
     // can be finalized. Note that all new customs transactions must have a
     // change output because customs always charges a fee for converting tokens.
     let confirmed_transactions: Vec<_> =
-        state::read_state(|s| finalized_txs(&s.submitted_transactions, &new_btc_utxos));
+        state::read_state(|s| {
+            finalized_txs(&s.submitted_transactions, &s.cpfp_transactions, &new_btc_utxos)
+        });
 
     // It's possible that some transactions we considered lost or rejected became finalized in the
     // meantime. If that happens, we should stop waiting for replacement transactions to finalize.
     let unstuck_transactions: Vec<_> =
-        state::read_state(|s| finalized_txs(&s.stuck_transactions, &new_btc_utxos));
+        state::read_state(|s| {
+            finalized_txs(&s.stuck_transactions, &s.cpfp_transactions, &new_btc_utxos)
+        });
 
     state::mutate_state(|s| {
         for (dest, utxos) in dest_btc_utxos {
@@ -675,14 +705,7 @@ async fn finalize_rune_txs() {
         }
         for tx in &confirmed_transactions {
             state::audit::confirm_transaction(s, &tx.txid);
-            if tx.runes_change_output.value > 0 {
-                let balance = RunesBalance {
-                    rune_id: tx.runes_change_output.rune_id.clone(),
-                    vout: tx.runes_change_output.vout,
-                    amount: tx.runes_change_output.value,
-                };
-                audit::update_runes_balance(s, tx.txid, balance);
-            }
+            update_runes_change_balances(s, tx);
             maybe_finalized_transactions.remove(&tx.txid);
         }
     });
@@ -708,22 +731,22 @@ async fn finalize_rune_txs() {
                 &tx.txid
             );
             state::audit::confirm_transaction(s, &tx.txid);
-            // The change value of mint rune tx is 0.
-            if tx.runes_change_output.value > 0 {
-                let balance = RunesBalance {
-                    rune_id: tx.runes_change_output.rune_id.clone(),
-                    vout: tx.runes_change_output.vout,
-                    amount: tx.runes_change_output.value,
-                };
-                audit::update_runes_balance(s, tx.txid, balance);
-            }
+            update_runes_change_balances(s, &tx);
         }
     });
 
+    // The child transactions that bumped the fee of the stuck transactions.
+    let cpfp_transactions = state::read_state(|s| s.cpfp_transactions.clone());
+
     // Do not replace transactions if less than MIN_RESUBMISSION_DELAY passed since their
-    // submission. This strategy works around short-term fee spikes.
-    maybe_finalized_transactions
-        .retain(|_txid, tx| tx.submitted_at + MIN_RESUBMISSION_DELAY.as_nanos() as u64 <= now);
+    // submission (or the submission of their child). This strategy works around short-term
+    // fee spikes.
+    maybe_finalized_transactions.retain(|txid, tx| {
+        let submitted_at = cpfp_transactions
+            .get(txid)
+            .map_or(tx.submitted_at, |child| child.submitted_at.max(tx.submitted_at));
+        submitted_at + MIN_RESUBMISSION_DELAY.as_nanos() as u64 <= now
+    });
 
     if maybe_finalized_transactions.is_empty() {
         // There are no transactions eligible for replacement.
@@ -767,7 +790,7 @@ async fn finalize_rune_txs() {
     );
 
     // We shall use the latest fee estimate for replacement transactions.
-    let fee_per_vbyte = match estimate_fee_per_vbyte().await {
+    let fee_per_vbyte = match estimate_fee_per_vbyte(FeeOperation::Replacement).await {
         Some(fee) => fee,
         None => return,
     };
@@ -776,7 +799,14 @@ async fn finalize_rune_txs() {
         let mut runes_utxos: BTreeSet<_> = submitted_tx.runes_utxos.iter().cloned().collect();
         let mut btc_utxos: BTreeSet<_> = submitted_tx.btc_utxos.iter().cloned().collect();
 
-        let tx_fee_per_vbyte = match submitted_tx.fee_per_vbyte {
+        let child = cpfp_transactions.get(&old_txid);
+        let prev_fee_per_vbyte = match (submitted_tx.fee_per_vbyte, child) {
+            (Some(fee), Some(child)) => Some(fee.max(child.fee_per_vbyte)),
+            (fee, None) => fee,
+            (None, Some(child)) => Some(child.fee_per_vbyte),
+        };
+
+        let tx_fee_per_vbyte = match prev_fee_per_vbyte {
             Some(prev_fee) => {
                 // Ensure that the fee is at least min relay fee higher than the previous
                 // transaction fee to comply with BIP-125 (https://en.bitcoin.it/wiki/BIP_0125).
@@ -784,28 +814,67 @@ async fn finalize_rune_txs() {
             }
             None => fee_per_vbyte,
         };
+        // Replacing the parent evicts its child transaction as well, the replacement
+        // must pay more than both of them together (BIP-125 rule 3).
+        let replacement_fee_per_vbyte =
+            tx_fee_per_vbyte.max(cpfp::min_replacement_fee_per_vbyte(&submitted_tx, child));
 
-        let build_tx_req = new_build_tx_req(submitted_tx.requests.clone());
+        let runes_main_address = |rune_id: &RuneId| {
+            main_bitcoin_address(&ecdsa_public_key, main_chain_id.clone(), rune_id.to_string())
+        };
+        let btc_main_address = main_bitcoin_address(
+            &ecdsa_public_key,
+            main_chain_id.clone(),
+            String::from(BTC_TOKEN),
+        );
 
-        let (unsigned_tx, runes_change, btc_change, used_runes_utxos, used_btc_utxos) =
-            match build_unsigned_transaction(
-                submitted_tx.rune_id,
+        let build_result = match submitted_tx.kind {
+            SubmittedTxKind::Release => build_unsigned_transaction(
                 &mut runes_utxos,
                 &mut btc_utxos,
-                main_bitcoin_address(
-                    &ecdsa_public_key,
-                    main_chain_id.clone(),
-                    submitted_tx.rune_id.to_string(),
-                ),
-                main_bitcoin_address(
-                    &ecdsa_public_key,
-                    main_chain_id.clone(),
-                    String::from(BTC_TOKEN),
-                ),
-                build_tx_req,
-                tx_fee_per_vbyte,
+                runes_main_address,
+                btc_main_address.clone(),
+                new_build_tx_req(submitted_tx.requests.clone()),
+                replacement_fee_per_vbyte,
                 true,
-            ) {
+            ),
+            SubmittedTxKind::Consolidation => consolidation::build_consolidation_transaction(
+                &mut runes_utxos,
+                &mut btc_utxos,
+                runes_main_address(&submitted_tx.rune_id),
+                btc_main_address.clone(),
+                replacement_fee_per_vbyte,
+            ),
+        };
+
+        // Bump the fee with a child transaction if the replacement is impossible
+        // or more expensive.
+        if let Ok((child_tx, child_change, child_fee)) =
+            cpfp::build_cpfp_transaction(&submitted_tx, btc_main_address, tx_fee_per_vbyte)
+        {
+            let use_cpfp = match &build_result {
+                Ok((unsigned_tx, ..)) => {
+                    let (_, parent_fee) = cpfp::parent_vsize_and_fee(&submitted_tx);
+                    child_fee < cpfp::unsigned_tx_fee(unsigned_tx).saturating_sub(parent_fee)
+                }
+                Err(_) => true,
+            };
+            if use_cpfp {
+                cpfp::send_cpfp_transaction(
+                    old_txid,
+                    child_tx,
+                    child_change,
+                    tx_fee_per_vbyte,
+                    btc_network,
+                    main_destination(main_chain_id.clone(), BTC_TOKEN.into()),
+                )
+                .await;
+                continue;
+            }
+        }
+
+        let (unsigned_tx, runes_changes, btc_change, used_runes_utxos, used_btc_utxos) =
+            match build_result {
                 Ok(tx) => tx,
                 // If it's impossible to build a new transaction, the fees probably became too high.
                 // Let's ignore this transaction and wait for fees to go down.
@@ -820,6 +889,21 @@ async fn finalize_rune_txs() {
                 }
             };
 
+        let min_fee = cpfp::min_replacement_fee(
+            &submitted_tx,
+            child,
+            fake_sign(&unsigned_tx).vsize() as u64,
+        );
+        if cpfp::unsigned_tx_fee(&unsigned_tx) < min_fee {
+            log!(
+                ERROR,
+                "[finalize_requests]: the replacement of stuck transaction {} pays less than the required fee {}",
+                &submitted_tx.txid,
+                min_fee
+            );
+            continue;
+        }
+
         let outpoint_dests = state::read_state(|s| filter_output_destinations(s, &unsigned_tx));
 
         assert!(
@@ -865,16 +949,20 @@ async fn finalize_rune_txs() {
                     &old_txid,
                     hex::encode(tx::encode_into(&signed_tx, Vec::new()))
                 );
+                let (runes_change_output, extra_runes_change_outputs) =
+                    split_runes_change_outputs(runes_changes);
                 let new_tx = SubmittedBtcTransactionV2 {
-                    rune_id: submitted_tx.rune_id,
+                    rune_id: runes_change_output.rune_id,
                     requests: submitted_tx.requests,
                     runes_utxos: used_runes_utxos,
                     btc_utxos: used_btc_utxos,
                     txid: new_txid,
                     submitted_at: ic_cdk::api::time(),
-                    runes_change_output: runes_change,
+                    runes_change_output,
                     btc_change_output: btc_change,
-                    fee_per_vbyte: Some(tx_fee_per_vbyte),
+                    fee_per_vbyte: Some(replacement_fee_per_vbyte),
+                    extra_runes_change_outputs,
+                    kind: submitted_tx.kind,
                 };
 
                 state::mutate_state(|s| {
@@ -949,9 +1037,35 @@ async fn finalize_gen_ticket_txs() {
     }
 }
 
+/// Makes the runes change outputs of a finalized transaction available.
+fn update_runes_change_balances(s: &mut state::CustomsState, tx: &SubmittedBtcTransactionV2) {
+    // The change value of mint rune tx is 0.
+    for output in tx.runes_change_outputs().filter(|output| output.value > 0) {
+        let balance = RunesBalance {
+            rune_id: output.rune_id,
+            vout: output.vout,
+            amount: output.value,
+        };
+        audit::update_runes_balance(s, tx.txid, balance);
+    }
+}
+
 async fn update_tx_hash_to_hub(tx: &SubmittedBtcTransactionV2) {
+    // The consolidation transactions don't fulfill any tickets.
+    if tx.requests.is_empty() {
+        return;
+    }
+    // The mint-and-bridge requests are not tickets of the hub.
+    let ticket_ids: Vec<_> = tx
+        .requests
+        .iter()
+        .filter(|r| !r.ticket_id.starts_with(MINT_KEY_PREFIX))
+        .map(|r| r.ticket_id.clone())
+        .collect();
+    if ticket_ids.is_empty() {
+        return;
+    }
     let hub_principal = read_state(|s| s.hub_principal);
-    let ticket_ids = tx.requests.iter().map(|r| r.ticket_id.clone()).collect();
     if let Err(err) =
         hub::batch_update_tx_hash(hub_principal, ticket_ids, tx.txid.to_string()).await
     {
@@ -961,11 +1075,11 @@ async fn update_tx_hash_to_hub(tx: &SubmittedBtcTransactionV2) {
 
 fn new_build_tx_req(requests: Vec<RuneTxRequest>) -> BuildTxReq {
     if requests[0].action == TxAction::Mint {
-        BuildTxReq::MintTxReq(requests[0].address.clone())
+        BuildTxReq::MintTxReq(requests[0].rune_id, requests[0].address.clone())
     } else {
         let outputs: Vec<_> = requests
             .iter()
-            .map(|req| (req.address.clone(), req.amount))
+            .map(|req| (req.rune_id, req.address.clone(), req.amount))
             .collect();
 
         BuildTxReq::EdictTxReq(outputs)
@@ -1101,8 +1215,9 @@ pub fn fake_sign(unsigned_tx: &tx::UnsignedTransaction) -> tx::SignedTransaction
     }
 }
 
-/// Gathers ECDSA signatures for all the inputs in the specified unsigned
-/// transaction.
+/// Gathers ECDSA signatures for the inputs spending P2WPKH outputs and
+/// Schnorr signatures for the inputs spending P2TR outputs in the specified
+/// unsigned transaction.
 ///
 /// # Panics
 ///
@@ -1112,36 +1227,96 @@ pub async fn sign_transaction(
     output_destinations: &BTreeMap<tx::OutPoint, Destination>,
     unsigned_tx: tx::UnsignedTransaction,
 ) -> Result<tx::SignedTransaction, call_error::CallError> {
-    use crate::address::{derivation_path, derive_public_key};
+    use crate::address::{
+        derivation_path, derive_public_key, destination_to_bitcoin_address,
+        destination_to_p2tr_bitcoin_address, AddressType,
+    };
+
+    let destinations: Vec<&Destination> = unsigned_tx
+        .inputs
+        .iter()
+        .map(|input| {
+            output_destinations
+                .get(&input.previous_output)
+                .unwrap_or_else(|| {
+                    panic!("bug: no account for outpoint {:?}", input.previous_output)
+                })
+        })
+        .collect();
+
+    // The taproot signature hash commits to the scriptPubKeys of all the spent outputs.
+    let taproot_sighasher = if destinations
+        .iter()
+        .any(|destination| destination.effective_address_type() == AddressType::P2tr)
+    {
+        updates::get_btc_address::init_schnorr_public_key().await;
+        let prevout_addresses: Vec<BitcoinAddress> = read_state(|s| {
+            destinations
+                .iter()
+                .map(|destination| match destination.effective_address_type() {
+                    AddressType::P2wpkh => destination_to_bitcoin_address(
+                        &s.get_ecdsa_key(destination.token.clone()).1,
+                        destination,
+                    ),
+                    AddressType::P2tr => {
+                        destination_to_p2tr_bitcoin_address(&s.get_schnorr_key().1, destination)
+                    }
+                })
+                .collect()
+        });
+        Some(tx::TaprootSigHasher::new(&unsigned_tx, &prevout_addresses))
+    } else {
+        None
+    };
 
     let mut signed_inputs = Vec::with_capacity(unsigned_tx.inputs.len());
     let sighasher = tx::TxSigHasher::new(&unsigned_tx);
-    for input in &unsigned_tx.inputs {
+    for (index, (input, destination)) in unsigned_tx.inputs.iter().zip(destinations).enumerate() {
         let outpoint = &input.previous_output;
+        let path = derivation_path(destination);
 
-        let destination = output_destinations
-            .get(outpoint)
-            .unwrap_or_else(|| panic!("bug: no account for outpoint {:?}", outpoint));
+        let signed_input = match destination.effective_address_type() {
+            AddressType::P2wpkh => {
+                let (key_name, ecdsa_public_key) =
+                    read_state(|s| s.get_ecdsa_key(destination.token.clone()));
 
-        let (key_name, ecdsa_public_key) =
-            read_state(|s| s.get_ecdsa_key(destination.token.clone()));
+                let pubkey =
+                    ByteBuf::from(derive_public_key(&ecdsa_public_key, destination).public_key);
+                let pkhash = tx::hash160(&pubkey);
 
-        let path = derivation_path(destination);
-        let pubkey = ByteBuf::from(derive_public_key(&ecdsa_public_key, destination).public_key);
-        let pkhash = tx::hash160(&pubkey);
+                let sighash = sighasher.sighash(input, &pkhash);
 
-        let sighash = sighasher.sighash(input, &pkhash);
+                let sec1_signature =
+                    management::sign_with_ecdsa(key_name, DerivationPath::new(path), sighash)
+                        .await?;
 
-        let sec1_signature =
-            management::sign_with_ecdsa(key_name.clone(), DerivationPath::new(path), sighash)
-                .await?;
-
-        signed_inputs.push(tx::SignedInput {
-            signature: signature::EncodedSignature::from_sec1(&sec1_signature),
-            pubkey,
-            previous_output: outpoint.clone(),
-            sequence: input.sequence,
-        });
+                tx::SignedInput {
+                    signature: signature::EncodedSignature::from_sec1(&sec1_signature),
+                    pubkey,
+                    previous_output: outpoint.clone(),
+                    sequence: input.sequence,
+                }
+            }
+            AddressType::P2tr => {
+                let key_name = read_state(|s| s.schnorr_key_name());
+                let sighash = taproot_sighasher
+                    .as_ref()
+                    .expect("bug: the taproot sighasher must be initialized")
+                    .sighash(index);
+
+                let schnorr_signature =
+                    management::sign_with_schnorr_taproot(key_name, path, sighash).await?;
+
+                tx::SignedInput {
+                    signature: signature::EncodedSignature::from_schnorr(&schnorr_signature),
+                    // The key path witness only holds the signature.
+                    pubkey: ByteBuf::new(),
+                    previous_output: outpoint.clone(),
+                    sequence: input.sequence,
+                }
+            }
+        };
+        signed_inputs.push(signed_input);
     }
     Ok(tx::SignedTransaction {
         inputs: signed_inputs,
@@ -1159,8 +1334,8 @@ pub enum BuildTxError {
 }
 
 pub enum BuildTxReq {
-    EdictTxReq(Vec<(BitcoinAddress, u128)>),
-    MintTxReq(BitcoinAddress),
+    EdictTxReq(Vec<(RuneId, BitcoinAddress, u128)>),
+    MintTxReq(RuneId, BitcoinAddress),
 }
 
 /// Builds a transaction that transfer runes token to the specified destination accounts
@@ -1172,8 +1347,7 @@ pub enum BuildTxReq {
 ///
 /// * `available_runes_utxos` - The set of all Runes UTXOs customs owns
 /// * `available_btc_utxos` - The set of all BTC UTXOs customs owns
-/// * `outputs` - The destination BTC addresses and respective amounts.
-/// * `runes_main_address` - The BTC address of the customs's main account do absorb the Runes change.
+/// * `runes_main_address` - Returns the BTC address of the customs's main account of a rune to absorb its Runes change.
 /// * `btc_main_address` - The BTC address of the customs's main account do absorb the BTC change.
 /// * `fee_per_vbyte` - The current 50th percentile of BTC fees, in millisatoshi/byte
 /// * `is_resubmission` - A flag indicating whether to resubmit
@@ -1183,10 +1357,9 @@ pub enum BuildTxReq {
 /// This function panics if the `outputs` vector is empty as it indicates a bug
 /// in the caller's code.
 pub fn build_unsigned_transaction(
-    rune_id: RuneId,
     available_runes_utxos: &mut BTreeSet<RunesUtxo>,
     available_btc_utxos: &mut BTreeSet<Utxo>,
-    runes_main_address: BitcoinAddress,
+    runes_main_address: impl Fn(&RuneId) -> BitcoinAddress,
     btc_main_address: BitcoinAddress,
     req: BuildTxReq,
     fee_per_vbyte: u64,
@@ -1194,7 +1367,7 @@ pub fn build_unsigned_transaction(
 ) -> Result<
     (
         tx::UnsignedTransaction,
-        RunesChangeOutput,
+        Vec<RunesChangeOutput>,
         BtcChangeOutput,
         Vec<RunesUtxo>,
         Vec<Utxo>,
@@ -1207,78 +1380,124 @@ pub fn build_unsigned_transaction(
     /// https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki
     const SEQUENCE_RBF_ENABLED: u32 = 0xfffffffd;
 
-    let (stone, rune_change_output, rune_change_address, rune_utxos, outputs) = match req {
+    let (stone, rune_change_outputs, rune_change_addresses, rune_utxos, outputs) = match req {
         BuildTxReq::EdictTxReq(outputs) => {
             assert!(!outputs.is_empty());
 
-            let amount = outputs.iter().map(|(_, amount)| amount).sum::<u128>();
-            let rune_utxos = utxos_selection(amount, available_runes_utxos, outputs.len(), |u| {
-                if u.runes.rune_id.eq(&rune_id) {
-                    u.runes.amount
-                } else {
-                    0
-                }
-            });
-
-            if rune_utxos.is_empty() {
-                return Err(BuildTxError::NotEnoughFunds);
+            // The total amount and the number of outputs of each rune.
+            let mut rune_amounts: BTreeMap<RuneId, (u128, usize)> = BTreeMap::new();
+            for (rune_id, _, amount) in outputs.iter() {
+                let (total, count) = rune_amounts.entry(*rune_id).or_default();
+                *total += amount;
+                *count += 1;
             }
 
-            let inputs_value = rune_utxos.iter().map(|u| u.runes.amount).sum::<u128>();
-            debug_assert!(inputs_value >= amount);
+            let mut rune_utxos: Vec<RunesUtxo> = vec![];
+            let mut rune_change_outputs = vec![];
+            for (idx, (rune_id, (amount, outputs_count))) in rune_amounts.iter().enumerate() {
+                let selected = utxos_selection(*amount, available_runes_utxos, *outputs_count, |u| {
+                    if u.runes.rune_id.eq(rune_id) {
+                        u.runes.amount
+                    } else {
+                        0
+                    }
+                });
 
-            let burn_amount = outputs
-                .iter()
-                .filter(|(address, _)| matches!(address, BitcoinAddress::OpReturn(_)))
-                .map(|(_, amount)| amount)
-                .sum::<u128>();
+                if selected.is_empty() {
+                    for utxo in rune_utxos {
+                        available_runes_utxos.insert(utxo);
+                    }
+                    return Err(BuildTxError::NotEnoughFunds);
+                }
 
-            let outputs = outputs
-                .iter()
-                .filter(|(address, _)| !matches!(address, BitcoinAddress::OpReturn(_)))
-                .map(|(address, amount)| (address.clone(), *amount))
-                .collect::<Vec<(BitcoinAddress, u128)>>();
+                let inputs_value = selected.iter().map(|u| u.runes.amount).sum::<u128>();
+                debug_assert!(inputs_value >= *amount);
 
-            let mut edicts = vec![];
-            if burn_amount > 0 {
-                edicts.push(Edict {
-                    id: rune_id.into(),
-                    amount: burn_amount,
-                    output: 0,
+                // The change of each rune goes to its own main address, right after the OP_RETURN output.
+                rune_change_outputs.push(state::RunesChangeOutput {
+                    rune_id: *rune_id,
+                    vout: (idx + 1) as u32,
+                    value: inputs_value - amount,
                 });
+                rune_utxos.extend(selected);
             }
 
+            let mut edicts = vec![];
+            for rune_id in rune_amounts.keys() {
+                let burn_amount = outputs
+                    .iter()
+                    .filter(|(id, address, _)| {
+                        id.eq(rune_id) && matches!(address, BitcoinAddress::OpReturn(_))
+                    })
+                    .map(|(_, _, amount)| amount)
+                    .sum::<u128>();
+                if burn_amount > 0 {
+                    edicts.push(Edict {
+                        id: *rune_id,
+                        amount: burn_amount,
+                        output: 0,
+                    });
+                }
+            }
+
+            let outputs = outputs
+                .into_iter()
+                .filter(|(_, address, _)| !matches!(address, BitcoinAddress::OpReturn(_)))
+                .collect::<Vec<(RuneId, BitcoinAddress, u128)>>();
+
+            let first_output = rune_change_outputs.len() + 1;
             edicts.append(
                 &mut outputs
                     .iter()
                     .enumerate()
-                    .map(|(idx, (_, amount))| Edict {
-                        id: rune_id.into(),
+                    .map(|(idx, (rune_id, _, amount))| Edict {
+                        id: *rune_id,
                         amount: *amount,
-                        output: (idx + 2) as u32,
+                        output: (idx + first_output) as u32,
                     })
                     .collect::<Vec<Edict>>(),
             );
 
-            let stone = Runestone { edicts, mint: None };
+            // The change of the first rune goes to the default output, the changes
+            // of the other runes need explicit edicts.
+            edicts.append(
+                &mut rune_change_outputs
+                    .iter()
+                    .skip(1)
+                    .filter(|change| change.value > 0)
+                    .map(|change| Edict {
+                        id: change.rune_id,
+                        amount: change.value,
+                        output: change.vout,
+                    })
+                    .collect::<Vec<Edict>>(),
+            );
 
-            let rune_change_output = state::RunesChangeOutput {
-                rune_id,
-                vout: 1,
-                value: inputs_value - amount,
+            let stone = Runestone {
+                edicts,
+                ..Default::default()
             };
+
+            let rune_change_addresses = rune_change_outputs
+                .iter()
+                .map(|change| runes_main_address(&change.rune_id))
+                .collect::<Vec<_>>();
+            let outputs = outputs
+                .into_iter()
+                .map(|(_, address, amount)| (address, amount))
+                .collect::<Vec<(BitcoinAddress, u128)>>();
             (
                 stone,
-                rune_change_output,
-                runes_main_address,
+                rune_change_outputs,
+                rune_change_addresses,
                 rune_utxos,
                 outputs,
             )
         }
-        BuildTxReq::MintTxReq(receiver) => {
+        BuildTxReq::MintTxReq(rune_id, receiver) => {
             let stone = Runestone {
                 mint: Some(rune_id),
-                edicts: vec![],
+                ..Default::default()
             };
 
             let rune_change_output = state::RunesChangeOutput {
@@ -1289,7 +1508,7 @@ pub fn build_unsigned_transaction(
                 // and the corresponding rune utxo will be placed in customs at that time.
                 value: 0,
             };
-            (stone, rune_change_output, receiver, vec![], vec![])
+            (stone, vec![rune_change_output], vec![receiver], vec![], vec![])
         }
     };
 
@@ -1301,19 +1520,21 @@ pub fn build_unsigned_transaction(
         }
     });
 
-    const MIN_OUTPUT_AMOUNT: u64 = 546;
+    let mut tx_outputs = vec![tx::TxOut {
+        value: 0,
+        address: BitcoinAddress::OpReturn(stone.encipher()),
+    }];
 
-    let mut tx_outputs = vec![
-        tx::TxOut {
-            value: 0,
-            address: BitcoinAddress::OpReturn(stone.encipher()),
-        },
-        // Runes token change
-        tx::TxOut {
-            value: MIN_OUTPUT_AMOUNT,
-            address: rune_change_address,
-        },
-    ];
+    // Runes token changes
+    tx_outputs.append(
+        &mut rune_change_addresses
+            .iter()
+            .map(|address| tx::TxOut {
+                address: address.clone(),
+                value: MIN_OUTPUT_AMOUNT,
+            })
+            .collect(),
+    );
 
     tx_outputs.append(
         &mut outputs
@@ -1341,8 +1562,8 @@ pub fn build_unsigned_transaction(
         (tx_outputs.len() + 1) as u64,
     );
     let fee: u64 = (tx_vsize as u64 * fee_per_vbyte) / 1000;
-    // Additional MIN_OUTPUT_AMOUNT are used as the value of the outputs(two change output + multiple dest runes outputs).
-    let non_op_return_outputs_sz = (outputs.len() + 2) as u64;
+    // Additional MIN_OUTPUT_AMOUNT are used as the value of the outputs(runes change outputs + btc change output + multiple dest runes outputs).
+    let non_op_return_outputs_sz = (outputs.len() + rune_change_addresses.len() + 1) as u64;
     // Select twise the fee to handle resubmissions.
     let select_fee = fee * 2 + MIN_OUTPUT_AMOUNT * non_op_return_outputs_sz;
 
@@ -1430,7 +1651,7 @@ pub fn build_unsigned_transaction(
 
     Ok((
         unsigned_tx,
-        rune_change_output,
+        rune_change_outputs,
         btc_change_out,
         ScopeGuard::into_inner(rune_utxos_guard),
         ScopeGuard::into_inner(btc_utxos_guard),
@@ -1459,41 +1680,56 @@ pub fn commit_etching_task() {
     });
 }
 
-pub async fn commit_etching_txs() {
-    if let Some(id) = read_state(|s|s.stash_etching_ids.get(0))  {
-        let etching_args_opt = read_state(|s|s.stash_etchings.get(&id.content));
-        match etching_args_opt {
-            None => {
+/// The etching fails and its fee is refunded after this number of failed attempts
+/// to build its commit transaction.
+pub const MAX_ETCHING_COMMIT_ATTEMPTS: u32 = 10;
 
+pub async fn commit_etching_txs() {
+    // The etchings are committed in the order they are stashed,
+    // the cancelled etchings are skipped.
+    let next = read_state(|s| {
+        s.stash_etching_ids.iter().find_map(|id| {
+            s.pending_etching_requests
+                .get(&id.content)
+                .filter(|req| req.status == EtchingStatus::Stashed)
+                .map(|req| (id.content, req))
+        })
+    });
+    let (etching_key, req) = match next {
+        Some(next) => next,
+        None => {
+            mutate_state(|s| while s.stash_etching_ids.pop().is_some() {});
+            return;
+        }
+    };
+    let fee_rate = fee_policy::advance_fee_rate(FeeOperation::Etching);
+    let _guard = crate::guard::CommittingEtchingGuard::new(etching_key.clone());
+    match etching_rune_v2(fee_rate, &req.etching_args).await {
+        Ok(mut sr) => {
+            if sr.status == EtchingStatus::Failed {
+                log!(ERROR, "after send etching commit error: {:?}", sr.err_info);
             }
-            Some(args) => {
-                let fee_rate = read_state(|s|{
-                    let high = s.bitcoin_fee_rate.high;
-                    if high == 0 {
-                        5
-                    }else {
-                        high + 2
-                    }
-                });
-                match etching_rune_v2(fee_rate, &args).await {
-                    Ok(sr) => {
-                        if sr.status == SendCommitSuccess {
-                            mutate_state(|s|s.stash_etchings.remove(&id.content));
-                        } else if sr.status == SendCommitFailed{
-                            log!(ERROR, "after send etching commit error: {:?}", sr.err_info);
-                        }
-                        mutate_state(|s|s.stash_etching_ids.pop());
-                        mutate_state(|s| s.pending_etching_requests.insert(id.content, sr));
-                    }
-                    Err(e) => {
-                        log!(WARNING, "BEFORE send etching commit error: {:?}", e);
-                    },
-                }
+            sr.stashed_at = req.stashed_at;
+            mutate_state(|s| s.pending_etching_requests.insert(etching_key.clone(), sr));
+        }
+        Err(e) => {
+            log!(WARNING, "BEFORE send etching commit error: {:?}", e);
+            let mut req = req;
+            req.reason = Some(e.to_string());
+            req.commit_attempts += 1;
+            if req.commit_attempts >= MAX_ETCHING_COMMIT_ATTEMPTS {
+                log!(
+                    ERROR,
+                    "etching {} failed after {} attempts",
+                    etching_key,
+                    req.commit_attempts
+                );
+                req.status = EtchingStatus::Failed;
             }
+            mutate_state(|s| s.pending_etching_requests.insert(etching_key.clone(), req));
         }
     }
-
-
+    crate::runes_etching::transactions::compact_stash_etching_ids();
 }
 
 pub fn process_etching_task() {
@@ -1503,6 +1739,7 @@ pub fn process_etching_task() {
             None => return,
         };
         handle_etching_result_task().await;
+        handle_mint_result_task().await;
     });
 }
 
@@ -1528,7 +1765,7 @@ pub fn process_ticket_msg_task() {
 
 pub fn refresh_fee_task() {
     ic_cdk::spawn(async {
-        let _ = estimate_fee_per_vbyte().await;
+        fee_policy::refresh_fee_percentiles().await;
     });
 }
 
@@ -1588,3 +1825,216 @@ pub fn estimate_fee(
     let bitcoin_fee = vsize * median_fee_millisatoshi_per_vbyte / 1000;
     RedeemFee { bitcoin_fee }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNE_A: RuneId = RuneId { block: 840000, tx: 3 };
+    const RUNE_B: RuneId = RuneId { block: 840001, tx: 5 };
+    const FEE_PER_VBYTE: u64 = 10_000;
+
+    fn utxo(id: u8, value: u64) -> Utxo {
+        Utxo {
+            outpoint: OutPoint {
+                txid: [id; 32].into(),
+                vout: 1,
+            },
+            value,
+            height: 0,
+        }
+    }
+
+    fn runes_utxo(id: u8, rune_id: RuneId, amount: u128) -> RunesUtxo {
+        RunesUtxo {
+            raw: utxo(id, MIN_OUTPUT_AMOUNT),
+            runes: RunesBalance {
+                rune_id,
+                vout: 1,
+                amount,
+            },
+        }
+    }
+
+    fn runes_main_address(rune_id: &RuneId) -> BitcoinAddress {
+        BitcoinAddress::P2wpkhV0([rune_id.tx as u8; 20])
+    }
+
+    fn btc_main_address() -> BitcoinAddress {
+        BitcoinAddress::P2wpkhV0([0; 20])
+    }
+
+    #[test]
+    fn test_build_unsigned_transaction_with_several_runes() {
+        let mut runes_utxos: BTreeSet<_> =
+            [runes_utxo(1, RUNE_A, 1_000), runes_utxo(2, RUNE_B, 500)]
+                .into_iter()
+                .collect();
+        let mut btc_utxos: BTreeSet<_> = [utxo(3, 100_000)].into_iter().collect();
+        let receiver_a = BitcoinAddress::P2wpkhV0([10; 20]);
+        let receiver_b = BitcoinAddress::P2wpkhV0([11; 20]);
+
+        let (unsigned_tx, runes_changes, btc_change, used_runes_utxos, used_btc_utxos) =
+            build_unsigned_transaction(
+                &mut runes_utxos,
+                &mut btc_utxos,
+                runes_main_address,
+                btc_main_address(),
+                BuildTxReq::EdictTxReq(vec![
+                    (RUNE_A, receiver_a.clone(), 100),
+                    (RUNE_A, BitcoinAddress::OpReturn(vec![]), 50),
+                    (RUNE_B, receiver_b.clone(), 200),
+                ]),
+                FEE_PER_VBYTE,
+                false,
+            )
+            .unwrap();
+
+        assert!(runes_utxos.is_empty());
+        assert!(btc_utxos.is_empty());
+        assert_eq!(used_runes_utxos.len(), 2);
+        assert_eq!(used_btc_utxos.len(), 1);
+
+        // Each rune has its own change output right after the OP_RETURN output.
+        assert_eq!(
+            runes_changes,
+            vec![
+                RunesChangeOutput {
+                    rune_id: RUNE_A,
+                    vout: 1,
+                    value: 850,
+                },
+                RunesChangeOutput {
+                    rune_id: RUNE_B,
+                    vout: 2,
+                    value: 300,
+                },
+            ]
+        );
+        let addresses = unsigned_tx
+            .outputs
+            .iter()
+            .map(|out| out.address.clone())
+            .skip(1)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            addresses,
+            vec![
+                runes_main_address(&RUNE_A),
+                runes_main_address(&RUNE_B),
+                receiver_a,
+                receiver_b,
+                btc_main_address(),
+            ]
+        );
+
+        // The burn goes to the OP_RETURN output, the change of the first rune to
+        // the default output and the change of the other runes needs an edict.
+        let stone = Runestone {
+            edicts: vec![
+                Edict {
+                    id: RUNE_A,
+                    amount: 50,
+                    output: 0,
+                },
+                Edict {
+                    id: RUNE_A,
+                    amount: 100,
+                    output: 3,
+                },
+                Edict {
+                    id: RUNE_B,
+                    amount: 200,
+                    output: 4,
+                },
+                Edict {
+                    id: RUNE_B,
+                    amount: 300,
+                    output: 2,
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            unsigned_tx.outputs[0].address,
+            BitcoinAddress::OpReturn(stone.encipher())
+        );
+
+        let fee = fake_sign(&unsigned_tx).vsize() as u64 * FEE_PER_VBYTE / 1000;
+        assert_eq!(btc_change.vout, 5);
+        assert_eq!(
+            btc_change.value,
+            2 * MIN_OUTPUT_AMOUNT + 100_000 - fee - 5 * MIN_OUTPUT_AMOUNT + MIN_OUTPUT_AMOUNT
+        );
+        assert_eq!(unsigned_tx.outputs[5].value, btc_change.value);
+    }
+
+    #[test]
+    fn test_build_unsigned_transaction_not_enough_funds() {
+        let mut runes_utxos: BTreeSet<_> =
+            [runes_utxo(1, RUNE_A, 1_000), runes_utxo(2, RUNE_B, 100)]
+                .into_iter()
+                .collect();
+        let mut btc_utxos: BTreeSet<_> = [utxo(3, 100_000)].into_iter().collect();
+
+        let result = build_unsigned_transaction(
+            &mut runes_utxos,
+            &mut btc_utxos,
+            runes_main_address,
+            btc_main_address(),
+            BuildTxReq::EdictTxReq(vec![
+                (RUNE_A, BitcoinAddress::P2wpkhV0([10; 20]), 100),
+                (RUNE_B, BitcoinAddress::P2wpkhV0([11; 20]), 200),
+            ]),
+            FEE_PER_VBYTE,
+            false,
+        );
+
+        assert_eq!(result.err(), Some(BuildTxError::NotEnoughFunds));
+        // The UTXOs selected for the first rune are put back.
+        assert_eq!(runes_utxos.len(), 2);
+        assert_eq!(btc_utxos.len(), 1);
+    }
+
+    #[test]
+    fn test_build_unsigned_transaction_not_enough_gas() {
+        let mut runes_utxos: BTreeSet<_> =
+            [runes_utxo(1, RUNE_A, 1_000), runes_utxo(2, RUNE_B, 500)]
+                .into_iter()
+                .collect();
+        let mut btc_utxos = BTreeSet::new();
+
+        let result = build_unsigned_transaction(
+            &mut runes_utxos,
+            &mut btc_utxos,
+            runes_main_address,
+            btc_main_address(),
+            BuildTxReq::EdictTxReq(vec![
+                (RUNE_A, BitcoinAddress::P2wpkhV0([10; 20]), 100),
+                (RUNE_B, BitcoinAddress::P2wpkhV0([11; 20]), 200),
+            ]),
+            FEE_PER_VBYTE,
+            false,
+        );
+
+        assert_eq!(result.err(), Some(BuildTxError::NotEnoughGas));
+        assert_eq!(runes_utxos.len(), 2);
+    }
+
+    #[test]
+    fn test_split_runes_change_outputs() {
+        let change = |rune_id, vout| RunesChangeOutput {
+            rune_id,
+            vout,
+            value: 0,
+        };
+        let (first, others) =
+            split_runes_change_outputs(vec![change(RUNE_A, 1), change(RUNE_B, 2)]);
+        assert_eq!(first, change(RUNE_A, 1));
+        assert_eq!(others, vec![change(RUNE_B, 2)]);
+
+        let (first, others) = split_runes_change_outputs(vec![change(RUNE_A, 1)]);
+        assert_eq!(first, change(RUNE_A, 1));
+        assert!(others.is_empty());
+    }
+}
@@ -25,6 +25,11 @@ pub struct UpgradeArgs {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hub_principal: Option<Principal>,
+
+    /// The max fee rate (in millisatoshi per vbyte) at which the customs
+    /// consolidates its UTXOs, zero disables the consolidation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consolidation_max_fee_per_vbyte: Option<u64>,
 }
 
 pub fn post_upgrade(upgrade_args: Option<UpgradeArgs>) {
@@ -52,6 +57,8 @@ pub fn post_upgrade(upgrade_args: Option<UpgradeArgs>) {
 
     replace_state(state);
 
+    crate::runes_etching::transactions::migrate_stash_etchings();
+
     let end = ic_cdk::api::instruction_counter();
 
     log!(
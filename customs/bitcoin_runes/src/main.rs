@@ -15,27 +15,32 @@ use ic_cdk_timers::set_timer_interval;
 use ordinals::SpacedRune;
 use serde::Serialize;
 
+use bitcoin_customs::consolidation::consolidate_utxos_task;
+use bitcoin_customs::deposit_watcher::watch_deposits_task;
+use bitcoin_customs::fee_policy::{advance_fee_rate, FeeOperation, FeeRatePolicy};
 use bitcoin_customs::lifecycle::upgrade::UpgradeArgs;
 use bitcoin_customs::lifecycle::{self, init::CustomArg};
 use bitcoin_customs::metrics::encode_metrics;
 use bitcoin_customs::queries::{EstimateFeeArgs, GetGenTicketReqsArgs, RedeemFee};
 use bitcoin_customs::runes_etching::fee_calculator::MAX_LOGO_CONTENT_SIZE;
-use bitcoin_customs::runes_etching::transactions::EtchingStatus::{
-    SendRevealFailed, SendRevealSuccess,
-};
-use bitcoin_customs::runes_etching::transactions::{estimate_tx_vbytes, internal_etching, SendEtchingInfo, stash_etching};
+use bitcoin_customs::runes_etching::mint::{MintArgs, MintFeeToken, SendMintInfo};
+use bitcoin_customs::runes_etching::transactions::{estimate_tx_vbytes, SendEtchingInfo, stash_etching};
 use bitcoin_customs::runes_etching::{EtchingArgs, LogoParams};
 use bitcoin_customs::state::eventlog::Event::UpdateFeeCollector;
 use bitcoin_customs::state::{audit, mutate_state, read_state, GenTicketRequestV2, GenTicketStatus, ReleaseTokenStatus, SetTxFeePerVbyteArgs, BitcoinFeeRate};
 use bitcoin_customs::storage::record_event;
+use bitcoin_customs::updates::deposit_psbt::BuildDepositPsbtArgs;
 use bitcoin_customs::updates::generate_ticket::{GenerateTicketArgs, GenerateTicketError};
+use bitcoin_customs::updates::register_deposit_watch::{
+    RegisterDepositWatchArgs, RegisterDepositWatchError,
+};
 use bitcoin_customs::updates::update_btc_utxos::UpdateBtcUtxosErr;
 use bitcoin_customs::updates::{
     self,
     get_btc_address::GetBtcAddressArgs,
     update_runes_balance::{UpdateRunesBalanceArgs, UpdateRunesBalanceError},
 };
-use bitcoin_customs::{commit_etching_task, management, process_directive_msg_task, process_etching_task, process_ticket_msg_task, process_tx_task, refresh_fee_task, CustomsInfo, ECDSAPublicKey, TokenResp, FEE_ESTIMATE_DELAY, INTERVAL_COMMIT_ETCHING, INTERVAL_HANDLE_ETCHING, INTERVAL_PROCESSING, INTERVAL_QUERY_DIRECTIVES};
+use bitcoin_customs::{commit_etching_task, process_directive_msg_task, process_etching_task, process_ticket_msg_task, process_tx_task, refresh_fee_task, CustomsInfo, ECDSAPublicKey, TokenResp, FEE_ESTIMATE_DELAY, INTERVAL_COMMIT_ETCHING, INTERVAL_CONSOLIDATION, INTERVAL_DEPOSIT_WATCHER, INTERVAL_HANDLE_ETCHING, INTERVAL_PROCESSING, INTERVAL_QUERY_DIRECTIVES};
 use bitcoin_customs::{
     state::eventlog::{Event, GetEventsArg},
     storage,
@@ -53,8 +58,10 @@ fn init(args: CustomArg) {
             set_timer_interval(INTERVAL_PROCESSING, process_tx_task);
             set_timer_interval(INTERVAL_PROCESSING, process_ticket_msg_task);
             set_timer_interval(INTERVAL_QUERY_DIRECTIVES, process_directive_msg_task);
-           // set_timer_interval(FEE_ESTIMATE_DELAY, refresh_fee_task);
+            set_timer_interval(FEE_ESTIMATE_DELAY, refresh_fee_task);
             set_timer_interval(INTERVAL_COMMIT_ETCHING, commit_etching_task);
+            set_timer_interval(INTERVAL_CONSOLIDATION, consolidate_utxos_task);
+            set_timer_interval(INTERVAL_DEPOSIT_WATCHER, watch_deposits_task);
             #[cfg(feature = "self_check")]
             ok_or_die(check_invariants())
         }
@@ -98,7 +105,7 @@ fn check_invariants() -> Result<(), String> {
 #[cfg(feature = "self_check")]
 #[update]
 async fn refresh_fee_percentiles() {
-    let _ = bitcoin_customs::estimate_fee_per_vbyte().await;
+    bitcoin_customs::fee_policy::refresh_fee_percentiles().await;
 }
 
 fn check_postcondition<T>(t: T) -> T {
@@ -121,9 +128,11 @@ fn post_upgrade(custom_arg: Option<CustomArg>) {
     set_timer_interval(INTERVAL_PROCESSING, process_tx_task);
     set_timer_interval(INTERVAL_PROCESSING, process_ticket_msg_task);
     set_timer_interval(INTERVAL_QUERY_DIRECTIVES, process_directive_msg_task);
-   // set_timer_interval(FEE_ESTIMATE_DELAY, refresh_fee_task);
+    set_timer_interval(FEE_ESTIMATE_DELAY, refresh_fee_task);
     set_timer_interval(INTERVAL_HANDLE_ETCHING, process_etching_task);
     set_timer_interval(INTERVAL_COMMIT_ETCHING, commit_etching_task);
+    set_timer_interval(INTERVAL_CONSOLIDATION, consolidate_utxos_task);
+    set_timer_interval(INTERVAL_DEPOSIT_WATCHER, watch_deposits_task);
 }
 
 #[update]
@@ -162,6 +171,13 @@ pub fn set_tx_fee_per_vbyte(args: SetTxFeePerVbyteArgs) -> Result<(), String> {
     }
 }
 
+#[update(guard = "is_controller")]
+pub fn set_fee_rate_policy(policy: FeeRatePolicy) -> Result<(), String> {
+    policy.validate()?;
+    mutate_state(|s| audit::update_fee_rate_policy(s, policy));
+    Ok(())
+}
+
 
 
 #[query]
@@ -192,31 +208,25 @@ pub fn get_etching(key: String) -> Option<SendEtchingInfo> {
     if r.is_some() {
         return r;
     }
-    let r = match read_state(|s| s.finalized_etching_requests.get(&key.clone())) {
-        None => None,
-        Some(r) => Some(r.into()),
-    };
-    if r.is_some() {
-        return r;
-    }
-    match read_state(|s| s.stash_etchings.get(&key.clone())) {
-        None => None,
-        Some(r) => Some(r.into()),
-    }
+    read_state(|s| s.finalized_etching_requests.get(&key.clone())).map(|r| r.into())
 }
 
+/// Returns the etchings of the caller with their statuses and the reasons of the failures.
+#[query]
+pub fn get_etching_status() -> Vec<SendEtchingInfo> {
+    get_etching_by_user(ic_cdk::caller())
+}
 
 #[update]
-pub async fn etching(fee_rate: u64, args: EtchingArgs) -> Result<String, String> {
-    let fee_rate = read_state(|s|{
-        let high = s.bitcoin_fee_rate.high;
-        if high == 0 {
-            5
-        }else {
-            high + 2
-        }
-    });
-    internal_etching(fee_rate, args).await
+pub fn cancel_etching(key: String) -> Result<(), String> {
+    bitcoin_customs::runes_etching::transactions::cancel_etching(key)
+}
+
+
+#[update]
+pub async fn etching(_fee_rate: u64, args: EtchingArgs) -> Result<String, String> {
+    let fee_rate = advance_fee_rate(FeeOperation::Etching);
+    stash_etching(fee_rate, args).await
 }
 
 #[update(guard = "is_controller")]
@@ -224,33 +234,43 @@ pub async fn etching_v2(args: EtchingArgs) -> Result<String, String> {
     stash_etching(5, args).await
 }
 
-#[update(guard = "is_controller")]
-pub async fn remove_error_etching(commit_tx: String) {
-    mutate_state(|s|s.pending_etching_requests.remove(&commit_tx));
+#[update]
+pub async fn mint_and_bridge(args: MintArgs) -> Result<String, String> {
+    bitcoin_customs::runes_etching::mint::mint_and_bridge(args).await
 }
 
-#[update(guard = "is_controller")]
-pub async fn canister_icp() {
-    let id = ic_cdk::id();
+#[update]
+pub async fn estimate_mint_fee(fee_token: Option<MintFeeToken>) -> Result<u128, String> {
+    bitcoin_customs::runes_etching::mint::estimate_mint_fee(fee_token.unwrap_or_default()).await
+}
 
+#[query]
+pub fn get_mint(key: String) -> Option<SendMintInfo> {
+    read_state(|s| {
+        s.pending_mint_requests
+            .get(&key)
+            .or_else(|| s.finalized_mint_requests.get(&key))
+            .map(|r| r.into())
+    })
 }
 
-#[update(guard = "is_controller")]
-pub async fn etching_reveal(commit_txid: String) {
-    let mut req = read_state(|s| s.pending_etching_requests.get(&commit_txid)).unwrap();
-    match management::send_etching(&req.txs[1]).await {
-        Ok(_) => {
-            req.status = SendRevealSuccess;
-        }
-        Err(e) => {
-            req.status = SendRevealFailed;
-            req.err_info = Some(e);
-        }
-    }
-    req.reveal_at = ic_cdk::api::time();
-    mutate_state(|s| s.pending_etching_requests.insert(commit_txid, req));
+#[query]
+pub fn get_mints_by_user(user_addr: Principal) -> Vec<SendMintInfo> {
+    read_state(|s| {
+        s.pending_mint_requests
+            .iter()
+            .chain(s.finalized_mint_requests.iter())
+            .filter(|(_, v)| v.payer == user_addr.to_text())
+            .map(|(_, v)| v.into())
+            .collect()
+    })
 }
 
+#[update(guard = "is_controller")]
+pub async fn canister_icp() {
+    let id = ic_cdk::id();
+
+}
 
 #[query]
 fn release_token_status(ticket_id: String) -> ReleaseTokenStatus {
@@ -305,6 +325,14 @@ pub fn is_runes_oracle() -> Result<(), String> {
     })
 }
 
+pub fn is_hub() -> Result<(), String> {
+    if read_state(|s| s.hub_principal == ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err("caller is not hub".to_string())
+    }
+}
+
 pub fn is_controller() -> Result<(), String> {
     if ic_cdk::api::is_controller(&ic_cdk::caller()) {
         Ok(())
@@ -313,6 +341,20 @@ pub fn is_controller() -> Result<(), String> {
     }
 }
 
+/// Notified by the hub once the customs subscribed the push mode, the tickets
+/// are pulled immediately instead of waiting for the next timer.
+#[update(guard = "is_hub")]
+fn on_new_tickets(from_seq: u64) {
+    log!(INFO, "[on_new_tickets] notified from seq: {}", from_seq);
+    process_ticket_msg_task();
+}
+
+#[update(guard = "is_hub")]
+fn on_new_directives(from_seq: u64) {
+    log!(INFO, "[on_new_directives] notified from seq: {}", from_seq);
+    process_directive_msg_task();
+}
+
 #[update(guard = "is_runes_oracle")]
 async fn update_runes_balance(args: UpdateRunesBalanceArgs) -> Result<(), UpdateRunesBalanceError> {
     check_postcondition(updates::update_runes_balance(args).await)
@@ -328,6 +370,23 @@ async fn generate_ticket(args: GenerateTicketArgs) -> Result<(), GenerateTicketE
     check_postcondition(updates::generate_ticket(args, None).await)
 }
 
+#[update]
+async fn build_deposit_psbt(args: BuildDepositPsbtArgs) -> Result<String, GenerateTicketError> {
+    check_postcondition(updates::deposit_psbt::build_deposit_psbt(args).await)
+}
+
+#[update]
+async fn submit_deposit_psbt(psbt: String) -> Result<String, GenerateTicketError> {
+    check_postcondition(updates::deposit_psbt::submit_deposit_psbt(psbt).await)
+}
+
+#[update]
+async fn register_deposit_watch(
+    args: RegisterDepositWatchArgs,
+) -> Result<String, RegisterDepositWatchError> {
+    check_postcondition(updates::register_deposit_watch(args).await)
+}
+
 #[query]
 fn get_runes_oracles() -> Vec<Principal> {
     read_state(|s| s.runes_oracles.iter().cloned().collect())
@@ -426,6 +485,8 @@ fn get_customs_info() -> CustomsInfo {
         generate_ticket_counter: s.generate_ticket_counter,
         release_token_counter: s.release_token_counter,
         bitcoin_fee_rate: s.bitcoin_fee_rate.clone(),
+        fee_rate_policy: s.fee_rate_policy.clone(),
+        fee_rate_history: s.fee_rate_history.iter().cloned().collect(),
         etching_acount_info: s.etching_acount_info.clone(),
         ord_indexer_principal: s.ord_indexer_principal,
         icpswap_principal: s.icpswap_principal,
@@ -458,6 +519,18 @@ fn get_token_list() -> Vec<TokenResp> {
     })
 }
 
+/// the runes locked by the customs, it`s used by the hub to reconcile the token position
+#[query]
+fn get_token_supply(token_id: String) -> Result<u128, String> {
+    read_state(|s| {
+        let (rune_id, _) = s
+            .tokens
+            .get(&token_id)
+            .ok_or(format!("token not found: {}", token_id))?;
+        Ok(s.locked_runes_supply(rune_id))
+    })
+}
+
 #[query(hidden = true)]
 fn http_request(req: HttpRequest) -> HttpResponse {
     if ic_cdk::api::data_certificate().is_none() {
@@ -18,6 +18,8 @@ use ic_ic00_types::{
 };
 use omnity_types::ic_log::CRITICAL;
 use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
 
 async fn call<I, O>(method: &str, payment: u64, input: &I) -> Result<O, CallError>
 where
@@ -188,31 +190,19 @@ pub async fn get_current_fees(network: Network) -> Result<Vec<MillisatoshiPerByt
 
 /// Sends the transaction to the network the management canister interacts with.
 pub async fn send_etching(transaction: &Transaction) -> Result<(), CallError> {
-    use ic_cdk::api::management_canister::bitcoin::BitcoinNetwork;
     let network = read_state(|s| s.btc_network);
-    let cdk_network = match network {
-        Network::Mainnet => BitcoinNetwork::Mainnet,
-        Network::Testnet => BitcoinNetwork::Testnet,
-        Network::Regtest => BitcoinNetwork::Regtest,
-    };
-    let tx_bytes = bitcoin::consensus::serialize(&transaction);
-    ic_cdk::api::management_canister::bitcoin::bitcoin_send_transaction(
-        ic_cdk::api::management_canister::bitcoin::SendTransactionRequest {
-            transaction: tx_bytes,
-            network: cdk_network,
-        },
-    )
-    .await
-    .map_err(|(code, msg)| CallError {
-        method: "bitcoin_send_transaction".to_string(),
-        reason: Reason::from_reject(code, msg),
-    })
+    send_raw_transaction(bitcoin::consensus::serialize(&transaction), network).await
 }
 
 pub async fn send_transaction(
     transaction: &tx::SignedTransaction,
     network: Network,
 ) -> Result<(), CallError> {
+    send_raw_transaction(transaction.serialize(), network).await
+}
+
+/// Sends the consensus encoded transaction to the bitcoin network.
+pub async fn send_raw_transaction(transaction: Vec<u8>, network: Network) -> Result<(), CallError> {
     use ic_cdk::api::management_canister::bitcoin::BitcoinNetwork;
 
     let cdk_network = match network {
@@ -220,10 +210,9 @@ pub async fn send_transaction(
         Network::Testnet => BitcoinNetwork::Testnet,
         Network::Regtest => BitcoinNetwork::Regtest,
     };
-    let tx_bytes = transaction.serialize();
     ic_cdk::api::management_canister::bitcoin::bitcoin_send_transaction(
         ic_cdk::api::management_canister::bitcoin::SendTransactionRequest {
-            transaction: tx_bytes,
+            transaction,
             network: cdk_network,
         },
     )
@@ -286,6 +275,110 @@ pub async fn sign_with_ecdsa(
     Ok(reply.signature)
 }
 
+#[derive(CandidType, Clone, Debug, Deserialize)]
+#[allow(non_camel_case_types)]
+enum SchnorrAlgorithm {
+    bip340secp256k1,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize)]
+struct SchnorrKeyId {
+    algorithm: SchnorrAlgorithm,
+    name: String,
+}
+
+#[derive(CandidType, Debug)]
+struct SchnorrPublicKeyArgs {
+    canister_id: Option<Principal>,
+    derivation_path: Vec<ByteBuf>,
+    key_id: SchnorrKeyId,
+}
+
+#[derive(CandidType, Debug, Deserialize)]
+struct SchnorrPublicKeyResponse {
+    public_key: ByteBuf,
+    chain_code: ByteBuf,
+}
+
+#[derive(CandidType, Debug)]
+struct Bip341 {
+    merkle_root_hash: ByteBuf,
+}
+
+#[derive(CandidType, Debug)]
+#[allow(non_camel_case_types)]
+enum SignWithSchnorrAux {
+    bip341(Bip341),
+}
+
+#[derive(CandidType, Debug)]
+struct SignWithSchnorrArgs {
+    message: ByteBuf,
+    derivation_path: Vec<ByteBuf>,
+    key_id: SchnorrKeyId,
+    aux: Option<SignWithSchnorrAux>,
+}
+
+#[derive(CandidType, Debug, Deserialize)]
+struct SignWithSchnorrReply {
+    signature: ByteBuf,
+}
+
+/// Fetches the BIP-340 public key of this canister at the given derivation path
+/// from the threshold Schnorr API.
+pub async fn schnorr_public_key(
+    key_name: String,
+    derivation_path: Vec<ByteBuf>,
+) -> Result<ECDSAPublicKey, CallError> {
+    call(
+        "schnorr_public_key",
+        /*payment=*/ 0,
+        &SchnorrPublicKeyArgs {
+            canister_id: None,
+            derivation_path,
+            key_id: SchnorrKeyId {
+                algorithm: SchnorrAlgorithm::bip340secp256k1,
+                name: key_name,
+            },
+        },
+    )
+    .await
+    .map(|response: SchnorrPublicKeyResponse| ECDSAPublicKey {
+        public_key: response.public_key.into_vec(),
+        chain_code: response.chain_code.into_vec(),
+    })
+}
+
+/// Signs a taproot sighash using the threshold Schnorr API. The derived key is tweaked
+/// without a script tree, as expected when spending a P2TR output through the key path.
+pub async fn sign_with_schnorr_taproot(
+    key_name: String,
+    derivation_path: Vec<ByteBuf>,
+    message_hash: [u8; 32],
+) -> Result<Vec<u8>, CallError> {
+    // The cost of a single threshold Schnorr signature is 26_153_846_153.
+    // ref: https://internetcomputer.org/docs/current/references/t-sigs-how-it-works#fees-for-the-t-schnorr-production-key
+    const CYCLES_PER_SIGNATURE: u64 = 30_000_000_000;
+
+    let reply: SignWithSchnorrReply = call(
+        "sign_with_schnorr",
+        CYCLES_PER_SIGNATURE,
+        &SignWithSchnorrArgs {
+            message: ByteBuf::from(message_hash.to_vec()),
+            derivation_path,
+            key_id: SchnorrKeyId {
+                algorithm: SchnorrAlgorithm::bip340secp256k1,
+                name: key_name,
+            },
+            aux: Some(SignWithSchnorrAux::bip341(Bip341 {
+                merkle_root_hash: ByteBuf::new(),
+            })),
+        },
+    )
+    .await?;
+    Ok(reply.signature.into_vec())
+}
+
 pub async fn raw_rand() -> CallResult<[u8; 32]> {
     let (random_bytes,): (Vec<u8>,) =
         ic_cdk::api::call::call(Principal::management_canister(), "raw_rand", ()).await?;
@@ -1,3 +1,4 @@
+use crate::fee_policy::{fresh_fee_percentiles, is_congested, FeeOperation};
 use crate::state::{self, RuneTxRequest};
 use std::cell::Cell;
 
@@ -92,6 +93,48 @@ pub fn encode_metrics(
             state::read_state(|s| s.stuck_transactions.len() as f64),
         )?;
 
+    metrics
+        .gauge_vec(
+            "bitcoin_customs_consolidation_transaction_count",
+            "Total count of non-finalized consolidation transaction, by status.",
+        )?
+        .value(
+            &[("status", "submitted")],
+            state::read_state(|s| {
+                s.submitted_transactions
+                    .iter()
+                    .filter(|tx| tx.kind == state::SubmittedTxKind::Consolidation)
+                    .count()
+            }) as f64,
+        )?
+        .value(
+            &[("status", "stuck")],
+            state::read_state(|s| {
+                s.stuck_transactions
+                    .iter()
+                    .filter(|tx| tx.kind == state::SubmittedTxKind::Consolidation)
+                    .count()
+            }) as f64,
+        )?;
+
+    metrics.encode_gauge(
+        "bitcoin_customs_cpfp_transaction_count",
+        state::read_state(|s| s.cpfp_transactions.len()) as f64,
+        "Total count of child transactions bumping the fee of stuck transactions.",
+    )?;
+
+    metrics.encode_counter(
+        "bitcoin_customs_finalized_consolidations",
+        state::read_state(|s| s.finalized_consolidations_count) as f64,
+        "Total number of finalized consolidation transactions.",
+    )?;
+
+    metrics.encode_gauge(
+        "bitcoin_customs_consolidation_max_fee_per_vbyte",
+        state::read_state(|s| s.consolidation_max_fee_per_vbyte.unwrap_or_default()) as f64,
+        "The max fee rate in millisatoshi per vbyte to consolidate UTXOs, zero if disabled.",
+    )?;
+
     metrics.encode_gauge(
         "bitcoin_customs_longest_resubmission_chain_size",
         state::read_state(|s| s.longest_resubmission_chain_size() as f64),
@@ -169,6 +212,38 @@ pub fn encode_metrics(
         "Median Bitcoin transaction fee per vbyte in Satoshi.",
     )?;
 
+    let mut fee_rates = metrics.gauge_vec(
+        "bitcoin_customs_fee_per_vbyte",
+        "The last fee rate per vbyte in millisatoshi chosen by the policy, by operation.",
+    )?;
+    for operation in [
+        FeeOperation::Release,
+        FeeOperation::Consolidation,
+        FeeOperation::Etching,
+        FeeOperation::Replacement,
+    ] {
+        if let Some(record) = state::read_state(|s| {
+            s.fee_rate_history
+                .iter()
+                .rev()
+                .find(|r| r.operation == operation)
+                .cloned()
+        }) {
+            fee_rates = fee_rates.value(
+                &[("operation", operation.label())],
+                record.fee_per_vbyte as f64,
+            )?;
+        }
+    }
+
+    metrics.encode_gauge(
+        "bitcoin_customs_mempool_congested",
+        state::read_state(|s| {
+            is_congested(fresh_fee_percentiles(s, ic_cdk::api::time()))
+        }) as u8 as f64,
+        "Whether the fee percentiles show a congested mempool.",
+    )?;
+
     metrics.encode_gauge(
         "bitcoin_customs_next_ticket_seq",
         state::read_state(|s| s.next_ticket_seq) as f64,
@@ -181,5 +256,11 @@ pub fn encode_metrics(
         "Next sequence of query directives. ",
     )?;
 
+    metrics.encode_gauge(
+        "bitcoin_customs_deposit_watches",
+        state::read_state(|s| s.deposit_watches.len()) as f64,
+        "Number of destinations watched for the deposit auto-detection.",
+    )?;
+
     Ok(())
 }
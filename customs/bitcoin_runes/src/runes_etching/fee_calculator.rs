@@ -6,6 +6,7 @@ use ic_canister_log::log;
 use ic_cdk::{caller, id};
 use icrc_ledger_client_cdk::{CdkRuntime, ICRC1Client};
 use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc1::transfer::TransferArg;
 use icrc_ledger_types::icrc2::allowance::{Allowance, AllowanceArgs};
 use icrc_ledger_types::icrc2::transfer_from::TransferFromArgs;
 use num_traits::ToPrimitive;
@@ -15,7 +16,8 @@ use crate::runes_etching::constants::POSTAGE;
 use crate::runes_etching::Utxo;
 use crate::state::mutate_state;
 
-const ICP_LEDGER_CANISTER_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+pub const ICP_LEDGER_CANISTER_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+pub const CKBTC_LEDGER_CANISTER_ID: &str = "mxzaz-hqaaa-aaaar-qaada-cai";
 pub const INPUT_SIZE_VBYTES: u64 = 74;
 pub const OUTPUT_SIZE_VBYTES: u64 = 31;
 pub const TX_OVERHEAD_VBYTES: u64 = 21;
@@ -32,6 +34,11 @@ pub fn select_utxos(fee_rate: u64, fixed_size: u64) -> anyhow::Result<Vec<Utxo>>
         let u = s.etching_fee_utxos.pop();
         match u {
             None => {
+                for utxo in selected_utxos.iter().rev() {
+                    s.etching_fee_utxos
+                        .push(utxo)
+                        .expect("failed to put back the fee utxos");
+                }
                 return Err(anyhow!("InsufficientFunds"));
             }
             Some(utxo) => {
@@ -43,9 +50,11 @@ pub fn select_utxos(fee_rate: u64, fixed_size: u64) -> anyhow::Result<Vec<Utxo>>
     })
 }
 
-pub async fn check_allowance(fee_amt: u64) -> anyhow::Result<u64> {
-    let allowance = allowance(caller()).await?;
-    let allx = allowance.allowance.0.to_u64().unwrap_or_default();
+/// Checks that the caller approved at least `fee_amt` tokens of the ledger to the
+/// customs, returns the allowance.
+pub async fn check_allowance(ledger: Principal, fee_amt: u128) -> anyhow::Result<u128> {
+    let allowance = ledger_allowance(ledger, caller()).await?;
+    let allx = allowance.allowance.0.to_u128().unwrap_or_default();
     log!(
         INFO,
         "query allowance result: {}, {}",
@@ -58,13 +67,17 @@ pub async fn check_allowance(fee_amt: u64) -> anyhow::Result<u64> {
             fee_amt, allx
         )));
     }
-    if allowance.expires_at.is_some() {
+    if allowance
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= ic_cdk::api::time())
+    {
         return Err(anyhow!("allowance is expired".to_string()));
     }
     Ok(allx)
 }
 
-pub async fn transfer_etching_fees(amount: u128) -> anyhow::Result<()> {
+/// Transfers the etching fee from the caller, returns the amount received by the customs.
+pub async fn transfer_etching_fees(amount: u128) -> anyhow::Result<u128> {
     let canister = Principal::from_str(ICP_LEDGER_CANISTER_ID).unwrap();
     let client = ICRC1Client {
         runtime: CdkRuntime,
@@ -100,6 +113,94 @@ pub async fn transfer_etching_fees(amount: u128) -> anyhow::Result<()> {
             ))
         })?;
 
+    match result {
+        Ok(_) => Ok(transfer_amount.0.to_u128().unwrap_or_default()),
+        Err(e) => Err(anyhow!(format!("transfer fee error{:?}", e))),
+    }
+}
+
+/// Refunds the etching fee to the user, the ledger fee is deducted from the amount.
+pub async fn refund_etching_fees(to: Principal, amount: u128) -> anyhow::Result<()> {
+    refund_fee(
+        Principal::from_str(ICP_LEDGER_CANISTER_ID).unwrap(),
+        to,
+        amount,
+    )
+    .await
+}
+
+/// Refunds `amount` tokens of the ledger to the user, the ledger fee is deducted
+/// from the amount.
+pub async fn refund_fee(ledger: Principal, to: Principal, amount: u128) -> anyhow::Result<()> {
+    let client = ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id: ledger,
+    };
+    let fee = client
+        .fee()
+        .await
+        .map_err(|e| anyhow!(format!("Failed to get icrc fee, error: {:?}", e).to_string(),))?;
+    let fee = fee.0.to_u128().unwrap_or_default();
+    if amount <= fee {
+        return Ok(());
+    }
+    let result = client
+        .transfer(TransferArg {
+            from_subaccount: None,
+            to: Account {
+                owner: to,
+                subaccount: None,
+            },
+            fee: None,
+            created_at_time: Some(ic_cdk::api::time()),
+            memo: None,
+            amount: Nat::from(amount - fee),
+        })
+        .await
+        .map_err(|(code, msg)| {
+            anyhow!(format!(
+                "cannot refund the etching fee: {} (reject_code = {})",
+                msg, code
+            ))
+        })?;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(anyhow!(format!("refund fee error{:?}", e))),
+    }
+}
+
+/// Transfers `amount` tokens of the ledger from the caller to the customs,
+/// the ledger fee is paid by the caller on top of the amount.
+pub async fn transfer_fee_from_caller(ledger: Principal, amount: u128) -> anyhow::Result<()> {
+    let client = ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id: ledger,
+    };
+    let result = client
+        .transfer_from(TransferFromArgs {
+            spender_subaccount: None,
+            from: Account {
+                owner: caller(),
+                subaccount: None,
+            },
+            to: Account {
+                owner: id(),
+                subaccount: None,
+            },
+            amount: Nat::from(amount),
+            fee: None,
+            memo: None,
+            created_at_time: Some(ic_cdk::api::time()),
+        })
+        .await
+        .map_err(|(code, msg)| {
+            anyhow!(format!(
+                "cannot transfer fee: {} (reject_code = {})",
+                msg, code
+            ))
+        })?;
+
     match result {
         Ok(_) => Ok(()),
         Err(e) => Err(anyhow!(format!("transfer fee error{:?}", e))),
@@ -107,9 +208,12 @@ pub async fn transfer_etching_fees(amount: u128) -> anyhow::Result<()> {
 }
 
 pub async fn allowance(acct: Principal) -> anyhow::Result<Allowance> {
-    let canister = Principal::from_str(ICP_LEDGER_CANISTER_ID).unwrap();
+    ledger_allowance(Principal::from_str(ICP_LEDGER_CANISTER_ID).unwrap(), acct).await
+}
+
+pub async fn ledger_allowance(ledger: Principal, acct: Principal) -> anyhow::Result<Allowance> {
     let allowance: (Allowance,) = ic_cdk::call(
-        canister,
+        ledger,
         "icrc2_allowance",
         (AllowanceArgs {
             account: Account {
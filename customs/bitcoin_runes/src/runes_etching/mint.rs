@@ -0,0 +1,599 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_btc_interface::{OutPoint, Txid};
+use ic_canister_log::log;
+use ic_cdk::caller;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use omnity_types::ic_log::{ERROR, INFO};
+use omnity_types::rune_id::RuneId;
+use omnity_types::{ChainState, TxAction};
+use serde::Serialize;
+
+use crate::call_error::{CallError, Reason};
+use crate::destination::Destination;
+use crate::fee_policy::{advance_fee_rate, FeeOperation};
+use crate::guard::MintKeyGuard;
+use crate::runes_etching::fee_calculator::{
+    check_allowance, refund_fee, transfer_fee_from_caller, CKBTC_LEDGER_CANISTER_ID,
+    ICP_LEDGER_CANISTER_ID,
+};
+use crate::runes_etching::icp_swap::estimate_etching_fee;
+use crate::runes_etching::sync::MintError;
+use crate::runes_etching::OrdinalsTerms;
+use crate::state::{
+    mutate_state, read_state, GenTicketRequestV2, GenTicketStatus, ReleaseTokenStatus, RUNES_TOKEN,
+};
+use crate::updates::generate_ticket::{
+    accept_deposit, fetch_new_utxos, validate_generate_ticket_args, GenerateTicketArgs,
+    GenerateTicketError,
+};
+use crate::updates::get_btc_address::{
+    destination_to_deposit_address_from_state, init_ecdsa_public_key,
+};
+use crate::updates::rune_tx::{generate_rune_tx_request, RuneTxArgs};
+use crate::{tx_vsize_estimate, MIN_OUTPUT_AMOUNT};
+
+/// The prefix of the keys of the mint requests, the keys are also the ticket ids
+/// of the rune tx requests minting the runes.
+pub const MINT_KEY_PREFIX: &str = "Bitcoin-mint-";
+
+/// The ticket of a confirmed mint is generated at most this many times, the mint
+/// fails once the attempts are used up.
+const MAX_MINT_TICKET_ATTEMPTS: u32 = 10;
+
+#[derive(CandidType, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum MintFeeToken {
+    #[default]
+    ICP,
+    CKBTC,
+}
+
+impl MintFeeToken {
+    pub fn ledger(&self) -> Principal {
+        match self {
+            MintFeeToken::ICP => Principal::from_str(ICP_LEDGER_CANISTER_ID).unwrap(),
+            MintFeeToken::CKBTC => Principal::from_str(CKBTC_LEDGER_CANISTER_ID).unwrap(),
+        }
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MintArgs {
+    pub rune_id: String,
+    pub target_chain_id: String,
+    pub receiver: String,
+    /// The token the fee is paid with, ICP by default.
+    pub fee_token: Option<MintFeeToken>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, CandidType)]
+pub enum MintStatus {
+    Initial,
+    MintSubmitted,
+    MintFailed,
+    TicketGenerated,
+    Final,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct SendMintRequest {
+    pub rune_id: RuneId,
+    pub token_id: String,
+    pub target_chain_id: String,
+    pub receiver: String,
+    pub amount: u128,
+    pub payer: String,
+    pub fee_token: MintFeeToken,
+    pub fee_amount: u128,
+    pub mint_txid: Option<String>,
+    pub err_info: Option<String>,
+    pub mint_at: u64,
+    pub ticket_at: u64,
+    pub status: MintStatus,
+    /// The failed attempts to generate the ticket of the confirmed mint.
+    #[serde(default)]
+    pub ticket_attempts: u32,
+}
+
+impl Storable for SendMintRequest {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = vec![];
+        let _ = ciborium::ser::into_writer(self, &mut bytes);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::de::from_reader(bytes.as_ref()).expect("failed to decode mint request")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, CandidType)]
+pub struct SendMintInfo {
+    pub rune_id: String,
+    pub target_chain_id: String,
+    pub receiver: String,
+    pub amount: u128,
+    pub payer: String,
+    pub mint_txid: String,
+    pub err_info: String,
+    pub time_at: u64,
+    pub status: MintStatus,
+}
+
+impl From<SendMintRequest> for SendMintInfo {
+    fn from(value: SendMintRequest) -> Self {
+        SendMintInfo {
+            rune_id: value.rune_id.to_string(),
+            target_chain_id: value.target_chain_id,
+            receiver: value.receiver,
+            amount: value.amount,
+            payer: value.payer,
+            mint_txid: value.mint_txid.unwrap_or_default(),
+            err_info: value.err_info.unwrap_or_default(),
+            time_at: value.mint_at,
+            status: value.status,
+        }
+    }
+}
+
+/// The part of the rune entry of the runes indexer needed to check the mint terms.
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct RuneEntry {
+    pub block: u64,
+    pub mints: u128,
+    pub terms: Option<RuneTerms>,
+}
+
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub struct RuneTerms {
+    pub amount: Option<u128>,
+    pub cap: Option<u128>,
+    pub height: (Option<u64>, Option<u64>),
+    pub offset: (Option<u64>, Option<u64>),
+}
+
+impl From<RuneTerms> for OrdinalsTerms {
+    fn from(terms: RuneTerms) -> Self {
+        // A missing amount or cap makes the rune unmintable, as in ord.
+        OrdinalsTerms {
+            amount: terms.amount.unwrap_or_default(),
+            cap: terms.cap.unwrap_or_default(),
+            height: terms.height,
+            offset: terms.offset,
+        }
+    }
+}
+
+pub async fn get_rune_entry(rune_id: &RuneId) -> Result<Option<RuneEntry>, CallError> {
+    let method = "get_rune_by_id";
+    let ord_principal = read_state(|s| s.ord_indexer_principal.unwrap());
+    let resp: (Option<RuneEntry>,) =
+        ic_cdk::api::call::call(ord_principal, method, (rune_id.to_string(),))
+            .await
+            .map_err(|(code, message)| CallError {
+                method: method.to_string(),
+                reason: Reason::from_reject(code, message),
+            })?;
+    Ok(resp.0)
+}
+
+/// The balance of a rune in an output reported by the runes indexer.
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct OutputRuneBalance {
+    pub confirmations: u32,
+    pub rune_id: String,
+    pub amount: u128,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum RunesIndexerError {
+    MaxOutpointsExceeded,
+}
+
+/// Returns the rune balances of the outputs, `None` for the outputs the runes
+/// indexer has not indexed yet.
+pub async fn get_rune_balances_for_outputs(
+    outpoints: &[OutPoint],
+) -> Result<Vec<Option<Vec<OutputRuneBalance>>>, CallError> {
+    let method = "get_rune_balances_for_outputs";
+    let ord_principal = read_state(|s| s.ord_indexer_principal).ok_or(CallError {
+        method: method.to_string(),
+        reason: Reason::CanisterError("the runes indexer is not set".to_string()),
+    })?;
+    let outpoints: Vec<String> = outpoints
+        .iter()
+        .map(|outpoint| format!("{}:{}", outpoint.txid, outpoint.vout))
+        .collect();
+    let resp: (Result<Vec<Option<Vec<OutputRuneBalance>>>, RunesIndexerError>,) =
+        ic_cdk::api::call::call(ord_principal, method, (outpoints,))
+            .await
+            .map_err(|(code, message)| CallError {
+                method: method.to_string(),
+                reason: Reason::from_reject(code, message),
+            })?;
+    resp.0.map_err(|e| CallError {
+        method: method.to_string(),
+        reason: Reason::CanisterError(format!("{:?}", e)),
+    })
+}
+
+pub async fn get_latest_block() -> Result<(u32, String), CallError> {
+    let method = "get_latest_block";
+    let ord_principal = read_state(|s| s.ord_indexer_principal.unwrap());
+    let resp: ((u32, String),) = ic_cdk::api::call::call(ord_principal, method, ())
+        .await
+        .map_err(|(code, message)| CallError {
+            method: method.to_string(),
+            reason: Reason::from_reject(code, message),
+        })?;
+    Ok(resp.0)
+}
+
+/// Estimates the fee of a mint in the fee token, it covers the mint transaction
+/// and the postage of the minted runes.
+pub async fn estimate_mint_fee(fee_token: MintFeeToken) -> Result<u128, String> {
+    let fee_rate = advance_fee_rate(FeeOperation::Release);
+    // The inputs pay the fee, the outputs are the runestone, the minted runes and the change.
+    let vsize = tx_vsize_estimate(2, 3);
+    match fee_token {
+        MintFeeToken::CKBTC => Ok((fee_rate * vsize + MIN_OUTPUT_AMOUNT) as u128),
+        MintFeeToken::ICP => estimate_etching_fee(fee_rate, vsize as u128).await,
+    }
+}
+
+/// Charges the caller the mint fee and queues a transaction that mints the rune to
+/// the deposit address of the receiver, returns the key of the mint request.
+/// Once the transaction is confirmed the customs generates the ticket to the target chain.
+/// The fee is refunded if the mint can't be queued.
+pub async fn mint_and_bridge(args: MintArgs) -> Result<String, String> {
+    if read_state(|s| s.chain_state == ChainState::Deactive) {
+        return Err("chain state is deactive!".to_string());
+    }
+    let rune_id = RuneId::from_str(&args.rune_id).map_err(|e| e.to_string())?;
+    let token_id = read_state(|s| {
+        s.tokens
+            .iter()
+            .find(|(_, (r, _))| rune_id.eq(r))
+            .map(|(token_id, _)| token_id.clone())
+    })
+    .ok_or(format!("unsupported token: {}", args.rune_id))?;
+    if !read_state(|s| {
+        s.counterparties
+            .get(&args.target_chain_id)
+            .is_some_and(|c| c.chain_state == ChainState::Active)
+    }) {
+        return Err(format!("unsupported chain id: {}", args.target_chain_id));
+    }
+
+    let payer = caller();
+    let mint_key = MintKeyGuard::new(&payer);
+    let key = mint_key.key().to_string();
+
+    let entry = get_rune_entry(&rune_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or(format!("rune not found: {}", rune_id))?;
+    let (height, _) = get_latest_block().await.map_err(|e| e.to_string())?;
+    let terms: OrdinalsTerms = entry
+        .terms
+        .map(Into::into)
+        .ok_or(MintError::Unmintable.to_string())?;
+    // The mint transaction is included in the next block at the earliest.
+    let amount = terms
+        .mintable(entry.block, entry.mints, height as u64 + 1)
+        .map_err(|e| e.to_string())?;
+
+    let fee_token = args.fee_token.unwrap_or_default();
+    let fee_amount = estimate_mint_fee(fee_token).await?;
+    check_allowance(fee_token.ledger(), fee_amount)
+        .await
+        .map_err(|e| e.to_string())?;
+    transfer_fee_from_caller(fee_token.ledger(), fee_amount)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let request = SendMintRequest {
+        rune_id,
+        token_id: token_id.clone(),
+        target_chain_id: args.target_chain_id.clone(),
+        receiver: args.receiver.clone(),
+        amount,
+        payer: payer.to_text(),
+        fee_token,
+        fee_amount,
+        mint_txid: None,
+        err_info: None,
+        mint_at: ic_cdk::api::time(),
+        ticket_at: 0,
+        status: MintStatus::Initial,
+        ticket_attempts: 0,
+    };
+    let result = generate_rune_tx_request(RuneTxArgs {
+        ticket_id: key.clone(),
+        token_id,
+        src_chain: args.target_chain_id,
+        action: TxAction::Mint,
+        amount,
+        receiver: args.receiver,
+    })
+    .await;
+    if let Err(e) = result {
+        log!(ERROR, "failed to queue the mint {}: {:?}", key, e);
+        refund_mint(key, request, format!("{:?}", e)).await;
+        return Err(format!("{:?}", e));
+    }
+    mutate_state(|s| s.pending_mint_requests.insert(key.clone(), request));
+    log!(INFO, "queued the mint {} of {} {}", key, amount, rune_id);
+    Ok(key)
+}
+
+/// Generates the ticket of the runes minted by the confirmed transaction.
+async fn generate_mint_ticket(
+    req: &SendMintRequest,
+    mint_txid: String,
+) -> Result<(), GenerateTicketError> {
+    let args = GenerateTicketArgs {
+        target_chain_id: req.target_chain_id.clone(),
+        receiver: req.receiver.clone(),
+        rune_id: req.rune_id.to_string(),
+        amount: req.amount,
+        txid: mint_txid,
+        address_type: None,
+    };
+    let (rune_id, txid, token_id) = match validate_generate_ticket_args(&args) {
+        Err(GenerateTicketError::AlreadySubmitted | GenerateTicketError::AlreadyProcessed) => {
+            return Ok(())
+        }
+        result => result?,
+    };
+
+    let address = mint_deposit_address(req).await;
+    let (new_utxos, _, allocations) = fetch_new_utxos(txid, &address).await?;
+    if new_utxos.is_empty() {
+        return Err(GenerateTicketError::NoNewUtxos);
+    }
+
+    let request = GenTicketRequestV2 {
+        address,
+        target_chain_id: args.target_chain_id,
+        receiver: args.receiver,
+        token_id,
+        rune_id,
+        amount: args.amount,
+        txid,
+        new_utxos,
+        received_at: ic_cdk::api::time(),
+        allocations: Some(allocations),
+        address_type: None,
+    };
+    // The fee of the bridge is covered by the mint fee.
+    accept_deposit(args.txid, request, None, Some(req.mint_at)).await
+}
+
+/// The deposit address of the receiver, the runes are minted to.
+async fn mint_deposit_address(req: &SendMintRequest) -> String {
+    init_ecdsa_public_key().await;
+    let destination = Destination {
+        target_chain_id: req.target_chain_id.clone(),
+        receiver: req.receiver.clone(),
+        token: Some(RUNES_TOKEN.into()),
+        address_type: None,
+    };
+    read_state(|s| destination_to_deposit_address_from_state(s, &destination))
+}
+
+/// Returns whether the outputs the confirmed mint sent to the deposit address hold
+/// the minted runes, none until the runes indexer indexed all of them. The mint
+/// carries no runes if the cap was reached before it was confirmed.
+async fn mint_holds_runes(
+    req: &SendMintRequest,
+    mint_txid: &str,
+) -> Result<Option<bool>, GenerateTicketError> {
+    let txid = Txid::from_str(mint_txid).map_err(|_| GenerateTicketError::InvalidTxId)?;
+    let address = mint_deposit_address(req).await;
+    let (utxos, _, _) = fetch_new_utxos(txid, &address).await?;
+    let outpoints = utxos
+        .iter()
+        .map(|utxo| utxo.outpoint.clone())
+        .collect::<Vec<_>>();
+    let balances = get_rune_balances_for_outputs(&outpoints)
+        .await
+        .map_err(|e| GenerateTicketError::TemporarilyUnavailable(e.to_string()))?;
+    Ok(holds_runes(&req.rune_id.to_string(), &balances))
+}
+
+fn holds_runes(rune_id: &str, balances: &[Option<Vec<OutputRuneBalance>>]) -> Option<bool> {
+    let mut holds = false;
+    for balance in balances {
+        holds |= balance
+            .as_ref()?
+            .iter()
+            .any(|balance| balance.rune_id == rune_id && balance.amount > 0);
+    }
+    Some(holds)
+}
+
+/// Fails the mint and refunds its fee to the payer.
+async fn refund_mint(key: String, mut req: SendMintRequest, reason: String) {
+    req.status = MintStatus::MintFailed;
+    req.err_info = Some(reason.clone());
+    finalize_mint_request(key.clone(), req.clone());
+    let refund = match Principal::from_text(&req.payer) {
+        Ok(payer) => refund_fee(req.fee_token.ledger(), payer, req.fee_amount).await,
+        Err(e) => Err(anyhow::anyhow!("invalid payer: {}", e)),
+    };
+    if let Err(refund_err) = refund {
+        log!(
+            ERROR,
+            "failed to refund the fee of the mint {}: {:?}",
+            key,
+            refund_err
+        );
+        req.err_info = Some(format!("{}, refund failed: {:?}", reason, refund_err));
+        mutate_state(|s| s.finalized_mint_requests.insert(key, req));
+    }
+}
+
+fn finalize_mint_request(key: String, req: SendMintRequest) {
+    mutate_state(|s| {
+        s.pending_mint_requests.remove(&key);
+        s.finalized_mint_requests.insert(key, req);
+    });
+}
+
+pub async fn handle_mint_result_task() {
+    if read_state(|s| s.pending_mint_requests.is_empty()) {
+        return;
+    }
+    let kvs = read_state(|s| {
+        s.pending_mint_requests
+            .iter()
+            .collect::<BTreeMap<String, SendMintRequest>>()
+    });
+    for (k, mut req) in kvs {
+        match req.status {
+            MintStatus::Initial | MintStatus::MintSubmitted => {
+                match read_state(|s| s.rune_tx_status(&k)) {
+                    ReleaseTokenStatus::Pending
+                    | ReleaseTokenStatus::Signing
+                    | ReleaseTokenStatus::Sending(_) => {}
+                    ReleaseTokenStatus::Submitted(txid) => {
+                        // The transaction can be replaced until it's confirmed.
+                        if req.mint_txid.as_ref() != Some(&txid) {
+                            req.status = MintStatus::MintSubmitted;
+                            req.mint_txid = Some(txid);
+                            mutate_state(|s| s.pending_mint_requests.insert(k, req));
+                        }
+                    }
+                    ReleaseTokenStatus::Confirmed(txid) => {
+                        req.mint_txid = Some(txid.clone());
+                        match generate_mint_ticket(&req, txid.clone()).await {
+                            Ok(()) => {
+                                log!(INFO, "generated the ticket of the mint {}", k);
+                                req.status = MintStatus::TicketGenerated;
+                                req.ticket_at = ic_cdk::api::time();
+                                req.err_info = None;
+                            }
+                            Err(e) => {
+                                log!(ERROR, "mint generate ticket error: {} {:?}", k, e);
+                                req.err_info = Some(format!("{:?}", e));
+                                req.ticket_attempts += 1;
+                                match mint_holds_runes(&req, &txid).await {
+                                    Ok(Some(false)) => {
+                                        log!(ERROR, "the mint {} holds no runes", k);
+                                        let reason = "the mint transaction holds no runes";
+                                        refund_mint(k, req, reason.to_string()).await;
+                                        continue;
+                                    }
+                                    // The runes held by the deposit address are left to the admin.
+                                    _ if req.ticket_attempts >= MAX_MINT_TICKET_ATTEMPTS => {
+                                        log!(ERROR, "gave up the ticket of the mint {}", k);
+                                        req.status = MintStatus::MintFailed;
+                                        finalize_mint_request(k, req);
+                                        continue;
+                                    }
+                                    _ => {}
+                                }
+                            }
+                        }
+                        mutate_state(|s| s.pending_mint_requests.insert(k, req));
+                    }
+                    ReleaseTokenStatus::Unknown => {
+                        log!(ERROR, "the mint transaction of {} is removed", k);
+                        req.status = MintStatus::MintFailed;
+                        req.err_info = Some("the mint transaction is removed".to_string());
+                        finalize_mint_request(k, req);
+                    }
+                }
+            }
+            MintStatus::TicketGenerated => {
+                let finalized = req
+                    .mint_txid
+                    .as_ref()
+                    .and_then(|txid| Txid::from_str(txid).ok())
+                    .is_some_and(|txid| {
+                        matches!(
+                            read_state(|s| s.generate_ticket_status(txid)),
+                            GenTicketStatus::Finalized(_)
+                        )
+                    });
+                if finalized {
+                    req.status = MintStatus::Final;
+                    finalize_mint_request(k, req);
+                }
+            }
+            MintStatus::MintFailed | MintStatus::Final => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms() -> OrdinalsTerms {
+        OrdinalsTerms {
+            amount: 1000,
+            cap: 10,
+            height: (Some(100), Some(200)),
+            offset: (Some(20), Some(150)),
+        }
+    }
+
+    #[test]
+    fn test_mintable() {
+        // The rune is etched at block 90: mintable from 110 until 200.
+        assert_eq!(terms().mintable(90, 0, 110), Ok(1000));
+        assert_eq!(terms().mintable(90, 9, 199), Ok(1000));
+        assert_eq!(terms().mintable(90, 0, 109), Err(MintError::Start(110)));
+        assert_eq!(terms().mintable(90, 0, 200), Err(MintError::End(200)));
+        assert_eq!(terms().mintable(90, 10, 150), Err(MintError::Cap(10)));
+        // The rune is etched at block 10: mintable from 100 until 160.
+        assert_eq!(terms().mintable(10, 0, 160), Err(MintError::End(160)));
+    }
+
+    #[test]
+    fn test_missing_terms_are_unmintable() {
+        let terms: OrdinalsTerms = RuneTerms {
+            amount: Some(1000),
+            cap: None,
+            height: (None, None),
+            offset: (None, None),
+        }
+        .into();
+        assert_eq!(terms.mintable(90, 0, 100), Err(MintError::Cap(0)));
+    }
+
+    #[test]
+    fn test_holds_runes() {
+        let balance = |rune_id: &str, amount| OutputRuneBalance {
+            confirmations: 1,
+            rune_id: rune_id.to_string(),
+            amount,
+        };
+        assert_eq!(holds_runes("840000:3", &[]), Some(false));
+        assert_eq!(
+            holds_runes(
+                "840000:3",
+                &[Some(vec![]), Some(vec![balance("840000:3", 1000)])]
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            holds_runes("840000:3", &[Some(vec![balance("840000:4", 1000)])]),
+            Some(false)
+        );
+        assert_eq!(
+            holds_runes("840000:3", &[Some(vec![balance("840000:3", 0)])]),
+            Some(false)
+        );
+        // An output not indexed yet may still hold the runes.
+        assert_eq!(holds_runes("840000:3", &[Some(vec![]), None]), None);
+    }
+}
@@ -10,6 +10,7 @@ use ic_stable_structures::Storable;
 use ic_stable_structures::storable::Bound;
 
 use crate::runes_etching::fee_calculator::MAX_LOGO_CONTENT_SIZE;
+use crate::runes_etching::sync::MintError;
 pub use error::{InscriptionParseError, OrdError};
 pub use inscription::iid::InscriptionId;
 pub use inscription::nft::Nft;
@@ -22,12 +23,13 @@ pub use wallet::{
     SignCommitTransactionArgs, Utxo, Wallet,
 };
 use crate::runes_etching::transactions::{SendEtchingInfo, SendEtchingRequest};
-use crate::runes_etching::transactions::EtchingStatus::Initial;
+use crate::runes_etching::transactions::EtchingStatus::Stashed;
 
 pub mod error;
 pub mod fee_calculator;
 pub mod icp_swap;
 pub mod inscription;
+pub mod mint;
 pub mod result;
 pub mod sync;
 pub mod transactions;
@@ -61,6 +63,46 @@ impl OrdinalsTerms {
         }
         Ok(())
     }
+
+    /// Returns the amount of a mint in the block at `height`, following the
+    /// rules of ord for a rune etched in `etching_block` and minted `mints` times.
+    pub fn mintable(
+        &self,
+        etching_block: u64,
+        mints: u128,
+        height: u64,
+    ) -> Result<u128, MintError> {
+        let start = [
+            self.height.0,
+            self.offset.0.map(|offset| offset.saturating_add(etching_block)),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+        if let Some(start) = start {
+            if height < start {
+                return Err(MintError::Start(start));
+            }
+        }
+
+        let end = [
+            self.height.1,
+            self.offset.1.map(|offset| offset.saturating_add(etching_block)),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if let Some(end) = end {
+            if height >= end {
+                return Err(MintError::End(end));
+            }
+        }
+
+        if mints >= self.cap {
+            return Err(MintError::Cap(self.cap));
+        }
+        Ok(self.amount)
+    }
 }
 
 impl EtchingArgs {
@@ -103,6 +145,9 @@ pub struct InternalEtchingArgs {
     pub symbol: Option<String>,
     pub terms: Option<OrdinalsTerms>,
     pub turbo: bool,
+    /// The ICP fee collected from the user, refunded if the etching fails.
+    #[serde(default)]
+    pub fee_amount: u128,
 }
 
 impl Into<SendEtchingInfo> for InternalEtchingArgs {
@@ -114,7 +159,7 @@ impl Into<SendEtchingInfo> for InternalEtchingArgs {
             err_info: "".to_string(),
             time_at: ic_cdk::api::time(),
             script_out_address: "".to_string(),
-            status: Initial,
+            status: Stashed,
             receiver: self.premine_receiver_principal.clone(),
         }
     }
@@ -165,6 +210,7 @@ impl Into<InternalEtchingArgs> for (EtchingArgs, Principal) {
             symbol: args.symbol,
             terms: args.terms,
             turbo: args.turbo,
+            fee_amount: 0,
         }
     }
 }
@@ -1,8 +1,8 @@
 use std::collections::{BTreeMap, HashMap};
 use std::time::Duration;
 
+use bitcoin::Amount;
 use candid::CandidType;
-use ic_btc_interface::Network;
 use ic_canister_log::log;
 use runes_indexer_interface::GetEtchingResult;
 use serde::Deserialize;
@@ -13,14 +13,14 @@ use omnity_types::ic_log::{ERROR, INFO};
 
 use crate::call_error::{CallError, Reason};
 use crate::hub::execute_proposal;
-use crate::management::{get_bitcoin_balance, CallSource};
-use crate::runes_etching::transactions::EtchingStatus::{
-    Final, SendCommitFailed, SendRevealFailed, SendRevealSuccess,Initial
+use crate::management::{get_bitcoin_balance, get_utxos, CallSource};
+use crate::runes_etching::topup::topup;
+use crate::runes_etching::transactions::{
+    finalize_etching, release_commit_utxos, EtchingStatus, SendEtchingRequest,
 };
-use crate::runes_etching::transactions::{EtchingStatus, SendEtchingRequest};
-use crate::runes_etching::InternalEtchingArgs;
+use crate::runes_etching::{InternalEtchingArgs, Utxo};
 use crate::state::{mutate_state, read_state};
-use crate::updates::generate_ticket::{GenerateTicketArgs};
+use crate::updates::generate_ticket::{GenerateTicketArgs, GenerateTicketError};
 use crate::{finalization_time_estimate, state, updates};
 
 pub async fn get_etching(txid: &str) -> Result<Option<GetEtchingResult>, CallError> {
@@ -104,13 +104,101 @@ pub enum MintError {
     Unmintable,
 }
 
-fn check_time(confirmation_blocks: u32, req_time: u64) -> bool {
-    let now = ic_cdk::api::time();
+/// The time after which the customs checks whether an unconfirmed commit transaction
+/// was double-spent, and after which an unconfirmed reveal transaction is sent again.
+const ETCHING_TIMEOUT: Duration = Duration::from_secs(48 * 3600);
+
+/// The time after which an unconfirmed commit transaction whose inputs are unspent
+/// is considered evicted, the default mempool expiry is two weeks.
+const COMMIT_EVICTION_TIMEOUT: Duration = Duration::from_secs(15 * 24 * 3600);
+
+fn is_due(confirmation_blocks: u32, req_time: u64) -> bool {
     let network = read_state(|s| s.btc_network);
     let wait_time = finalization_time_estimate(confirmation_blocks, network);
-    let check_timeline = req_time + (wait_time.as_nanos() as u64);
-    let check_time_window = Duration::from_secs(21600).as_nanos() as u64;
-    check_timeline < now && now < check_timeline + check_time_window
+    req_time + (wait_time.as_nanos() as u64) < ic_cdk::api::time()
+}
+
+fn is_timed_out(sent_at: u64, timeout: Duration) -> bool {
+    sent_at + (timeout.as_nanos() as u64) < ic_cdk::api::time()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CommitOutcome {
+    /// The commit transaction may still be confirmed.
+    Pending,
+    /// The inputs of the commit transaction are unspent long after it was sent.
+    Evicted(Vec<Utxo>),
+    /// An input of the commit transaction is spent but its output doesn't exist,
+    /// the other inputs are unspent.
+    DoubleSpent(Vec<Utxo>),
+}
+
+/// Checks whether the unconfirmed commit transaction of the etching can still be confirmed.
+/// The inputs are fetched before the commit output, so that a commit confirmed between
+/// the two calls is never taken for a double-spend.
+async fn commit_outcome(req: &SendEtchingRequest) -> Result<CommitOutcome, CallError> {
+    let network = read_state(|s| s.btc_network);
+    let fee_address = read_state(|s| s.etching_acount_info.address.clone());
+    let fee_utxos = get_utxos(network, &fee_address, 1, CallSource::Custom)
+        .await?
+        .utxos;
+    let commit_balance =
+        get_bitcoin_balance(network, &req.script_out_address, 1, CallSource::Custom).await?;
+    if commit_balance > 0 {
+        return Ok(CommitOutcome::Pending);
+    }
+    // The change of an earlier commit is spent before that commit is confirmed.
+    let unconfirmed_commits: Vec<_> = read_state(|s| {
+        s.pending_etching_requests
+            .iter()
+            .filter(|(_, r)| r.status == EtchingStatus::CommitSent)
+            .filter_map(|(_, r)| r.txs.first().map(|tx| tx.txid()))
+            .collect()
+    });
+    let mut unspent_inputs = vec![];
+    for input in &req.txs[0].input {
+        let prevout = input.previous_output;
+        if unconfirmed_commits.contains(&prevout.txid) {
+            return Ok(CommitOutcome::Pending);
+        }
+        if let Some(utxo) = fee_utxos.iter().find(|utxo| {
+            utxo.outpoint.vout == prevout.vout
+                && utxo.outpoint.txid.to_string() == prevout.txid.to_string()
+        }) {
+            unspent_inputs.push(Utxo {
+                id: prevout.txid,
+                index: prevout.vout,
+                amount: Amount::from_sat(utxo.value),
+            });
+        }
+    }
+    if unspent_inputs.len() < req.txs[0].input.len() {
+        Ok(CommitOutcome::DoubleSpent(unspent_inputs))
+    } else if is_timed_out(req.commit_at, COMMIT_EVICTION_TIMEOUT) {
+        Ok(CommitOutcome::Evicted(unspent_inputs))
+    } else {
+        Ok(CommitOutcome::Pending)
+    }
+}
+
+/// Sends the reveal transaction again while the commit output is unspent, otherwise
+/// the reveal is confirmed but the indexer doesn't know the etching, so it fails.
+async fn resend_or_fail_reveal(k: String, mut req: SendEtchingRequest) {
+    let network = read_state(|s| s.btc_network);
+    match get_bitcoin_balance(network, &req.script_out_address, 1, CallSource::Custom).await {
+        Ok(0) => fail_etching(k, req, "the etching is not found by the indexer".into()),
+        Ok(_) => {
+            if let Err(e) = crate::management::send_etching(&req.txs[1]).await {
+                log!(ERROR, "resend etching reveal error: {}", e.to_string());
+                req.reason = Some(e.to_string());
+            } else {
+                req.reason = None;
+            }
+            req.reveal_at = ic_cdk::api::time();
+            mutate_state(|s| s.pending_etching_requests.insert(k, req));
+        }
+        Err(e) => log!(ERROR, "failed to check the reveal of {}: {}", k, e),
+    }
 }
 
 pub async fn handle_etching_result_task() {
@@ -124,12 +212,32 @@ pub async fn handle_etching_result_task() {
     });
     for (k, mut req) in kvs {
         match req.status.clone() {
-            EtchingStatus::SendCommitSuccess => {
-                if !check_time(4, req.commit_at) {
+            EtchingStatus::CommitSent => {
+                if !is_due(4, req.commit_at) {
                     continue;
                 }
+                if is_timed_out(req.commit_at, ETCHING_TIMEOUT) {
+                    match commit_outcome(&req).await {
+                        Ok(CommitOutcome::Pending) => {}
+                        Ok(CommitOutcome::Evicted(inputs)) => {
+                            release_commit_utxos(&req, inputs);
+                            fail_etching(k, req, "the commit transaction is evicted".into());
+                            continue;
+                        }
+                        Ok(CommitOutcome::DoubleSpent(inputs)) => {
+                            release_commit_utxos(&req, inputs);
+                            fail_etching(k, req, "the commit transaction is double-spent".into());
+                            continue;
+                        }
+                        Err(e) => {
+                            log!(ERROR, "failed to check the commit of {}: {}", k, e);
+                            continue;
+                        }
+                    }
+                }
+                let network = read_state(|s| s.btc_network);
                 let balance = get_bitcoin_balance(
-                    Network::Mainnet,
+                    network,
                     &req.script_out_address,
                     6,
                     CallSource::Custom,
@@ -139,50 +247,37 @@ pub async fn handle_etching_result_task() {
                 if balance == 0 {
                     continue;
                 }
-                let r = crate::management::send_etching(&req.txs[1]).await;
-                if r.is_err() {
-                    req.status = SendRevealFailed;
-                    req.err_info = r.err();
-                } else {
-                    req.status = SendRevealSuccess
+                match crate::management::send_etching(&req.txs[1]).await {
+                    Ok(_) => {
+                        req.status = EtchingStatus::RevealSent;
+                        req.reason = None;
+                        req.reveal_at = ic_cdk::api::time();
+                    }
+                    Err(e) => {
+                        log!(ERROR, "send etching reveal error: {}", e.to_string());
+                        req.reason = Some(e.to_string());
+                    }
                 }
-                req.reveal_at = ic_cdk::api::time();
                 mutate_state(|s| s.pending_etching_requests.insert(k, req));
             }
-            EtchingStatus::SendRevealSuccess => {
-                if !check_time(1, req.reveal_at) {
+            EtchingStatus::RevealSent => {
+                if !is_due(1, req.reveal_at) {
                     continue;
                 }
                 //query etching,
                 let tx = req.txs[1].txid().to_string();
-                let rune = get_etching(tx.as_str()).await;
-                match rune {
-                    Ok(resp_opt) => {
-                        match resp_opt {
-                            None => {
-                            }
-                            Some(resp) => {
-                                log!(INFO, "Etching result:  {}.{}, {}",tx, resp.rune_id.clone(),resp.confirmations);
-                                if resp.confirmations >= 1 {
-                                    let r = send_add_token(
-                                        req.etching_args.clone(),
-                                        resp.rune_id.clone(),
-                                        tx.as_str(),
-                                    )
-                                        .await;
-                                    match r {
-                                        Ok(_) => {
-                                            req.status = EtchingStatus::TokenAdded;
-                                            mutate_state(|s| s.pending_etching_requests.insert(k.clone(), req));
-                                        }
-                                        Err(e) => {
-                                            log!(ERROR, "send add token error: {}", e.to_string());
-                                            // do nothing
-                                        }
-                                    }
-                                }
-
-                            }
+                match get_etching(tx.as_str()).await {
+                    Ok(Some(resp)) => {
+                        log!(INFO, "Etching result:  {}.{}, {}",tx, resp.rune_id.clone(),resp.confirmations);
+                        if resp.confirmations >= 1 {
+                            req.status = EtchingStatus::Confirmed;
+                            req.rune_id = Some(resp.rune_id);
+                            mutate_state(|s| s.pending_etching_requests.insert(k, req));
+                        }
+                    }
+                    Ok(None) => {
+                        if is_timed_out(req.reveal_at, ETCHING_TIMEOUT) {
+                            resend_or_fail_reveal(k, req).await;
                         }
                     }
                     Err(e) => {
@@ -190,51 +285,77 @@ pub async fn handle_etching_result_task() {
                     }
                 }
             }
-            EtchingStatus::TokenAdded => {
-                //generate_ticket
-                if let Some(t) = read_state(|s| s.tokens.get(&req.etching_args.token_id).cloned()) {
-                    match req.etching_args.premine {
-                        None => {
-                            mutate_state(|s| {
-                                req.status = Final;
-                                s.finalized_etching_requests.insert(k.clone(), req);
-                                mutate_state(|s| s.pending_etching_requests.remove(&k));
-                            });
-                        }
-                        Some(premine) => {
-                            let generate_ticket_args = GenerateTicketArgs {
-                                target_chain_id: req.etching_args.target_chain_id.clone(),
-                                receiver: req.etching_args.premine_receiver_principal.clone(),
-                                rune_id: format!("{}", t.0),
-                                amount: premine,
-                                txid: req.txs[1].txid().to_string(),
-                            };
-                            log!(INFO, "etching generate ticket params: {:?}", generate_ticket_args);
-                            match updates::generate_ticket::generate_ticket(
-                                generate_ticket_args,
-                                Some(req.reveal_at),
-                            )
-                            .await
-                            {
-                                Ok(_) => {
-                                    mutate_state(|s| {
-                                        req.status = Final;
-                                        s.finalized_etching_requests.insert(k.clone(), req);
-                                    });
-                                    mutate_state(|s| s.pending_etching_requests.remove(&k));
-                                }
-                                Err(e) => {
-                                    log!(INFO, "etching generate ticket error: {:?}", e);
-                                }
-                            }
-                        }
-                    }
-
+            // The etchings of the earlier versions stay pending in TokenAdded
+            // once the token is proposed.
+            EtchingStatus::Confirmed | EtchingStatus::TokenAdded => {
+                if req.status == EtchingStatus::TokenAdded {
+                    req.token_proposed = true;
                 }
+                add_etched_token(k, req).await;
+            }
+            EtchingStatus::Failed => {
+                crate::runes_etching::transactions::refund_etching(k, req).await;
             }
-            EtchingStatus::Final | SendCommitFailed | SendRevealFailed | Initial=> {}
+            EtchingStatus::Stashed | EtchingStatus::Refunded => {}
         }
     }
 }
 
-
+/// Adds the token of the etched rune and bridges the premine to the user.
+async fn add_etched_token(k: String, mut req: SendEtchingRequest) {
+    let reveal_txid = req.txs[1].txid().to_string();
+    let token = read_state(|s| s.tokens.get(&req.etching_args.token_id).cloned());
+    let t = match token {
+        Some(t) => t,
+        None => {
+            if req.token_proposed {
+                return;
+            }
+            let rune_id = match req.rune_id.clone() {
+                Some(rune_id) => rune_id,
+                None => return,
+            };
+            match send_add_token(req.etching_args.clone(), rune_id, reveal_txid.as_str()).await {
+                Ok(_) => {
+                    req.token_proposed = true;
+                    mutate_state(|s| s.pending_etching_requests.insert(k, req));
+                }
+                Err(e) => {
+                    log!(ERROR, "send add token error: {}", e.to_string());
+                }
+            }
+            return;
+        }
+    };
+    if let Some(premine) = req.etching_args.premine {
+        let generate_ticket_args = GenerateTicketArgs {
+            target_chain_id: req.etching_args.target_chain_id.clone(),
+            receiver: req.etching_args.premine_receiver_principal.clone(),
+            rune_id: format!("{}", t.0),
+            amount: premine,
+            txid: reveal_txid,
+            address_type: None,
+        };
+        log!(INFO, "etching generate ticket params: {:?}", generate_ticket_args);
+        match updates::generate_ticket::generate_ticket(generate_ticket_args, Some(req.reveal_at))
+            .await
+        {
+            Ok(_)
+            | Err(GenerateTicketError::AlreadySubmitted)
+            | Err(GenerateTicketError::AlreadyProcessed) => {}
+            Err(e) => {
+                log!(INFO, "etching generate ticket error: {:?}", e);
+                return;
+            }
+        }
+    }
+    req.status = EtchingStatus::TokenAdded;
+    req.reason = None;
+    let fee_amount = req.etching_args.fee_amount;
+    finalize_etching(k, req);
+    // The fee is kept until the etching completes so that it can be refunded.
+    if fee_amount > 0 {
+        let r = topup(fee_amount as u64).await;
+        log!(INFO, "etching topup result:{:?}", r);
+    }
+}
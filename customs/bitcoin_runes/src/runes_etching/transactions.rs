@@ -4,30 +4,29 @@ use std::str::FromStr;
 
 use anyhow::anyhow;
 use bitcoin::{Address, Amount, PublicKey, Transaction, Txid};
-use candid::{CandidType, Deserialize};
+use candid::{CandidType, Deserialize, Principal};
 use ic_canister_log::log;
 use ic_cdk::caller;
 use ic_stable_structures::storable::Bound;
 use ic_stable_structures::Storable;
-use omnity_types::ic_log::INFO;
+use omnity_types::ic_log::{ERROR, INFO};
 use ordinals::{Etching, Rune, SpacedRune, Terms};
 use serde::Serialize;
 
-use crate::call_error::{CallError, Reason};
+use crate::call_error::CallError;
 use crate::runes_etching::constants::POSTAGE;
 use crate::runes_etching::fee_calculator::{
-    check_allowance, select_utxos, transfer_etching_fees, FIXED_COMMIT_TX_VBYTES, INPUT_SIZE_VBYTES,
+    check_allowance, refund_etching_fees, select_utxos, transfer_etching_fees,
+    FIXED_COMMIT_TX_VBYTES, ICP_LEDGER_CANISTER_ID, INPUT_SIZE_VBYTES,
 };
 use crate::runes_etching::fees::Fees;
 use crate::runes_etching::icp_swap::estimate_etching_fee;
-use crate::runes_etching::transactions::EtchingStatus::{SendCommitFailed, SendCommitSuccess};
 use crate::runes_etching::wallet::builder::{EtchingKey, EtchingTransactionArgs};
 use crate::runes_etching::wallet::{CreateCommitTransactionArgsV2, Runestone};
 use crate::runes_etching::{
     EtchingArgs, InternalEtchingArgs, LogoParams, Nft, OrdResult, OrdTransactionBuilder,
     SignCommitTransactionArgs, Utxo,
 };
-use crate::runes_etching::topup::topup;
 use crate::state::{mutate_state, read_state};
 use crate::updates::etching::init_etching_account_info;
 use crate::updates::get_btc_address::GetBtcAddressArgs;
@@ -41,20 +40,70 @@ pub struct SendEtchingRequest {
     pub reveal_at: u64,
     pub script_out_address: String,
     pub status: EtchingStatus,
+    /// The id of the etched rune, known once the reveal transaction is confirmed.
+    #[serde(default)]
+    pub rune_id: Option<String>,
+    /// Whether the customs proposed to add the token of the rune to the hub.
+    #[serde(default)]
+    pub token_proposed: bool,
+    /// The reason of the last failure of the etching.
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub stashed_at: u64,
+    /// The number of the attempts to build the commit transaction that failed.
+    #[serde(default)]
+    pub commit_attempts: u32,
+}
+
+impl SendEtchingRequest {
+    pub fn stashed(etching_args: InternalEtchingArgs) -> Self {
+        SendEtchingRequest {
+            etching_args,
+            txs: vec![],
+            err_info: None,
+            commit_at: 0,
+            reveal_at: 0,
+            script_out_address: "".to_string(),
+            status: EtchingStatus::Stashed,
+            rune_id: None,
+            token_proposed: false,
+            reason: None,
+            stashed_at: ic_cdk::api::time(),
+            commit_attempts: 0,
+        }
+    }
+
+    /// Returns the reason of the last failure, if any.
+    pub fn err_info(&self) -> String {
+        match (&self.reason, &self.err_info) {
+            (Some(reason), _) => reason.clone(),
+            (None, Some(e)) => e.to_string(),
+            (None, None) => "".to_string(),
+        }
+    }
 }
 
 impl From<SendEtchingRequest> for SendEtchingInfo {
     fn from(value: SendEtchingRequest) -> Self {
-        let err_info = match value.err_info {
-            None => "".to_string(),
-            Some(e) => e.to_string(),
-        };
         SendEtchingInfo {
             etching_args: value.etching_args.clone().into(),
-            err_info,
-            commit_txid: value.txs[0].txid().to_string(),
-            reveal_txid: value.txs[1].txid().to_string(),
-            time_at: value.commit_at,
+            err_info: value.err_info(),
+            commit_txid: value
+                .txs
+                .first()
+                .map(|tx| tx.txid().to_string())
+                .unwrap_or_default(),
+            reveal_txid: value
+                .txs
+                .get(1)
+                .map(|tx| tx.txid().to_string())
+                .unwrap_or_default(),
+            time_at: if value.commit_at > 0 {
+                value.commit_at
+            } else {
+                value.stashed_at
+            },
             script_out_address: value.script_out_address,
             status: value.status,
             receiver: value.etching_args.premine_receiver_principal,
@@ -89,15 +138,37 @@ impl Storable for SendEtchingRequest {
     const BOUND: Bound = Bound::Unbounded;
 }
 
+/// The state of an etching. The aliases decode the statuses of the earlier versions.
+///
+/// Stashed -> CommitSent -> RevealSent -> Confirmed -> TokenAdded,
+/// or Failed -> Refunded if the etching fails or the user cancels it.
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, CandidType)]
 pub enum EtchingStatus {
-    Initial,
-    SendCommitSuccess,
-    SendCommitFailed,
-    SendRevealSuccess,
-    SendRevealFailed,
+    /// The fee is collected and the etching waits in the queue, it can be cancelled.
+    #[serde(alias = "Initial")]
+    Stashed,
+    /// The commit transaction is sent.
+    #[serde(alias = "SendCommitSuccess")]
+    CommitSent,
+    /// The reveal transaction is sent.
+    #[serde(alias = "SendRevealSuccess")]
+    RevealSent,
+    /// The runes indexer found the etching, the token is being added.
+    Confirmed,
+    /// The token is added and the premine is bridged.
+    #[serde(alias = "Final")]
     TokenAdded,
-    Final,
+    /// The etching failed, the fee is being refunded.
+    #[serde(alias = "SendCommitFailed", alias = "SendRevealFailed")]
+    Failed,
+    /// The fee is refunded to the user.
+    Refunded,
+}
+
+impl EtchingStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, EtchingStatus::TokenAdded | EtchingStatus::Refunded)
+    }
 }
 
 pub fn find_commit_remain_fee(t: &Transaction) -> Option<Utxo> {
@@ -114,67 +185,25 @@ pub fn find_commit_remain_fee(t: &Transaction) -> Option<Utxo> {
     }
 }
 
-pub async fn etching_rune(
-    fee_rate: u64,
-    args: &InternalEtchingArgs,
-) -> anyhow::Result<(SendEtchingRequest, u64)> {
-    let (commit_tx_size, reveal_size) =
-        estimate_tx_vbytes(args.rune_name.as_str(), args.logo.clone()).await?;
-    let icp_fee_amt = estimate_etching_fee(fee_rate, (commit_tx_size + reveal_size) as u128)
-        .await
-        .map_err(|e| anyhow!(e))?;
-    let allowance = check_allowance(icp_fee_amt as u64).await?;
-    let vins = select_utxos(fee_rate, reveal_size as u64 + FIXED_COMMIT_TX_VBYTES)?;
-    log!(INFO, "selected fee utxos: {:?}", vins);
-    let commit_size = vins.len() as u64 * INPUT_SIZE_VBYTES + FIXED_COMMIT_TX_VBYTES;
-    let fee = Fees {
-        commit_fee: Amount::from_sat(commit_size * fee_rate),
-        reveal_fee: Amount::from_sat(reveal_size as u64 * fee_rate + POSTAGE * 2),
-    };
-    let result = generate_etching_transactions(fee, vins.clone(), args)
-        .await
-        .map_err(|e| {
-            mutate_state(|s| {
-                for in_utxo in vins.clone() {
-                    s.etching_fee_utxos
-                        .push(&in_utxo)
-                        .expect("retire utxo failed");
-                }
-            });
-            e
-        })?;
-    let mut send_res = SendEtchingRequest {
-        etching_args: args.clone(),
-        txs: result.txs.clone(),
-        err_info: None,
-        commit_at: ic_cdk::api::time(),
-        reveal_at: 0,
-        script_out_address: result.script_out_address.clone(),
-        status: SendCommitSuccess,
-    };
-    if let Err(e) = crate::management::send_etching(&result.txs[0]).await {
-        send_res.status = SendCommitFailed;
-        send_res.err_info = Some(e);
-    }
-    //修改fee utxo列表
-    if send_res.status == SendCommitSuccess {
-        //insert_utxo
-        if let Some(u) = find_commit_remain_fee(&send_res.txs.first().cloned().unwrap()) {
-            let _ = mutate_state(|s| s.etching_fee_utxos.push(&u));
-        }
-    } else {
-        mutate_state(|s| {
-            for in_utxo in vins {
-                s.etching_fee_utxos
-                    .push(&in_utxo)
-                    .expect("retire utxo failed1");
+/// Removes the change of the commit transaction that can't be confirmed anymore from
+/// the fee UTXOs, and returns its unspent inputs to them.
+pub fn release_commit_utxos(req: &SendEtchingRequest, unspent_inputs: Vec<Utxo>) {
+    let change = req.txs.first().and_then(find_commit_remain_fee);
+    mutate_state(|s| {
+        let mut utxos = vec![];
+        while let Some(utxo) = s.etching_fee_utxos.pop() {
+            if Some(&utxo) != change.as_ref() {
+                utxos.push(utxo);
             }
-        });
-    }
-    Ok((send_res, allowance))
+        }
+        for utxo in utxos.iter().rev().chain(unspent_inputs.iter()) {
+            s.etching_fee_utxos
+                .push(utxo)
+                .expect("failed to release the commit utxos");
+        }
+    });
 }
 
-
 pub async fn etching_rune_v2(
     fee_rate: u64,
     args: &InternalEtchingArgs,
@@ -207,14 +236,19 @@ pub async fn etching_rune_v2(
         commit_at: ic_cdk::api::time(),
         reveal_at: 0,
         script_out_address: result.script_out_address.clone(),
-        status: SendCommitSuccess,
+        status: EtchingStatus::CommitSent,
+        rune_id: None,
+        token_proposed: false,
+        reason: None,
+        stashed_at: 0,
+        commit_attempts: 0,
     };
     if let Err(e) = crate::management::send_etching(&result.txs[0]).await {
-        send_res.status = SendCommitFailed;
+        send_res.status = EtchingStatus::Failed;
         send_res.err_info = Some(e);
     }
     //修改fee utxo列表
-    if send_res.status == SendCommitSuccess {
+    if send_res.status == EtchingStatus::CommitSent {
         //insert_utxo
         if let Some(u) = find_commit_remain_fee(&send_res.txs.first().cloned().unwrap()) {
             let _ = mutate_state(|s| s.etching_fee_utxos.push(&u));
@@ -312,6 +346,7 @@ pub async fn generate_etching_transactions(
     let get_btc_address_args = GetBtcAddressArgs {
         target_chain_id: "eICP".to_string(),
         receiver: args.premine_receiver_principal.to_string(),
+        address_type: None,
     };
     let receipient = crate::updates::get_btc_address::get_btc_address(get_btc_address_args).await;
     let receipient = Address::from_str(receipient.as_str())
@@ -422,7 +457,7 @@ pub async fn estimate_tx_vbytes(
     Ok((commit_tx.unsigned_tx.vsize(), reveal_transaction.vsize()))
 }
 
-
+/// Collects the etching fee and queues the etching, returns the key of the etching.
 pub async fn stash_etching(fee_rate: u64, args: EtchingArgs) -> Result<String, String> {
     let space_rune = SpacedRune::from_str(args.rune_name.as_str()).map_err(|e| e.to_string())?;
     check_name_duplication(space_rune.rune)?;
@@ -430,49 +465,112 @@ pub async fn stash_etching(fee_rate: u64, args: EtchingArgs) -> Result<String, S
     args.check().map_err(|e| e.to_string())?;
     let (commit_tx_size, reveal_size) =
         estimate_tx_vbytes(args.rune_name.as_str(), args.logo.clone()).await.map_err(|e|e.to_string())?;
-   let icp_fee_amt = estimate_etching_fee(fee_rate, (commit_tx_size + reveal_size) as u128)
+    let icp_fee_amt = estimate_etching_fee(fee_rate, (commit_tx_size + reveal_size) as u128)
         .await
         .map_err(|e| e.to_string())?;
-    let allowance = check_allowance(icp_fee_amt as u64).await.map_err(|e|e.to_string())?;
-    let _ = transfer_etching_fees(allowance as u128).await.map_err(|e|e.to_string())?;
-    let r = topup(allowance).await;
-    log!(INFO, "etching topup result:{:?}", r);
-    let internal_args: InternalEtchingArgs = (args.clone(), caller).into();
+    let icp_ledger = Principal::from_str(ICP_LEDGER_CANISTER_ID).unwrap();
+    let allowance = check_allowance(icp_ledger, icp_fee_amt).await.map_err(|e|e.to_string())?;
+    let fee_amount = transfer_etching_fees(allowance).await.map_err(|e|e.to_string())?;
+    let mut internal_args: InternalEtchingArgs = (args.clone(), caller).into();
+    internal_args.fee_amount = fee_amount;
     let etching_key = format!("Bitcoin-runes-{}", args.rune_name);
     mutate_state(|s|{
-        s.stash_etchings.insert(etching_key.clone(), internal_args);
-        s.stash_etching_ids.push(&EtchingKey::new(etching_key.clone()));
+        s.pending_etching_requests
+            .insert(etching_key.clone(), SendEtchingRequest::stashed(internal_args));
+        s.stash_etching_ids.push(&EtchingKey::new(etching_key.clone()))
+            .expect("failed to queue the etching");
     });
     Ok(etching_key)
 }
 
-pub async fn internal_etching(fee_rate: u64, args: EtchingArgs) -> Result<String, String> {
-    let space_rune = SpacedRune::from_str(args.rune_name.as_str()).map_err(|e| e.to_string())?;
-    check_name_duplication(space_rune.rune)?;
-    let caller = caller();
-    args.check().map_err(|e| e.to_string())?;
-    let internal_args: InternalEtchingArgs = (args, caller).into();
-    let r = etching_rune(fee_rate, &internal_args).await;
-    match r {
-        Ok((sr, allowance)) => {
-            if sr.status == SendCommitSuccess {
-                let commit_tx_id = sr.txs[0].txid().to_string();
-                mutate_state(|s| s.pending_etching_requests.insert(commit_tx_id.clone(), sr));
-                let r = transfer_etching_fees(allowance as u128).await;
-                log!(INFO, "transfer etching fee result: {:?}", r);
-                Ok(commit_tx_id)
-            } else {
-                Err(sr
-                    .err_info
-                    .unwrap_or(CallError {
-                        method: "send commit tx".to_string(),
-                        reason: Reason::QueueIsFull,
-                    })
-                    .to_string())
-            }
+/// Cancels an etching of the caller that is still in the queue,
+/// its fee is refunded by the etching task.
+pub fn cancel_etching(etching_key: String) -> Result<(), String> {
+    let mut req = read_state(|s| s.pending_etching_requests.get(&etching_key))
+        .ok_or("etching not found".to_string())?;
+    if req.etching_args.premine_receiver_principal != caller().to_text() {
+        return Err("only the creator can cancel the etching".to_string());
+    }
+    if req.status != EtchingStatus::Stashed
+        || read_state(|s| s.committing_etching.as_ref() == Some(&etching_key))
+    {
+        return Err(format!(
+            "the etching can not be cancelled in status {:?}",
+            req.status
+        ));
+    }
+    req.status = EtchingStatus::Failed;
+    req.reason = Some("cancelled by the user".to_string());
+    mutate_state(|s| s.pending_etching_requests.insert(etching_key.clone(), req));
+    log!(INFO, "the etching {} is cancelled by the user", etching_key);
+    Ok(())
+}
+
+/// Refunds the fee of a failed etching, the etching stays failed and is refunded
+/// again later if the refund fails.
+pub async fn refund_etching(etching_key: String, mut req: SendEtchingRequest) {
+    let payer = match Principal::from_text(&req.etching_args.premine_receiver_principal) {
+        Ok(payer) => payer,
+        Err(e) => {
+            log!(ERROR, "invalid etching fee payer {}: {}", etching_key, e);
+            return finalize_etching(etching_key, req);
         }
-        Err(e) => Err(e.to_string()),
+    };
+    // The etchings of the earlier versions don't record the fee.
+    if req.etching_args.fee_amount == 0 {
+        return finalize_etching(etching_key, req);
     }
+    match refund_etching_fees(payer, req.etching_args.fee_amount).await {
+        Ok(()) => {
+            log!(INFO, "refunded the etching fee of {}", etching_key);
+            req.status = EtchingStatus::Refunded;
+            finalize_etching(etching_key, req);
+        }
+        Err(e) => {
+            log!(ERROR, "failed to refund the etching fee of {}: {}", etching_key, e);
+        }
+    }
+}
+
+/// Moves the etching to the finalized etchings.
+pub fn finalize_etching(etching_key: String, req: SendEtchingRequest) {
+    mutate_state(|s| {
+        s.pending_etching_requests.remove(&etching_key);
+        s.finalized_etching_requests.insert(etching_key, req);
+    });
+}
+
+/// Removes the etchings that are not stashed anymore from the queue, keeping the order.
+pub fn compact_stash_etching_ids() {
+    mutate_state(|s| {
+        let mut ids = vec![];
+        while let Some(id) = s.stash_etching_ids.pop() {
+            let stashed = s
+                .pending_etching_requests
+                .get(&id.content)
+                .is_some_and(|req| req.status == EtchingStatus::Stashed);
+            if stashed {
+                ids.push(id);
+            }
+        }
+        for id in ids.iter().rev() {
+            s.stash_etching_ids
+                .push(id)
+                .expect("failed to queue the etching");
+        }
+    });
+}
+
+/// Moves the etchings stashed by the earlier versions to the pending etchings.
+pub fn migrate_stash_etchings() {
+    mutate_state(|s| {
+        let stashed: Vec<_> = s.stash_etchings.iter().collect();
+        for (etching_key, args) in stashed {
+            s.stash_etchings.remove(&etching_key);
+            s.pending_etching_requests
+                .insert(etching_key, SendEtchingRequest::stashed(args));
+        }
+    });
 }
 
 pub fn check_name_duplication(rune: Rune) -> Result<(), String> {
@@ -488,6 +586,10 @@ pub fn check_name_duplication(rune: Rune) -> Result<(), String> {
     });
     kvs.append(&mut kvs1);
     for (_k, v) in kvs {
+        // The name of a failed etching can be etched again.
+        if matches!(v.status, EtchingStatus::Failed | EtchingStatus::Refunded) {
+            continue;
+        }
         let space_rune = SpacedRune::from_str(v.etching_args.rune_name.as_str()).unwrap();
         if space_rune.rune == rune {
             return Err("the rune name is already etching".to_string());
@@ -498,7 +600,7 @@ pub fn check_name_duplication(rune: Rune) -> Result<(), String> {
 
 #[cfg(test)]
 mod test {
-    use crate::runes_etching::transactions::estimate_tx_vbytes;
+    use crate::runes_etching::transactions::{estimate_tx_vbytes, EtchingStatus};
     use crate::runes_etching::LogoParams;
 
     #[tokio::test]
@@ -513,4 +615,21 @@ mod test {
         let r = estimate_tx_vbytes(rune_name, Some(logo)).await.unwrap();
         println!("{} {}", r.0, r.1);
     }
+
+    #[test]
+    fn test_decode_legacy_etching_status() {
+        let decode = |status: &str| -> EtchingStatus {
+            let mut bytes = vec![];
+            ciborium::ser::into_writer(&status, &mut bytes).unwrap();
+            ciborium::de::from_reader(bytes.as_slice()).unwrap()
+        };
+        assert_eq!(decode("Initial"), EtchingStatus::Stashed);
+        assert_eq!(decode("SendCommitSuccess"), EtchingStatus::CommitSent);
+        assert_eq!(decode("SendRevealSuccess"), EtchingStatus::RevealSent);
+        assert_eq!(decode("SendCommitFailed"), EtchingStatus::Failed);
+        assert_eq!(decode("SendRevealFailed"), EtchingStatus::Failed);
+        assert_eq!(decode("Final"), EtchingStatus::TokenAdded);
+        assert!(decode("Refunded").is_terminal());
+        assert!(!decode("Confirmed").is_terminal());
+    }
 }
@@ -1,5 +1,11 @@
+pub mod allocation;
+pub mod cenotaph;
+pub mod etching;
+mod flag;
+mod message;
 pub mod runestone;
 mod tag;
 mod varint;
 
-pub use runestone::{Edict, Runestone};
+pub use allocation::OutputAllocation;
+pub use runestone::{Artifact, Edict, Runestone};
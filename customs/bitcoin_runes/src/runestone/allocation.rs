@@ -0,0 +1,166 @@
+use bitcoin::TxOut;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use omnity_types::rune_id::RuneId;
+
+use super::runestone::Artifact;
+
+/// The runes an output of the deposit transaction can receive according to its runestone.
+/// The rune balances of the inputs are unknown to the customs, so the amount of an edict
+/// is only the upper bound of what the output receives.
+#[derive(CandidType, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputAllocation {
+    pub vout: u32,
+    // the output receives the unallocated runes, of which the amount is unknown
+    pub default_output: bool,
+    // the amount allocated by each edict, `None` if the edict allocates all the remaining runes
+    pub edicts: Vec<(RuneId, Option<u128>)>,
+}
+
+impl OutputAllocation {
+    pub fn new(artifact: Option<&Artifact>, outputs: &[TxOut], vout: u32) -> Self {
+        let mut allocation = Self {
+            vout,
+            ..Default::default()
+        };
+        let first_non_op_return = outputs
+            .iter()
+            .position(|output| !output.script_pubkey.is_op_return())
+            .map(|i| i as u32);
+
+        let runestone = match artifact {
+            // all the runes in the inputs are burned
+            Some(Artifact::Cenotaph(_)) => return allocation,
+            Some(Artifact::Runestone(runestone)) => runestone,
+            None => {
+                allocation.default_output = first_non_op_return == Some(vout);
+                return allocation;
+            }
+        };
+
+        let is_op_return = outputs
+            .get(vout as usize)
+            .map_or(true, |output| output.script_pubkey.is_op_return());
+        for edict in runestone.edicts.iter() {
+            let allocated = if edict.output as usize == outputs.len() {
+                // the amount is allocated to each non-OP_RETURN output
+                !is_op_return
+            } else {
+                edict.output == vout
+            };
+            if allocated {
+                let amount = (edict.amount > 0).then_some(edict.amount);
+                allocation.edicts.push((edict.id, amount));
+            }
+        }
+        allocation.default_output = runestone.pointer.or(first_non_op_return) == Some(vout);
+        allocation
+    }
+
+    /// the max amount of the rune the output can receive, `None` if it`s unbounded
+    pub fn max_amount(&self, rune_id: &RuneId) -> Option<u128> {
+        if self.default_output {
+            return None;
+        }
+        self.edicts
+            .iter()
+            .filter(|(id, _)| id == rune_id)
+            .try_fold(0u128, |total, (_, amount)| {
+                amount.map(|amount| total.saturating_add(amount))
+            })
+    }
+
+    pub fn can_receive(&self, rune_id: &RuneId, amount: u128) -> bool {
+        self.max_amount(rune_id).map_or(true, |max| amount <= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runestone::cenotaph::Cenotaph;
+    use crate::runestone::{Edict, Runestone};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, ScriptBuf, WPubkeyHash};
+
+    const RUNE: RuneId = RuneId { block: 840000, tx: 3 };
+
+    fn outputs() -> Vec<TxOut> {
+        let output = |script_pubkey| TxOut {
+            value: Amount::from_sat(546),
+            script_pubkey,
+        };
+        vec![
+            output(ScriptBuf::from_bytes(vec![0x6a])),
+            output(ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20]))),
+            output(ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([2; 20]))),
+        ]
+    }
+
+    fn runestone(edicts: Vec<Edict>, pointer: Option<u32>) -> Artifact {
+        Artifact::Runestone(Runestone {
+            edicts,
+            pointer,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_default_output() {
+        let outputs = outputs();
+        let allocation = OutputAllocation::new(None, &outputs, 1);
+        assert!(allocation.default_output);
+        assert_eq!(allocation.max_amount(&RUNE), None);
+        assert_eq!(OutputAllocation::new(None, &outputs, 2).max_amount(&RUNE), Some(0));
+
+        let artifact = runestone(vec![], Some(2));
+        assert!(!OutputAllocation::new(Some(&artifact), &outputs, 1).default_output);
+        assert!(OutputAllocation::new(Some(&artifact), &outputs, 2).default_output);
+    }
+
+    #[test]
+    fn test_edict_allocation() {
+        let outputs = outputs();
+        let artifact = runestone(
+            vec![
+                Edict {
+                    id: RUNE,
+                    amount: 100,
+                    output: 2,
+                },
+                Edict {
+                    id: RUNE,
+                    amount: 50,
+                    output: 3,
+                },
+            ],
+            None,
+        );
+        let allocation = OutputAllocation::new(Some(&artifact), &outputs, 2);
+        assert!(!allocation.default_output);
+        assert_eq!(allocation.max_amount(&RUNE), Some(150));
+        assert!(allocation.can_receive(&RUNE, 150));
+        assert!(!allocation.can_receive(&RUNE, 151));
+        assert!(!allocation.can_receive(&RuneId { block: 1, tx: 0 }, 1));
+
+        let artifact = runestone(
+            vec![Edict {
+                id: RUNE,
+                amount: 0,
+                output: 2,
+            }],
+            None,
+        );
+        let allocation = OutputAllocation::new(Some(&artifact), &outputs, 2);
+        assert_eq!(allocation.max_amount(&RUNE), None);
+    }
+
+    #[test]
+    fn test_cenotaph_burns_runes() {
+        let outputs = outputs();
+        let artifact = Artifact::Cenotaph(Cenotaph::default());
+        let allocation = OutputAllocation::new(Some(&artifact), &outputs, 1);
+        assert!(!allocation.can_receive(&RUNE, 1));
+    }
+}
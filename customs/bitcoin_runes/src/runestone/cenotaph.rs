@@ -0,0 +1,25 @@
+use serde::Serialize;
+
+use omnity_types::rune_id::RuneId;
+
+#[derive(Serialize, Debug, PartialEq, Copy, Clone)]
+pub enum Flaw {
+    EdictOutput,
+    EdictRuneId,
+    InvalidScript,
+    Opcode,
+    SupplyOverflow,
+    TrailingIntegers,
+    TruncatedField,
+    UnrecognizedEvenTag,
+    UnrecognizedFlag,
+    Varint,
+}
+
+/// A malformed runestone, all the runes in the inputs of the transaction are burned
+#[derive(Default, Serialize, Debug, PartialEq, Copy, Clone)]
+pub struct Cenotaph {
+    pub etching: Option<u128>,
+    pub flaw: Option<Flaw>,
+    pub mint: Option<RuneId>,
+}
@@ -1,9 +1,14 @@
 use bitcoin::blockdata::{constants, opcodes, script};
-use bitcoin::script::PushBytesBuf;
+use bitcoin::script::{Instruction, PushBytesBuf};
+use bitcoin::TxOut;
 use serde::Serialize;
 
 use omnity_types::rune_id::RuneId;
 
+use crate::runestone::cenotaph::{Cenotaph, Flaw};
+use crate::runestone::etching::{Etching, Terms};
+use crate::runestone::flag::Flag;
+use crate::runestone::message::Message;
 use crate::runestone::tag::Tag;
 
 use super::varint;
@@ -17,22 +22,82 @@ pub struct Edict {
     pub output: u32,
 }
 
-#[derive(Default)]
+impl Edict {
+    pub fn from_integers(
+        output_count: usize,
+        id: RuneId,
+        amount: u128,
+        output: u128,
+    ) -> Option<Self> {
+        let output = u32::try_from(output).ok()?;
+        // `output == output_count` divides the amount between all non-OP_RETURN outputs
+        if output as usize > output_count {
+            return None;
+        }
+        Some(Self { id, amount, output })
+    }
+}
+
+#[derive(Default, Serialize, Debug, PartialEq, Clone)]
 pub struct Runestone {
     pub edicts: Vec<Edict>,
+    pub etching: Option<Etching>,
     pub mint: Option<RuneId>,
+    pub pointer: Option<u32>,
+}
+
+/// The deciphered runestone, or the cenotaph if the runestone is malformed
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub enum Artifact {
+    Cenotaph(Cenotaph),
+    Runestone(Runestone),
+}
+
+#[derive(Debug, PartialEq)]
+enum Payload {
+    Valid(Vec<u8>),
+    Invalid(Flaw),
 }
 
 impl Runestone {
     pub fn encipher(&self) -> Vec<u8> {
-        assert!(!self.edicts.is_empty() || self.mint.is_some());
+        assert!(!self.edicts.is_empty() || self.mint.is_some() || self.etching.is_some());
 
         let mut payload = Vec::new();
 
+        if let Some(etching) = self.etching {
+            let mut flags = 0;
+            Flag::Etching.set(&mut flags);
+            if etching.terms.is_some() {
+                Flag::Terms.set(&mut flags);
+            }
+            if etching.turbo {
+                Flag::Turbo.set(&mut flags);
+            }
+            Tag::Flags.encode([flags], &mut payload);
+
+            Tag::Rune.encode_option(etching.rune, &mut payload);
+            Tag::Divisibility.encode_option(etching.divisibility, &mut payload);
+            Tag::Spacers.encode_option(etching.spacers, &mut payload);
+            Tag::Symbol.encode_option(etching.symbol.map(u32::from), &mut payload);
+            Tag::Premine.encode_option(etching.premine, &mut payload);
+
+            if let Some(terms) = etching.terms {
+                Tag::Amount.encode_option(terms.amount, &mut payload);
+                Tag::Cap.encode_option(terms.cap, &mut payload);
+                Tag::HeightStart.encode_option(terms.height.0, &mut payload);
+                Tag::HeightEnd.encode_option(terms.height.1, &mut payload);
+                Tag::OffsetStart.encode_option(terms.offset.0, &mut payload);
+                Tag::OffsetEnd.encode_option(terms.offset.1, &mut payload);
+            }
+        }
+
         if let Some(RuneId { block, tx }) = self.mint {
             Tag::Mint.encode([block.into(), tx.into()], &mut payload);
         }
 
+        Tag::Pointer.encode_option(self.pointer, &mut payload);
+
         if !self.edicts.is_empty() {
             varint::encode_to_vec(Tag::Body.into(), &mut payload);
 
@@ -63,4 +128,246 @@ impl Runestone {
 
         builder.into_script().to_bytes()
     }
+
+    /// Decipher the runestone from the outputs of the transaction,
+    /// `None` if there is no runestone in the transaction.
+    pub fn decipher(outputs: &[TxOut]) -> Option<Artifact> {
+        let payload = match Self::payload(outputs)? {
+            Payload::Valid(payload) => payload,
+            Payload::Invalid(flaw) => {
+                return Some(Artifact::Cenotaph(Cenotaph {
+                    flaw: Some(flaw),
+                    ..Default::default()
+                }));
+            }
+        };
+
+        let Ok(integers) = Self::integers(&payload) else {
+            return Some(Artifact::Cenotaph(Cenotaph {
+                flaw: Some(Flaw::Varint),
+                ..Default::default()
+            }));
+        };
+
+        let Message {
+            mut flaw,
+            edicts,
+            mut fields,
+        } = Message::from_integers(outputs.len(), &integers);
+
+        let mut flags = Tag::Flags
+            .take(&mut fields, |[flags]| Some(flags))
+            .unwrap_or_default();
+
+        let etching = Flag::Etching.take(&mut flags).then(|| Etching {
+            divisibility: Tag::Divisibility.take(&mut fields, |[divisibility]| {
+                let divisibility = u8::try_from(divisibility).ok()?;
+                (divisibility <= Etching::MAX_DIVISIBILITY).then_some(divisibility)
+            }),
+            premine: Tag::Premine.take(&mut fields, |[premine]| Some(premine)),
+            rune: Tag::Rune.take(&mut fields, |[rune]| Some(rune)),
+            spacers: Tag::Spacers.take(&mut fields, |[spacers]| {
+                let spacers = u32::try_from(spacers).ok()?;
+                (spacers <= Etching::MAX_SPACERS).then_some(spacers)
+            }),
+            symbol: Tag::Symbol.take(&mut fields, |[symbol]| {
+                char::from_u32(u32::try_from(symbol).ok()?)
+            }),
+            terms: Flag::Terms.take(&mut flags).then(|| Terms {
+                cap: Tag::Cap.take(&mut fields, |[cap]| Some(cap)),
+                height: (
+                    Tag::HeightStart.take(&mut fields, |[start]| u64::try_from(start).ok()),
+                    Tag::HeightEnd.take(&mut fields, |[end]| u64::try_from(end).ok()),
+                ),
+                amount: Tag::Amount.take(&mut fields, |[amount]| Some(amount)),
+                offset: (
+                    Tag::OffsetStart.take(&mut fields, |[start]| u64::try_from(start).ok()),
+                    Tag::OffsetEnd.take(&mut fields, |[end]| u64::try_from(end).ok()),
+                ),
+            }),
+            turbo: Flag::Turbo.take(&mut flags),
+        });
+
+        let mint = Tag::Mint.take(&mut fields, |[block, tx]| {
+            let id = RuneId {
+                block: block.try_into().ok()?,
+                tx: tx.try_into().ok()?,
+            };
+            (id.block != 0 || id.tx == 0).then_some(id)
+        });
+
+        let pointer = Tag::Pointer.take(&mut fields, |[pointer]| {
+            let pointer = u32::try_from(pointer).ok()?;
+            ((pointer as usize) < outputs.len()).then_some(pointer)
+        });
+
+        if etching.is_some_and(|etching| etching.supply().is_none()) {
+            flaw.get_or_insert(Flaw::SupplyOverflow);
+        }
+
+        if flags != 0 {
+            flaw.get_or_insert(Flaw::UnrecognizedFlag);
+        }
+
+        if fields.keys().any(|tag| tag % 2 == 0) {
+            flaw.get_or_insert(Flaw::UnrecognizedEvenTag);
+        }
+
+        if let Some(flaw) = flaw {
+            return Some(Artifact::Cenotaph(Cenotaph {
+                flaw: Some(flaw),
+                mint,
+                etching: etching.and_then(|etching| etching.rune),
+            }));
+        }
+
+        Some(Artifact::Runestone(Self {
+            edicts,
+            etching,
+            mint,
+            pointer,
+        }))
+    }
+
+    fn payload(outputs: &[TxOut]) -> Option<Payload> {
+        for output in outputs {
+            let mut instructions = output.script_pubkey.instructions();
+
+            // the payload starts with OP_RETURN followed by the protocol identifier
+            if instructions.next() != Some(Ok(Instruction::Op(opcodes::all::OP_RETURN))) {
+                continue;
+            }
+            if instructions.next() != Some(Ok(Instruction::Op(MAGIC_NUMBER))) {
+                continue;
+            }
+
+            // the payload is the concatenation of the remaining data pushes
+            let mut payload = Vec::new();
+            for result in instructions {
+                match result {
+                    Ok(Instruction::PushBytes(push)) => payload.extend_from_slice(push.as_bytes()),
+                    Ok(Instruction::Op(_)) => return Some(Payload::Invalid(Flaw::Opcode)),
+                    Err(_) => return Some(Payload::Invalid(Flaw::InvalidScript)),
+                }
+            }
+            return Some(Payload::Valid(payload));
+        }
+
+        None
+    }
+
+    fn integers(payload: &[u8]) -> Result<Vec<u128>, varint::Error> {
+        let mut integers = Vec::new();
+        let mut i = 0;
+
+        while i < payload.len() {
+            let (integer, length) = varint::decode(&payload[i..])?;
+            integers.push(integer);
+            i += length;
+        }
+
+        Ok(integers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, ScriptBuf, WPubkeyHash};
+
+    fn outputs(runestone: Vec<u8>) -> Vec<TxOut> {
+        vec![
+            TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::from_bytes(runestone),
+            },
+            TxOut {
+                value: Amount::from_sat(546),
+                script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20])),
+            },
+            TxOut {
+                value: Amount::from_sat(546),
+                script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([2; 20])),
+            },
+        ]
+    }
+
+    #[test]
+    fn deciphers_enciphered_runestone() {
+        let runestone = Runestone {
+            edicts: vec![
+                Edict {
+                    id: RuneId { block: 840000, tx: 3 },
+                    amount: 1000,
+                    output: 2,
+                },
+                Edict {
+                    id: RuneId { block: 840010, tx: 1 },
+                    amount: 0,
+                    output: 3,
+                },
+            ],
+            etching: Some(Etching {
+                divisibility: Some(2),
+                premine: Some(100),
+                rune: Some(99246114928149462),
+                spacers: Some(1),
+                symbol: Some('$'),
+                terms: Some(Terms {
+                    amount: Some(10),
+                    cap: Some(1000),
+                    height: (Some(840000), None),
+                    offset: (None, Some(100)),
+                }),
+                turbo: true,
+            }),
+            mint: Some(RuneId { block: 1, tx: 0 }),
+            pointer: Some(1),
+        };
+        let artifact = Runestone::decipher(&outputs(runestone.encipher()));
+        assert_eq!(artifact, Some(Artifact::Runestone(runestone)));
+    }
+
+    #[test]
+    fn no_runestone_without_magic_number() {
+        let mut push = PushBytesBuf::new();
+        push.extend_from_slice(&[0, 1, 2]).unwrap();
+        let script = script::Builder::new()
+            .push_opcode(opcodes::all::OP_RETURN)
+            .push_slice(push)
+            .into_script()
+            .to_bytes();
+        assert_eq!(Runestone::decipher(&outputs(script)), None);
+    }
+
+    #[test]
+    fn invalid_payloads_are_cenotaphs() {
+        let cenotaph = |payload: &[u8]| {
+            let mut push = PushBytesBuf::new();
+            push.extend_from_slice(payload).unwrap();
+            let script = script::Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .push_opcode(MAGIC_NUMBER)
+                .push_slice(push)
+                .into_script()
+                .to_bytes();
+            match Runestone::decipher(&outputs(script)) {
+                Some(Artifact::Cenotaph(cenotaph)) => cenotaph.flaw,
+                artifact => panic!("expected cenotaph, got {:?}", artifact),
+            }
+        };
+        // unterminated varint
+        assert_eq!(cenotaph(&[128]), Some(Flaw::Varint));
+        // unrecognized even tag
+        assert_eq!(cenotaph(&[24, 1]), Some(Flaw::UnrecognizedEvenTag));
+        // edict output out of range
+        assert_eq!(cenotaph(&[0, 1, 0, 10, 4]), Some(Flaw::EdictOutput));
+        // trailing integers in the body
+        assert_eq!(cenotaph(&[0, 1, 0, 10]), Some(Flaw::TrailingIntegers));
+        // truncated field
+        assert_eq!(cenotaph(&[20]), Some(Flaw::TruncatedField));
+        // unrecognized flag
+        assert_eq!(cenotaph(&[2, 8]), Some(Flaw::UnrecognizedFlag));
+    }
 }
@@ -13,22 +13,46 @@ pub fn encode_to_vec(mut n: u128, v: &mut Vec<u8>) {
     v.push(n.to_le_bytes()[0]);
 }
 
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    Overlong,
+    Overflow,
+    Unterminated,
+}
+
+pub fn decode(buffer: &[u8]) -> Result<(u128, usize), Error> {
+    let mut n = 0u128;
+
+    for (i, &byte) in buffer.iter().enumerate() {
+        if i > 18 {
+            return Err(Error::Overlong);
+        }
+
+        let value = u128::from(byte) & 0b0111_1111;
+
+        if i == 18 && value & 0b0111_1100 != 0 {
+            return Err(Error::Overflow);
+        }
+
+        n |= value << (7 * i);
+
+        if byte & 0b1000_0000 == 0 {
+            return Ok((n, i + 1));
+        }
+    }
+
+    Err(Error::Unterminated)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[derive(PartialEq, Debug)]
-    enum Error {
-        Overlong,
-        Overflow,
-        Unterminated,
-    }
-
     #[test]
     fn zero_round_trips_successfully() {
         let n = 0;
         let encoded = encode(n);
-        let (decoded, length) = try_decode(&encoded).unwrap();
+        let (decoded, length) = decode(&encoded).unwrap();
         assert_eq!(decoded, n);
         assert_eq!(length, encoded.len());
     }
@@ -37,7 +61,7 @@ mod tests {
     fn u128_max_round_trips_successfully() {
         let n = u128::MAX;
         let encoded = encode(n);
-        let (decoded, length) = try_decode(&encoded).unwrap();
+        let (decoded, length) = decode(&encoded).unwrap();
         assert_eq!(decoded, n);
         assert_eq!(length, encoded.len());
     }
@@ -47,7 +71,7 @@ mod tests {
         for i in 0..128 {
             let n = 1 << i;
             let encoded = encode(n);
-            let (decoded, length) = try_decode(&encoded).unwrap();
+            let (decoded, length) = decode(&encoded).unwrap();
             assert_eq!(decoded, n);
             assert_eq!(length, encoded.len());
         }
@@ -60,7 +84,7 @@ mod tests {
         for i in 0..129 {
             n = n << 1 | (i % 2);
             let encoded = encode(n);
-            let (decoded, length) = try_decode(&encoded).unwrap();
+            let (decoded, length) = decode(&encoded).unwrap();
             assert_eq!(decoded, n);
             assert_eq!(length, encoded.len());
         }
@@ -78,49 +102,49 @@ mod tests {
             128, 128, 0,
         ];
 
-        assert_eq!(try_decode(&VALID), Ok((0, 19)));
-        assert_eq!(try_decode(&INVALID), Err(Error::Overlong));
+        assert_eq!(decode(&VALID), Ok((0, 19)));
+        assert_eq!(decode(&INVALID), Err(Error::Overlong));
     }
 
     #[test]
     fn varints_may_not_overflow_u128() {
         assert_eq!(
-            try_decode(&[
+            decode(&[
                 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128,
                 128, 128, 64,
             ]),
             Err(Error::Overflow)
         );
         assert_eq!(
-            try_decode(&[
+            decode(&[
                 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128,
                 128, 128, 32,
             ]),
             Err(Error::Overflow)
         );
         assert_eq!(
-            try_decode(&[
+            decode(&[
                 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128,
                 128, 128, 16,
             ]),
             Err(Error::Overflow)
         );
         assert_eq!(
-            try_decode(&[
+            decode(&[
                 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128,
                 128, 128, 8,
             ]),
             Err(Error::Overflow)
         );
         assert_eq!(
-            try_decode(&[
+            decode(&[
                 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128,
                 128, 128, 4,
             ]),
             Err(Error::Overflow)
         );
         assert_eq!(
-            try_decode(&[
+            decode(&[
                 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128,
                 128, 128, 2,
             ]),
@@ -130,30 +154,6 @@ mod tests {
 
     #[test]
     fn varints_must_be_terminated() {
-        assert_eq!(try_decode(&[128]), Err(Error::Unterminated));
-    }
-
-    fn try_decode(buffer: &[u8]) -> Result<(u128, usize), Error> {
-        let mut n = 0u128;
-
-        for (i, &byte) in buffer.iter().enumerate() {
-            if i > 18 {
-                return Err(Error::Overlong);
-            }
-
-            let value = u128::from(byte) & 0b0111_1111;
-
-            if i == 18 && value & 0b0111_1100 != 0 {
-                return Err(Error::Overflow);
-            }
-
-            n |= value << (7 * i);
-
-            if byte & 0b1000_0000 == 0 {
-                return Ok((n, i + 1));
-            }
-        }
-
-        Err(Error::Unterminated)
+        assert_eq!(decode(&[128]), Err(Error::Unterminated));
     }
 }
@@ -43,6 +43,17 @@ impl EncodedSignature {
         Self(Cow::Owned(sig))
     }
 
+    /// Encodes a BIP-340 Schnorr signature for a taproot key path spend.
+    /// The sighash type byte is omitted because the signature uses SIGHASH_DEFAULT.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the signature is not 64 bytes long.
+    pub fn from_schnorr(signature: &[u8]) -> Self {
+        assert_eq!(signature.len(), 64);
+        Self(Cow::Owned(signature.to_vec()))
+    }
+
     /// Returns the longest valid encoded signature.
     pub fn fake() -> Self {
         Self(Cow::Borrowed(&FAKE_SIG[..]))
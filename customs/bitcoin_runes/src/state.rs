@@ -12,15 +12,20 @@ use std::{
 pub mod audit;
 pub mod eventlog;
 
+use crate::fee_policy::{FeeRatePolicy, FeeRateRecord, MAX_FEE_RATE_HISTORY};
 use crate::lifecycle::init::InitArgs;
 use crate::lifecycle::upgrade::UpgradeArgs;
+use crate::runes_etching::mint::{SendMintRequest, MINT_KEY_PREFIX};
 use crate::runes_etching::transactions::SendEtchingRequest;
 use crate::runes_etching::{EtchingArgs, InternalEtchingArgs};
 use crate::storage::VMem;
-use crate::{address::BitcoinAddress, ECDSAPublicKey};
+use crate::{
+    address::{AddressType, BitcoinAddress},
+    ECDSAPublicKey,
+};
 use crate::{
     destination::Destination,
-    runestone::{Edict, Runestone},
+    runestone::{Edict, OutputAllocation, Runestone},
 };
 use candid::{CandidType, Deserialize, Principal};
 pub use ic_btc_interface::Network;
@@ -114,6 +119,25 @@ pub struct GenTicketRequestV2 {
     pub txid: Txid,
     pub new_utxos: Vec<Utxo>,
     pub received_at: u64,
+    /// The runes the new utxos can receive according to the runestone of the deposit
+    /// transaction, it`s None for the requests accepted before the runestone is deciphered.
+    #[serde(default)]
+    pub allocations: Option<Vec<OutputAllocation>>,
+    /// The type of the deposit address, None for P2WPKH.
+    #[serde(default)]
+    pub address_type: Option<AddressType>,
+}
+
+impl GenTicketRequestV2 {
+    /// Returns the destination of the deposit address the new utxos belong to.
+    pub fn destination(&self) -> Destination {
+        Destination {
+            target_chain_id: self.target_chain_id.clone(),
+            receiver: self.receiver.clone(),
+            token: Some(RUNES_TOKEN.into()),
+            address_type: self.address_type,
+        }
+    }
 }
 
 impl From<GenTicketRequest> for GenTicketRequestV2 {
@@ -128,6 +152,8 @@ impl From<GenTicketRequest> for GenTicketRequestV2 {
             txid: value.txid,
             new_utxos: Default::default(),
             received_at: value.received_at,
+            allocations: None,
+            address_type: None,
         }
     }
 }
@@ -188,6 +214,16 @@ pub struct SubmittedBtcTransaction {
     pub fee_per_vbyte: Option<u64>,
 }
 
+/// The kind of a transaction the customs sent to the Bitcoin network.
+#[derive(candid::CandidType, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubmittedTxKind {
+    /// Releases runes for the rune tx requests.
+    #[default]
+    Release,
+    /// Sweeps small Runes and BTC UTXOs of the customs into fewer outputs.
+    Consolidation,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SubmittedBtcTransactionV2 {
     pub rune_id: RuneId,
@@ -200,6 +236,73 @@ pub struct SubmittedBtcTransactionV2 {
     pub btc_change_output: BtcChangeOutput,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fee_per_vbyte: Option<u64>,
+    /// The change outputs of the other runes packed into the same transaction.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_runes_change_outputs: Vec<RunesChangeOutput>,
+    #[serde(default)]
+    pub kind: SubmittedTxKind,
+}
+
+impl SubmittedBtcTransactionV2 {
+    /// The runes change outputs of all the runes in the transaction,
+    /// the first one is the change output of `rune_id`.
+    pub fn runes_change_outputs(&self) -> impl Iterator<Item = &RunesChangeOutput> {
+        std::iter::once(&self.runes_change_output).chain(self.extra_runes_change_outputs.iter())
+    }
+
+    pub fn rune_ids(&self) -> Vec<RuneId> {
+        self.runes_change_outputs()
+            .map(|output| output.rune_id)
+            .collect()
+    }
+}
+
+/// A child transaction that spends the BTC change output of a stuck transaction
+/// with a high fee, so that the miners confirm both of them (child-pays-for-parent).
+#[derive(candid::CandidType, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpfpTransaction {
+    pub txid: Txid,
+    /// The stuck transaction whose BTC change output the child spends.
+    pub parent_txid: Txid,
+    /// The tx btc change output of the child transaction.
+    pub btc_change_output: BtcChangeOutput,
+    /// The fee per vbyte (in millisatoshi) that the parent and the child pay together.
+    pub fee_per_vbyte: u64,
+    pub submitted_at: u64,
+}
+
+/// A deposit destination that the customs scans for new UTXOs, so that the
+/// generate ticket requests are created without the user submitting the txid.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepositWatch {
+    /// The rune the user deposits to the destination.
+    pub rune_id: RuneId,
+    /// The IC time at which the user registered the destination, the watch
+    /// expires after [crate::deposit_watcher::DEPOSIT_WATCH_EXPIRATION_NANOS].
+    pub registered_at: u64,
+    /// The principal that registered the destination, it is limited to
+    /// [crate::deposit_watcher::MAX_DEPOSIT_WATCHES_PER_CALLER] watches.
+    #[serde(default)]
+    pub registered_by: Option<Principal>,
+    /// The IC time of the last scan of the destination.
+    #[serde(skip)]
+    pub last_scanned_at: u64,
+    /// The deposit outputs the watcher could not turn into requests, or whose
+    /// requests the oracle dropped, for example because the runestone does not
+    /// allow the indexed balance. They are not fetched again, the user still can
+    /// submit them with `generate_ticket`.
+    #[serde(skip)]
+    pub skipped_outpoints: BTreeSet<OutPoint>,
+}
+
+/// A deposit submitted as a signed PSBT, recorded before its transaction is
+/// broadcast and until the hub accepts its pending ticket.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingDepositPsbt {
+    pub request: GenTicketRequestV2,
+    pub bridge_fee: Option<u128>,
+    /// The IC time at which the customs broadcast the deposit transaction.
+    pub submitted_at: u64,
 }
 
 /// The outcome of a release token request.
@@ -266,6 +369,10 @@ pub struct CustomsState {
 
     pub prod_ecdsa_public_key: Option<ECDSAPublicKey>,
 
+    /// The Customs threshold Schnorr (BIP-340) public key, used to derive the P2TR deposit addresses.
+    #[serde(default)]
+    pub schnorr_public_key: Option<ECDSAPublicKey>,
+
     /// The minimum number of confirmations on the Bitcoin chain.
     pub min_confirmations: u32,
 
@@ -309,9 +416,31 @@ pub struct CustomsState {
     /// Maps ID of a replacement transaction to the ID of the corresponding stuck transaction.
     pub rev_replacement_txid: BTreeMap<Txid, Txid>,
 
+    /// Maps ID of a stuck transaction to the child transaction that bumps its fee.
+    #[serde(default)]
+    pub cpfp_transactions: BTreeMap<Txid, CpfpTransaction>,
+
+    /// The destinations registered for the deposit auto-detection.
+    #[serde(default)]
+    pub deposit_watches: BTreeMap<Destination, DepositWatch>,
+
+    /// The deposits submitted as signed PSBTs whose pending ticket the hub did
+    /// not accept yet.
+    #[serde(default)]
+    pub pending_deposit_psbts: BTreeMap<Txid, PendingDepositPsbt>,
+
     /// The total number of finalized requests.
     pub finalized_requests_count: u64,
 
+    /// The total number of finalized consolidation transactions.
+    #[serde(default)]
+    pub finalized_consolidations_count: u64,
+
+    /// The max fee rate (in millisatoshi per vbyte) at which the customs consolidates
+    /// its UTXOs, the consolidation is disabled if it's not set.
+    #[serde(default)]
+    pub consolidation_max_fee_per_vbyte: Option<u64>,
+
     /// The set of Runes UTXOs unused in pending transactions.
     pub available_runes_utxos: BTreeSet<RunesUtxo>,
 
@@ -345,6 +474,14 @@ pub struct CustomsState {
     #[serde(default)]
     pub bitcoin_fee_rate: BitcoinFeeRate,
 
+    /// The policy choosing the fee rates of the transactions.
+    #[serde(default)]
+    pub fee_rate_policy: FeeRatePolicy,
+
+    /// The recent fee rates chosen by the policy, the oldest first.
+    #[serde(skip)]
+    pub fee_rate_history: VecDeque<FeeRateRecord>,
+
     pub rpc_url: Option<String>,
 
     /// Process one timer event at a time.
@@ -360,11 +497,19 @@ pub struct CustomsState {
     #[serde(skip)]
     pub is_process_etching_msg: bool,
 
+    #[serde(skip)]
+    pub is_watching_deposits: bool,
+
     /// The mode in which the customs runs.
     pub chain_state: ChainState,
 
     pub last_fee_per_vbyte: Vec<u64>,
 
+    /// The IC time of the last successful refresh of the fee percentiles,
+    /// the percentiles are unknown until the first refresh.
+    #[serde(skip)]
+    pub last_fee_per_vbyte_updated_at: Option<u64>,
+
     #[serde(default)]
     pub fee_token_factor: Option<u128>,
 
@@ -392,11 +537,31 @@ pub struct CustomsState {
     #[serde(skip, default = "crate::storage::init_finalized_etching_requests")]
     pub finalized_etching_requests: StableBTreeMap<String, SendEtchingRequest, VMem>,
 
+    /// The etchings queued by the earlier versions, moved to the pending etchings on upgrade.
     #[serde(skip, default = "crate::storage::init_stash_etchings")]
     pub stash_etchings: StableBTreeMap<String, InternalEtchingArgs, VMem>,
 
     #[serde(skip, default = "crate::storage::init_stash_etching_ids")]
     pub stash_etching_ids: StableVec<EtchingKey, VMem>,
+
+    /// The etching whose commit transaction is being sent, it can't be cancelled.
+    #[serde(skip)]
+    pub committing_etching: Option<String>,
+
+    /// The mint-and-bridge requests of the users.
+    #[serde(skip, default = "crate::storage::init_pending_mint_requests")]
+    pub pending_mint_requests: StableBTreeMap<String, SendMintRequest, VMem>,
+    #[serde(skip, default = "crate::storage::init_finalized_mint_requests")]
+    pub finalized_mint_requests: StableBTreeMap<String, SendMintRequest, VMem>,
+
+    /// The keys of the mint requests being created, they are reserved before
+    /// the first call so that concurrent requests never share a key.
+    #[serde(skip)]
+    pub reserved_mint_keys: BTreeSet<String>,
+
+    /// The counter of the keys of the mint requests.
+    #[serde(skip)]
+    pub next_mint_nonce: u64,
 }
 
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
@@ -489,6 +654,7 @@ impl CustomsState {
             min_confirmations,
             chain_state,
             hub_principal,
+            consolidation_max_fee_per_vbyte,
         }: UpgradeArgs,
     ) {
         if let Some(max_time_in_queue_nanos) = max_time_in_queue_nanos {
@@ -512,6 +678,17 @@ impl CustomsState {
         if let Some(hub_principal) = hub_principal {
             self.hub_principal = hub_principal;
         }
+        if let Some(max_fee) = consolidation_max_fee_per_vbyte {
+            // Zero disables the consolidation.
+            self.consolidation_max_fee_per_vbyte = (max_fee > 0).then_some(max_fee);
+        }
+    }
+
+    /// Returns true if a consolidation transaction is waiting for the confirmations.
+    pub fn has_pending_consolidation(&self) -> bool {
+        self.submitted_transactions
+            .iter()
+            .any(|tx| tx.kind == SubmittedTxKind::Consolidation)
     }
 
     pub fn validate_config(&self) {
@@ -628,6 +805,23 @@ impl CustomsState {
         }
     }
 
+    /// The threshold Schnorr keys share the names of the threshold ECDSA keys.
+    pub fn schnorr_key_name(&self) -> String {
+        if cfg!(feature = "non_prod") {
+            self.ecdsa_key_name.clone()
+        } else {
+            PROD_KEY.into()
+        }
+    }
+
+    pub fn get_schnorr_key(&self) -> (String, ECDSAPublicKey) {
+        let pub_key = self
+            .schnorr_public_key
+            .clone()
+            .expect("the Schnorr public key must be initialized");
+        (self.schnorr_key_name(), pub_key)
+    }
+
     // public for only for tests
     pub(crate) fn add_utxos(&mut self, destination: Destination, utxos: Vec<Utxo>, is_runes: bool) {
         if utxos.is_empty() {
@@ -673,10 +867,41 @@ impl CustomsState {
         }
     }
 
+    /// The runes locked by the customs, excluding the amount owed to the pending release requests.
+    pub fn locked_runes_supply(&self, rune_id: &RuneId) -> u128 {
+        let available: u128 = self
+            .available_runes_utxos
+            .iter()
+            .filter(|utxo| utxo.runes.rune_id == *rune_id)
+            .map(|utxo| utxo.runes.amount)
+            .sum();
+        let changes: u128 = self
+            .submitted_transactions
+            .iter()
+            .flat_map(|tx| tx.runes_change_outputs())
+            .filter(|output| output.rune_id == *rune_id)
+            .map(|output| output.value)
+            .sum();
+        let pending: u128 = self
+            .pending_rune_tx_requests
+            .get(rune_id)
+            .map_or(0, |requests| {
+                requests
+                    .iter()
+                    .filter(|req| req.action != TxAction::Mint)
+                    .map(|req| req.amount)
+                    .sum()
+            });
+        (available + changes).saturating_sub(pending)
+    }
+
     pub fn generate_ticket_status(&self, tx_id: Txid) -> GenTicketStatus {
         if let Some(req) = self.pending_gen_ticket_requests.get(&tx_id) {
             return GenTicketStatus::Pending(req.clone());
         }
+        if let Some(pending) = self.pending_deposit_psbts.get(&tx_id) {
+            return GenTicketStatus::Pending(pending.request.clone());
+        }
         if let Some(req) = self.confirmed_gen_ticket_requests.get(&tx_id) {
             return GenTicketStatus::Confirmed(req.clone());
         }
@@ -744,49 +969,68 @@ impl CustomsState {
         }
     }
 
-    /// Forms a batch of rune tx requests that the customs can fulfill.
-    pub fn build_batch(&mut self, rune_id: RuneId, max_size: usize) -> Vec<RuneTxRequest> {
-        assert!(self.pending_rune_tx_requests.contains_key(&rune_id));
-
-        let available_utxos_value = self
-            .available_runes_utxos
-            .iter()
-            .filter(|u| u.runes.rune_id.eq(&rune_id))
-            .map(|u| u.runes.amount)
-            .sum::<u128>();
-        let mut batch = vec![];
-        let mut tx_amount = 0;
-        let requests = self.pending_rune_tx_requests.entry(rune_id).or_default();
-
-        if let Some(pos) = requests.iter().position(|req| req.action == TxAction::Mint) {
-            let req = requests.remove(pos);
-            batch.push(req);
-            return batch;
+    /// Forms a batch of rune tx requests that the customs can fulfill,
+    /// the requests of several runes are packed into one transaction
+    /// as long as the runestone fits into the OP_RETURN output.
+    pub fn build_batch(&mut self, rune_ids: &[RuneId], max_size: usize) -> Vec<RuneTxRequest> {
+        // A mint request is sent in its own transaction.
+        for rune_id in rune_ids {
+            let requests = self.pending_rune_tx_requests.entry(*rune_id).or_default();
+            if let Some(pos) = requests.iter().position(|req| req.action == TxAction::Mint) {
+                return vec![requests.remove(pos)];
+            }
         }
 
+        let mut batch = vec![];
         let mut edicts = vec![];
-        for req in std::mem::take(requests) {
-            edicts.push(Edict {
-                id: req.rune_id.into(),
-                amount: req.amount,
-                output: 0,
-            });
-            // Maybe there is a better optimized version.
-            let script = Runestone {
-                edicts: edicts.clone(),
-                mint: None,
+        for rune_id in rune_ids {
+            let available_utxos_value = self
+                .available_runes_utxos
+                .iter()
+                .filter(|u| u.runes.rune_id.eq(rune_id))
+                .map(|u| u.runes.amount)
+                .sum::<u128>();
+            // The change of the first rune goes to the default output,
+            // the other runes need an edict for the change.
+            let change_edict = !edicts.is_empty();
+            if change_edict {
+                edicts.push(Edict {
+                    id: *rune_id,
+                    amount: available_utxos_value,
+                    output: 0,
+                });
             }
-            .encipher();
-            if script.len() > 82
-                || available_utxos_value < req.amount + tx_amount
-                || batch.len() >= max_size
-            {
-                // Put this request back to the queue until we have enough liquid UTXOs.
-                requests.push(req);
-                edicts.pop();
-            } else {
-                tx_amount += req.amount;
-                batch.push(req.clone());
+
+            let mut tx_amount = 0;
+            let batch_len = batch.len();
+            let requests = self.pending_rune_tx_requests.entry(*rune_id).or_default();
+            for req in std::mem::take(requests) {
+                edicts.push(Edict {
+                    id: req.rune_id.into(),
+                    amount: req.amount,
+                    output: 0,
+                });
+                // Maybe there is a better optimized version.
+                let script = Runestone {
+                    edicts: edicts.clone(),
+                    ..Default::default()
+                }
+                .encipher();
+                if script.len() > 82
+                    || available_utxos_value < req.amount + tx_amount
+                    || batch.len() >= max_size
+                {
+                    // Put this request back to the queue until we have enough liquid UTXOs.
+                    requests.push(req);
+                    edicts.pop();
+                } else {
+                    tx_amount += req.amount;
+                    batch.push(req.clone());
+                }
+            }
+            if change_edict && batch.len() == batch_len {
+                // None of the requests of this rune fits into the transaction.
+                edicts.retain(|edict| edict.id != *rune_id);
             }
         }
 
@@ -855,12 +1099,122 @@ impl CustomsState {
         for utxo in finalized_tx.btc_utxos.iter() {
             self.forget_utxo(utxo);
         }
+        if finalized_tx.kind == SubmittedTxKind::Consolidation {
+            self.finalized_consolidations_count += 1;
+        }
         self.finalized_requests_count += finalized_tx.requests.len() as u64;
         for request in finalized_tx.requests {
             self.push_finalized_rune_tx(request.ticket_id, FinalizedStatus::Confirmed(*txid));
         }
 
         self.cleanup_tx_replacement_chain(txid);
+
+        // The child transactions of the finalized transaction and of the transactions
+        // it replaced are not needed anymore.
+        let (submitted, stuck) = (&self.submitted_transactions, &self.stuck_transactions);
+        self.cpfp_transactions.retain(|parent_txid, _| {
+            submitted
+                .iter()
+                .chain(stuck.iter())
+                .any(|tx| &tx.txid == parent_txid)
+        });
+    }
+
+    /// Returns true if the transaction is submitted and not finalized or replaced yet.
+    pub(crate) fn has_submitted_transaction(&self, txid: &Txid) -> bool {
+        self.submitted_transactions.iter().any(|tx| &tx.txid == txid)
+    }
+
+    pub(crate) fn record_cpfp_transaction(&mut self, child: CpfpTransaction) {
+        assert!(
+            self.has_submitted_transaction(&child.parent_txid),
+            "BUG: attempted to bump the fee of an unknown transaction"
+        );
+        self.cpfp_transactions.insert(child.parent_txid, child);
+    }
+
+    /// Records the fee rate chosen for an operation if it differs from the last one.
+    pub fn record_fee_rate(&mut self, record: FeeRateRecord) {
+        let last = self
+            .fee_rate_history
+            .iter()
+            .rev()
+            .find(|r| r.operation == record.operation);
+        if last.is_some_and(|r| r.fee_per_vbyte == record.fee_per_vbyte) {
+            return;
+        }
+        if self.fee_rate_history.len() >= MAX_FEE_RATE_HISTORY {
+            self.fee_rate_history.pop_front();
+        }
+        self.fee_rate_history.push_back(record);
+    }
+
+    pub(crate) fn register_deposit_watch(
+        &mut self,
+        destination: Destination,
+        rune_id: RuneId,
+        registered_at: u64,
+        registered_by: Option<Principal>,
+    ) {
+        let watch = self
+            .deposit_watches
+            .entry(destination)
+            .or_insert_with(|| DepositWatch {
+                rune_id,
+                registered_at,
+                registered_by,
+                last_scanned_at: 0,
+                skipped_outpoints: Default::default(),
+            });
+        if watch.rune_id != rune_id {
+            watch.skipped_outpoints.clear();
+        }
+        watch.rune_id = rune_id;
+        watch.registered_at = registered_at;
+        watch.registered_by = registered_by;
+    }
+
+    /// Reserves a key of a mint request of the caller that is not used by
+    /// any pending, finalized or reserved mint request.
+    pub(crate) fn reserve_mint_key(&mut self, caller: &Principal) -> String {
+        if self.next_mint_nonce == 0 {
+            // The counter is not persisted, the stored requests are skipped after an upgrade.
+            self.next_mint_nonce =
+                self.pending_mint_requests.len() + self.finalized_mint_requests.len();
+        }
+        loop {
+            let key = format!("{}{}-{}", MINT_KEY_PREFIX, caller, self.next_mint_nonce);
+            self.next_mint_nonce += 1;
+            if !self.pending_mint_requests.contains_key(&key)
+                && !self.finalized_mint_requests.contains_key(&key)
+                && self.reserved_mint_keys.insert(key.clone())
+            {
+                return key;
+            }
+        }
+    }
+
+    /// Skips the outputs of the dropped deposit request in the watches of its
+    /// destination, so that the watcher does not create the request again.
+    pub(crate) fn skip_watched_deposit(&mut self, request: &GenTicketRequestV2) {
+        for (destination, watch) in self.deposit_watches.iter_mut() {
+            if destination.target_chain_id == request.target_chain_id
+                && destination.receiver == request.receiver
+                && destination.address_type == request.address_type
+            {
+                watch
+                    .skipped_outpoints
+                    .extend(request.new_utxos.iter().map(|utxo| utxo.outpoint));
+            }
+        }
+    }
+
+    /// Returns the number of the destinations registered by the caller.
+    pub(crate) fn deposit_watches_of(&self, caller: &Principal) -> usize {
+        self.deposit_watches
+            .values()
+            .filter(|w| w.registered_by.as_ref() == Some(caller))
+            .count()
     }
 
     fn cleanup_tx_replacement_chain(&mut self, confirmed_txid: &Txid) {
@@ -941,6 +1295,8 @@ impl CustomsState {
         // tx points to the old transaction now.
         debug_assert_eq!(&tx.txid, old_txid);
 
+        // The replacement invalidates the child transaction of the old one.
+        self.cpfp_transactions.remove(old_txid);
         self.stuck_transactions.push(tx);
         self.replacement_txid.insert(*old_txid, new_txid);
         self.rev_replacement_txid.insert(new_txid, *old_txid);
@@ -1173,6 +1529,31 @@ impl CustomsState {
             "stuck_transactions do not match"
         );
 
+        ensure_eq!(
+            self.cpfp_transactions,
+            other.cpfp_transactions,
+            "cpfp_transactions do not match"
+        );
+
+        // The scan bookkeeping of the watches is not recorded in the event log.
+        let watches = |s: &Self| -> Vec<(Destination, RuneId, u64)> {
+            s.deposit_watches
+                .iter()
+                .map(|(dest, watch)| (dest.clone(), watch.rune_id, watch.registered_at))
+                .collect()
+        };
+        ensure_eq!(
+            watches(self),
+            watches(other),
+            "deposit_watches do not match"
+        );
+
+        ensure_eq!(
+            self.pending_deposit_psbts,
+            other.pending_deposit_psbts,
+            "pending_deposit_psbts do not match"
+        );
+
         ensure_eq!(
             self.pending_rune_tx_requests.len(),
             other.pending_rune_tx_requests.len(),
@@ -1224,6 +1605,7 @@ impl From<InitArgs> for CustomsState {
             ecdsa_key_name: args.ecdsa_key_name,
             ecdsa_public_key: None,
             prod_ecdsa_public_key: None,
+            schnorr_public_key: None,
             min_confirmations: args
                 .min_confirmations
                 .unwrap_or(crate::lifecycle::init::DEFAULT_MIN_CONFIRMATIONS),
@@ -1239,8 +1621,13 @@ impl From<InitArgs> for CustomsState {
             submitted_transactions: Default::default(),
             replacement_txid: Default::default(),
             rev_replacement_txid: Default::default(),
+            cpfp_transactions: Default::default(),
+            deposit_watches: Default::default(),
+            pending_deposit_psbts: Default::default(),
             stuck_transactions: Default::default(),
             finalized_requests_count: 0,
+            finalized_consolidations_count: 0,
+            consolidation_max_fee_per_vbyte: None,
             available_runes_utxos: Default::default(),
             available_fee_utxos: Default::default(),
             outpoint_utxos: Default::default(),
@@ -1254,12 +1641,16 @@ impl From<InitArgs> for CustomsState {
             is_process_directive_msg: false,
             is_process_ticket_msg: false,
             is_process_etching_msg: false,
+            is_watching_deposits: false,
             chain_state: args.chain_state,
             hub_principal: args.hub_principal,
             runes_oracles: BTreeSet::from_iter(vec![args.runes_oracle_principal]),
             bitcoin_fee_rate: Default::default(),
             rpc_url: None,
             last_fee_per_vbyte: vec![1; 100],
+            last_fee_per_vbyte_updated_at: None,
+            fee_rate_policy: Default::default(),
+            fee_rate_history: Default::default(),
             fee_token_factor: None,
             target_chain_factor: Default::default(),
             fee_collector_address: "".to_string(),
@@ -1271,6 +1662,11 @@ impl From<InitArgs> for CustomsState {
             icpswap_principal: None,
             etching_fee_utxos: crate::storage::init_etching_fee_utxos(),
             stash_etching_ids: crate::storage::init_stash_etching_ids(),
+            committing_etching: None,
+            pending_mint_requests: crate::storage::init_pending_mint_requests(),
+            finalized_mint_requests: crate::storage::init_finalized_mint_requests(),
+            reserved_mint_keys: Default::default(),
+            next_mint_nonce: 0,
         }
     }
 }
@@ -1312,3 +1708,168 @@ pub fn replace_state(state: CustomsState) {
         *s.borrow_mut() = Some(state);
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::init::BtcNetwork;
+    use ic_base_types::CanisterId;
+
+    const RUNE_A: RuneId = RuneId { block: 840000, tx: 3 };
+    const RUNE_B: RuneId = RuneId { block: 840001, tx: 5 };
+    const RUNE_C: RuneId = RuneId { block: 840002, tx: 1 };
+
+    fn state() -> CustomsState {
+        CustomsState::from(InitArgs {
+            btc_network: BtcNetwork::Regtest,
+            ecdsa_key_name: "some_key".to_string(),
+            max_time_in_queue_nanos: 0,
+            min_confirmations: None,
+            chain_state: ChainState::Active,
+            hub_principal: CanisterId::from(0).into(),
+            runes_oracle_principal: CanisterId::from(0).into(),
+            chain_id: "Bitcoin".into(),
+        })
+    }
+
+    fn add_utxo(s: &mut CustomsState, id: u8, rune_id: RuneId, amount: u128) {
+        s.available_runes_utxos.insert(RunesUtxo {
+            raw: Utxo {
+                outpoint: OutPoint {
+                    txid: [id; 32].into(),
+                    vout: 1,
+                },
+                value: 546,
+                height: 0,
+            },
+            runes: RunesBalance {
+                rune_id,
+                vout: 1,
+                amount,
+            },
+        });
+    }
+
+    fn add_request(s: &mut CustomsState, id: u32, rune_id: RuneId, amount: u128, action: TxAction) {
+        s.pending_rune_tx_requests
+            .entry(rune_id)
+            .or_default()
+            .push(RuneTxRequest {
+                ticket_id: id.to_string(),
+                action,
+                rune_id,
+                amount,
+                address: BitcoinAddress::P2wpkhV0([id as u8; 20]),
+                received_at: 0,
+            });
+    }
+
+    fn pending(s: &CustomsState, rune_id: RuneId) -> Vec<TicketId> {
+        s.pending_rune_tx_requests
+            .get(&rune_id)
+            .map(|requests| requests.iter().map(|r| r.ticket_id.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn ticket_ids(batch: &[RuneTxRequest]) -> Vec<TicketId> {
+        batch.iter().map(|r| r.ticket_id.clone()).collect()
+    }
+
+    #[test]
+    fn test_build_batch_with_several_runes() {
+        let mut s = state();
+        add_utxo(&mut s, 1, RUNE_A, 1_000);
+        add_utxo(&mut s, 2, RUNE_B, 1_000);
+        add_request(&mut s, 1, RUNE_A, 100, TxAction::Redeem);
+        add_request(&mut s, 2, RUNE_A, 200, TxAction::Redeem);
+        add_request(&mut s, 3, RUNE_B, 300, TxAction::Burn);
+
+        let batch = s.build_batch(&[RUNE_A, RUNE_B], 10);
+        assert_eq!(ticket_ids(&batch), vec!["1", "2", "3"]);
+        assert!(pending(&s, RUNE_A).is_empty());
+        assert!(pending(&s, RUNE_B).is_empty());
+    }
+
+    #[test]
+    fn test_build_batch_mint_request_alone() {
+        let mut s = state();
+        add_utxo(&mut s, 1, RUNE_A, 1_000);
+        add_request(&mut s, 1, RUNE_A, 100, TxAction::Redeem);
+        add_request(&mut s, 2, RUNE_B, 0, TxAction::Mint);
+
+        let batch = s.build_batch(&[RUNE_A, RUNE_B], 10);
+        assert_eq!(ticket_ids(&batch), vec!["2"]);
+        assert_eq!(pending(&s, RUNE_A), vec!["1"]);
+        assert!(pending(&s, RUNE_B).is_empty());
+    }
+
+    #[test]
+    fn test_build_batch_puts_back_unfunded_requests() {
+        let mut s = state();
+        add_utxo(&mut s, 1, RUNE_A, 150);
+        add_utxo(&mut s, 2, RUNE_C, 1_000);
+        add_request(&mut s, 1, RUNE_A, 100, TxAction::Redeem);
+        add_request(&mut s, 2, RUNE_A, 100, TxAction::Redeem);
+        add_request(&mut s, 3, RUNE_A, 50, TxAction::Redeem);
+        // The customs holds no UTXO of this rune.
+        add_request(&mut s, 4, RUNE_B, 10, TxAction::Redeem);
+        add_request(&mut s, 5, RUNE_C, 10, TxAction::Redeem);
+
+        let batch = s.build_batch(&[RUNE_A, RUNE_B, RUNE_C], 10);
+        assert_eq!(ticket_ids(&batch), vec!["1", "3", "5"]);
+        assert_eq!(pending(&s, RUNE_A), vec!["2"]);
+        assert_eq!(pending(&s, RUNE_B), vec!["4"]);
+        assert!(pending(&s, RUNE_C).is_empty());
+    }
+
+    #[test]
+    fn test_build_batch_respects_max_size() {
+        let mut s = state();
+        add_utxo(&mut s, 1, RUNE_A, 1_000);
+        add_utxo(&mut s, 2, RUNE_B, 1_000);
+        for id in 1..=3 {
+            add_request(&mut s, id, RUNE_A, 10, TxAction::Redeem);
+        }
+        add_request(&mut s, 4, RUNE_B, 10, TxAction::Redeem);
+
+        let batch = s.build_batch(&[RUNE_A, RUNE_B], 2);
+        assert_eq!(ticket_ids(&batch), vec!["1", "2"]);
+        assert_eq!(pending(&s, RUNE_A), vec!["3"]);
+        assert_eq!(pending(&s, RUNE_B), vec!["4"]);
+    }
+
+    #[test]
+    fn test_build_batch_runestone_limit() {
+        const AMOUNT: u128 = 1_000_000_000_000_000_000;
+        let mut s = state();
+        add_utxo(&mut s, 1, RUNE_A, AMOUNT * 100);
+        for id in 1..=20 {
+            add_request(&mut s, id, RUNE_A, AMOUNT, TxAction::Redeem);
+        }
+
+        let batch = s.build_batch(&[RUNE_A], 100);
+        assert!(!batch.is_empty());
+        assert!(batch.len() < 20);
+        assert_eq!(batch.len() + pending(&s, RUNE_A).len(), 20);
+
+        let edicts = |count: usize| {
+            (0..count)
+                .map(|_| Edict {
+                    id: RUNE_A,
+                    amount: AMOUNT,
+                    output: 0,
+                })
+                .collect::<Vec<_>>()
+        };
+        let encipher = |edicts| {
+            Runestone {
+                edicts,
+                ..Default::default()
+            }
+            .encipher()
+            .len()
+        };
+        assert!(encipher(edicts(batch.len())) <= 82);
+        assert!(encipher(edicts(batch.len() + 1)) > 82);
+    }
+}
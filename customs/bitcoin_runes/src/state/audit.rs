@@ -1,8 +1,10 @@
 //! State modifications that should end up in the event log.
 
-use super::{eventlog::Event, CustomsState, GenTicketRequestV2, RuneId, RuneTxRequest, RunesBalance, SubmittedBtcTransactionV2, BitcoinFeeRate, mutate_state};
+use super::{eventlog::Event, CpfpTransaction, CustomsState, GenTicketRequestV2, PendingDepositPsbt, RuneId, RuneTxRequest, RunesBalance, SubmittedBtcTransactionV2, BitcoinFeeRate, mutate_state};
 use crate::storage::record_event;
-use crate::{destination::Destination, state::RUNES_TOKEN};
+use crate::destination::Destination;
+use crate::fee_policy::FeeRatePolicy;
+use candid::Principal;
 use ic_btc_interface::{Txid, Utxo};
 use omnity_types::{Chain, Factor, ToggleState, Token};
 
@@ -51,6 +53,7 @@ pub fn accept_rune_tx_request(state: &mut CustomsState, request: RuneTxRequest)
 
 pub fn accept_generate_ticket_request(state: &mut CustomsState, request: GenTicketRequestV2) {
     record_event(&Event::AcceptedGenTicketRequestV3(request.clone()));
+    state.pending_deposit_psbts.remove(&request.txid);
     state
         .pending_gen_ticket_requests
         .insert(request.txid, request);
@@ -65,11 +68,7 @@ pub fn confirm_generate_ticket_request(state: &mut CustomsState, req: GenTicketR
         .is_some());
 
     let new_utxos = req.new_utxos.clone();
-    let dest = Destination {
-        target_chain_id: req.target_chain_id.clone(),
-        receiver: req.receiver.clone(),
-        token: Some(RUNES_TOKEN.into()),
-    };
+    let dest = req.destination();
     state.confirmed_gen_ticket_requests.insert(req.txid, req);
     state.add_utxos(dest, new_utxos, true);
 }
@@ -95,6 +94,12 @@ pub fn update_bitcoin_fee_rate(fee_rate: BitcoinFeeRate) {
     record_event(&Event::UpdateBitcoinFeeRate(fee_rate.clone()));
     mutate_state(|s|s.bitcoin_fee_rate = fee_rate);
 }
+
+pub fn update_fee_rate_policy(state: &mut CustomsState, policy: FeeRatePolicy) {
+    record_event(&Event::UpdatedFeeRatePolicy(policy.clone()));
+    state.fee_rate_policy = policy;
+}
+
 pub fn update_runes_balance(state: &mut CustomsState, txid: Txid, balance: RunesBalance) {
     record_event(&Event::UpdatedRunesBalance {
         txid,
@@ -140,6 +145,8 @@ pub fn sent_transaction(state: &mut CustomsState, tx: SubmittedBtcTransactionV2)
         btc_change_output: tx.btc_change_output.clone(),
         submitted_at: tx.submitted_at,
         fee_per_vbyte: tx.fee_per_vbyte,
+        extra_runes_change_outputs: tx.extra_runes_change_outputs.clone(),
+        kind: tx.kind,
     });
 
     state.push_submitted_transaction(tx);
@@ -164,10 +171,57 @@ pub fn replace_transaction(
         fee_per_vbyte: new_tx
             .fee_per_vbyte
             .expect("bug: all replacement transactions must have the fee"),
+        extra_runes_change_outputs: new_tx.extra_runes_change_outputs.clone(),
     });
     state.replace_transaction(&old_txid, new_tx);
 }
 
+pub fn sent_cpfp_transaction(state: &mut CustomsState, child: CpfpTransaction) {
+    record_event(&Event::SentCpfpTransaction {
+        parent_txid: child.parent_txid,
+        txid: child.txid,
+        btc_change_output: child.btc_change_output.clone(),
+        fee_per_vbyte: child.fee_per_vbyte,
+        submitted_at: child.submitted_at,
+    });
+    state.record_cpfp_transaction(child);
+}
+
+pub fn register_deposit_watch(
+    state: &mut CustomsState,
+    destination: Destination,
+    rune_id: RuneId,
+    registered_at: u64,
+    registered_by: Principal,
+) {
+    record_event(&Event::RegisteredDepositWatch {
+        destination: destination.clone(),
+        rune_id,
+        registered_at,
+        registered_by: Some(registered_by),
+    });
+    state.register_deposit_watch(destination, rune_id, registered_at, Some(registered_by));
+}
+
+pub fn remove_deposit_watch(state: &mut CustomsState, destination: Destination) {
+    record_event(&Event::RemovedDepositWatch {
+        destination: destination.clone(),
+    });
+    state.deposit_watches.remove(&destination);
+}
+
+pub fn accept_deposit_psbt(state: &mut CustomsState, pending: PendingDepositPsbt) {
+    record_event(&Event::AcceptedDepositPsbt(pending.clone()));
+    state
+        .pending_deposit_psbts
+        .insert(pending.request.txid, pending);
+}
+
+pub fn remove_deposit_psbt(state: &mut CustomsState, txid: Txid) {
+    record_event(&Event::RemovedDepositPsbt { txid });
+    state.pending_deposit_psbts.remove(&txid);
+}
+
 pub fn update_fee(state: &mut CustomsState, fee: Factor) {
     record_event(&Event::UpdatedFee { fee: fee.clone() });
     match fee {
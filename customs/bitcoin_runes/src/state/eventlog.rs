@@ -1,8 +1,9 @@
-use super::{BtcChangeOutput, GenTicketRequest, GenTicketRequestV2, RuneId, RuneTxRequest, RunesBalance, RunesUtxo, SubmittedBtcTransactionV2, RUNES_TOKEN, BitcoinFeeRate};
+use super::{BtcChangeOutput, GenTicketRequest, GenTicketRequestV2, RuneId, RuneTxRequest, RunesBalance, RunesUtxo, SubmittedBtcTransactionV2, BitcoinFeeRate};
 use crate::destination::Destination;
+use crate::fee_policy::FeeRatePolicy;
 use crate::lifecycle::init::InitArgs;
 use crate::lifecycle::upgrade::UpgradeArgs;
-use crate::state::{CustomsState, ReleaseTokenRequest, RunesChangeOutput};
+use crate::state::{CpfpTransaction, CustomsState, PendingDepositPsbt, ReleaseTokenRequest, RunesChangeOutput, SubmittedTxKind};
 use candid::Principal;
 use ic_btc_interface::{Txid, Utxo};
 use omnity_types::{Chain, Factor, TicketId, ToggleState, Token};
@@ -55,6 +56,10 @@ pub enum Event {
     #[serde(rename = "update_bitcoin_fee_rate")]
     UpdateBitcoinFeeRate(BitcoinFeeRate),
 
+    /// Indicates that the controller updated the fee rate policy.
+    #[serde(rename = "updated_fee_rate_policy")]
+    UpdatedFeeRatePolicy(FeeRatePolicy),
+
     #[serde(rename = "updated_runes_balance")]
     UpdatedRunesBalance {
         #[serde(rename = "txid")]
@@ -129,6 +134,14 @@ pub enum Event {
         #[serde(rename = "fee")]
         #[serde(skip_serializing_if = "Option::is_none")]
         fee_per_vbyte: Option<u64>,
+        /// The change outputs of the other runes packed into the transaction.
+        #[serde(rename = "extra_runes_change_outputs")]
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        extra_runes_change_outputs: Vec<RunesChangeOutput>,
+        /// The kind of the transaction.
+        #[serde(rename = "kind")]
+        #[serde(default)]
+        kind: SubmittedTxKind,
     },
 
     /// Indicates that the customs sent out a new transaction to replace an older transaction
@@ -152,6 +165,29 @@ pub enum Event {
         /// The fee per vbyte (in millisatoshi) that we used for the transaction.
         #[serde(rename = "fee")]
         fee_per_vbyte: u64,
+        #[serde(rename = "extra_runes_change_outputs")]
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        extra_runes_change_outputs: Vec<RunesChangeOutput>,
+    },
+
+    /// Indicates that the customs sent out a child transaction spending the BTC
+    /// change output of a stuck transaction to bump its fee.
+    #[serde(rename = "sent_cpfp_transaction")]
+    SentCpfpTransaction {
+        /// The Txid of the stuck transaction.
+        #[serde(rename = "parent_txid")]
+        parent_txid: Txid,
+        /// The Txid of the child transaction.
+        #[serde(rename = "txid")]
+        txid: Txid,
+        #[serde(rename = "btc_change_output")]
+        btc_change_output: BtcChangeOutput,
+        /// The fee per vbyte (in millisatoshi) that the parent and the child pay together.
+        #[serde(rename = "fee")]
+        fee_per_vbyte: u64,
+        /// The IC time at which the customs submitted the child transaction.
+        #[serde(rename = "submitted_at")]
+        submitted_at: u64,
     },
 
     /// Indicates that the customs received enough confirmations for a bitcoin
@@ -162,6 +198,29 @@ pub enum Event {
         txid: Txid,
     },
 
+    /// Indicates that the user registered the destination for the deposit auto-detection.
+    #[serde(rename = "registered_deposit_watch")]
+    RegisteredDepositWatch {
+        destination: Destination,
+        rune_id: RuneId,
+        registered_at: u64,
+        #[serde(default)]
+        registered_by: Option<Principal>,
+    },
+
+    /// Indicates that the customs stopped scanning the destination.
+    #[serde(rename = "removed_deposit_watch")]
+    RemovedDepositWatch { destination: Destination },
+
+    /// Indicates that the customs is about to broadcast a deposit submitted as
+    /// a signed PSBT, before the hub accepts its pending ticket.
+    #[serde(rename = "accepted_deposit_psbt")]
+    AcceptedDepositPsbt(PendingDepositPsbt),
+
+    /// Indicates that the customs failed to broadcast the deposit transaction.
+    #[serde(rename = "removed_deposit_psbt")]
+    RemovedDepositPsbt { txid: Txid },
+
     #[serde(rename = "added_runes_oracle")]
     AddedRunesOracle { principal: Principal },
 
@@ -249,15 +308,12 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CustomsState, R
             }
             Event::AcceptedGenTicketRequestV2(req) => {
                 let new_utxos = req.new_utxos.clone();
-                let dest = Destination {
-                    target_chain_id: req.target_chain_id.clone(),
-                    receiver: req.receiver.clone(),
-                    token: Some(RUNES_TOKEN.into()),
-                };
+                let dest = req.destination();
                 state.confirmed_gen_ticket_requests.insert(req.txid, req);
                 state.add_utxos(dest, new_utxos, true);
             }
             Event::AcceptedGenTicketRequestV3(req) => {
+                state.pending_deposit_psbts.remove(&req.txid);
                 state.pending_gen_ticket_requests.insert(req.txid, req);
             }
             Event::ConfirmedGenTicketRequest(req) => {
@@ -271,11 +327,7 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CustomsState, R
                         ))
                     })?;
                 let new_utxos = req.new_utxos.clone();
-                let dest = Destination {
-                    target_chain_id: req.target_chain_id.clone(),
-                    receiver: req.receiver.clone(),
-                    token: Some(RUNES_TOKEN.into()),
-                };
+                let dest = req.destination();
                 state.confirmed_gen_ticket_requests.insert(req.txid, req);
                 state.add_utxos(dest, new_utxos, true);
             }
@@ -324,6 +376,8 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CustomsState, R
                 runes_change_output,
                 btc_change_output,
                 submitted_at,
+                extra_runes_change_outputs,
+                kind,
             } => {
                 let mut release_token_requests = Vec::with_capacity(request_release_ids.len());
                 for release_id in request_release_ids {
@@ -353,6 +407,8 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CustomsState, R
                     runes_change_output,
                     btc_change_output,
                     submitted_at,
+                    extra_runes_change_outputs,
+                    kind,
                 });
             }
             Event::ReplacedBtcTransaction {
@@ -362,8 +418,9 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CustomsState, R
                 btc_change_output,
                 submitted_at,
                 fee_per_vbyte,
+                extra_runes_change_outputs,
             } => {
-                let (requests, runes_utxos, btc_utxos) = match state
+                let (requests, runes_utxos, btc_utxos, kind) = match state
                     .submitted_transactions
                     .iter()
                     .find(|tx| tx.txid == old_txid)
@@ -372,6 +429,7 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CustomsState, R
                         tx.requests.clone(),
                         tx.runes_utxos.clone(),
                         tx.btc_utxos.clone(),
+                        tx.kind,
                     ),
                     None => {
                         return Err(ReplayLogError::InconsistentLog(format!(
@@ -393,12 +451,61 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CustomsState, R
                         btc_change_output,
                         submitted_at,
                         fee_per_vbyte: Some(fee_per_vbyte),
+                        extra_runes_change_outputs,
+                        kind,
                     },
                 );
             }
+            Event::SentCpfpTransaction {
+                parent_txid,
+                txid,
+                btc_change_output,
+                fee_per_vbyte,
+                submitted_at,
+            } => {
+                if !state
+                    .submitted_transactions
+                    .iter()
+                    .any(|tx| tx.txid == parent_txid)
+                {
+                    return Err(ReplayLogError::InconsistentLog(format!(
+                        "Cannot bump the fee of a non-existent transaction {}",
+                        &parent_txid
+                    )));
+                }
+                state.record_cpfp_transaction(CpfpTransaction {
+                    txid,
+                    parent_txid,
+                    btc_change_output,
+                    fee_per_vbyte,
+                    submitted_at,
+                });
+            }
             Event::ConfirmedBtcTransaction { txid } => {
                 state.finalize_transaction(&txid);
             }
+            Event::RegisteredDepositWatch {
+                destination,
+                rune_id,
+                registered_at,
+                registered_by,
+            } => state.register_deposit_watch(
+                destination,
+                rune_id,
+                registered_at,
+                registered_by,
+            ),
+            Event::RemovedDepositWatch { destination } => {
+                state.deposit_watches.remove(&destination);
+            }
+            Event::AcceptedDepositPsbt(pending) => {
+                state
+                    .pending_deposit_psbts
+                    .insert(pending.request.txid, pending);
+            }
+            Event::RemovedDepositPsbt { txid } => {
+                state.pending_deposit_psbts.remove(&txid);
+            }
             Event::AddedRunesOracle { principal } => {
                 state.runes_oracles.insert(principal);
             }
@@ -433,6 +540,9 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<CustomsState, R
             Event::UpdateBitcoinFeeRate(f) => {
                 state.bitcoin_fee_rate = f;
             }
+            Event::UpdatedFeeRatePolicy(policy) => {
+                state.fee_rate_policy = policy;
+            }
         }
     }
 
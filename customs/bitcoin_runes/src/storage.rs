@@ -7,6 +7,7 @@ use ic_stable_structures::{
 };
 use std::cell::RefCell;
 use std::ops::Deref;
+use crate::runes_etching::mint::SendMintRequest;
 use crate::runes_etching::InternalEtchingArgs;
 use crate::runes_etching::wallet::builder::EtchingKey;
 
@@ -17,6 +18,8 @@ const PENDING_ETCHING_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(51);
 const FINALIZED_ETCHING_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(52);
 const STASH_ETCHINGS_MEMORY_ID: MemoryId = MemoryId::new(53);
 const STASH_ETCHING_IDS_MEMORY_ID: MemoryId = MemoryId::new(54);
+const PENDING_MINT_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(55);
+const FINALIZED_MINT_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(56);
 
 pub type VMem = VirtualMemory<DefaultMemoryImpl>;
 type EventLog = StableLog<Vec<u8>, VMem, VMem>;
@@ -138,3 +141,14 @@ pub fn init_stash_etchings() -> StableBTreeMap<String, InternalEtchingArgs, VMem
     }))
 }
 
+pub fn init_pending_mint_requests() -> StableBTreeMap<String, SendMintRequest, VMem> {
+    StableBTreeMap::init(with_memory_manager(|m| {
+        m.get(PENDING_MINT_REQUESTS_MEMORY_ID)
+    }))
+}
+
+pub fn init_finalized_mint_requests() -> StableBTreeMap<String, SendMintRequest, VMem> {
+    StableBTreeMap::init(with_memory_manager(|m| {
+        m.get(FINALIZED_MINT_REQUESTS_MEMORY_ID)
+    }))
+}
@@ -22,6 +22,8 @@ const MARKER: u8 = 0;
 const FLAGS: u8 = 1;
 // The signature applies to all inputs and outputs.
 pub const SIGHASH_ALL: u32 = 1;
+// The default signature hash type of taproot inputs, omitted from the signature.
+pub const SIGHASH_DEFAULT: u8 = 0;
 
 /// Bitcoin script opcodes.
 mod ops {
@@ -202,7 +204,8 @@ pub struct SignedInput {
     pub sequence: u32,
     pub signature: EncodedSignature,
     // The public key bytes.
-    // Must be PUBKEY_LEN bytes long.
+    // Must be PUBKEY_LEN bytes long, or empty for a taproot key path spend
+    // whose witness only holds the signature.
     pub pubkey: ByteBuf,
 }
 
@@ -341,6 +344,96 @@ impl<'a> TxSigHasher<'a> {
     }
 }
 
+fn single_sha256(encode: impl FnOnce(&mut Sha256)) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    encode(&mut hasher);
+    hasher.finish()
+}
+
+/// Computes the signature hashes of the inputs spending taproot outputs through
+/// the key path with SIGHASH_DEFAULT.
+pub struct TaprootSigHasher<'a> {
+    tx: &'a UnsignedTransaction,
+    sha_prevouts: [u8; 32],
+    sha_amounts: [u8; 32],
+    sha_scriptpubkeys: [u8; 32],
+    sha_sequences: [u8; 32],
+    sha_outputs: [u8; 32],
+}
+
+impl<'a> TaprootSigHasher<'a> {
+    /// Creates a hasher for the transaction, `prevout_addresses` lists the
+    /// addresses of the outputs spent by the inputs, in the input order.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if there is not exactly one address per input.
+    pub fn new(tx: &'a UnsignedTransaction, prevout_addresses: &[BitcoinAddress]) -> Self {
+        assert_eq!(tx.inputs.len(), prevout_addresses.len());
+
+        Self {
+            tx,
+            sha_prevouts: single_sha256(|hasher| {
+                for input in tx.inputs.iter() {
+                    input.previous_output.encode(hasher);
+                }
+            }),
+            sha_amounts: single_sha256(|hasher| {
+                for input in tx.inputs.iter() {
+                    input.value.encode(hasher);
+                }
+            }),
+            sha_scriptpubkeys: single_sha256(|hasher| {
+                for address in prevout_addresses {
+                    encode_address_script_pubkey(address, hasher);
+                }
+            }),
+            sha_sequences: single_sha256(|hasher| {
+                for input in tx.inputs.iter() {
+                    input.sequence.encode(hasher);
+                }
+            }),
+            sha_outputs: single_sha256(|hasher| {
+                for output in tx.outputs.iter() {
+                    output.encode(hasher);
+                }
+            }),
+        }
+    }
+
+    /// Returns the bytes that the input with the specified index needs to sign
+    /// for a taproot key path spend.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the `index` is invalid transaction input index.
+    pub fn sighash(&self, index: usize) -> [u8; 32] {
+        // Spec:
+        // https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#common-signature-message
+        assert!(index < self.tx.inputs.len());
+
+        let tag = Sha256::hash(b"TapSighash");
+        let mut hasher = Sha256::new();
+        hasher.write(&tag);
+        hasher.write(&tag);
+        // Sighash epoch.
+        hasher.write(&[0]);
+        // SIGHASH_DEFAULT commits to all the inputs and outputs.
+        hasher.write(&[SIGHASH_DEFAULT]);
+        TX_VERSION.encode(&mut hasher);
+        self.tx.lock_time.encode(&mut hasher);
+        hasher.write(&self.sha_prevouts);
+        hasher.write(&self.sha_amounts);
+        hasher.write(&self.sha_scriptpubkeys);
+        hasher.write(&self.sha_sequences);
+        hasher.write(&self.sha_outputs);
+        // Spend type: key path spend without annex.
+        hasher.write(&[0]);
+        (index as u32).encode(&mut hasher);
+        hasher.finish()
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct UnsignedTransaction {
     pub inputs: Vec<UnsignedInput>,
@@ -547,12 +640,163 @@ impl Encode for SignedTransaction {
         self.inputs.encode(buf);
         self.outputs.encode(buf);
         for txin in self.inputs.iter() {
-            [
-                Bytes::new(txin.signature.as_slice()),
-                Bytes::new(&txin.pubkey),
-            ][..]
-                .encode(buf);
+            if txin.pubkey.is_empty() {
+                // See: https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#script-validation-rules
+                [Bytes::new(txin.signature.as_slice())][..].encode(buf);
+            } else {
+                [
+                    Bytes::new(txin.signature.as_slice()),
+                    Bytes::new(&txin.pubkey),
+                ][..]
+                    .encode(buf);
+            }
         }
         self.lock_time.encode(buf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes32(s: &str) -> [u8; 32] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    fn bytes20(s: &str) -> [u8; 20] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    /// The key path spending test vector of BIP-341:
+    /// https://github.com/bitcoin/bips/blob/master/bip-0341/wallet-test-vectors.json
+    #[test]
+    fn test_taproot_sighash_bip341_vector() {
+        let inputs = [
+            (
+                "7de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b55963115f3b334e9c",
+                1,
+                0x00000000,
+                420_000_000,
+            ),
+            (
+                "d7b7cab57b1393ace2d064f4d4a2cb8af6def61273e127517d44759b6dafdd99",
+                0,
+                0xffffffff,
+                462_000_000,
+            ),
+            (
+                "f8e1f583384333689228c5d28eac13366be082dc57441760d957275419a41842",
+                0,
+                0xffffffff,
+                294_000_000,
+            ),
+            (
+                "f0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b",
+                1,
+                0xfffffffe,
+                504_000_000,
+            ),
+            (
+                "aa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd3c5a52ee1239e0ba6c",
+                0,
+                0xfffffffe,
+                630_000_000,
+            ),
+            (
+                "956149bdc66faa968eb2be2d2faa29718acbfe3941215893a2a3446d32acd050",
+                0,
+                0x00000000,
+                378_000_000,
+            ),
+            (
+                "e664b9773b88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c94",
+                1,
+                0x00000000,
+                672_000_000,
+            ),
+            (
+                "e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7eadfd4eabf",
+                0,
+                0xffffffff,
+                546_000_000,
+            ),
+            (
+                "a778eb6a263dc090464cd125c466b5a99667720b1c110468831d058aa1b82af1",
+                1,
+                0xffffffff,
+                588_000_000,
+            ),
+        ];
+        let prevout_addresses = [
+            BitcoinAddress::P2trV1(bytes32(
+                "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343",
+            )),
+            BitcoinAddress::P2trV1(bytes32(
+                "147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3",
+            )),
+            BitcoinAddress::P2pkh(bytes20("751e76e8199196d454941c45d1b3a323f1433bd6")),
+            BitcoinAddress::P2trV1(bytes32(
+                "e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e",
+            )),
+            BitcoinAddress::P2trV1(bytes32(
+                "91b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605",
+            )),
+            BitcoinAddress::P2wpkhV0(bytes20("7dd65592d0ab2fe0d0257d571abf032cd9db93dc")),
+            BitcoinAddress::P2trV1(bytes32(
+                "75169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831",
+            )),
+            BitcoinAddress::P2trV1(bytes32(
+                "712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5",
+            )),
+            BitcoinAddress::P2trV1(bytes32(
+                "77e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220",
+            )),
+        ];
+        let tx = UnsignedTransaction {
+            inputs: inputs
+                .iter()
+                .map(|(txid, vout, sequence, value)| UnsignedInput {
+                    previous_output: OutPoint {
+                        txid: bytes32(txid).into(),
+                        vout: *vout,
+                    },
+                    value: *value,
+                    sequence: *sequence,
+                })
+                .collect(),
+            outputs: vec![TxOut {
+                address: BitcoinAddress::P2pkh(bytes20("06afd46bcdfd22ef94ac122aa11f241244a37ecc")),
+                value: 1_000_000_000,
+            }],
+            lock_time: 500_000_000,
+        };
+
+        let mut hasher = TaprootSigHasher::new(&tx, &prevout_addresses);
+        assert_eq!(
+            hasher.sha_prevouts,
+            bytes32("e3b33bb4ef3a52ad1fffb555c0d82828eb22737036eaeb02a235d82b909c4c3f")
+        );
+        assert_eq!(
+            hasher.sha_amounts,
+            bytes32("58a6964a4f5f8f0b642ded0a8a553be7622a719da71d1f5befcefcdee8e0fde6")
+        );
+        assert_eq!(
+            hasher.sha_scriptpubkeys,
+            bytes32("23ad0f61ad2bca5ba6a7693f50fce988e17c3780bf2b1e720cfbb38fbdd52e21")
+        );
+        assert_eq!(
+            hasher.sha_sequences,
+            bytes32("18959c7221ab5ce9e26c3cd67b22c24f8baa54bac281d8e6b05e400e6c3a957e")
+        );
+
+        // The second output of the vector pays to a non-standard script that has
+        // no address, the hash of the outputs is taken from the vector.
+        hasher.sha_outputs =
+            bytes32("a2e6dab7c1f0dcd297c8d61647fd17d821541ea69c3cc37dcbad7f90d4eb4bc5");
+        // The input 4 is the one signed with SIGHASH_DEFAULT.
+        assert_eq!(
+            hasher.sighash(4),
+            bytes32("4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef")
+        );
+    }
+}
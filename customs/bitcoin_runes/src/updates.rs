@@ -1,6 +1,8 @@
+pub mod deposit_psbt;
 pub mod etching;
 pub mod generate_ticket;
 pub mod get_btc_address;
+pub mod register_deposit_watch;
 mod rpc_types;
 pub mod rune_tx;
 pub mod update_btc_utxos;
@@ -9,6 +11,7 @@ pub mod update_runes_balance;
 pub use generate_ticket::generate_ticket;
 pub use get_btc_address::get_btc_address;
 pub use get_btc_address::get_main_btc_address;
+pub use register_deposit_watch::register_deposit_watch;
 pub use rune_tx::generate_rune_tx_request;
 pub use update_btc_utxos::update_btc_utxos;
 pub use update_runes_balance::update_runes_balance;
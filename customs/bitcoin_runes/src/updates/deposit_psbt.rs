@@ -0,0 +1,587 @@
+//! One-step deposit: the customs builds an unsigned PSBT that transfers the runes
+//! of the user to the deposit address, and accepts the PSBT back once the user
+//! signed it, broadcasting the transaction and creating the generate ticket request.
+
+use crate::address::{AddressType, BitcoinAddress};
+use crate::destination::Destination;
+use crate::fee_policy::FeeOperation;
+use crate::guard::generate_ticket_guard;
+use crate::runestone::{Edict, Runestone};
+use crate::state::{
+    audit, mutate_state, read_state, GenTicketRequestV2, PendingDepositPsbt, RUNES_TOKEN,
+};
+use crate::updates::generate_ticket::{
+    accept_deposit, check_allocations, decipher_allocations, validate_generate_ticket_args,
+    GenerateTicketArgs, GenerateTicketError,
+};
+use crate::updates::get_btc_address::{
+    destination_to_deposit_address_from_state, init_deposit_public_key, init_ecdsa_public_key,
+};
+use crate::{estimate_fee_per_vbyte, tx, tx_vsize_estimate, MIN_OUTPUT_AMOUNT};
+use crate::{hub, management};
+use base64::Engine;
+use bitcoin::psbt::{raw::ProprietaryKey, Psbt};
+use bitcoin::{Amount, ScriptBuf, Transaction, TxOut};
+use candid::{CandidType, Deserialize};
+use ic_btc_interface::{Network, OutPoint, Txid, Utxo};
+use ic_canister_log::log;
+use omnity_types::ic_log::{ERROR, INFO};
+use omnity_types::rune_id::RuneId;
+use omnity_types::ChainState;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// The prefix of the PSBT proprietary key holding the deposit memo.
+const PSBT_MEMO_PREFIX: &[u8] = b"omnity";
+
+/// The delay after which the ticket of a broadcast PSBT deposit is sent again,
+/// it leaves time to the submission to get the response of the hub.
+const PENDING_DEPOSIT_RETRY_DELAY_NANOS: u64 = 10 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DepositPsbtInput {
+    pub txid: String,
+    pub vout: u32,
+    pub value: u64,
+    /// The address of the output spent by the input, used by the wallet to sign the input.
+    pub address: String,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BuildDepositPsbtArgs {
+    pub target_chain_id: String,
+    pub receiver: String,
+    pub rune_id: String,
+    pub amount: u128,
+    #[serde(default)]
+    pub address_type: Option<AddressType>,
+    /// The UTXOs of the user holding the runes and the BTC paying the fees.
+    pub inputs: Vec<DepositPsbtInput>,
+    /// The address receiving the remaining runes and the BTC change.
+    pub change_address: String,
+}
+
+/// The destination of the deposit, carried by the PSBT instead of an OP_RETURN output.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct DepositMemo {
+    target_chain_id: String,
+    receiver: String,
+    rune_id: String,
+    amount: u128,
+    address_type: Option<AddressType>,
+}
+
+fn memo_key() -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: PSBT_MEMO_PREFIX.to_vec(),
+        subtype: 0,
+        key: vec![],
+    }
+}
+
+fn to_bitcoin_network(network: Network) -> bitcoin::Network {
+    match network {
+        Network::Mainnet => bitcoin::Network::Bitcoin,
+        Network::Testnet => bitcoin::Network::Testnet,
+        Network::Regtest => bitcoin::Network::Regtest,
+    }
+}
+
+fn parse_address(address: &str, network: Network) -> Result<BitcoinAddress, GenerateTicketError> {
+    BitcoinAddress::parse(address, network)
+        .map_err(|e| GenerateTicketError::InvalidAddress(format!("{}: {}", address, e)))
+}
+
+fn script_pubkey(address: &str, network: Network) -> Result<ScriptBuf, GenerateTicketError> {
+    bitcoin::Address::from_str(address)
+        .and_then(|address| address.require_network(to_bitcoin_network(network)))
+        .map(|address| address.script_pubkey())
+        .map_err(|e| GenerateTicketError::InvalidAddress(format!("{}: {}", address, e)))
+}
+
+fn check_chain_state() -> Result<(), GenerateTicketError> {
+    if read_state(|s| s.chain_state == ChainState::Deactive) {
+        return Err(GenerateTicketError::TemporarilyUnavailable(
+            "chain state is deactive!".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Builds the deposit transaction:
+///
+/// * vout 0: the runestone transferring the amount to the deposit address,
+///   the remaining runes go to the change output.
+/// * vout 1: the deposit address.
+/// * vout 2: the bridge fee, if the target chain requires it.
+/// * last vout: the runes and BTC change of the user.
+pub fn build_deposit_transaction(
+    inputs: Vec<(OutPoint, u64)>,
+    rune_id: RuneId,
+    amount: u128,
+    deposit_address: BitcoinAddress,
+    bridge_fee: Option<(BitcoinAddress, u64)>,
+    change_address: BitcoinAddress,
+    fee_per_vbyte: u64,
+) -> Result<tx::UnsignedTransaction, GenerateTicketError> {
+    /// Having a sequence number lower than (0xffffffff - 1) signals the use of replacement by fee.
+    const SEQUENCE_RBF_ENABLED: u32 = 0xfffffffd;
+
+    let change_vout = if bridge_fee.is_some() { 3 } else { 2 };
+    let runestone = Runestone {
+        edicts: vec![Edict {
+            id: rune_id,
+            amount,
+            output: 1,
+        }],
+        pointer: Some(change_vout),
+        ..Default::default()
+    };
+
+    let mut outputs = vec![
+        tx::TxOut {
+            value: 0,
+            address: BitcoinAddress::OpReturn(runestone.encipher()),
+        },
+        tx::TxOut {
+            value: MIN_OUTPUT_AMOUNT,
+            address: deposit_address,
+        },
+    ];
+    if let Some((address, value)) = bridge_fee {
+        outputs.push(tx::TxOut { value, address });
+    }
+
+    let available = inputs.iter().map(|(_, value)| value).sum::<u64>();
+    let spent = outputs.iter().map(|output| output.value).sum::<u64>();
+    let fee =
+        tx_vsize_estimate(inputs.len() as u64, outputs.len() as u64 + 1) * fee_per_vbyte / 1000;
+    let required = spent + fee + MIN_OUTPUT_AMOUNT;
+    if available < required {
+        return Err(GenerateTicketError::NotEnoughFunds {
+            required,
+            available,
+        });
+    }
+    outputs.push(tx::TxOut {
+        value: available - spent - fee,
+        address: change_address,
+    });
+
+    Ok(tx::UnsignedTransaction {
+        inputs: inputs
+            .into_iter()
+            .map(|(previous_output, value)| tx::UnsignedInput {
+                previous_output,
+                value,
+                sequence: SEQUENCE_RBF_ENABLED,
+            })
+            .collect(),
+        outputs,
+        lock_time: 0,
+    })
+}
+
+/// Returns the base64 encoded unsigned PSBT of the deposit.
+pub async fn build_deposit_psbt(args: BuildDepositPsbtArgs) -> Result<String, GenerateTicketError> {
+    check_chain_state()?;
+    if args.amount == 0 {
+        return Err(GenerateTicketError::AmountIsZero);
+    }
+    init_ecdsa_public_key().await;
+    init_deposit_public_key(args.address_type).await;
+
+    let rune_id = RuneId::from_str(&args.rune_id)
+        .map_err(|e| GenerateTicketError::InvalidRuneId(e.to_string()))?;
+    let btc_network = read_state(|s| s.btc_network);
+
+    let destination = Destination {
+        target_chain_id: args.target_chain_id.clone(),
+        receiver: args.receiver.clone(),
+        token: Some(RUNES_TOKEN.into()),
+        address_type: args.address_type,
+    };
+    let deposit_address = read_state(|s| destination_to_deposit_address_from_state(s, &destination));
+
+    let (fee, fee_collector) = read_state(|s| s.get_transfer_fee_info(&args.target_chain_id));
+    let bridge_fee = match (fee, fee_collector) {
+        (Some(fee), Some(fee_collector)) => Some((
+            parse_address(&fee_collector, btc_network)?,
+            u64::try_from(fee).map_err(|_| GenerateTicketError::NotPayFees)?,
+        )),
+        _ => None,
+    };
+
+    let inputs = args
+        .inputs
+        .iter()
+        .map(|input| {
+            let txid =
+                Txid::from_str(&input.txid).map_err(|_| GenerateTicketError::InvalidTxId)?;
+            Ok((
+                OutPoint {
+                    txid,
+                    vout: input.vout,
+                },
+                input.value,
+            ))
+        })
+        .collect::<Result<Vec<_>, GenerateTicketError>>()?;
+
+    let fee_per_vbyte = estimate_fee_per_vbyte(FeeOperation::Release).await.ok_or_else(|| {
+        GenerateTicketError::TemporarilyUnavailable("the fee rate is unknown".into())
+    })?;
+
+    let unsigned_tx = build_deposit_transaction(
+        inputs,
+        rune_id,
+        args.amount,
+        parse_address(&deposit_address, btc_network)?,
+        bridge_fee,
+        parse_address(&args.change_address, btc_network)?,
+        fee_per_vbyte,
+    )?;
+
+    let unsigned_tx: Transaction =
+        bitcoin::consensus::deserialize(&tx::encode_into(&unsigned_tx, Vec::new()))
+            .expect("bug: failed to decode a valid unsigned transaction");
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+        .map_err(|e| GenerateTicketError::InvalidPsbt(e.to_string()))?;
+    for (psbt_input, input) in psbt.inputs.iter_mut().zip(args.inputs.iter()) {
+        psbt_input.witness_utxo = Some(TxOut {
+            value: Amount::from_sat(input.value),
+            script_pubkey: script_pubkey(&input.address, btc_network)?,
+        });
+    }
+
+    let memo = DepositMemo {
+        target_chain_id: args.target_chain_id,
+        receiver: args.receiver,
+        rune_id: args.rune_id,
+        amount: args.amount,
+        address_type: args.address_type,
+    };
+    let mut memo_bytes = vec![];
+    ciborium::ser::into_writer(&memo, &mut memo_bytes)
+        .expect("bug: failed to encode the deposit memo");
+    psbt.proprietary.insert(memo_key(), memo_bytes);
+
+    Ok(base64::engine::general_purpose::STANDARD.encode(psbt.serialize()))
+}
+
+/// Accepts the signed PSBT of the deposit, broadcasts the transaction and
+/// creates the generate ticket request. Returns the txid of the deposit.
+pub async fn submit_deposit_psbt(psbt: String) -> Result<String, GenerateTicketError> {
+    check_chain_state()?;
+
+    let psbt = base64::engine::general_purpose::STANDARD
+        .decode(psbt)
+        .map_err(|e| GenerateTicketError::InvalidPsbt(e.to_string()))
+        .and_then(|bytes| {
+            Psbt::deserialize(&bytes).map_err(|e| GenerateTicketError::InvalidPsbt(e.to_string()))
+        })?;
+    let memo: DepositMemo = psbt
+        .proprietary
+        .get(&memo_key())
+        .ok_or_else(|| GenerateTicketError::InvalidPsbt("missing the deposit memo".into()))
+        .and_then(|bytes| {
+            ciborium::de::from_reader(bytes.as_slice())
+                .map_err(|e| GenerateTicketError::InvalidPsbt(e.to_string()))
+        })?;
+    if memo.amount == 0 {
+        return Err(GenerateTicketError::AmountIsZero);
+    }
+
+    let mut signed_tx = psbt.unsigned_tx.clone();
+    for (index, (txin, input)) in signed_tx.input.iter_mut().zip(psbt.inputs).enumerate() {
+        if input.final_script_witness.is_none() && input.final_script_sig.is_none() {
+            return Err(GenerateTicketError::InvalidPsbt(format!(
+                "the input {} is not finalized",
+                index
+            )));
+        }
+        txin.witness = input.final_script_witness.unwrap_or_default();
+        txin.script_sig = input.final_script_sig.unwrap_or_default();
+    }
+
+    // The script sigs of the legacy and wrapped inputs are part of the txid,
+    // so it is taken from the finalized transaction.
+    let args = GenerateTicketArgs {
+        target_chain_id: memo.target_chain_id,
+        receiver: memo.receiver,
+        rune_id: memo.rune_id,
+        amount: memo.amount,
+        txid: signed_tx.txid().to_string(),
+        address_type: memo.address_type,
+    };
+
+    init_ecdsa_public_key().await;
+    init_deposit_public_key(args.address_type).await;
+    let _guard = generate_ticket_guard()?;
+
+    let (rune_id, txid, token_id) = validate_generate_ticket_args(&args)?;
+
+    let btc_network = read_state(|s| s.btc_network);
+    let destination = Destination {
+        target_chain_id: args.target_chain_id.clone(),
+        receiver: args.receiver.clone(),
+        token: Some(RUNES_TOKEN.into()),
+        address_type: args.address_type,
+    };
+    let address = read_state(|s| destination_to_deposit_address_from_state(s, &destination));
+    let deposit_script = script_pubkey(&address, btc_network)?;
+
+    let new_utxos: Vec<Utxo> = signed_tx
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| output.script_pubkey == deposit_script)
+        .map(|(vout, output)| Utxo {
+            outpoint: OutPoint {
+                txid,
+                vout: vout as u32,
+            },
+            value: output.value.to_sat(),
+            // The height is updated when the transaction is confirmed.
+            height: 0,
+        })
+        .collect();
+    if new_utxos.is_empty() {
+        return Err(GenerateTicketError::NoNewUtxos);
+    }
+
+    let allocations = decipher_allocations(&signed_tx.output, &new_utxos)?;
+    check_allocations(&rune_id, args.amount, &allocations)?;
+
+    let (fee, fee_collector) = read_state(|s| s.get_transfer_fee_info(&args.target_chain_id));
+    if let (Some(fee_value), Some(fee_collector)) = (fee, fee_collector) {
+        let fee_script = script_pubkey(&fee_collector, btc_network)?;
+        if !signed_tx.output.iter().any(|output| {
+            output.script_pubkey == fee_script && output.value.to_sat() as u128 == fee_value
+        }) {
+            return Err(GenerateTicketError::NotPayFees);
+        }
+    }
+
+    let now = ic_cdk::api::time();
+    let pending = PendingDepositPsbt {
+        request: GenTicketRequestV2 {
+            address,
+            target_chain_id: args.target_chain_id,
+            receiver: args.receiver,
+            token_id,
+            rune_id,
+            amount: args.amount,
+            txid,
+            new_utxos,
+            received_at: now,
+            allocations: Some(allocations),
+            address_type: args.address_type,
+        },
+        bridge_fee: fee,
+        submitted_at: now,
+    };
+    // The deposit is recorded before the transaction is broadcast, so that the
+    // ticket is still sent to the hub if the call to the hub fails afterwards.
+    mutate_state(|s| audit::accept_deposit_psbt(s, pending.clone()));
+
+    if let Err(err) =
+        management::send_raw_transaction(bitcoin::consensus::serialize(&signed_tx), btc_network)
+            .await
+    {
+        mutate_state(|s| audit::remove_deposit_psbt(s, txid));
+        return Err(GenerateTicketError::SendTransactionErr(err.to_string()));
+    }
+
+    if let Err(err) = accept_deposit(
+        args.txid.clone(),
+        pending.request,
+        pending.bridge_fee,
+        Some(pending.submitted_at),
+    )
+    .await
+    {
+        // The transaction is out, the ticket is sent again by the deposit watcher.
+        log!(
+            ERROR,
+            "[submit_deposit_psbt]: failed to send the ticket of the deposit {}: {:?}",
+            txid,
+            err
+        );
+    }
+    Ok(args.txid)
+}
+
+/// Sends the tickets of the deposits submitted as PSBTs that the hub did not
+/// accept, once the submission had time to complete.
+pub async fn retry_pending_deposit_psbts() {
+    let now = ic_cdk::api::time();
+    let pending_deposits: Vec<PendingDepositPsbt> = read_state(|s| {
+        s.pending_deposit_psbts
+            .values()
+            .filter(|pending| pending.submitted_at + PENDING_DEPOSIT_RETRY_DELAY_NANOS <= now)
+            .cloned()
+            .collect()
+    });
+    let hub_principal = read_state(|s| s.hub_principal);
+
+    for pending in pending_deposits {
+        let txid = pending.request.txid;
+        // The hub may have accepted the ticket while its response was lost.
+        match hub::get_ticket_status(hub_principal, txid.to_string()).await {
+            Ok(Some(_)) => {
+                mutate_state(|s| {
+                    if s.pending_deposit_psbts.contains_key(&txid) {
+                        audit::accept_generate_ticket_request(s, pending.request);
+                    }
+                });
+                continue;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                log!(
+                    ERROR,
+                    "[retry_pending_deposit_psbts]: failed to get the ticket status of {}: {}",
+                    txid,
+                    err
+                );
+                continue;
+            }
+        }
+        if !read_state(|s| s.pending_deposit_psbts.contains_key(&txid)) {
+            continue;
+        }
+        match accept_deposit(
+            txid.to_string(),
+            pending.request,
+            pending.bridge_fee,
+            Some(pending.submitted_at),
+        )
+        .await
+        {
+            Ok(()) => log!(
+                INFO,
+                "[retry_pending_deposit_psbts]: sent the ticket of the deposit {}",
+                txid
+            ),
+            Err(err) => log!(
+                ERROR,
+                "[retry_pending_deposit_psbts]: failed to send the ticket of the deposit {}: {:?}",
+                txid,
+                err
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNE: RuneId = RuneId { block: 840000, tx: 3 };
+
+    fn inputs() -> Vec<(OutPoint, u64)> {
+        vec![
+            (
+                OutPoint {
+                    txid: [1; 32].into(),
+                    vout: 0,
+                },
+                MIN_OUTPUT_AMOUNT,
+            ),
+            (
+                OutPoint {
+                    txid: [2; 32].into(),
+                    vout: 1,
+                },
+                20_000,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_build_deposit_transaction() {
+        let deposit = BitcoinAddress::P2trV1([1; 32]);
+        let change = BitcoinAddress::P2wpkhV0([2; 20]);
+        let unsigned_tx = build_deposit_transaction(
+            inputs(),
+            RUNE,
+            1_000,
+            deposit.clone(),
+            None,
+            change.clone(),
+            10_000,
+        )
+        .unwrap();
+
+        assert_eq!(unsigned_tx.outputs.len(), 3);
+        assert_eq!(unsigned_tx.outputs[1].address, deposit);
+        assert_eq!(unsigned_tx.outputs[2].address, change);
+        let fee = tx_vsize_estimate(2, 3) * 10;
+        assert_eq!(
+            unsigned_tx.outputs[2].value,
+            MIN_OUTPUT_AMOUNT + 20_000 - MIN_OUTPUT_AMOUNT - fee
+        );
+
+        let outputs: Vec<TxOut> = unsigned_tx
+            .outputs
+            .iter()
+            .map(|output| {
+                let mut script = vec![];
+                tx::encode_address_script_pubkey(&output.address, &mut script);
+                TxOut {
+                    value: Amount::from_sat(output.value),
+                    // Skips the length prefix of the script.
+                    script_pubkey: ScriptBuf::from_bytes(script[1..].to_vec()),
+                }
+            })
+            .collect();
+        let new_utxos = vec![Utxo {
+            outpoint: OutPoint {
+                txid: [3; 32].into(),
+                vout: 1,
+            },
+            value: MIN_OUTPUT_AMOUNT,
+            height: 0,
+        }];
+        let allocations = decipher_allocations(&outputs, &new_utxos).unwrap();
+        assert_eq!(allocations[0].max_amount(&RUNE), Some(1_000));
+    }
+
+    #[test]
+    fn test_build_deposit_transaction_with_bridge_fee() {
+        let fee_collector = BitcoinAddress::P2wpkhV0([3; 20]);
+        let unsigned_tx = build_deposit_transaction(
+            inputs(),
+            RUNE,
+            1_000,
+            BitcoinAddress::P2trV1([1; 32]),
+            Some((fee_collector.clone(), 5_000)),
+            BitcoinAddress::P2wpkhV0([2; 20]),
+            10_000,
+        )
+        .unwrap();
+        assert_eq!(unsigned_tx.outputs.len(), 4);
+        assert_eq!(unsigned_tx.outputs[2].address, fee_collector);
+        assert_eq!(unsigned_tx.outputs[2].value, 5_000);
+
+        assert_eq!(
+            build_deposit_transaction(
+                inputs(),
+                RUNE,
+                1_000,
+                BitcoinAddress::P2trV1([1; 32]),
+                Some((fee_collector, 20_000)),
+                BitcoinAddress::P2wpkhV0([2; 20]),
+                10_000,
+            ),
+            Err(GenerateTicketError::NotEnoughFunds {
+                required: MIN_OUTPUT_AMOUNT
+                    + 20_000
+                    + tx_vsize_estimate(2, 4) * 10
+                    + MIN_OUTPUT_AMOUNT,
+                available: MIN_OUTPUT_AMOUNT + 20_000,
+            })
+        );
+    }
+}
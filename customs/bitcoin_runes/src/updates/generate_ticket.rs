@@ -1,11 +1,13 @@
+use crate::address::AddressType;
 use crate::destination::Destination;
 use crate::guard::{generate_ticket_guard, GuardError};
 use crate::hub;
+use crate::runestone::{Artifact, OutputAllocation, Runestone};
 use crate::state::{
     audit, mutate_state, read_state, GenTicketRequestV2, GenTicketStatus, RUNES_TOKEN,
 };
 use crate::updates::get_btc_address::{
-    destination_to_p2wpkh_address_from_state, init_ecdsa_public_key,
+    destination_to_deposit_address_from_state, init_deposit_public_key, init_ecdsa_public_key,
 };
 use crate::updates::rpc_types;
 use crate::updates::rpc_types::Transaction;
@@ -16,7 +18,7 @@ use ic_cdk::api::management_canister::http_request::{
     TransformFunc,
 };
 use omnity_types::rune_id::RuneId;
-use omnity_types::{ChainState, Memo, Ticket, TicketType, TxAction};
+use omnity_types::{ChainState, Memo, Ticket, TicketType, TokenId, TxAction};
 use serde::Serialize;
 use std::str::FromStr;
 
@@ -27,6 +29,9 @@ pub struct GenerateTicketArgs {
     pub rune_id: String,
     pub amount: u128,
     pub txid: String,
+    /// The type of the deposit address, P2WPKH by default.
+    #[serde(default)]
+    pub address_type: Option<AddressType>,
 }
 
 #[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
@@ -37,6 +42,7 @@ pub enum GenerateTicketError {
     NoNewUtxos,
     TxNotFoundInMemPool,
     InvalidRuneId(String),
+    InvalidRunestone(String),
     InvalidTxId,
     UnsupportedChainId(String),
     UnsupportedToken(String),
@@ -44,6 +50,10 @@ pub enum GenerateTicketError {
     RpcError(String),
     AmountIsZero,
     NotPayFees,
+    InvalidAddress(String),
+    InvalidPsbt(String),
+    NotEnoughFunds { required: u64, available: u64 },
+    SendTransactionErr(String),
 }
 
 impl From<GuardError> for GenerateTicketError {
@@ -71,56 +81,29 @@ pub async fn generate_ticket(
     }
 
     init_ecdsa_public_key().await;
+    init_deposit_public_key(args.address_type).await;
     let _guard = generate_ticket_guard()?;
 
-    let rune_id = RuneId::from_str(&args.rune_id)
-        .map_err(|e| GenerateTicketError::InvalidRuneId(e.to_string()))?;
-
-    let txid = Txid::from_str(&args.txid).map_err(|_| GenerateTicketError::InvalidTxId)?;
-
-    if !read_state(|s| {
-        s.counterparties
-            .get(&args.target_chain_id)
-            .is_some_and(|c| c.chain_state == ChainState::Active)
-    }) {
-        return Err(GenerateTicketError::UnsupportedChainId(
-            args.target_chain_id.clone(),
-        ));
-    }
-
-    let token_id = read_state(|s| {
-        if let Some((token_id, _)) = s.tokens.iter().find(|(_, (r, _))| rune_id.eq(r)) {
-            Ok(token_id.clone())
-        } else {
-            Err(GenerateTicketError::UnsupportedToken(args.rune_id))
-        }
-    })?;
-
-    read_state(|s| match s.generate_ticket_status(txid) {
-        GenTicketStatus::Pending(_) | GenTicketStatus::Confirmed(_) => {
-            Err(GenerateTicketError::AlreadySubmitted)
-        }
-        GenTicketStatus::Finalized(_) => Err(GenerateTicketError::AlreadyProcessed),
-        GenTicketStatus::Unknown => Ok(()),
-    })?;
-
-    let (chain_id, hub_principal) = read_state(|s| (s.chain_id.clone(), s.hub_principal));
+    let (rune_id, txid, token_id) = validate_generate_ticket_args(&args)?;
 
     let destination = Destination {
         target_chain_id: args.target_chain_id.clone(),
         receiver: args.receiver.clone(),
         token: Some(RUNES_TOKEN.into()),
+        address_type: args.address_type,
     };
 
-    let address = read_state(|s| destination_to_p2wpkh_address_from_state(s, &destination));
+    let address = read_state(|s| destination_to_deposit_address_from_state(s, &destination));
 
     // In order to prevent the memory from being exhausted,
     // ensure that the user has transferred token to this address.
-    let (new_utxos, tx) = fetch_new_utxos(txid, &address).await?;
+    let (new_utxos, tx, allocations) = fetch_new_utxos(txid, &address).await?;
     if new_utxos.is_empty() {
         return Err(GenerateTicketError::NoNewUtxos);
     }
 
+    check_allocations(&rune_id, args.amount, &allocations)?;
+
     //check whether need to pay fees for transfer. If fee is None, that means paying fees is not need
     let (fee, addr) = read_state(|s| s.get_transfer_fee_info(&args.target_chain_id));
     match fee {
@@ -145,9 +128,58 @@ pub async fn generate_ticket(
         }
     }
 
+    let request = GenTicketRequestV2 {
+        address,
+        target_chain_id: args.target_chain_id,
+        receiver: args.receiver,
+        token_id,
+        rune_id,
+        amount: args.amount,
+        txid,
+        new_utxos,
+        received_at: ic_cdk::api::time(),
+        allocations: Some(allocations),
+        address_type: args.address_type,
+    };
+
+    accept_deposit(args.txid, request, fee, time).await
+}
+
+/// Checks that the runestone of the deposit transaction is able to transfer
+/// the amount of the rune to the deposit address.
+pub(crate) fn check_allocations(
+    rune_id: &RuneId,
+    amount: u128,
+    allocations: &[OutputAllocation],
+) -> Result<(), GenerateTicketError> {
+    let max_amount = allocations
+        .iter()
+        .try_fold(0u128, |total, allocation| {
+            allocation
+                .max_amount(rune_id)
+                .map(|amount| total.saturating_add(amount))
+        });
+    if let Some(max_amount) = max_amount.filter(|max_amount| *max_amount < amount) {
+        return Err(GenerateTicketError::InvalidRunestone(format!(
+            "at most {} of {} can be transferred to the deposit address",
+            max_amount, rune_id
+        )));
+    }
+    Ok(())
+}
+
+/// Sends the pending ticket of the deposit to the hub and accepts the generate ticket request.
+pub(crate) async fn accept_deposit(
+    ticket_id: String,
+    request: GenTicketRequestV2,
+    bridge_fee: Option<u128>,
+    time: Option<u64>,
+) -> Result<(), GenerateTicketError> {
+    let (chain_id, hub_principal) = read_state(|s| (s.chain_id.clone(), s.hub_principal));
+
     let memo_json = Memo {
         memo: None,
-        bridge_fee: fee.unwrap_or_default(),
+        bridge_fee: bridge_fee.unwrap_or_default(),
     }
     .convert_to_memo_json()
     .unwrap_or_default();
@@ -155,45 +187,96 @@ pub async fn generate_ticket(
     hub::pending_ticket(
         hub_principal,
         Ticket {
-            ticket_id: args.txid.clone(),
+            ticket_id,
             ticket_type: TicketType::Normal,
             ticket_time: time.unwrap_or(ic_cdk::api::time()),
             src_chain: chain_id,
-            dst_chain: args.target_chain_id.clone(),
+            dst_chain: request.target_chain_id.clone(),
             action: TxAction::Transfer,
-            token: token_id.clone(),
-            amount: args.amount.to_string(),
+            token: request.token_id.clone(),
+            amount: request.amount.to_string(),
             sender: None,
-            receiver: args.receiver.clone(),
+            receiver: request.receiver.clone(),
             memo: Some(memo_json.as_bytes().to_vec()),
         },
     )
     .await
     .map_err(|err| GenerateTicketError::SendTicketErr(format!("{}", err)))?;
 
-    let request = GenTicketRequestV2 {
-        address,
-        target_chain_id: args.target_chain_id,
-        receiver: args.receiver,
-        token_id,
-        rune_id,
-        amount: args.amount,
-        txid,
-        new_utxos: new_utxos.clone(),
-        received_at: ic_cdk::api::time(),
-    };
-
     mutate_state(|s| {
         audit::accept_generate_ticket_request(s, request);
     });
     Ok(())
 }
 
-async fn fetch_new_utxos(
+/// Checks the arguments of a generate ticket request,
+/// returns the rune id, the txid of the deposit and the token id.
+pub(crate) fn validate_generate_ticket_args(
+    args: &GenerateTicketArgs,
+) -> Result<(RuneId, Txid, TokenId), GenerateTicketError> {
+    let rune_id = RuneId::from_str(&args.rune_id)
+        .map_err(|e| GenerateTicketError::InvalidRuneId(e.to_string()))?;
+
+    let txid = Txid::from_str(&args.txid).map_err(|_| GenerateTicketError::InvalidTxId)?;
+
+    if !read_state(|s| {
+        s.counterparties
+            .get(&args.target_chain_id)
+            .is_some_and(|c| c.chain_state == ChainState::Active)
+    }) {
+        return Err(GenerateTicketError::UnsupportedChainId(
+            args.target_chain_id.clone(),
+        ));
+    }
+
+    let token_id = read_state(|s| {
+        if let Some((token_id, _)) = s.tokens.iter().find(|(_, (r, _))| rune_id.eq(r)) {
+            Ok(token_id.clone())
+        } else {
+            Err(GenerateTicketError::UnsupportedToken(args.rune_id.clone()))
+        }
+    })?;
+
+    read_state(|s| match s.generate_ticket_status(txid) {
+        GenTicketStatus::Pending(_) | GenTicketStatus::Confirmed(_) => {
+            Err(GenerateTicketError::AlreadySubmitted)
+        }
+        GenTicketStatus::Finalized(_) => Err(GenerateTicketError::AlreadyProcessed),
+        GenTicketStatus::Unknown => Ok(()),
+    })?;
+
+    Ok((rune_id, txid, token_id))
+}
+
+/// Fetch the deposit transaction and decipher its runestone,
+/// returns the new utxos with the runes they can receive.
+pub(crate) async fn fetch_new_utxos(
     txid: Txid,
     address: &String,
-) -> Result<(Vec<Utxo>, Transaction), GenerateTicketError> {
-    fetch_new_utxos_outcall(txid, address).await
+) -> Result<(Vec<Utxo>, Transaction, Vec<OutputAllocation>), GenerateTicketError> {
+    let (new_utxos, tx) = fetch_new_utxos_outcall(txid, address).await?;
+    let outputs = tx.outputs().map_err(GenerateTicketError::RpcError)?;
+    let allocations = decipher_allocations(&outputs, &new_utxos)?;
+    Ok((new_utxos, tx, allocations))
+}
+
+/// Deciphers the runestone of the deposit transaction,
+/// returns the runes the new utxos can receive.
+pub(crate) fn decipher_allocations(
+    outputs: &[bitcoin::TxOut],
+    new_utxos: &[Utxo],
+) -> Result<Vec<OutputAllocation>, GenerateTicketError> {
+    let artifact = Runestone::decipher(outputs);
+    if let Some(Artifact::Cenotaph(cenotaph)) = &artifact {
+        return Err(GenerateTicketError::InvalidRunestone(format!(
+            "the runes are burned by the cenotaph: {:?}",
+            cenotaph.flaw
+        )));
+    }
+    Ok(new_utxos
+        .iter()
+        .map(|utxo| OutputAllocation::new(artifact.as_ref(), outputs, utxo.outpoint.vout))
+        .collect())
 }
 
 async fn fetch_new_utxos_outcall(
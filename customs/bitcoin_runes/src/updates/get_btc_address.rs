@@ -1,5 +1,5 @@
 use crate::{
-    address::main_bitcoin_address,
+    address::{destination_to_p2tr_address, main_bitcoin_address, AddressType},
     destination::Destination,
     state::{mutate_state, read_state, CustomsState, PROD_KEY, RUNES_TOKEN},
     ECDSAPublicKey,
@@ -14,6 +14,9 @@ use serde::Serialize;
 pub struct GetBtcAddressArgs {
     pub target_chain_id: String,
     pub receiver: String,
+    /// The type of the deposit address, P2WPKH by default.
+    #[serde(default)]
+    pub address_type: Option<AddressType>,
 }
 
 /// PRECONDITION: s.ecdsa_public_key.is_some()
@@ -43,21 +46,53 @@ pub fn destination_to_p2wpkh_address_from_state_v0(
     )
 }
 
+/// Returns the deposit address of the destination, according to its address type.
+///
+/// PRECONDITION: the public key of the address type is initialized.
+pub fn destination_to_deposit_address_from_state(
+    s: &CustomsState,
+    destination: &Destination,
+) -> String {
+    match destination.effective_address_type() {
+        AddressType::P2wpkh => destination_to_p2wpkh_address_from_state(s, destination),
+        AddressType::P2tr => destination_to_p2tr_address(
+            s.btc_network,
+            s.schnorr_public_key
+                .as_ref()
+                .expect("bug: the Schnorr public key must be initialized"),
+            destination,
+        ),
+    }
+}
+
 pub async fn get_btc_address(args: GetBtcAddressArgs) -> String {
-    init_ecdsa_public_key().await;
+    init_deposit_public_key(args.address_type).await;
 
     read_state(|s| {
-        destination_to_p2wpkh_address_from_state(
+        destination_to_deposit_address_from_state(
             s,
             &Destination {
                 target_chain_id: args.target_chain_id,
                 receiver: args.receiver,
                 token: Some(RUNES_TOKEN.into()),
+                address_type: args.address_type,
             },
         )
     })
 }
 
+/// Initializes the public key the deposit addresses of the specified type derive from.
+pub async fn init_deposit_public_key(address_type: Option<AddressType>) {
+    match address_type.unwrap_or_default() {
+        AddressType::P2wpkh => {
+            init_ecdsa_public_key().await;
+        }
+        AddressType::P2tr => {
+            init_schnorr_public_key().await;
+        }
+    }
+}
+
 pub async fn get_main_btc_address(token: String) -> String {
     let pub_key = init_ecdsa_public_key().await;
     let (network, chain_id) = read_state(|s| (s.btc_network, s.chain_id.clone()));
@@ -104,11 +139,31 @@ pub async fn init_ecdsa_public_key() -> ECDSAPublicKey {
     prod_pub_key
 }
 
+/// Initializes the Customs threshold Schnorr public key. This function must be
+/// called before deriving or spending P2TR deposit addresses.
+pub async fn init_schnorr_public_key() -> ECDSAPublicKey {
+    if let Some(key) = read_state(|s| s.schnorr_public_key.clone()) {
+        return key;
+    };
+    let key_name = read_state(|s| s.schnorr_key_name());
+    let pub_key = crate::management::schnorr_public_key(key_name, vec![])
+        .await
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("failed to retrieve Schnorr public key: {e}")));
+    log!(
+        INFO,
+        "Schnorr public key set to {}, chain code to {}",
+        hex::encode(&pub_key.public_key),
+        hex::encode(&pub_key.chain_code)
+    );
+    mutate_state(|s| s.schnorr_public_key = Some(pub_key.clone()));
+    pub_key
+}
+
 #[cfg(test)]
 mod tests {
     use ic_btc_interface::Network;
 
-    use crate::address::network_and_public_key_to_p2wpkh;
+    use crate::address::{network_and_public_key_to_p2tr, network_and_public_key_to_p2wpkh};
 
     fn check_network_and_public_key_result(network: Network, pk_hex: &str, expected: &str) {
         assert_eq!(
@@ -179,4 +234,17 @@ mod tests {
             check_network_and_public_key_result(Network::Mainnet, pk, p2wpkhs);
         }
     }
+
+    #[test]
+    fn network_and_public_key_to_p2tr_mainnet() {
+        // example taken from https://github.com/bitcoin/bips/blob/master/bip-0086.mediawiki#test-vectors
+        assert_eq!(
+            network_and_public_key_to_p2tr(
+                Network::Mainnet,
+                &hex::decode("02cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")
+                    .unwrap()
+            ),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+    }
 }
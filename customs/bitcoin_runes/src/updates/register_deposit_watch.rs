@@ -0,0 +1,107 @@
+use crate::address::AddressType;
+use crate::deposit_watcher::{MAX_DEPOSIT_WATCHES, MAX_DEPOSIT_WATCHES_PER_CALLER};
+use crate::destination::Destination;
+use crate::state::{audit, mutate_state, read_state, RUNES_TOKEN};
+use crate::updates::get_btc_address::{
+    destination_to_deposit_address_from_state, init_deposit_public_key, init_ecdsa_public_key,
+};
+use candid::{CandidType, Deserialize, Principal};
+use ic_canister_log::log;
+use omnity_types::ic_log::INFO;
+use omnity_types::rune_id::RuneId;
+use omnity_types::ChainState;
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RegisterDepositWatchArgs {
+    pub target_chain_id: String,
+    pub receiver: String,
+    pub rune_id: String,
+    /// The type of the deposit address, P2WPKH by default.
+    #[serde(default)]
+    pub address_type: Option<AddressType>,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum RegisterDepositWatchError {
+    TemporarilyUnavailable(String),
+    InvalidRuneId(String),
+    UnsupportedChainId(String),
+    UnsupportedToken(String),
+    TooManyWatches,
+    TooManyWatchesOfCaller,
+    AnonymousCaller,
+}
+
+/// Registers the deposit address of the destination for the deposit auto-detection,
+/// returns the deposit address.
+/// Registering the same destination again with the same rune only returns the
+/// deposit address, the watch is not renewed.
+pub async fn register_deposit_watch(
+    args: RegisterDepositWatchArgs,
+) -> Result<String, RegisterDepositWatchError> {
+    let caller = ic_cdk::api::caller();
+    if caller == Principal::anonymous() {
+        return Err(RegisterDepositWatchError::AnonymousCaller);
+    }
+
+    if read_state(|s| s.chain_state == ChainState::Deactive) {
+        return Err(RegisterDepositWatchError::TemporarilyUnavailable(
+            "chain state is deactive!".into(),
+        ));
+    }
+
+    let rune_id = RuneId::from_str(&args.rune_id)
+        .map_err(|e| RegisterDepositWatchError::InvalidRuneId(e.to_string()))?;
+
+    if !read_state(|s| {
+        s.counterparties
+            .get(&args.target_chain_id)
+            .is_some_and(|c| c.chain_state == ChainState::Active)
+    }) {
+        return Err(RegisterDepositWatchError::UnsupportedChainId(
+            args.target_chain_id,
+        ));
+    }
+
+    if !read_state(|s| s.tokens.values().any(|(r, _)| rune_id.eq(r))) {
+        return Err(RegisterDepositWatchError::UnsupportedToken(args.rune_id));
+    }
+
+    init_ecdsa_public_key().await;
+    init_deposit_public_key(args.address_type).await;
+
+    let destination = Destination {
+        target_chain_id: args.target_chain_id,
+        receiver: args.receiver,
+        token: Some(RUNES_TOKEN.into()),
+        address_type: args.address_type,
+    };
+
+    let address = mutate_state(|s| {
+        let address = destination_to_deposit_address_from_state(s, &destination);
+        match s.deposit_watches.get(&destination) {
+            Some(watch) if watch.rune_id == rune_id => return Ok(address),
+            Some(_) => {}
+            None => {
+                if s.deposit_watches.len() >= MAX_DEPOSIT_WATCHES {
+                    return Err(RegisterDepositWatchError::TooManyWatches);
+                }
+                if s.deposit_watches_of(&caller) >= MAX_DEPOSIT_WATCHES_PER_CALLER {
+                    return Err(RegisterDepositWatchError::TooManyWatchesOfCaller);
+                }
+            }
+        }
+        audit::register_deposit_watch(s, destination, rune_id, ic_cdk::api::time(), caller);
+        Ok(address)
+    })?;
+
+    log!(
+        INFO,
+        "[register_deposit_watch]: watching the deposits of {} to {}",
+        rune_id,
+        address
+    );
+    Ok(address)
+}
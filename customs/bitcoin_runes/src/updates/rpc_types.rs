@@ -1,3 +1,4 @@
+use bitcoin::{Amount, ScriptBuf};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -7,6 +8,23 @@ pub struct Transaction {
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct TxOut {
+    pub scriptpubkey: String,
     pub scriptpubkey_address: Option<String>,
     pub value: u64,
 }
+
+impl Transaction {
+    pub fn outputs(&self) -> Result<Vec<bitcoin::TxOut>, String> {
+        self.vout
+            .iter()
+            .map(|out| {
+                let script_pubkey = ScriptBuf::from_hex(&out.scriptpubkey)
+                    .map_err(|e| format!("invalid scriptpubkey {}: {}", out.scriptpubkey, e))?;
+                Ok(bitcoin::TxOut {
+                    value: Amount::from_sat(out.value),
+                    script_pubkey,
+                })
+            })
+            .collect()
+    }
+}
@@ -80,6 +80,7 @@ pub async fn generate_rune_tx_request(args: RuneTxArgs) -> Result<(), GenRuneTxR
                 target_chain_id: args.src_chain,
                 receiver: args.receiver,
                 token: Some(RUNES_TOKEN.into()),
+                address_type: None,
             },
         ),
         TxAction::Transfer => {
@@ -6,6 +6,7 @@ use ic_btc_interface::{OutPoint, Txid};
 use ic_canister_log::log;
 use omnity_types::ic_log::{ERROR, INFO};
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct UpdateRunesBalanceArgs {
@@ -18,6 +19,7 @@ pub enum UpdateRunesBalanceError {
     RequestNotFound,
     AleardyProcessed,
     MismatchWithGenTicketReq,
+    MismatchWithRunestone,
     UtxoNotFound,
     FinalizeTicketErr(String),
     RequestNotConfirmed,
@@ -55,7 +57,10 @@ pub async fn update_runes_balance(
 
     let amount = args.balances.iter().map(|b| b.amount).sum::<u128>();
     if amount != req.amount || args.balances.iter().any(|b| b.rune_id != req.rune_id) {
-        mutate_state(|s| audit::remove_confirmed_request(s, &req.txid));
+        mutate_state(|s| {
+            audit::remove_confirmed_request(s, &req.txid);
+            s.skip_watched_deposit(&req);
+        });
         log!(
             ERROR,
             "[update_runes_balance] amount mismatch for ticket_id: {}, request amount: {}, oracle amount: {}, oracle: {}",
@@ -67,6 +72,36 @@ pub async fn update_runes_balance(
         return Err(UpdateRunesBalanceError::MismatchWithGenTicketReq);
     }
 
+    // cross check the oracle balances with the runestone of the deposit transaction
+    if let Some(allocations) = &req.allocations {
+        let mut balances = BTreeMap::new();
+        for balance in &args.balances {
+            *balances.entry((balance.vout, balance.rune_id)).or_insert(0u128) += balance.amount;
+        }
+        let mismatched = balances.iter().find(|((vout, rune_id), amount)| {
+            !allocations
+                .iter()
+                .find(|allocation| allocation.vout == *vout)
+                .is_some_and(|allocation| allocation.can_receive(rune_id, **amount))
+        });
+        if let Some(((vout, rune_id), amount)) = mismatched {
+            mutate_state(|s| {
+                audit::remove_confirmed_request(s, &req.txid);
+                s.skip_watched_deposit(&req);
+            });
+            log!(
+                ERROR,
+                "[update_runes_balance] runestone mismatch for ticket_id: {}, vout: {}, rune_id: {}, oracle amount: {}, oracle: {}",
+                args.txid.to_string(),
+                vout,
+                rune_id,
+                amount,
+                ic_cdk::caller().to_string(),
+            );
+            return Err(UpdateRunesBalanceError::MismatchWithRunestone);
+        }
+    }
+
     let hub_principal = read_state(|s| s.hub_principal);
     hub::finalize_ticket(hub_principal, args.txid.to_string())
         .await
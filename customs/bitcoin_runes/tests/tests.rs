@@ -158,6 +158,7 @@ fn test_customs() {
         &GetBtcAddressArgs {
             target_chain_id: String::from(COSMOS_HUB),
             receiver: String::from("cosmos1kwf682z5rxj38jsemljvdh67ykswns77j3euur"),
+            address_type: None,
         },
     );
     let address_1 = Address::from_str(&btc_address_1).expect("invalid bitcoin address");
@@ -167,6 +168,7 @@ fn test_customs() {
         &GetBtcAddressArgs {
             target_chain_id: String::from(COSMOS_HUB),
             receiver: String::from("cosmos12thfgc5swxymm549p7u0qtzvqdepq2m3j4srn6"),
+            address_type: None,
         },
     );
     let address_2 = Address::from_str(&btc_address_2).expect("invalid bitcoin address");
@@ -388,6 +390,7 @@ impl CustomsSetup {
                         Encode!(&GetBtcAddressArgs {
                             target_chain_id: dest.target_chain_id,
                             receiver: dest.receiver,
+                            address_type: dest.address_type,
                         })
                         .unwrap(),
                     )
@@ -767,6 +770,7 @@ fn test_gen_ticket_no_new_utxos() {
         rune_id: RUNE_ID_1.into(),
         amount: 1000,
         txid: random_txid().to_string(),
+        address_type: None,
     });
     assert_eq!(result, Err(GenerateTicketError::NoNewUtxos));
 }
@@ -815,6 +819,7 @@ fn test_gen_ticket_success() {
         target_chain_id: target_chain_id.clone(),
         receiver: receiver.clone(),
         token: None,
+        address_type: None,
     });
 
     customs.push_utxos(vec![(deposit_address, utxo)]);
@@ -824,6 +829,7 @@ fn test_gen_ticket_success() {
         rune_id: RUNE_ID_1.into(),
         amount: 100_000_000,
         txid: txid.to_string(),
+        address_type: None,
     });
     assert_eq!(result, Ok(()));
     assert!(matches!(
@@ -851,6 +857,7 @@ fn test_duplicate_submit_gen_ticket() {
         target_chain_id: target_chain_id.clone(),
         receiver: receiver.clone(),
         token: None,
+        address_type: None,
     });
 
     let args = GenerateTicketArgs {
@@ -859,6 +866,7 @@ fn test_duplicate_submit_gen_ticket() {
         rune_id: RUNE_ID_1.into(),
         amount: 100_000_000,
         txid: txid.to_string(),
+        address_type: None,
     };
 
     customs.push_utxos(vec![(deposit_address, utxo)]);
@@ -901,6 +909,7 @@ fn test_update_runes_balance_unconfirmed() {
         target_chain_id: target_chain_id.clone(),
         receiver: receiver.clone(),
         token: None,
+        address_type: None,
     });
 
     let args = GenerateTicketArgs {
@@ -909,6 +918,7 @@ fn test_update_runes_balance_unconfirmed() {
         rune_id: RUNE_ID_1.into(),
         amount: 100_000_000,
         txid: txid.to_string(),
+        address_type: None,
     };
 
     customs.push_utxos(vec![(deposit_address, utxo)]);
@@ -947,6 +957,7 @@ fn test_update_runes_balance_invalid() {
         target_chain_id: target_chain_id.clone(),
         receiver: receiver.clone(),
         token: None,
+        address_type: None,
     });
 
     let args = GenerateTicketArgs {
@@ -955,6 +966,7 @@ fn test_update_runes_balance_invalid() {
         rune_id: RUNE_ID_1.into(),
         amount: 100_000_000,
         txid: txid.to_string(),
+        address_type: None,
     };
 
     customs.push_utxos(vec![(deposit_address, utxo)]);
@@ -1014,6 +1026,7 @@ fn test_update_runes_balance_multi_utxos() {
         target_chain_id: target_chain_id.clone(),
         receiver: receiver.clone(),
         token: None,
+        address_type: None,
     });
 
     let args = GenerateTicketArgs {
@@ -1022,6 +1035,7 @@ fn test_update_runes_balance_multi_utxos() {
         rune_id: RUNE_ID_1.into(),
         amount: 300_000_000,
         txid: txid.to_string(),
+        address_type: None,
     };
 
     customs.push_utxos(vec![
@@ -1097,6 +1111,7 @@ fn deposit_runes_to_main_address(
         target_chain_id: target_chain_id.clone(),
         receiver: receiver.clone(),
         token: None,
+        address_type: None,
     });
 
     customs.push_utxos(vec![(deposit_address, utxo)]);
@@ -1106,6 +1121,7 @@ fn deposit_runes_to_main_address(
         rune_id: rune_id.clone(),
         amount: 100_000_000,
         txid: txid.to_string(),
+        address_type: None,
     });
     assert_eq!(result, Ok(()));
 
@@ -22,6 +22,28 @@ pub async fn send_ticket(hub_principal: Principal, ticket: Ticket) -> Result<(),
     Ok(data)
 }
 
+pub async fn ack_tickets(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    ack(hub_principal, "ack_tickets", seq).await
+}
+
+pub async fn ack_directives(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    ack(hub_principal, "ack_directives", seq).await
+}
+
+async fn ack(hub_principal: Principal, method: &str, seq: Seq) -> Result<(), CallError> {
+    let resp: (Result<(), omnity_types::Error>,) =
+        ic_cdk::api::call::call(hub_principal, method, (None::<ChainId>, seq))
+            .await
+            .map_err(|(code, message)| CallError {
+                method: method.to_string(),
+                reason: Reason::from_reject(code, message),
+            })?;
+    resp.0.map_err(|err| CallError {
+        method: method.to_string(),
+        reason: Reason::CanisterError(err.to_string()),
+    })
+}
+
 pub async fn query_tickets(
     hub_principal: Principal,
     offset: u64,
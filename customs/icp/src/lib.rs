@@ -125,6 +125,12 @@ async fn process_tickets() {
                     },
                 }
             }
+            let next_seq = read_state(|s| s.next_ticket_seq);
+            if next_seq > offset {
+                if let Err(err) = hub::ack_tickets(hub_principal, next_seq - 1).await {
+                    log!(ERROR, "[process tickets] failed to ack tickets, err: {}", err);
+                }
+            }
         }
         Err(err) => {
             log!(ERROR, "[process tickets] failed to query tickets, err: {}", err);
@@ -182,6 +188,14 @@ async fn process_directives() {
             mutate_state(|s| {
                 s.next_directive_seq = next_seq;
             });
+            if next_seq > offset {
+                if let Err(err) = hub::ack_directives(hub_principal, next_seq - 1).await {
+                    log!(ERROR,
+                        "[process directives] failed to ack directives, err: {:?}",
+                        err
+                    );
+                }
+            }
         }
         Err(err) => {
             log!(ERROR,
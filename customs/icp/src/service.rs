@@ -10,7 +10,7 @@ use ic_cdk_macros::{init, post_upgrade, query, update};
 use ic_cdk_timers::set_timer_interval;
 use ic_ledger_types::{AccountIdentifier, Subaccount};
 use omnity_types::MintTokenStatus::{Finalized, Unknown};
-use omnity_types::{Chain, MintTokenStatus, Seq, Ticket, TicketId, Token};
+use omnity_types::{Chain, MintTokenStatus, Seq, Ticket, TicketId, Token, TokenId};
 use ic_canister_log::log;
 use omnity_types::ic_log::INFO;
 
@@ -22,6 +22,22 @@ pub fn is_controller() -> Result<(), String> {
     }
 }
 
+pub fn is_hub() -> Result<(), String> {
+    if read_state(|s| s.hub_principal) == ic_cdk::caller() {
+        Ok(())
+    } else {
+        Err("caller is not hub".to_string())
+    }
+}
+
+pub fn is_hub_or_controller() -> Result<(), String> {
+    if read_state(|s| s.hub_principal) == ic_cdk::caller() {
+        Ok(())
+    } else {
+        is_controller()
+    }
+}
+
 #[init]
 fn init(args: InitArgs) {
     lifecycle::init(args);
@@ -39,6 +55,20 @@ fn post_upgrade() {
     );
 }
 
+/// Notified by the hub once the customs subscribed the push mode, the tickets
+/// are pulled immediately instead of waiting for the next timer.
+#[update(guard = "is_hub")]
+fn on_new_tickets(from_seq: Seq) {
+    log!(INFO, "[on_new_tickets] notified from seq: {}", from_seq);
+    periodic_task();
+}
+
+#[update(guard = "is_hub")]
+fn on_new_directives(from_seq: Seq) {
+    log!(INFO, "[on_new_directives] notified from seq: {}", from_seq);
+    periodic_task();
+}
+
 fn check_anonymous_caller() {
     if ic_cdk::caller() == Principal::anonymous() {
         panic!("anonymous caller not allowed")
@@ -74,6 +104,11 @@ fn get_account_identifier_text(principal: Principal) -> String {
     AccountIdentifier::new(&ic_cdk::api::id(), &subaccount).to_hex()
 }
 
+#[update(guard = "is_hub_or_controller")]
+async fn get_token_supply(token_id: TokenId) -> Result<u128, String> {
+    updates::generate_ticket::locked_token_supply(&token_id).await
+}
+
 #[query]
 fn get_chain_list() -> Vec<Chain> {
     crate::state::get_chain_list()
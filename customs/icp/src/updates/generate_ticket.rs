@@ -177,6 +177,37 @@ pub async fn generate_ticket(
     Ok(GenerateTicketOk { ticket_id })
 }
 
+/// the tokens locked in the main account of the customs, it`s used by the hub to reconcile the token position
+pub async fn locked_token_supply(token_id: &String) -> Result<u128, String> {
+    if is_icp(token_id) {
+        let balance = ic_balance_of(&DEFAULT_SUBACCOUNT)
+            .await
+            .map_err(|e| format!("Failed to get icp balance, error: {:?}", e))?;
+        return Ok(balance.e8s() as u128);
+    }
+    let ledger_id = get_token_principal(token_id).ok_or(format!("token not found: {}", token_id))?;
+    let client = ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id: ledger_id,
+    };
+    let balance = client
+        .balance_of(Account {
+            owner: ic_cdk::id(),
+            subaccount: None,
+        })
+        .await
+        .map_err(|(code, msg)| {
+            format!(
+                "Failed to get balance from ledger: {} code: {} msg: {}",
+                ledger_id, code, msg
+            )
+        })?;
+    balance
+        .0
+        .to_u128()
+        .ok_or(format!("balance does not fit into u128: {}", balance))
+}
+
 async fn ic_balance_of(subaccount: &IcSubaccount) -> Result<Tokens, GenerateTicketError> {
     let account_identifier = AccountIdentifier::new(&ic_cdk::api::id(), &subaccount);
     let balance_args = ic_ledger_types::AccountBalanceArgs {
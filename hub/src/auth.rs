@@ -30,6 +30,32 @@ pub fn is_admin() -> Result<(), String> {
     })
 }
 
+pub fn is_controller() -> Result<(), String> {
+    let caller = ic_cdk::api::caller();
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(())
+    } else {
+        log!(ERROR, "{:?} Not Controller!", caller.to_string());
+        Err("Not Controller!".into())
+    }
+}
+
+pub fn is_approver() -> Result<(), String> {
+    let caller = ic_cdk::api::caller();
+    with_state(|s| {
+        if !s
+            .governance
+            .as_ref()
+            .is_some_and(|g| g.approvers.contains(&caller))
+        {
+            log!(ERROR, "{:?} Not Approver!", caller.to_string());
+            Err("Not Approver!".into())
+        } else {
+            Ok(())
+        }
+    })
+}
+
 pub fn is_runes_oracle() -> Result<(), String> {
     let caller = ic_cdk::api::caller();
     with_state(|s| {
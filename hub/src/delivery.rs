@@ -0,0 +1,79 @@
+use std::borrow::Cow;
+
+use candid::CandidType;
+use ic_canister_log::log;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use omnity_types::hub_types::TicketStatus;
+use omnity_types::ic_log::{ERROR, INFO};
+use omnity_types::{ChainId, TicketId, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::state::with_state_mut;
+
+pub const CHECK_DELIVERY_INTERVAL: u64 = 10 * 60;
+// the overdue tickets left are resubmitted by the next ticks
+pub const MAX_RESUBMISSIONS_PER_TICK: usize = 50;
+
+/// The delivery deadline of a ticket sent to a chain with the delivery policy
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Delivery {
+    pub dst_chain: ChainId,
+    pub deadline: Timestamp,
+    // how many times the original ticket has been resubmitted automatically
+    pub auto_resubmitted: u32,
+}
+
+impl Delivery {
+    pub fn is_overdue(&self, now: Timestamp) -> bool {
+        now > self.deadline
+    }
+}
+
+impl Storable for Delivery {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = vec![];
+        let _ = ciborium::ser::into_writer(self, &mut bytes);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let delivery =
+            ciborium::de::from_reader(bytes.as_ref()).expect("failed to decode Delivery");
+        delivery
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct OverdueTicket {
+    pub ticket_id: TicketId,
+    pub dst_chain: ChainId,
+    pub deadline: Timestamp,
+    pub auto_resubmitted: u32,
+    pub status: Option<TicketStatus>,
+}
+
+/// resubmit the overdue tickets of the chains allowing automatic resubmission
+pub fn resubmit_overdue_tickets_task() {
+    let now = ic_cdk::api::time();
+    with_state_mut(|hub_state| {
+        for ticket_id in hub_state.auto_resubmittable_tickets(now, MAX_RESUBMISSIONS_PER_TICK) {
+            match hub_state.requeue_ticket(&ticket_id, now, true) {
+                Ok(new_id) => log!(
+                    INFO,
+                    "The overdue ticket (`{}`) is resubmitted as (`{}`)",
+                    ticket_id,
+                    new_id
+                ),
+                Err(e) => log!(
+                    ERROR,
+                    "failed to resubmit the overdue ticket (`{}`): {:?}",
+                    ticket_id,
+                    e
+                ),
+            }
+        }
+    });
+}
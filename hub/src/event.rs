@@ -1,4 +1,9 @@
 use crate::auth::Permission;
+use crate::delivery::Delivery;
+use crate::governance::ProposalRecord;
+use crate::rate_limit::{HeldTicket, TicketVolume};
+use crate::resubmission::Resubmission;
+use crate::supply::SupplyReport;
 use crate::lifecycle::init::InitArgs;
 use crate::lifecycle::upgrade::UpgradeArgs;
 use crate::state::HubState;
@@ -13,7 +18,10 @@ use std::cell::RefCell;
 
 use crate::memory::{init_event_log, Memory};
 
-use omnity_types::hub_types::{ChainMeta, ChainTokenFactor, Subscribers, TokenKey, TokenMeta};
+use omnity_types::hub_types::{
+    ChainMeta, ChainTokenFactor, DeliveryPolicy, GovernanceConfig, Subscribers,
+    SupplyAuditConfig, TicketStatus, TokenKey, TokenMeta, TokenRateLimit,
+};
 
 use omnity_types::ToggleState;
 use serde::{Deserialize, Serialize};
@@ -52,6 +60,24 @@ pub fn events(args: GetEventsArg) -> Vec<Event> {
     })
 }
 
+/// Returns the events in the range, without the query limit.
+pub fn events_range(start: u64, length: u64) -> Vec<Event> {
+    EVENTS.with(|events| {
+        events
+            .borrow()
+            .iter()
+            .skip(start as usize)
+            .take(length as usize)
+            .map(|bytes| decode_event(&bytes))
+            .collect()
+    })
+}
+
+/// Reconstructs the hub state from the stable event log.
+pub fn replay_event_log() -> Result<HubState, ReplayLogError> {
+    EVENTS.with(|events| replay(events.borrow().iter().map(|bytes| decode_event(&bytes))))
+}
+
 /// Returns the current number of events in the log.
 pub fn count_events() -> u64 {
     EVENTS.with(|events| events.borrow().len())
@@ -129,11 +155,75 @@ pub enum Event {
     #[serde(rename = "updated_token_position")]
     UpdatedTokenPosition { position: TokenKey, amount: u128 },
 
+    // replaced by the `UpdatedResubmission`, kept for the history events
     #[serde(rename = "resubmit_ticket")]
     ResubmitTicket { ticket_id: String, timestamp: u64 },
 
     #[serde(rename = "updated_tx_hash")]
     UpdatedTxHash { ticket_id: String, tx_hash: String },
+
+    #[serde(rename = "updated_governance")]
+    UpdatedGovernance(GovernanceConfig),
+
+    #[serde(rename = "saved_governance_proposal")]
+    SavedGovernanceProposal(ProposalRecord),
+
+    #[serde(rename = "updated_rate_limit")]
+    UpdatedRateLimit(TokenRateLimit),
+
+    #[serde(rename = "removed_rate_limit")]
+    RemovedRateLimit(String),
+
+    #[serde(rename = "held_ticket")]
+    ParkedTicket(HeldTicket),
+
+    #[serde(rename = "released_held_ticket")]
+    ReleasedTicket { ticket_id: String },
+
+    #[serde(rename = "updated_ticket_status")]
+    UpdatedTicketStatus {
+        ticket_id: String,
+        status: TicketStatus,
+    },
+
+    #[serde(rename = "acked_tickets")]
+    AckedTickets { chain_id: String, seq: u64 },
+
+    #[serde(rename = "updated_delivery_policy")]
+    UpdatedDeliveryPolicy(DeliveryPolicy),
+
+    #[serde(rename = "tracked_delivery")]
+    TrackedDelivery {
+        ticket_id: String,
+        delivery: Delivery,
+    },
+
+    #[serde(rename = "resolved_delivery")]
+    ResolvedDelivery { ticket_id: String },
+
+    #[serde(rename = "updated_resubmission")]
+    UpdatedResubmission(Resubmission),
+
+    #[serde(rename = "updated_resubmit_cooldown")]
+    UpdatedResubmitCooldown { chain_id: String, cooldown: u64 },
+
+    #[serde(rename = "updated_supply_audit")]
+    UpdatedSupplyAudit(SupplyAuditConfig),
+
+    #[serde(rename = "mismatched_supply")]
+    MismatchedSupply(SupplyReport),
+
+    #[serde(rename = "updated_push_subscription")]
+    UpdatedPushSubscription { chain_id: String, enabled: bool },
+
+    #[serde(rename = "acked_directives")]
+    AckedDirectives { chain_id: String, seq: u64 },
+
+    #[serde(rename = "recorded_ticket_volume")]
+    RecordedTicketVolume {
+        token_id: String,
+        volume: TicketVolume,
+    },
 }
 
 #[derive(Debug)]
@@ -158,123 +248,218 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<HubState, Repla
     };
 
     for event in events {
-        match event {
-            Event::Init(args) => {
-                hub_state
-                    .caller_perms
-                    .insert(args.admin.to_string(), Permission::Update);
-                hub_state.admin = args.admin;
-            }
-            Event::Upgrade(args) => {
-                hub_state.upgrade(args);
-            }
-            Event::UpdatedChain(chain) => {
-                hub_state
-                    .chains
-                    .insert(chain.chain_id.to_string(), chain.clone());
-                // update auth
-                hub_state
-                    .caller_chain_map
-                    .insert(chain.canister_id.to_string(), chain.chain_id.to_string());
+        apply_event(&mut hub_state, event);
+    }
+    Ok(hub_state)
+}
 
+/// Applies the event after the Init event to the hub state.
+pub fn apply_event(hub_state: &mut HubState, event: Event) {
+    match event {
+        Event::Init(args) => {
+            hub_state
+                .caller_perms
+                .insert(args.admin.to_string(), Permission::Update);
+            hub_state.admin = args.admin;
+        }
+        Event::Upgrade(args) => {
+            hub_state.upgrade(args);
+        }
+        Event::UpdatedChain(chain) => {
+            hub_state
+                .chains
+                .insert(chain.chain_id.to_string(), chain.clone());
+            // update auth
+            hub_state
+                .caller_chain_map
+                .insert(chain.canister_id.to_string(), chain.chain_id.to_string());
+
+            hub_state
+                .caller_perms
+                .insert(chain.canister_id.to_string(), Permission::Update);
+        }
+        Event::UpdatedChainCounterparties(chain) => {
+            hub_state
+                .chains
+                .insert(chain.chain_id.to_string(), chain.clone());
+        }
+        Event::AddedToken(token) => {
+            hub_state.tokens.insert(token.token_id.to_string(), token);
+        }
+        Event::ToggledChainState { chain, state } => {
+            hub_state.chains.insert(state.chain_id.to_string(), chain);
+        }
+        Event::UpdatedFee(fee) => match fee {
+            Factor::UpdateTargetChainFactor(cf) => {
                 hub_state
-                    .caller_perms
-                    .insert(chain.canister_id.to_string(), Permission::Update);
+                    .target_chain_factors
+                    .insert(cf.target_chain_id, cf.target_chain_factor);
             }
-            Event::UpdatedChainCounterparties(chain) => {
+            Factor::UpdateFeeTokenFactor(tf) => {
                 hub_state
-                    .chains
-                    .insert(chain.chain_id.to_string(), chain.clone());
+                    .target_chain_factors
+                    .iter()
+                    .for_each(|(chain_id, _)| {
+                        let token_key =
+                            TokenKey::from(chain_id.to_string(), tf.fee_token.to_string());
+                        let fee_factor = ChainTokenFactor {
+                            target_chain_id: chain_id.to_string(),
+                            fee_token: tf.fee_token.to_string(),
+                            fee_token_factor: tf.fee_token_factor,
+                        };
+                        hub_state.fee_token_factors.insert(token_key, fee_factor);
+                    });
             }
-            Event::AddedToken(token) => {
-                hub_state.tokens.insert(token.token_id.to_string(), token);
-            }
-            Event::ToggledChainState { chain, state } => {
-                hub_state.chains.insert(state.chain_id.to_string(), chain);
-            }
-            Event::UpdatedFee(fee) => match fee {
-                Factor::UpdateTargetChainFactor(cf) => {
-                    hub_state
-                        .target_chain_factors
-                        .insert(cf.target_chain_id, cf.target_chain_factor);
-                }
-                Factor::UpdateFeeTokenFactor(tf) => {
+        },
+
+        Event::AddedTokenPosition { position, amount }
+        | Event::UpdatedTokenPosition { position, amount } => {
+            hub_state.token_position.insert(position, amount);
+        }
+        Event::PendingTicket { ticket } => {
+            hub_state
+                .pending_tickets
+                .insert(ticket.ticket_id.to_string(), ticket.clone());
+        }
+        Event::FinalizeTicket { ticket_id } => {
+            hub_state.pending_tickets.remove(&ticket_id);
+        }
+        Event::ReceivedTicket { seq_key, ticket } => {
+            hub_state
+                .ticket_seq
+                .insert(seq_key.chain_id.to_string(), seq_key.seq);
+            // add new ticket to queue
+            hub_state.ticket_queue.insert(seq_key, ticket.clone());
+            //save ticket to ledger
+            hub_state
+                .cross_ledger
+                .insert(ticket.ticket_id.to_string(), ticket.clone());
+        }
+        Event::SubDirectives { topic, subs } => {
+            hub_state.topic_subscribers.insert(topic, subs);
+        }
+        Event::UnSubDirectives { topic, sub } => {
+            if let Some(mut subscribers) = hub_state.topic_subscribers.get(&topic) {
+                if subscribers.subs.remove(&sub) {
                     hub_state
-                        .target_chain_factors
-                        .iter()
-                        .for_each(|(chain_id, _)| {
-                            let token_key =
-                                TokenKey::from(chain_id.to_string(), tf.fee_token.to_string());
-                            let fee_factor = ChainTokenFactor {
-                                target_chain_id: chain_id.to_string(),
-                                fee_token: tf.fee_token.to_string(),
-                                fee_token_factor: tf.fee_token_factor,
-                            };
-                            hub_state.fee_token_factors.insert(token_key, fee_factor);
-                        });
+                        .topic_subscribers
+                        .insert(topic.clone(), subscribers);
                 }
-            },
-
-            Event::AddedTokenPosition { position, amount }
-            | Event::UpdatedTokenPosition { position, amount } => {
-                hub_state.token_position.insert(position, amount);
             }
-            Event::PendingTicket { ticket } => {
-                hub_state
-                    .pending_tickets
-                    .insert(ticket.ticket_id.to_string(), ticket.clone());
-            }
-            Event::FinalizeTicket { ticket_id } => {
-                hub_state.pending_tickets.remove(&ticket_id);
+        }
+        Event::SavedDirective(dire) => {
+            hub_state.directives.insert(dire.hash(), dire);
+        }
+        Event::DeletedDirective(seq_key) => {
+            hub_state.dire_queue.remove(&seq_key);
+        }
+        Event::PubedDirective { seq_key, dire } => {
+            hub_state
+                .directive_seq
+                .insert(seq_key.chain_id.to_string(), seq_key.seq);
+            hub_state.dire_queue.insert(seq_key, dire);
+        }
+        Event::ResubmitTicket { .. } => {}
+
+        Event::UpdatedTxHash { ticket_id, tx_hash } => {
+            hub_state.tx_hashes.insert(ticket_id, tx_hash);
+        }
+        Event::UpdatedGovernance(config) => {
+            hub_state.governance = Some(config);
+        }
+        Event::SavedGovernanceProposal(record) => {
+            if record.id >= hub_state.next_proposal_id {
+                hub_state.next_proposal_id = record.id + 1;
             }
-            Event::ReceivedTicket { seq_key, ticket } => {
-                hub_state
-                    .ticket_seq
-                    .insert(seq_key.chain_id.to_string(), seq_key.seq);
-                // add new ticket to queue
-                hub_state
-                    .ticket_map
-                    .insert(seq_key, ticket.ticket_id.to_string());
-                //save ticket to ledger
-                hub_state
-                    .cross_ledger
-                    .insert(ticket.ticket_id.to_string(), ticket.clone());
+            hub_state.governance_proposals.insert(record.id, record);
+        }
+        Event::UpdatedRateLimit(limit) => {
+            hub_state
+                .rate_limits
+                .insert(limit.token_id.to_string(), limit);
+        }
+        Event::RemovedRateLimit(token_id) => {
+            hub_state.rate_limits.remove(&token_id);
+            hub_state.ticket_volumes.remove(&token_id);
+        }
+        Event::ParkedTicket(held_ticket) => {
+            hub_state
+                .held_tickets
+                .insert(held_ticket.ticket.ticket_id.to_string(), held_ticket);
+        }
+        Event::ReleasedTicket { ticket_id } => {
+            hub_state.held_tickets.remove(&ticket_id);
+        }
+        Event::UpdatedTicketStatus { ticket_id, status } => {
+            hub_state.track_in_flight(&ticket_id, &status);
+            hub_state.ticket_status.insert(ticket_id, status);
+        }
+        Event::AckedTickets { chain_id, seq } => {
+            hub_state.delivered_seq.insert(chain_id, seq);
+        }
+        Event::UpdatedDeliveryPolicy(policy) => {
+            hub_state
+                .delivery_policies
+                .insert(policy.chain_id.to_string(), policy);
+        }
+        Event::TrackedDelivery {
+            ticket_id,
+            delivery,
+        } => {
+            hub_state.deliveries.insert(ticket_id, delivery);
+        }
+        Event::ResolvedDelivery { ticket_id } => {
+            hub_state.deliveries.remove(&ticket_id);
+        }
+        Event::UpdatedResubmission(resubmission) => {
+            if let Some(resubmitted) = resubmission.tickets.last() {
+                hub_state.resubmission_origins.insert(
+                    resubmitted.ticket_id.to_string(),
+                    resubmission.origin_ticket_id.to_string(),
+                );
             }
-            Event::SubDirectives { topic, subs } => {
-                hub_state.topic_subscribers.insert(topic, subs);
+            hub_state
+                .resubmissions
+                .insert(resubmission.origin_ticket_id.to_string(), resubmission);
+        }
+        Event::UpdatedResubmitCooldown { chain_id, cooldown } => {
+            hub_state.resubmit_cooldowns.insert(chain_id, cooldown);
+        }
+        Event::UpdatedSupplyAudit(config) => hub_state.supply_audit = Some(config),
+        Event::MismatchedSupply(report) => {
+            hub_state.supply_reports.insert(
+                TokenKey::from(report.chain_id.to_string(), report.token_id.to_string()),
+                report,
+            );
+        }
+        Event::UpdatedPushSubscription { chain_id, enabled } => {
+            if enabled {
+                hub_state.push_subscribers.insert(chain_id);
+            } else {
+                hub_state.push_subscribers.remove(&chain_id);
             }
-            Event::UnSubDirectives { topic, sub } => {
-                if let Some(mut subscribers) = hub_state.topic_subscribers.get(&topic) {
-                    if subscribers.subs.remove(&sub) {
-                        hub_state
-                            .topic_subscribers
-                            .insert(topic.clone(), subscribers);
-                    }
+        }
+        Event::AckedDirectives { chain_id, seq } => {
+            hub_state.acked_directive_seq.insert(chain_id, seq);
+        }
+        Event::RecordedTicketVolume { token_id, volume } => {
+            let window = hub_state
+                .rate_limits
+                .get(&token_id)
+                .map(|limit| limit.window);
+            let volumes = hub_state.ticket_volumes.entry(token_id).or_default();
+            // only the volumes in the window of the latest one are kept
+            if let Some(window) = window {
+                while volumes
+                    .front()
+                    .is_some_and(|v| v.timestamp.saturating_add(window) < volume.timestamp)
+                {
+                    volumes.pop_front();
                 }
             }
-            Event::SavedDirective(dire) => {
-                hub_state.directives.insert(dire.hash(), dire);
-            }
-            Event::DeletedDirective(seq_key) => {
-                hub_state.dire_map.remove(&seq_key);
-            }
-            Event::PubedDirective { seq_key, dire } => {
-                hub_state
-                    .directive_seq
-                    .insert(seq_key.chain_id.to_string(), seq_key.seq);
-                hub_state.dire_map.insert(seq_key, dire);
-            }
-            Event::ResubmitTicket {
-                ticket_id: _,
-                timestamp,
-            } => hub_state.last_resubmit_ticket_time = timestamp,
-
-            Event::UpdatedTxHash { ticket_id, tx_hash } => {
-                hub_state.tx_hashes.insert(ticket_id, tx_hash);
-            }
+            volumes.push_back(volume);
         }
     }
-    Ok(hub_state)
 }
 
 #[cfg(test)]
@@ -382,4 +567,49 @@ mod tests {
 
         println!("{:?}", hub_state.admin);
     }
+
+    #[test]
+    fn test_replay_ticket_volumes() {
+        let volume = |timestamp| TicketVolume {
+            timestamp,
+            src_chain: "Bitcoin".to_string(),
+            dst_chain: "eICP".to_string(),
+            amount: 100,
+        };
+        let events = vec![
+            Event::Init(InitArgs {
+                admin: Principal::anonymous(),
+            }),
+            Event::UpdatedRateLimit(omnity_types::hub_types::TokenRateLimit {
+                token_id: "Bitcoin-RUNES-WTF".to_string(),
+                window: 10,
+                token_limit: Some(1_000),
+                src_chain_limit: None,
+                dst_chain_limit: None,
+                ticket_limit: None,
+                circuit_breaker: None,
+            }),
+            Event::RecordedTicketVolume {
+                token_id: "Bitcoin-RUNES-WTF".to_string(),
+                volume: volume(1),
+            },
+            Event::RecordedTicketVolume {
+                token_id: "Bitcoin-RUNES-WTF".to_string(),
+                volume: volume(5),
+            },
+            Event::RecordedTicketVolume {
+                token_id: "Bitcoin-RUNES-WTF".to_string(),
+                volume: volume(12),
+            },
+        ];
+        let hub_state = replay(events.into_iter()).unwrap();
+        // the volume out of the window of the latest one is dropped
+        assert_eq!(
+            hub_state
+                .ticket_volumes
+                .get("Bitcoin-RUNES-WTF")
+                .map(|volumes| volumes.iter().cloned().collect::<Vec<_>>()),
+            Some(vec![volume(5), volume(12)])
+        );
+    }
 }
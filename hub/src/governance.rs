@@ -0,0 +1,382 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+
+use candid::{CandidType, Principal};
+use ic_canister_log::log;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use omnity_types::hub_types::{GovernanceConfig, Proposal};
+use omnity_types::ic_log::{ERROR, INFO};
+use omnity_types::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::proposal::{execute_proposal, validate_proposal};
+use crate::state::{with_state, with_state_mut};
+
+const BITCOIN_CHAIN: &str = "Bitcoin";
+
+pub type ProposalId = u64;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub enum ProposalState {
+    Open,
+    Approved,
+    // the execution is running, the proposal can not be executed again
+    Executing,
+    Executed,
+    // the execution failed, the proposals must be submitted again
+    Failed,
+    Rejected,
+    Expired,
+}
+
+/// A submitted proposal and its voting progress
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProposalRecord {
+    pub id: ProposalId,
+    pub proposer: Principal,
+    pub proposals: Vec<Proposal>,
+    pub approvals: BTreeSet<Principal>,
+    pub rejections: BTreeSet<Principal>,
+    pub state: ProposalState,
+    pub submitted_at: u64,
+    pub approved_at: Option<u64>,
+    pub executed_at: Option<u64>,
+}
+
+impl ProposalRecord {
+    /// the proposal can be executed after the timelock since it was approved
+    pub fn executable_at(&self, config: &GovernanceConfig) -> Option<u64> {
+        self.approved_at.map(|t| t.saturating_add(config.timelock))
+    }
+
+    pub fn is_expired(&self, config: &GovernanceConfig, now: u64) -> bool {
+        matches!(self.state, ProposalState::Open)
+            && config.voting_period > 0
+            && now > self.submitted_at.saturating_add(config.voting_period)
+    }
+
+    /// the state as seen at the time, an open proposal past its voting period
+    /// is reported as expired even if no vote has marked it yet
+    pub fn with_current_state(mut self, config: Option<&GovernanceConfig>, now: u64) -> Self {
+        if config.is_some_and(|config| self.is_expired(config, now)) {
+            self.state = ProposalState::Expired;
+        }
+        self
+    }
+}
+
+impl Storable for ProposalRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = vec![];
+        let _ = ciborium::ser::into_writer(self, &mut bytes);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let record =
+            ciborium::de::from_reader(bytes.as_ref()).expect("failed to decode ProposalRecord");
+        record
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub fn validate_governance_config(config: &GovernanceConfig) -> Result<(), Error> {
+    if config.approvers.is_empty() {
+        log!(ERROR, "The approvers can not be empty");
+        return Err(Error::ProposalError(
+            "The approvers can not be empty".to_string(),
+        ));
+    }
+    if config.threshold == 0 || config.threshold as usize > config.approvers.len() {
+        log!(
+            ERROR,
+            "The threshold must be between 1 and the number of approvers"
+        );
+        return Err(Error::ProposalError(
+            "The threshold must be between 1 and the number of approvers".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn governance() -> Result<GovernanceConfig, Error> {
+    with_state(|s| s.governance.clone()).ok_or(Error::ProposalError(
+        "The governance is not configured".to_string(),
+    ))
+}
+
+/// Once the governance is configured, the proposals can not be executed directly,
+/// except the `AddToken` proposals of tokens issued on bitcoin sent by the bitcoin
+/// customs for the etched runes, which are added without the approvers and timelock.
+pub fn check_direct_execution(proposals: &[Proposal]) -> Result<(), Error> {
+    if with_state(|s| s.governance.is_none()) {
+        return Ok(());
+    }
+    let caller = ic_cdk::api::caller();
+    let from_bitcoin_customs = with_state(|s| {
+        s.chains
+            .get(&BITCOIN_CHAIN.to_string())
+            .is_some_and(|chain| chain.canister_id == caller.to_text())
+    });
+    if from_bitcoin_customs
+        && proposals
+            .iter()
+            .all(|p| matches!(p, Proposal::AddToken(token) if token.issue_chain == BITCOIN_CHAIN))
+    {
+        return Ok(());
+    }
+    log!(
+        ERROR,
+        "{:?} can not execute proposals directly, please submit them to the governance",
+        caller.to_string()
+    );
+    Err(Error::ProposalError(
+        "The proposals must be submitted to the governance".to_string(),
+    ))
+}
+
+/// Set the initial governance, the later changes must be approved by
+/// the `UpdateGovernance` proposal.
+pub fn init_governance(config: GovernanceConfig) -> Result<(), Error> {
+    validate_governance_config(&config)?;
+    if with_state(|s| s.governance.is_some()) {
+        return Err(Error::ProposalError(
+            "The governance already exists, submit an UpdateGovernance proposal to change it"
+                .to_string(),
+        ));
+    }
+    with_state_mut(|s| s.update_governance(config))
+}
+
+pub async fn submit_proposal(proposals: Vec<Proposal>) -> Result<ProposalId, Error> {
+    let config = governance()?;
+    validate_proposal(&proposals).await?;
+
+    let now = ic_cdk::api::time();
+    let proposer = ic_cdk::api::caller();
+    let mut record = ProposalRecord {
+        id: with_state(|s| s.next_proposal_id),
+        proposer,
+        proposals,
+        approvals: BTreeSet::from([proposer]),
+        rejections: BTreeSet::new(),
+        state: ProposalState::Open,
+        submitted_at: now,
+        approved_at: None,
+        executed_at: None,
+    };
+    if approvals(&record, &config) >= config.threshold as usize {
+        record.state = ProposalState::Approved;
+        record.approved_at = Some(now);
+    }
+    log!(
+        INFO,
+        "{} submitted proposal {}: {:?}",
+        proposer.to_string(),
+        record.id,
+        record.proposals
+    );
+    let id = record.id;
+    with_state_mut(|s| s.save_governance_proposal(record));
+    Ok(id)
+}
+
+/// the approvals of the current approvers, the votes of the removed approvers are not counted
+fn approvals(record: &ProposalRecord, config: &GovernanceConfig) -> usize {
+    record.approvals.intersection(&config.approvers).count()
+}
+
+pub fn vote_proposal(id: ProposalId, approve: bool) -> Result<ProposalState, Error> {
+    let config = governance()?;
+    let mut record = stored_proposal(id)?;
+    let now = ic_cdk::api::time();
+    if record.is_expired(&config, now) {
+        record.state = ProposalState::Expired;
+        with_state_mut(|s| s.save_governance_proposal(record));
+        return Err(Error::ProposalError(format!("The proposal {} is expired", id)));
+    }
+    if !matches!(record.state, ProposalState::Open) {
+        return Err(Error::ProposalError(format!(
+            "The proposal {} is {:?}, it can not be voted",
+            id, record.state
+        )));
+    }
+
+    let voter = ic_cdk::api::caller();
+    if record.approvals.contains(&voter) || record.rejections.contains(&voter) {
+        return Err(Error::ProposalError(format!(
+            "{} already voted for the proposal {}",
+            voter, id
+        )));
+    }
+    if approve {
+        record.approvals.insert(voter);
+    } else {
+        record.rejections.insert(voter);
+    }
+
+    let approvals = approvals(&record, &config);
+    // the votes of the removed approvers are not counted
+    let rejections = record.rejections.intersection(&config.approvers).count();
+    if approvals >= config.threshold as usize {
+        record.state = ProposalState::Approved;
+        record.approved_at = Some(now);
+    } else if config.approvers.len() - rejections < config.threshold as usize {
+        record.state = ProposalState::Rejected;
+    }
+    let state = record.state.clone();
+    with_state_mut(|s| s.save_governance_proposal(record));
+    Ok(state)
+}
+
+pub async fn execute_approved_proposal(id: ProposalId) -> Result<(), Error> {
+    let config = governance()?;
+    let mut record = stored_proposal(id)?;
+    if !matches!(record.state, ProposalState::Approved) {
+        return Err(Error::ProposalError(format!(
+            "The proposal {} is {:?}, it can not be executed",
+            id, record.state
+        )));
+    }
+    let now = ic_cdk::api::time();
+    if let Some(executable_at) = record.executable_at(&config) {
+        if now < executable_at {
+            return Err(Error::ProposalError(format!(
+                "The proposal {} is timelocked until {}",
+                id, executable_at
+            )));
+        }
+    }
+
+    // mark the proposal before the first await, so the concurrent calls can not execute it again
+    record.state = ProposalState::Executing;
+    with_state_mut(|s| s.save_governance_proposal(record.clone()));
+
+    // the hub state may have been changed since the proposal was submitted
+    let result = match validate_proposal(&record.proposals).await {
+        Ok(_) => execute_proposal(record.proposals.clone()).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => {
+            record.state = ProposalState::Executed;
+            record.executed_at = Some(now);
+        }
+        Err(ref e) => {
+            log!(ERROR, "failed to execute the proposal {}: {:?}", id, e);
+            record.state = ProposalState::Failed;
+        }
+    }
+    with_state_mut(|s| s.save_governance_proposal(record));
+    result
+}
+
+fn stored_proposal(id: ProposalId) -> Result<ProposalRecord, Error> {
+    with_state(|s| s.governance_proposals.get(&id)).ok_or(Error::ProposalError(format!(
+        "Not found the proposal {}",
+        id
+    )))
+}
+
+pub fn get_proposal(id: ProposalId) -> Result<ProposalRecord, Error> {
+    let config = with_state(|s| s.governance.clone());
+    let now = ic_cdk::api::time();
+    stored_proposal(id).map(|record| record.with_current_state(config.as_ref(), now))
+}
+
+pub fn get_proposals(
+    state: Option<ProposalState>,
+    offset: usize,
+    limit: usize,
+) -> Vec<ProposalRecord> {
+    let now = ic_cdk::api::time();
+    with_state(|s| {
+        let config = s.governance.as_ref();
+        s.governance_proposals
+            .iter()
+            .map(|(_, record)| record.with_current_state(config, now))
+            .filter(|record| state.as_ref().map_or(true, |state| record.state == *state))
+            .skip(offset)
+            .take(limit)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(approvers: u8, threshold: u32) -> GovernanceConfig {
+        GovernanceConfig {
+            approvers: (0..approvers)
+                .map(|i| Principal::from_slice(&[i]))
+                .collect(),
+            threshold,
+            timelock: 100,
+            voting_period: 1_000,
+        }
+    }
+
+    #[test]
+    fn test_validate_governance_config() {
+        assert!(validate_governance_config(&config(0, 0)).is_err());
+        assert!(validate_governance_config(&config(3, 0)).is_err());
+        assert!(validate_governance_config(&config(3, 4)).is_err());
+        assert!(validate_governance_config(&config(3, 2)).is_ok());
+    }
+
+    #[test]
+    fn test_proposal_expiry_and_timelock() {
+        let config = config(3, 2);
+        let mut record = ProposalRecord {
+            id: 0,
+            proposer: Principal::from_slice(&[0]),
+            proposals: vec![],
+            approvals: BTreeSet::from([Principal::from_slice(&[0])]),
+            rejections: BTreeSet::new(),
+            state: ProposalState::Open,
+            submitted_at: 10,
+            approved_at: None,
+            executed_at: None,
+        };
+        assert!(!record.is_expired(&config, 1_010));
+        assert!(record.is_expired(&config, 1_011));
+        assert_eq!(record.executable_at(&config), None);
+        assert_eq!(
+            record.clone().with_current_state(Some(&config), 1_010).state,
+            ProposalState::Open
+        );
+        assert_eq!(
+            record.clone().with_current_state(Some(&config), 1_011).state,
+            ProposalState::Expired
+        );
+        assert_eq!(
+            record.clone().with_current_state(None, 1_011).state,
+            ProposalState::Open
+        );
+
+        record.state = ProposalState::Approved;
+        record.approved_at = Some(500);
+        assert!(!record.is_expired(&config, 2_000));
+        assert_eq!(record.executable_at(&config), Some(600));
+    }
+
+    #[test]
+    fn test_approvals_of_current_approvers() {
+        let config = config(3, 2);
+        let record = ProposalRecord {
+            id: 0,
+            proposer: Principal::from_slice(&[9]),
+            proposals: vec![],
+            approvals: BTreeSet::from([Principal::from_slice(&[9]), Principal::from_slice(&[1])]),
+            rejections: BTreeSet::new(),
+            state: ProposalState::Open,
+            submitted_at: 0,
+            approved_at: None,
+            executed_at: None,
+        };
+        assert_eq!(approvals(&record, &config), 1);
+    }
+}
@@ -1,10 +1,18 @@
 pub mod auth;
+pub mod delivery;
 pub mod event;
+pub mod governance;
 pub mod lifecycle;
 pub mod memory;
 pub mod metrics;
 pub mod migration;
 pub mod proposal;
+pub mod push;
+pub mod rate_limit;
+pub mod rebuild;
+pub mod resubmission;
 pub mod self_help;
 pub mod state;
+pub mod supply;
 pub mod types;
+pub mod verify;
@@ -4,4 +4,7 @@ use serde::{Deserialize, Serialize};
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct UpgradeArgs {
     pub admin: Option<Principal>,
+    // rebuild the state from the event log instead of the serialized state
+    #[serde(default)]
+    pub rebuild_from_events: Option<bool>,
 }
@@ -7,8 +7,16 @@ use std::cell::RefCell;
 
 use omnity_types::{ChainId, Directive, SeqKey, Ticket, TicketId, TokenId, Topic};
 
-use omnity_types::hub_types::{ChainMeta, ChainTokenFactor, Subscribers, TokenKey, TokenMeta};
+use omnity_types::hub_types::{
+    ChainMeta, ChainTokenFactor, Subscribers, TicketStatus, TokenKey, TokenMeta,
+};
 use omnity_types::{Amount, TxHash};
+
+use crate::delivery::Delivery;
+use crate::governance::{ProposalId, ProposalRecord};
+use crate::rate_limit::HeldTicket;
+use crate::resubmission::Resubmission;
+use crate::supply::SupplyReport;
 const UPGRADES: MemoryId = MemoryId::new(0);
 const CHAIN: MemoryId = MemoryId::new(1);
 const TOKEN: MemoryId = MemoryId::new(2);
@@ -27,6 +35,13 @@ const METRIC_SEQS: MemoryId = MemoryId::new(14);
 const TICKET_METRIC: MemoryId = MemoryId::new(15);
 const TXHASHES: MemoryId = MemoryId::new(16);
 const PENDING_TICKETS: MemoryId = MemoryId::new(17);
+const GOVERNANCE_PROPOSALS: MemoryId = MemoryId::new(18);
+const HELD_TICKETS: MemoryId = MemoryId::new(19);
+const TICKET_STATUS: MemoryId = MemoryId::new(20);
+const DELIVERIES: MemoryId = MemoryId::new(21);
+const RESUBMISSIONS: MemoryId = MemoryId::new(22);
+const RESUBMISSION_ORIGINS: MemoryId = MemoryId::new(23);
+const SUPPLY_REPORTS: MemoryId = MemoryId::new(24);
 
 type InnerMemory = DefaultMemoryImpl;
 
@@ -180,3 +195,59 @@ pub fn init_metric_seqs() -> StableBTreeMap<Vec<u8>, u64, Memory> {
     StableBTreeMap::init(get_metric_seqs())
 }
 
+
+pub fn get_governance_proposals_memory() -> Memory {
+    with_memory_manager(|m| m.get(GOVERNANCE_PROPOSALS))
+}
+
+pub fn init_governance_proposals() -> StableBTreeMap<ProposalId, ProposalRecord, Memory> {
+    StableBTreeMap::init(get_governance_proposals_memory())
+}
+
+pub fn get_held_tickets_memory() -> Memory {
+    with_memory_manager(|m| m.get(HELD_TICKETS))
+}
+
+pub fn init_held_tickets() -> StableBTreeMap<TicketId, HeldTicket, Memory> {
+    StableBTreeMap::init(get_held_tickets_memory())
+}
+
+pub fn get_ticket_status_memory() -> Memory {
+    with_memory_manager(|m| m.get(TICKET_STATUS))
+}
+
+pub fn init_ticket_status() -> StableBTreeMap<TicketId, TicketStatus, Memory> {
+    StableBTreeMap::init(get_ticket_status_memory())
+}
+
+pub fn get_deliveries_memory() -> Memory {
+    with_memory_manager(|m| m.get(DELIVERIES))
+}
+
+pub fn init_deliveries() -> StableBTreeMap<TicketId, Delivery, Memory> {
+    StableBTreeMap::init(get_deliveries_memory())
+}
+
+pub fn get_resubmissions_memory() -> Memory {
+    with_memory_manager(|m| m.get(RESUBMISSIONS))
+}
+
+pub fn init_resubmissions() -> StableBTreeMap<TicketId, Resubmission, Memory> {
+    StableBTreeMap::init(get_resubmissions_memory())
+}
+
+pub fn get_resubmission_origins_memory() -> Memory {
+    with_memory_manager(|m| m.get(RESUBMISSION_ORIGINS))
+}
+
+pub fn init_resubmission_origins() -> StableBTreeMap<TicketId, TicketId, Memory> {
+    StableBTreeMap::init(get_resubmission_origins_memory())
+}
+
+pub fn get_supply_reports_memory() -> Memory {
+    with_memory_manager(|m| m.get(SUPPLY_REPORTS))
+}
+
+pub fn init_supply_reports() -> StableBTreeMap<TokenKey, SupplyReport, Memory> {
+    StableBTreeMap::init(get_supply_reports_memory())
+}
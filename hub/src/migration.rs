@@ -1,14 +1,41 @@
 use crate::auth::Permission;
 use crate::lifecycle::init::InitArgs;
 
-use crate::state::HubState;
+use crate::memory;
+use crate::state::{with_state_mut, HubState};
 
 use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
 
+use crate::governance::ProposalId;
+use crate::rate_limit::TicketVolume;
 use crate::self_help::AddRunesTokenReq;
-use omnity_types::{ChainId, Directive, Seq, SeqKey};
+use omnity_types::hub_types::{
+    DeliveryPolicy, GovernanceConfig, SupplyAuditConfig, TokenRateLimit,
+};
+use omnity_types::{ChainId, Directive, Seq, SeqKey, TicketId, TokenId};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::time::Duration;
+
+// the queue entries moved into the stable queues in one timer execution
+const MIGRATE_QUEUE_ENTRIES_PER_CHUNK: usize = 2_000;
+
+/// The heap queues of the pre state not moved into the stable queues yet. The entries
+/// are moved from the highest seq key down, so the entries left for a chain always
+/// precede its entries in the stable queue.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct QueueMigration {
+    pub dire_map: BTreeMap<SeqKey, Directive>,
+    pub ticket_map: BTreeMap<SeqKey, String>,
+}
+
+impl QueueMigration {
+    pub fn is_empty(&self) -> bool {
+        self.dire_map.is_empty() && self.ticket_map.is_empty()
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct PreHubState {
     // memory variable
@@ -17,11 +44,41 @@ pub struct PreHubState {
     pub admin: Principal,
     pub caller_chain_map: HashMap<String, ChainId>,
     pub caller_perms: HashMap<String, Permission>,
+    // replaced by the per ticket resubmission
+    #[serde(default)]
     pub last_resubmit_ticket_time: u64,
     pub add_runes_token_requests: BTreeMap<String, AddRunesTokenReq>,
     pub runes_oracles: BTreeSet<Principal>,
+    #[serde(default)]
+    pub governance: Option<GovernanceConfig>,
+    #[serde(default)]
+    pub next_proposal_id: ProposalId,
+    #[serde(default)]
+    pub rate_limits: BTreeMap<TokenId, TokenRateLimit>,
+    #[serde(default)]
+    pub ticket_volumes: BTreeMap<TokenId, VecDeque<TicketVolume>>,
+    #[serde(default)]
+    pub delivered_seq: HashMap<ChainId, Seq>,
+    #[serde(default)]
+    pub delivery_policies: BTreeMap<ChainId, DeliveryPolicy>,
+    #[serde(default)]
+    pub resubmit_cooldowns: BTreeMap<ChainId, u64>,
+    #[serde(default)]
+    pub supply_audit: Option<SupplyAuditConfig>,
+    #[serde(default)]
+    pub push_subscribers: BTreeSet<ChainId>,
+    #[serde(default)]
+    pub acked_directive_seq: HashMap<ChainId, Seq>,
+    // it`s collected from the ticket status once if the pre state has no in-flight tickets
+    #[serde(default)]
+    pub in_flight_tickets: Option<BTreeSet<TicketId>>,
+    // the heap queues are moved into the stable `dire_queue` and `ticket_queue`
+    #[serde(default)]
     pub dire_map: BTreeMap<SeqKey, Directive>,
+    #[serde(default)]
     pub ticket_map: BTreeMap<SeqKey, String>,
+    #[serde(default)]
+    pub queue_migration: Option<QueueMigration>,
 }
 
 // migrate pre state to current state
@@ -34,10 +91,51 @@ pub fn migrate(pre_state: PreHubState) -> HubState {
     cur_state.ticket_seq = pre_state.ticket_seq;
     cur_state.caller_chain_map = pre_state.caller_chain_map;
     cur_state.caller_perms = pre_state.caller_perms;
-    cur_state.last_resubmit_ticket_time = pre_state.last_resubmit_ticket_time;
     cur_state.add_runes_token_requests = pre_state.add_runes_token_requests;
     cur_state.runes_oracles = pre_state.runes_oracles;
-    cur_state.dire_map = pre_state.dire_map;
-    cur_state.ticket_map = pre_state.ticket_map;
+    cur_state.governance = pre_state.governance;
+    cur_state.next_proposal_id = pre_state.next_proposal_id;
+    cur_state.rate_limits = pre_state.rate_limits;
+    cur_state.ticket_volumes = pre_state.ticket_volumes;
+    cur_state.delivered_seq = pre_state.delivered_seq;
+    cur_state.delivery_policies = pre_state.delivery_policies;
+    cur_state.resubmit_cooldowns = pre_state.resubmit_cooldowns;
+    cur_state.supply_audit = pre_state.supply_audit;
+    cur_state.push_subscribers = pre_state.push_subscribers;
+    cur_state.acked_directive_seq = pre_state.acked_directive_seq;
+    match pre_state.in_flight_tickets {
+        Some(in_flight_tickets) => cur_state.in_flight_tickets = in_flight_tickets,
+        None => {
+            let statuses = cur_state.ticket_status.iter().collect::<Vec<_>>();
+            statuses.iter().for_each(|(ticket_id, status)| {
+                cur_state.track_in_flight(ticket_id, status);
+            });
+        }
+    }
+
+    match pre_state.queue_migration {
+        // the upgrade during the migration goes on with the entries left
+        Some(queue_migration) => cur_state.queue_migration = Some(queue_migration),
+        None if !pre_state.dire_map.is_empty() || !pre_state.ticket_map.is_empty() => {
+            // drop the stale queue entries at once, the heap queues are moved by the timer
+            cur_state.dire_queue = StableBTreeMap::new(memory::get_dire_queue_memory());
+            cur_state.ticket_queue = StableBTreeMap::new(memory::get_ticket_queue_memory());
+            cur_state.queue_migration = Some(QueueMigration {
+                dire_map: pre_state.dire_map,
+                ticket_map: pre_state.ticket_map,
+            });
+        }
+        None => {}
+    }
     cur_state
 }
+
+/// move the heap queues into the stable queues in chunks
+pub fn migrate_queues_task() {
+    let done =
+        with_state_mut(|hub_state| hub_state.migrate_queues(MIGRATE_QUEUE_ENTRIES_PER_CHUNK));
+    if done {
+        return;
+    }
+    ic_cdk_timers::set_timer(Duration::ZERO, migrate_queues_task);
+}
@@ -4,6 +4,7 @@ use omnity_types::hub_types::Proposal;
 use omnity_types::ic_log::WARNING;
 
 use crate::{
+    governance::validate_governance_config,
     state::{with_state, with_state_mut},
 };
 
@@ -183,6 +184,77 @@ pub async fn validate_proposal(proposals: &Vec<Proposal>) -> Result<Vec<String>,
                     }
                 }
             }
+            Proposal::UpdateGovernance(config) => {
+                validate_governance_config(config)?;
+
+                proposal_msgs.push(format!("The UpdateGovernance proposal: {}", config));
+            }
+            Proposal::UpdateRateLimit(limit) => {
+                if limit.window == 0 {
+                    log!(ERROR, "The rate limit window can not be zero");
+                    return Err(Error::ProposalError(
+                        "The rate limit window can not be zero".to_string(),
+                    ));
+                }
+                with_state(|hub_state| hub_state.token(&limit.token_id))?;
+
+                proposal_msgs.push(format!("The UpdateRateLimit proposal: {}", limit));
+            }
+            Proposal::RemoveRateLimit(token_id) => {
+                if !with_state(|hub_state| hub_state.rate_limits.contains_key(token_id)) {
+                    return Err(Error::ProposalError(format!(
+                        "The token(`{}`) has no rate limit",
+                        token_id
+                    )));
+                }
+
+                proposal_msgs.push(format!("The RemoveRateLimit proposal: {}", token_id));
+            }
+            Proposal::ReleaseHeldTicket(ticket_id) => {
+                if !with_state(|hub_state| hub_state.held_tickets.contains_key(ticket_id)) {
+                    return Err(Error::NotFoundTicketId(ticket_id.to_string()));
+                }
+
+                proposal_msgs.push(format!("The ReleaseHeldTicket proposal: {}", ticket_id));
+            }
+            Proposal::UpdateDeliveryPolicy(policy) => {
+                if policy.deadline == 0 {
+                    log!(ERROR, "The delivery deadline can not be zero");
+                    return Err(Error::ProposalError(
+                        "The delivery deadline can not be zero".to_string(),
+                    ));
+                }
+                with_state(|hub_state| hub_state.chain(&policy.chain_id))?;
+
+                proposal_msgs.push(format!("The UpdateDeliveryPolicy proposal: {}", policy));
+            }
+            Proposal::UpdateSupplyAudit(config) => {
+                if config.tolerance_bps > 10_000 {
+                    return Err(Error::ProposalError(
+                        "The tolerance bps can not be greater than 10000".to_string(),
+                    ));
+                }
+
+                proposal_msgs.push(format!("The UpdateSupplyAudit proposal: {}", config));
+            }
+            Proposal::RefundTicket(ticket_id) => {
+                if !with_state(|hub_state| {
+                    hub_state.cross_ledger.contains_key(ticket_id)
+                        || hub_state.held_tickets.contains_key(ticket_id)
+                }) {
+                    return Err(Error::NotFoundTicketId(ticket_id.to_string()));
+                }
+
+                proposal_msgs.push(format!("The RefundTicket proposal: {}", ticket_id));
+            }
+            Proposal::UpdateResubmitCooldown(chain_id, cooldown) => {
+                with_state(|hub_state| hub_state.chain(chain_id))?;
+
+                proposal_msgs.push(format!(
+                    "The UpdateResubmitCooldown proposal: \nchain id:{} \ncooldown:{}",
+                    chain_id, cooldown
+                ));
+            }
         }
     }
     Ok(proposal_msgs)
@@ -266,6 +338,41 @@ pub async fn execute_proposal(proposals: Vec<Proposal>) -> Result<(), Error> {
                         .pub_directive(Some(target_subs), &Directive::UpdateFee(factor.clone()))
                 })?;
             }
+
+            Proposal::UpdateGovernance(config) => {
+                with_state_mut(|hub_state| hub_state.update_governance(config))?;
+            }
+
+            Proposal::UpdateRateLimit(limit) => {
+                with_state_mut(|hub_state| hub_state.update_rate_limit(limit))?;
+            }
+
+            Proposal::RemoveRateLimit(token_id) => {
+                with_state_mut(|hub_state| hub_state.remove_rate_limit(&token_id))?;
+            }
+
+            Proposal::ReleaseHeldTicket(ticket_id) => {
+                with_state_mut(|hub_state| hub_state.release_held_ticket(&ticket_id))?;
+            }
+
+            Proposal::UpdateDeliveryPolicy(policy) => {
+                with_state_mut(|hub_state| hub_state.update_delivery_policy(policy))?;
+            }
+
+            Proposal::UpdateSupplyAudit(config) => {
+                with_state_mut(|hub_state| hub_state.update_supply_audit(config))?;
+            }
+
+            Proposal::RefundTicket(ticket_id) => {
+                let now = ic_cdk::api::time();
+                with_state_mut(|hub_state| hub_state.refund_ticket(&ticket_id, now))?;
+            }
+
+            Proposal::UpdateResubmitCooldown(chain_id, cooldown) => {
+                with_state_mut(|hub_state| {
+                    hub_state.update_resubmit_cooldown(chain_id, cooldown)
+                })?;
+            }
         }
     }
     Ok(())
@@ -0,0 +1,127 @@
+use candid::{CandidType, Principal};
+use ic_canister_log::log;
+use omnity_types::ic_log::{ERROR, INFO};
+use omnity_types::{ChainId, Seq};
+use serde::{Deserialize, Serialize};
+
+use crate::state::{with_state, with_state_mut};
+
+pub const NOTIFY_SUBSCRIBERS_INTERVAL: u64 = 5;
+const ON_NEW_TICKETS: &str = "on_new_tickets";
+const ON_NEW_DIRECTIVES: &str = "on_new_directives";
+
+/// The progress of a route consuming its tickets and directives
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct RouteLag {
+    pub chain_id: ChainId,
+    pub push_enabled: bool,
+    pub latest_ticket_seq: Option<Seq>,
+    pub acked_ticket_seq: Option<Seq>,
+    pub ticket_lag: u64,
+    pub latest_directive_seq: Option<Seq>,
+    pub acked_directive_seq: Option<Seq>,
+    pub directive_lag: u64,
+}
+
+impl RouteLag {
+    pub fn lag(latest_seq: Option<Seq>, acked_seq: Option<Seq>) -> u64 {
+        match (latest_seq, acked_seq) {
+            (Some(latest), Some(acked)) => latest.saturating_sub(acked),
+            (Some(latest), None) => latest + 1,
+            (None, _) => 0,
+        }
+    }
+}
+
+/// the next seq the route should consume
+fn next_seq(acked_seq: Option<&Seq>) -> Seq {
+    acked_seq.map_or(0, |seq| seq + 1)
+}
+
+/// notify the push subscribers of the new tickets and directives,
+/// the notification is one way, the route pulls the tickets and directives as before
+pub fn notify_subscribers_task() {
+    let notifications = with_state_mut(|hub_state| {
+        let tickets = std::mem::take(&mut hub_state.pending_ticket_notifications);
+        let directives = std::mem::take(&mut hub_state.pending_directive_notifications);
+        let notification = |chain_id: ChainId, method: &'static str, from_seq: Seq| {
+            let chain = hub_state.chains.get(&chain_id)?;
+            let canister_id = Principal::from_text(&chain.canister_id).ok()?;
+            Some((chain_id, canister_id, method, from_seq))
+        };
+        tickets
+            .into_iter()
+            .filter_map(|chain_id| {
+                let from_seq = next_seq(hub_state.delivered_seq.get(&chain_id));
+                notification(chain_id, ON_NEW_TICKETS, from_seq)
+            })
+            .chain(directives.into_iter().filter_map(|chain_id| {
+                let from_seq = next_seq(hub_state.acked_directive_seq.get(&chain_id));
+                notification(chain_id, ON_NEW_DIRECTIVES, from_seq)
+            }))
+            .collect::<Vec<_>>()
+    });
+
+    for (chain_id, canister_id, method, from_seq) in notifications {
+        match ic_cdk::notify(canister_id, method, (from_seq,)) {
+            Ok(()) => log!(INFO, "notified {} {} from seq {}", chain_id, method, from_seq),
+            Err(e) => {
+                log!(
+                    ERROR,
+                    "failed to notify {} {} from seq {}: {:?}",
+                    chain_id,
+                    method,
+                    from_seq,
+                    e
+                );
+                // the failed notification is retried by the next task
+                with_state_mut(|hub_state| {
+                    if method == ON_NEW_TICKETS {
+                        hub_state.pending_ticket_notifications.insert(chain_id);
+                    } else {
+                        hub_state.pending_directive_notifications.insert(chain_id);
+                    }
+                });
+            }
+        }
+    }
+}
+
+pub fn get_route_lags(chain_id: Option<ChainId>) -> Vec<RouteLag> {
+    with_state(|hub_state| {
+        hub_state
+            .chains
+            .iter()
+            .filter(|(id, _)| chain_id.as_ref().map_or(true, |chain_id| chain_id == id))
+            .map(|(chain_id, _)| {
+                let latest_ticket_seq = hub_state.ticket_seq.get(&chain_id).copied();
+                let acked_ticket_seq = hub_state.delivered_seq.get(&chain_id).copied();
+                let latest_directive_seq = hub_state.directive_seq.get(&chain_id).copied();
+                let acked_directive_seq = hub_state.acked_directive_seq.get(&chain_id).copied();
+                RouteLag {
+                    push_enabled: hub_state.push_subscribers.contains(&chain_id),
+                    chain_id,
+                    latest_ticket_seq,
+                    acked_ticket_seq,
+                    ticket_lag: RouteLag::lag(latest_ticket_seq, acked_ticket_seq),
+                    latest_directive_seq,
+                    acked_directive_seq,
+                    directive_lag: RouteLag::lag(latest_directive_seq, acked_directive_seq),
+                }
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_lag() {
+        assert_eq!(RouteLag::lag(None, None), 0);
+        assert_eq!(RouteLag::lag(Some(0), None), 1);
+        assert_eq!(RouteLag::lag(Some(9), Some(4)), 5);
+        assert_eq!(RouteLag::lag(Some(9), Some(9)), 0);
+    }
+}
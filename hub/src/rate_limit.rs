@@ -0,0 +1,162 @@
+use std::borrow::Cow;
+
+use candid::CandidType;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use omnity_types::hub_types::TokenRateLimit;
+use omnity_types::{ChainId, Ticket, Timestamp};
+use serde::{Deserialize, Serialize};
+
+/// The ticket parked by the rate limits
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct HeldTicket {
+    pub ticket: Ticket,
+    pub reason: String,
+    pub held_at: Timestamp,
+}
+
+impl Storable for HeldTicket {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = vec![];
+        let _ = ciborium::ser::into_writer(self, &mut bytes);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let held = ciborium::de::from_reader(bytes.as_ref()).expect("failed to decode HeldTicket");
+        held
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// The amount of a token moved by an accepted ticket
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct TicketVolume {
+    pub timestamp: Timestamp,
+    pub src_chain: ChainId,
+    pub dst_chain: ChainId,
+    pub amount: u128,
+}
+
+/// The volumes of a token in the rolling window, including the new ticket
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WindowVolume {
+    pub token: u128,
+    pub src_chain: u128,
+    pub dst_chain: u128,
+}
+
+impl WindowVolume {
+    pub fn new<'a>(
+        volumes: impl Iterator<Item = &'a TicketVolume>,
+        ticket: &Ticket,
+        amount: u128,
+    ) -> Self {
+        volumes.fold(
+            WindowVolume {
+                token: amount,
+                src_chain: amount,
+                dst_chain: amount,
+            },
+            |mut acc, v| {
+                acc.token = acc.token.saturating_add(v.amount);
+                if v.src_chain == ticket.src_chain {
+                    acc.src_chain = acc.src_chain.saturating_add(v.amount);
+                }
+                if v.dst_chain == ticket.dst_chain {
+                    acc.dst_chain = acc.dst_chain.saturating_add(v.amount);
+                }
+                acc
+            },
+        )
+    }
+
+    /// Returns the reason if the ticket breaches the limits
+    pub fn breach(&self, limit: &TokenRateLimit, amount: u128) -> Option<String> {
+        if limit.ticket_limit.is_some_and(|max| amount > max) {
+            Some(format!(
+                "the ticket amount {} exceeds the ticket limit {:?}",
+                amount, limit.ticket_limit
+            ))
+        } else if limit.token_limit.is_some_and(|max| self.token > max) {
+            Some(format!(
+                "the token volume {} exceeds the token limit {:?}",
+                self.token, limit.token_limit
+            ))
+        } else if limit.src_chain_limit.is_some_and(|max| self.src_chain > max) {
+            Some(format!(
+                "the src chain volume {} exceeds the src chain limit {:?}",
+                self.src_chain, limit.src_chain_limit
+            ))
+        } else if limit.dst_chain_limit.is_some_and(|max| self.dst_chain > max) {
+            Some(format!(
+                "the dst chain volume {} exceeds the dst chain limit {:?}",
+                self.dst_chain, limit.dst_chain_limit
+            ))
+        } else {
+            None
+        }
+    }
+
+    pub fn trips_circuit_breaker(&self, limit: &TokenRateLimit) -> bool {
+        limit
+            .circuit_breaker
+            .is_some_and(|max| self.src_chain > max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(src_chain: &str, dst_chain: &str, amount: u128) -> TicketVolume {
+        TicketVolume {
+            timestamp: 0,
+            src_chain: src_chain.to_string(),
+            dst_chain: dst_chain.to_string(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn test_window_volume_breach() {
+        let volumes = vec![
+            volume("Bitcoin", "EVM-Arbitrum", 100),
+            volume("EVM-Arbitrum", "Bitcoin", 50),
+            volume("Bitcoin", "ICP", 30),
+        ];
+        let ticket = Ticket {
+            src_chain: "Bitcoin".to_string(),
+            dst_chain: "EVM-Arbitrum".to_string(),
+            ..Default::default()
+        };
+        let window = WindowVolume::new(volumes.iter(), &ticket, 20);
+        assert_eq!(
+            window,
+            WindowVolume {
+                token: 200,
+                src_chain: 150,
+                dst_chain: 120,
+            }
+        );
+
+        let mut limit = TokenRateLimit {
+            token_id: "Bitcoin-runes-HOPE•YOU•GET•RICH".to_string(),
+            window: 1,
+            ..Default::default()
+        };
+        assert_eq!(window.breach(&limit, 20), None);
+        limit.dst_chain_limit = Some(120);
+        assert_eq!(window.breach(&limit, 20), None);
+        limit.src_chain_limit = Some(149);
+        assert!(window.breach(&limit, 20).is_some());
+        limit.src_chain_limit = None;
+        limit.ticket_limit = Some(10);
+        assert!(window.breach(&limit, 20).is_some());
+
+        assert!(!window.trips_circuit_breaker(&limit));
+        limit.circuit_breaker = Some(100);
+        assert!(window.trips_circuit_breaker(&limit));
+    }
+}
@@ -0,0 +1,98 @@
+//! Rebuild the state from the event log, it`s the recovery path once the serialized
+//! state can not be decoded. The event log is replayed in chunks by timers, the hub
+//! state is not set until the replay catches up with the log, so the calls and the
+//! tasks relying on the state are rejected meanwhile. The fields without events
+//! (e.g. the runes oracles) must be set again after the rebuild.
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use ic_canister_log::log;
+use ic_stable_structures::writer::Writer;
+use omnity_types::ic_log::{INFO, WARNING};
+
+use crate::event::{apply_event, count_events, events_range, Event};
+use crate::lifecycle::upgrade::UpgradeArgs;
+use crate::memory;
+use crate::state::{set_state, HubState};
+
+// the events replayed in one timer execution
+const REBUILD_EVENTS_PER_CHUNK: u64 = 5_000;
+
+thread_local! {
+    static REBUILD: RefCell<Option<Rebuild>> = RefCell::default();
+}
+
+struct Rebuild {
+    hub_state: HubState,
+    replayed_events: u64,
+    // applied once the state is rebuilt
+    upgrade_args: Option<UpgradeArgs>,
+    on_rebuilt: fn(),
+}
+
+pub fn is_rebuilding() -> bool {
+    REBUILD.with(|r| r.borrow().is_some())
+}
+
+/// the upgrade during the rebuild has no state to save, the saved state is emptied
+/// so the next upgrade fails to decode it unless it rebuilds the state again
+pub fn pre_upgrade() {
+    log!(
+        WARNING,
+        "the hub state is being rebuilt, the upgrade must rebuild it again"
+    );
+    let mut memory = memory::get_upgrades_memory();
+    Writer::new(&mut memory, 0)
+        .write(&0u32.to_le_bytes())
+        .expect("failed to save hub state len");
+}
+
+pub fn start_rebuild(upgrade_args: Option<UpgradeArgs>, on_rebuilt: fn()) {
+    log!(WARNING, "rebuild the hub state from the event log ...");
+    let mut hub_state = match events_range(0, 1).pop() {
+        Some(Event::Init(args)) => HubState::from(args),
+        event => panic!("the first event is not Init: {:?}", event),
+    };
+    hub_state.reset_stable_structures();
+    REBUILD.with(|r| {
+        *r.borrow_mut() = Some(Rebuild {
+            hub_state,
+            replayed_events: 1,
+            upgrade_args,
+            on_rebuilt,
+        })
+    });
+    ic_cdk_timers::set_timer(Duration::ZERO, rebuild_chunk);
+}
+
+fn rebuild_chunk() {
+    let caught_up = REBUILD.with(|r| {
+        let mut rebuild = r.borrow_mut();
+        let Some(rebuild) = rebuild.as_mut() else {
+            return true;
+        };
+        let events = events_range(rebuild.replayed_events, REBUILD_EVENTS_PER_CHUNK);
+        rebuild.replayed_events += events.len() as u64;
+        events
+            .into_iter()
+            .for_each(|event| apply_event(&mut rebuild.hub_state, event));
+        rebuild.replayed_events >= count_events()
+    });
+    if !caught_up {
+        ic_cdk_timers::set_timer(Duration::ZERO, rebuild_chunk);
+        return;
+    }
+    let Some(rebuild) = REBUILD.with(|r| r.borrow_mut().take()) else {
+        return;
+    };
+    let mut hub_state = rebuild.hub_state;
+    hub_state.apply_upgrade_args(rebuild.upgrade_args);
+    set_state(hub_state);
+    log!(
+        INFO,
+        "rebuilt the hub state from {} events",
+        rebuild.replayed_events
+    );
+    (rebuild.on_rebuilt)();
+}
@@ -0,0 +1,110 @@
+use std::borrow::Cow;
+
+use candid::CandidType;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use omnity_types::{TicketId, Timestamp};
+use serde::{Deserialize, Serialize};
+
+/// the default cooldown(ns) between two resubmissions of a ticket
+pub const DEFAULT_RESUBMIT_COOLDOWN: u64 = 6 * 3_600_000_000_000;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ResubmittedTicket {
+    pub ticket_id: TicketId,
+    pub resubmitted_at: Timestamp,
+    // resubmitted by the hub after the delivery deadline
+    pub auto: bool,
+}
+
+/// The resubmission chain of an original ticket,
+/// the generated tickets are `{ticket_id}_{ts}`
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Resubmission {
+    pub origin_ticket_id: TicketId,
+    pub tickets: Vec<ResubmittedTicket>,
+}
+
+impl Resubmission {
+    pub fn new(origin_ticket_id: TicketId) -> Self {
+        Self {
+            origin_ticket_id,
+            tickets: vec![],
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.tickets.len()
+    }
+
+    /// the latest ticket of the chain, it`s the original ticket if never resubmitted
+    pub fn latest_ticket_id(&self) -> &TicketId {
+        self.tickets
+            .last()
+            .map_or(&self.origin_ticket_id, |t| &t.ticket_id)
+    }
+
+    /// the original ticket and the tickets generated from it
+    pub fn ticket_ids(&self) -> impl Iterator<Item = &TicketId> {
+        std::iter::once(&self.origin_ticket_id).chain(self.tickets.iter().map(|t| &t.ticket_id))
+    }
+
+    /// the manual resubmission is throttled by the cooldown since the last resubmission,
+    /// or since the original ticket was sent if never resubmitted
+    pub fn cooldown_remaining(
+        &self,
+        origin_ticket_time: Timestamp,
+        cooldown: u64,
+        now: Timestamp,
+    ) -> Option<u64> {
+        let available_at = self
+            .tickets
+            .last()
+            .map_or(origin_ticket_time, |t| t.resubmitted_at)
+            .saturating_add(cooldown);
+        (available_at > now).then(|| available_at - now)
+    }
+}
+
+impl Storable for Resubmission {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = vec![];
+        let _ = ciborium::ser::into_writer(self, &mut bytes);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let resubmission =
+            ciborium::de::from_reader(bytes.as_ref()).expect("failed to decode Resubmission");
+        resubmission
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resubmission_cooldown() {
+        let mut resubmission = Resubmission::new("ticket".to_string());
+        assert_eq!(resubmission.latest_ticket_id(), "ticket");
+        assert_eq!(resubmission.cooldown_remaining(0, 100, 50), Some(50));
+        assert_eq!(resubmission.cooldown_remaining(0, 100, 100), None);
+
+        resubmission.tickets.push(ResubmittedTicket {
+            ticket_id: "ticket_1000".to_string(),
+            resubmitted_at: 1000,
+            auto: false,
+        });
+        assert_eq!(resubmission.count(), 1);
+        assert_eq!(resubmission.latest_ticket_id(), "ticket_1000");
+        assert_eq!(resubmission.cooldown_remaining(0, 100, 1050), Some(50));
+        assert_eq!(resubmission.cooldown_remaining(0, 100, 1100), None);
+        assert_eq!(
+            resubmission.ticket_ids().collect::<Vec<_>>(),
+            vec!["ticket", "ticket_1000"]
+        );
+    }
+}
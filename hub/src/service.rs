@@ -2,24 +2,40 @@ use candid::Principal;
 use ic_canisters_http_types::{HttpRequest, HttpResponse};
 use ic_cdk::{init, post_upgrade, pre_upgrade, query, update};
 use ic_ledger_types::AccountIdentifier;
-use omnity_hub::auth::{auth_query, auth_update, is_admin, is_runes_oracle, set_perms, Permission};
+use std::time::Duration;
+
+use ic_cdk_timers::{set_timer, set_timer_interval};
+use omnity_hub::auth::{
+    auth_query, auth_update, is_admin, is_approver, is_controller, is_runes_oracle, set_perms,
+    Permission,
+};
+use omnity_hub::delivery::{self, OverdueTicket, CHECK_DELIVERY_INTERVAL};
 use omnity_hub::event::{self, record_event, Event, GetEventsArg};
+use omnity_hub::governance::{self, ProposalId, ProposalRecord, ProposalState};
 use omnity_hub::lifecycle::init::HubArg;
+use omnity_hub::push::{self, RouteLag, NOTIFY_SUBSCRIBERS_INTERVAL};
+use omnity_hub::rate_limit::HeldTicket;
+use omnity_hub::rebuild;
+use omnity_hub::resubmission::Resubmission;
 use omnity_hub::metrics::{self, with_metrics};
+use omnity_hub::migration;
 use omnity_hub::self_help::{
     principal_to_subaccount, AddDestChainArgs, AddRunesTokenReq, FinalizeAddRunesArgs,
     LinkChainReq, SelfServiceError, ADD_CHAIN_FEE, ADD_TOKEN_FEE,
 };
 use omnity_hub::state::{with_state, with_state_mut};
+use omnity_hub::supply::{self, SupplyReport, RECONCILE_SUPPLY_INTERVAL};
+use omnity_hub::verify::{self, VerificationReport};
 use omnity_hub::{proposal, self_help};
 
 use ic_canister_log::log;
 use omnity_hub::lifecycle;
 use omnity_types::hub_types::{
-    TokenResp, Proposal, Subscribers,ChainMeta, TokenMeta
+    TokenResp, Proposal, Subscribers,ChainMeta, TokenMeta, GovernanceConfig, TokenRateLimit,
+    TicketStatus, DeliveryPolicy, SupplyAuditConfig
 };
 use omnity_types::TxHash;
-use omnity_types::ic_log::INFO;
+use omnity_types::ic_log::{ERROR, INFO};
 use omnity_types::{
     Chain, ChainId, ChainState, ChainType, Directive, Error, Factor, Seq, Ticket, TicketId,
     TokenId, TokenOnChain, Topic,
@@ -35,6 +51,7 @@ fn init(args: HubArg) {
 
             lifecycle::init(args.clone());
             record_event(&Event::Init(args));
+            start_tasks();
         }
         HubArg::Upgrade(_) => {
             panic!("expected InitArgs got UpgradeArgs");
@@ -45,13 +62,17 @@ fn init(args: HubArg) {
 #[pre_upgrade]
 fn pre_upgrade() {
     log!(INFO, "begin to execute pre_upgrade ...");
+    if rebuild::is_rebuilding() {
+        rebuild::pre_upgrade();
+        return;
+    }
     with_state(|hub_state| hub_state.pre_upgrade())
 }
 
 #[post_upgrade]
 fn post_upgrade(args: Option<HubArg>) {
     log!(INFO, "begin to execute post_upgrade with :{:?}", args);
-    HubState::post_upgrade(args);
+    HubState::post_upgrade(args, start_tasks);
     log!(
         INFO,
         "post_upgrade successfully, current version: {}",
@@ -59,13 +80,31 @@ fn post_upgrade(args: Option<HubArg>) {
     );
 }
 
+fn start_tasks() {
+    set_timer_interval(
+        Duration::from_secs(CHECK_DELIVERY_INTERVAL),
+        delivery::resubmit_overdue_tickets_task,
+    );
+    set_timer_interval(Duration::from_secs(RECONCILE_SUPPLY_INTERVAL), || {
+        ic_cdk::spawn(supply::reconcile_supply_task())
+    });
+    set_timer_interval(
+        Duration::from_secs(NOTIFY_SUBSCRIBERS_INTERVAL),
+        push::notify_subscribers_task,
+    );
+    set_timer(Duration::ZERO, migration::migrate_queues_task);
+}
+
 /// validate directive ,this method will be called by sns
 #[query(guard = "is_admin")]
 pub async fn validate_proposal(proposals: Vec<Proposal>) -> Result<Vec<String>, Error> {
     proposal::validate_proposal(&proposals).await
 }
+/// execute the proposals directly, once the governance is configured only the bitcoin customs
+/// can add the runes tokens issued on bitcoin this way, without the approvers and timelock
 #[update(guard = "is_admin")]
 pub async fn execute_proposal(proposals: Vec<Proposal>) -> Result<(), Error> {
+    governance::check_direct_execution(&proposals)?;
     proposal::execute_proposal(proposals).await
 }
 
@@ -81,6 +120,7 @@ pub async fn handle_chain(proposals: Vec<Proposal>) -> Result<(), Error> {
             Ok(())
         }
     })?;
+    governance::check_direct_execution(&proposals)?;
     // validate proposal
     proposal::validate_proposal(&proposals).await?;
     // execution proposal and generate directives
@@ -99,6 +139,7 @@ pub async fn handle_token(proposals: Vec<Proposal>) -> Result<(), Error> {
             Ok(())
         }
     })?;
+    governance::check_direct_execution(&proposals)?;
     // validate proposal
     proposal::validate_proposal(&proposals).await?;
     // exection proposal and generate directives
@@ -109,10 +150,54 @@ pub async fn handle_token(proposals: Vec<Proposal>) -> Result<(), Error> {
 #[update(guard = "is_admin")]
 pub async fn update_fee(factors: Vec<Factor>) -> Result<(), Error> {
     let proposals: Vec<Proposal> = factors.into_iter().map(Proposal::UpdateFee).collect();
+    governance::check_direct_execution(&proposals)?;
     proposal::validate_proposal(&proposals).await?;
     proposal::execute_proposal(proposals).await
 }
 
+/// set the initial approvers, the later changes must be made by the `UpdateGovernance` proposal,
+/// the proposals are executed by the governance except the runes tokens added by the bitcoin customs
+#[update(guard = "is_admin")]
+pub fn init_governance(config: GovernanceConfig) -> Result<(), Error> {
+    governance::init_governance(config)
+}
+
+/// submit the proposals to the approvers, the proposer`s vote is counted as an approval
+#[update(guard = "is_approver")]
+pub async fn submit_proposal(proposals: Vec<Proposal>) -> Result<ProposalId, Error> {
+    governance::submit_proposal(proposals).await
+}
+
+#[update(guard = "is_approver")]
+pub fn vote_proposal(id: ProposalId, approve: bool) -> Result<ProposalState, Error> {
+    governance::vote_proposal(id, approve)
+}
+
+/// execute the approved proposal after its timelock
+#[update(guard = "is_approver")]
+pub async fn execute_governance_proposal(id: ProposalId) -> Result<(), Error> {
+    governance::execute_approved_proposal(id).await
+}
+
+#[query]
+pub fn get_governance() -> Option<GovernanceConfig> {
+    with_state(|s| s.governance.clone())
+}
+
+#[query]
+pub fn get_governance_proposal(id: ProposalId) -> Result<ProposalRecord, Error> {
+    governance::get_proposal(id)
+}
+
+#[query]
+pub fn get_governance_proposals(
+    state: Option<ProposalState>,
+    offset: usize,
+    limit: usize,
+) -> Vec<ProposalRecord> {
+    governance::get_proposals(state, offset, limit)
+}
+
 #[update(guard = "auth_update")]
 pub async fn sub_directives(chain_id: Option<ChainId>, topics: Vec<Topic>) -> Result<(), Error> {
     log!(
@@ -148,21 +233,28 @@ pub async fn query_directives(
     with_state(|hub_state| hub_state.pull_directives(dst_chain_id, topic, offset, limit))
 }
 
+/// query directives for chain id from the seq on
+#[query(guard = "auth_query")]
+pub async fn query_directives_from_seq(
+    chain_id: Option<ChainId>,
+    topic: Option<Topic>,
+    from_seq: Seq,
+    limit: usize,
+) -> Result<Vec<(Seq, Directive)>, Error> {
+    let dst_chain_id = metrics::get_chain_id(chain_id)?;
+    with_state(|hub_state| hub_state.pull_directives_from_seq(dst_chain_id, topic, from_seq, limit))
+}
+
 /// check and push ticket into queue
 #[update(guard = "auth_update")]
 pub async fn send_ticket(ticket: Ticket) -> Result<(), Error> {
-    with_state_mut(|hub_state| {
-        // check ticket and update token on chain
-        hub_state.check_and_update(&ticket)?;
-        // push ticket into queue
-        hub_state.push_ticket(ticket)
-    })
+    with_state_mut(|hub_state| hub_state.send_ticket(ticket))
 }
 
 #[update(guard = "auth_update")]
 pub async fn resubmit_ticket(ticket: Ticket) -> Result<(), Error> {
     // No need to update the token since the old ticket has already added
-    with_state_mut(|hub_state| hub_state.resubmit_ticket(ticket))
+    with_state_mut(|hub_state| hub_state.resubmit_ticket(ticket, ic_cdk::api::time()))
 }
 
 /// query tickets for chain id,this method will be called by route and custom
@@ -176,6 +268,17 @@ pub async fn query_tickets(
     with_state(|hub_state| hub_state.pull_tickets(&dst_chain_id, offset, limit))
 }
 
+/// query tickets for chain id from the seq on
+#[query(guard = "auth_query")]
+pub async fn query_tickets_from_seq(
+    chain_id: Option<ChainId>,
+    from_seq: Seq,
+    limit: usize,
+) -> Result<Vec<(Seq, Ticket)>, Error> {
+    let dst_chain_id = metrics::get_chain_id(chain_id)?;
+    with_state(|hub_state| hub_state.pull_tickets_from_seq(&dst_chain_id, from_seq, limit))
+}
+
 #[update(guard = "auth_update")]
 pub async fn update_tx_hash(ticket_id: TicketId, tx_hash: String) -> Result<(), Error> {
     with_state_mut(|hub_state| hub_state.update_tx_hash(ticket_id, tx_hash))
@@ -189,6 +292,79 @@ pub async fn batch_update_tx_hash(ticket_ids: Vec<TicketId>, tx_hash: String) ->
     Ok(())
 }
 
+/// the route acknowledges it received the tickets up to the seq
+#[update(guard = "auth_update")]
+pub async fn ack_tickets(chain_id: Option<ChainId>, seq: Seq) -> Result<(), Error> {
+    let dst_chain_id = metrics::get_chain_id(chain_id)?;
+    with_state_mut(|hub_state| hub_state.ack_tickets(&dst_chain_id, seq))
+}
+
+/// the route acknowledges it received the directives up to the seq
+#[update(guard = "auth_update")]
+pub async fn ack_directives(chain_id: Option<ChainId>, seq: Seq) -> Result<(), Error> {
+    let dst_chain_id = metrics::get_chain_id(chain_id)?;
+    with_state_mut(|hub_state| hub_state.ack_directives(&dst_chain_id, seq))
+}
+
+/// the subscribed route is notified by `on_new_tickets` and `on_new_directives`
+/// when the tickets or directives are enqueued for it
+#[update(guard = "auth_update")]
+pub async fn subscribe_push(chain_id: Option<ChainId>, enabled: bool) -> Result<(), Error> {
+    let dst_chain_id = metrics::get_chain_id(chain_id)?;
+    with_state_mut(|hub_state| hub_state.update_push_subscription(&dst_chain_id, enabled))
+}
+
+/// get how far each route lags behind its tickets and directives
+#[query]
+pub fn get_route_lags(chain_id: Option<ChainId>) -> Vec<RouteLag> {
+    push::get_route_lags(chain_id)
+}
+
+/// the dst route reports the ticket can not be executed,
+/// the permanently failed ticket is refunded to the sender on the src chain
+#[update(guard = "auth_update")]
+pub async fn report_ticket_failure(
+    ticket_id: TicketId,
+    reason: String,
+    permanent: bool,
+) -> Result<(), Error> {
+    let caller = ic_cdk::api::caller();
+    let ticket = with_state(|hub_state| hub_state.cross_ledger.get(&ticket_id))
+        .ok_or(Error::NotFoundTicketId(ticket_id.to_string()))?;
+    // only the admin or the dst route can report the failure
+    if with_state(|hub_state| hub_state.admin != caller) && !ic_cdk::api::is_controller(&caller) {
+        let caller_chain = metrics::get_chain_id(None)?;
+        if caller_chain != ticket.dst_chain {
+            log!(
+                ERROR,
+                "{} is not the dst chain of the ticket (`{}`)",
+                caller_chain,
+                ticket_id
+            );
+            return Err(Error::CustomError(format!(
+                "Only the dst chain {} can report the failure",
+                ticket.dst_chain
+            )));
+        }
+    }
+    with_state_mut(|hub_state| hub_state.report_ticket_failure(&ticket_id, reason, permanent))
+}
+
+#[query]
+pub async fn get_ticket_status(ticket_id: TicketId) -> Result<TicketStatus, Error> {
+    with_state(|hub_state| hub_state.get_ticket_status(&ticket_id))
+}
+
+/// get the tickets with the same kind of status, the payload of the status is ignored
+#[query]
+pub async fn get_tickets_by_status(
+    status: TicketStatus,
+    offset: usize,
+    limit: usize,
+) -> Vec<(TicketId, TicketStatus)> {
+    with_state(|hub_state| hub_state.get_tickets_by_status(&status, offset, limit))
+}
+
 #[query(guard = "auth_query")]
 pub async fn query_tx_hash(ticket_id: TicketId) -> Result<TxHash, Error> {
     with_state(|hub_state| hub_state.get_tx_hash(&ticket_id))
@@ -432,6 +608,105 @@ pub async fn get_tx_hashes(offset: usize, limit: usize) -> Result<Vec<(TicketId,
     Ok(tx_hashes)
 }
 
+#[query]
+pub fn get_rate_limits() -> Vec<TokenRateLimit> {
+    with_state(|hub_state| hub_state.rate_limits.values().cloned().collect())
+}
+
+#[query]
+pub fn get_delivery_policies() -> Vec<DeliveryPolicy> {
+    with_state(|hub_state| hub_state.delivery_policies.values().cloned().collect())
+}
+
+/// get the tickets without the tx hash after the delivery deadline of the dst chain
+#[query]
+pub fn get_overdue_tickets(
+    chain_id: Option<ChainId>,
+    offset: usize,
+    limit: usize,
+) -> Vec<OverdueTicket> {
+    let now = ic_cdk::api::time();
+    with_state(|hub_state| hub_state.get_overdue_tickets(chain_id, now, offset, limit))
+}
+
+/// replay the event log in chunks and compare it with the chains, tokens, factors,
+/// positions and ledger, the result is returned by `get_event_log_verification`
+#[update(guard = "is_controller")]
+pub fn verify_event_log() -> Result<(), Error> {
+    verify::verify_event_log()
+}
+
+#[query(guard = "is_controller")]
+pub fn get_event_log_verification() -> Option<VerificationReport> {
+    verify::get_verification_report()
+}
+
+#[query]
+pub fn get_supply_audit() -> Option<SupplyAuditConfig> {
+    with_state(|hub_state| hub_state.supply_audit.clone())
+}
+
+/// get the latest supply reports, only the mismatched reports if `mismatched` is true
+#[query]
+pub fn get_supply_reports(
+    chain_id: Option<ChainId>,
+    mismatched: bool,
+    offset: usize,
+    limit: usize,
+) -> Vec<SupplyReport> {
+    with_state(|hub_state| {
+        hub_state
+            .supply_reports
+            .iter()
+            .filter(|(_, report)| {
+                chain_id
+                    .as_ref()
+                    .map_or(true, |chain_id| report.chain_id.eq(chain_id))
+                    && (!mismatched || report.mismatched)
+            })
+            .skip(offset)
+            .take(limit)
+            .map(|(_, report)| report)
+            .collect()
+    })
+}
+
+#[query]
+pub fn get_resubmit_cooldowns() -> Vec<(ChainId, u64)> {
+    with_state(|hub_state| {
+        hub_state
+            .resubmit_cooldowns
+            .iter()
+            .map(|(chain_id, cooldown)| (chain_id.to_string(), *cooldown))
+            .collect()
+    })
+}
+
+/// get the resubmission chain by the original ticket id or any resubmitted ticket id
+#[query]
+pub fn get_resubmission(ticket_id: TicketId) -> Result<Resubmission, Error> {
+    with_state(|hub_state| {
+        if !hub_state.cross_ledger.contains_key(&ticket_id) {
+            return Err(Error::NotFoundTicketId(ticket_id.to_string()));
+        }
+        Ok(hub_state.get_resubmission(&ticket_id))
+    })
+}
+
+#[query(guard = "auth_query")]
+pub async fn get_held_tickets(offset: usize, limit: usize) -> Result<Vec<HeldTicket>, Error> {
+    let held_tickets = with_state(|hub_state| {
+        hub_state
+            .held_tickets
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|(_, held_ticket)| held_ticket)
+            .collect::<Vec<_>>()
+    });
+    Ok(held_tickets)
+}
+
 #[update(guard = "auth_update")]
 pub async fn pending_ticket(ticket: Ticket) -> Result<(), Error> {
     with_state_mut(|hub_state| hub_state.pending_ticket(ticket))
@@ -1537,7 +1812,7 @@ mod tests {
         assert!(result.is_ok());
 
         with_state(|hus_state| {
-            hus_state.ticket_map.iter().for_each(|(seq_key, ticket)| {
+            hus_state.ticket_queue.iter().for_each(|(seq_key, ticket)| {
                 println!(" seq key: {:?} ticket: {:?}", seq_key, ticket)
             })
         });
@@ -1590,7 +1865,7 @@ mod tests {
         assert!(result.is_ok());
 
         with_state(|hus_state| {
-            hus_state.ticket_map.iter().for_each(|(seq_key, ticket)| {
+            hus_state.ticket_queue.iter().for_each(|(seq_key, ticket)| {
                 println!(" seq key: {:?} ticket: {:?}", seq_key, ticket)
             })
         });
@@ -1,13 +1,22 @@
 use crate::auth::Permission;
+use crate::delivery::{Delivery, OverdueTicket};
 use crate::event::{record_event, Event};
+use crate::governance::{ProposalId, ProposalRecord};
 use crate::lifecycle::init::{HubArg, InitArgs};
 use crate::lifecycle::upgrade::UpgradeArgs;
 use crate::memory::{self, Memory};
 use crate::metrics::with_metrics_mut;
+use crate::rebuild;
+use crate::supply::{SupplyReport, DEACTIVATE_AFTER_MISMATCHES};
+use crate::resubmission::{Resubmission, ResubmittedTicket, DEFAULT_RESUBMIT_COOLDOWN};
+use crate::rate_limit::{HeldTicket, TicketVolume, WindowVolume};
 
-// use crate::migration::{migrate, PreHubState};
+use crate::migration::{migrate, PreHubState, QueueMigration};
 pub use crate::self_help::{AddRunesTokenReq, FinalizeAddRunesArgs};
-use omnity_types::hub_types::{ChainMeta, ChainTokenFactor, Subscribers, TokenKey, TokenMeta};
+use omnity_types::hub_types::{
+    ChainMeta, ChainTokenFactor, DeliveryPolicy, GovernanceConfig, Subscribers,
+    SupplyAuditConfig, TicketStatus, TokenKey, TokenMeta, TokenRateLimit,
+};
 use omnity_types::{Amount, TxHash};
 use candid::Principal;
 use ic_canister_log::log;
@@ -20,9 +29,8 @@ use omnity_types::{
 };
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::num::ParseIntError;
-const HOUR: u64 = 3_600_000_000_000;
 
 thread_local! {
     static STATE: RefCell<Option<HubState>> = RefCell::default();
@@ -54,6 +62,22 @@ pub struct HubState {
     pub tx_hashes: StableBTreeMap<TicketId, TxHash, Memory>,
     #[serde(skip, default = "memory::init_pending_tickets")]
     pub pending_tickets: StableBTreeMap<TicketId, Ticket, Memory>,
+    #[serde(skip, default = "memory::init_governance_proposals")]
+    pub governance_proposals: StableBTreeMap<ProposalId, ProposalRecord, Memory>,
+    #[serde(skip, default = "memory::init_held_tickets")]
+    pub held_tickets: StableBTreeMap<TicketId, HeldTicket, Memory>,
+    #[serde(skip, default = "memory::init_ticket_status")]
+    pub ticket_status: StableBTreeMap<TicketId, TicketStatus, Memory>,
+    #[serde(skip, default = "memory::init_deliveries")]
+    pub deliveries: StableBTreeMap<TicketId, Delivery, Memory>,
+    // the original ticket id -> the resubmission chain
+    #[serde(skip, default = "memory::init_resubmissions")]
+    pub resubmissions: StableBTreeMap<TicketId, Resubmission, Memory>,
+    // the resubmitted ticket id -> the original ticket id
+    #[serde(skip, default = "memory::init_resubmission_origins")]
+    pub resubmission_origins: StableBTreeMap<TicketId, TicketId, Memory>,
+    #[serde(skip, default = "memory::init_supply_reports")]
+    pub supply_reports: StableBTreeMap<TokenKey, SupplyReport, Memory>,
 
     // memory variable
     pub directive_seq: HashMap<String, Seq>,
@@ -61,11 +85,31 @@ pub struct HubState {
     pub admin: Principal,
     pub caller_chain_map: HashMap<String, ChainId>,
     pub caller_perms: HashMap<String, Permission>,
-    pub last_resubmit_ticket_time: u64,
     pub add_runes_token_requests: BTreeMap<String, AddRunesTokenReq>,
     pub runes_oracles: BTreeSet<Principal>,
-    pub dire_map: BTreeMap<SeqKey, Directive>,
-    pub ticket_map: BTreeMap<SeqKey, String>,
+    pub governance: Option<GovernanceConfig>,
+    pub next_proposal_id: ProposalId,
+    pub rate_limits: BTreeMap<TokenId, TokenRateLimit>,
+    pub ticket_volumes: BTreeMap<TokenId, VecDeque<TicketVolume>>,
+    // the latest ticket seq acknowledged by the route
+    pub delivered_seq: HashMap<ChainId, Seq>,
+    pub delivery_policies: BTreeMap<ChainId, DeliveryPolicy>,
+    // the cooldown(ns) between two resubmissions of a ticket sent to the chain
+    pub resubmit_cooldowns: BTreeMap<ChainId, u64>,
+    pub supply_audit: Option<SupplyAuditConfig>,
+    // the routes notified of the new tickets and directives
+    pub push_subscribers: BTreeSet<ChainId>,
+    // the tickets counted in the token positions but not executed on the dst chain yet
+    pub in_flight_tickets: BTreeSet<TicketId>,
+    // the latest directive seq acknowledged by the route
+    pub acked_directive_seq: HashMap<ChainId, Seq>,
+    // the heap queues of the pre state being moved into the stable queues
+    pub queue_migration: Option<QueueMigration>,
+    // the subscribers to be notified by the next notification task
+    #[serde(skip)]
+    pub pending_ticket_notifications: BTreeSet<ChainId>,
+    #[serde(skip)]
+    pub pending_directive_notifications: BTreeSet<ChainId>,
 }
 
 impl From<InitArgs> for HubState {
@@ -83,16 +127,36 @@ impl From<InitArgs> for HubState {
             ticket_queue: StableBTreeMap::init(memory::get_ticket_queue_memory()),
             tx_hashes: StableBTreeMap::init(memory::get_tx_hashes_memory()),
             pending_tickets: StableBTreeMap::init(memory::get_pending_tickets_memory()),
+            governance_proposals: StableBTreeMap::init(memory::get_governance_proposals_memory()),
+            held_tickets: StableBTreeMap::init(memory::get_held_tickets_memory()),
+            ticket_status: StableBTreeMap::init(memory::get_ticket_status_memory()),
+            deliveries: StableBTreeMap::init(memory::get_deliveries_memory()),
+            resubmissions: StableBTreeMap::init(memory::get_resubmissions_memory()),
+            resubmission_origins: StableBTreeMap::init(
+                memory::get_resubmission_origins_memory(),
+            ),
+            supply_reports: StableBTreeMap::init(memory::get_supply_reports_memory()),
             directive_seq: HashMap::default(),
             ticket_seq: HashMap::default(),
             admin: args.admin,
             caller_chain_map: HashMap::default(),
             caller_perms: HashMap::from([(args.admin.to_string(), Permission::Update)]),
-            last_resubmit_ticket_time: 0,
             add_runes_token_requests: Default::default(),
             runes_oracles: Default::default(),
-            dire_map: BTreeMap::default(),
-            ticket_map: BTreeMap::default(),
+            governance: None,
+            next_proposal_id: 0,
+            rate_limits: BTreeMap::default(),
+            ticket_volumes: BTreeMap::default(),
+            delivered_seq: HashMap::default(),
+            delivery_policies: BTreeMap::default(),
+            resubmit_cooldowns: BTreeMap::default(),
+            supply_audit: None,
+            push_subscribers: BTreeSet::default(),
+            in_flight_tickets: BTreeSet::default(),
+            acked_directive_seq: HashMap::default(),
+            queue_migration: None,
+            pending_ticket_notifications: BTreeSet::default(),
+            pending_directive_notifications: BTreeSet::default(),
         }
     }
 }
@@ -139,7 +203,22 @@ impl HubState {
             .expect("failed to save hub state");
     }
 
-    pub fn post_upgrade(args: Option<HubArg>) {
+    /// `on_ready` is called once the state is set, after the rebuild if the state
+    /// is rebuilt from the event log
+    pub fn post_upgrade(args: Option<HubArg>, on_ready: fn()) {
+        let upgrade_args = match args {
+            Some(HubArg::Upgrade(upgrade_args)) => upgrade_args,
+            Some(HubArg::Init(_)) => panic!("expected Option<UpgradeArgs> got InitArgs."),
+            None => None,
+        };
+        if upgrade_args
+            .as_ref()
+            .is_some_and(|args| args.rebuild_from_events == Some(true))
+        {
+            rebuild::start_rebuild(upgrade_args, on_ready);
+            return;
+        }
+
         let memory = memory::get_upgrades_memory();
         // Read the length of the state bytes.
         let mut state_len_bytes = [0; 4];
@@ -151,27 +230,50 @@ impl HubState {
         memory.read(4, &mut state_bytes);
 
         // Deserialize pre state
-        let mut hub_state: HubState =
+        let pre_state: PreHubState =
             ciborium::de::from_reader(&*state_bytes).expect("failed to decode state");
 
         // migrate state
-        // let mut cur_state = migrate(pre_state);
-
-        if let Some(args) = args {
-            match args {
-                HubArg::Upgrade(upgrade_args) => {
-                    if let Some(args) = upgrade_args {
-                        if let Some(admin) = args.admin {
-                            hub_state.admin = admin;
-                        }
-                        record_event(&Event::Upgrade(args));
-                    }
-                }
-                HubArg::Init(_) => panic!("expected Option<UpgradeArgs> got InitArgs."),
-            };
-        }
+        let mut hub_state = migrate(pre_state);
+        hub_state.apply_upgrade_args(upgrade_args);
         // update state
         set_state(hub_state);
+        on_ready();
+    }
+
+    pub fn apply_upgrade_args(&mut self, upgrade_args: Option<UpgradeArgs>) {
+        if let Some(args) = upgrade_args {
+            if let Some(admin) = args.admin {
+                self.admin = admin;
+            }
+            record_event(&Event::Upgrade(args));
+        }
+    }
+
+    /// Re-initialise the stable structures on their memories, the entries left by
+    /// the stale state are dropped at once.
+    pub fn reset_stable_structures(&mut self) {
+        self.chains = StableBTreeMap::new(memory::get_chain_memory());
+        self.tokens = StableBTreeMap::new(memory::get_token_memory());
+        self.target_chain_factors = StableBTreeMap::new(memory::get_chain_factor_memory());
+        self.fee_token_factors = StableBTreeMap::new(memory::get_token_factor_memory());
+        self.directives = StableBTreeMap::new(memory::get_directive_memory());
+        self.dire_queue = StableBTreeMap::new(memory::get_dire_queue_memory());
+        self.topic_subscribers = StableBTreeMap::new(memory::get_subs_memory());
+        self.ticket_queue = StableBTreeMap::new(memory::get_ticket_queue_memory());
+        self.token_position = StableBTreeMap::new(memory::get_token_position_memory());
+        self.cross_ledger = StableBTreeMap::new(memory::get_ledger_memory());
+        self.tx_hashes = StableBTreeMap::new(memory::get_tx_hashes_memory());
+        self.pending_tickets = StableBTreeMap::new(memory::get_pending_tickets_memory());
+        self.governance_proposals =
+            StableBTreeMap::new(memory::get_governance_proposals_memory());
+        self.held_tickets = StableBTreeMap::new(memory::get_held_tickets_memory());
+        self.ticket_status = StableBTreeMap::new(memory::get_ticket_status_memory());
+        self.deliveries = StableBTreeMap::new(memory::get_deliveries_memory());
+        self.resubmissions = StableBTreeMap::new(memory::get_resubmissions_memory());
+        self.resubmission_origins =
+            StableBTreeMap::new(memory::get_resubmission_origins_memory());
+        self.supply_reports = StableBTreeMap::new(memory::get_supply_reports_memory());
     }
 
     pub fn upgrade(&mut self, args: UpgradeArgs) {
@@ -380,6 +482,21 @@ impl HubState {
         }
     }
 
+    pub fn update_governance(&mut self, config: GovernanceConfig) -> Result<(), Error> {
+        self.governance = Some(config.clone());
+        record_event(&Event::UpdatedGovernance(config));
+
+        Ok(())
+    }
+
+    pub fn save_governance_proposal(&mut self, record: ProposalRecord) {
+        if record.id >= self.next_proposal_id {
+            self.next_proposal_id = record.id + 1;
+        }
+        self.governance_proposals.insert(record.id, record.clone());
+        record_event(&Event::SavedGovernanceProposal(record));
+    }
+
     pub fn sub_directives(&mut self, chain_id: &ChainId, topics: &[Topic]) -> Result<(), Error> {
         topics.iter().try_for_each(|topic| {
             let mut subscribers = self.topic_subscribers.get(topic).unwrap_or_default();
@@ -488,7 +605,10 @@ impl HubState {
                 .or_insert(0);
 
             let seq_key = SeqKey::from(sub.to_string(), *latest_dire_seq);
-            self.dire_map.insert(seq_key.to_owned(), dire.to_owned());
+            self.dire_queue.insert(seq_key.to_owned(), dire.to_owned());
+            if self.push_subscribers.contains(sub) {
+                self.pending_directive_notifications.insert(sub.to_string());
+            }
             record_event(&Event::PubedDirective {
                 seq_key,
                 dire: dire.to_owned(),
@@ -498,6 +618,7 @@ impl HubState {
         Ok(())
     }
 
+    /// the offset is the number of the directives of the topic to skip
     pub fn pull_directives(
         &self,
         chain_id: ChainId,
@@ -505,25 +626,62 @@ impl HubState {
         offset: usize,
         limit: usize,
     ) -> Result<Vec<(Seq, Directive)>, Error> {
-        match topic {
-            Some(topic) => Ok(self
-                .dire_map
-                .iter()
-                .filter(|(seq_key, _)| seq_key.chain_id.eq(&chain_id))
-                .filter(|(_, dire)| dire.to_topic() == topic)
-                .skip(offset)
-                .take(limit)
-                .map(|(seq_key, dire)| (seq_key.seq, dire.to_owned()))
-                .collect::<Vec<_>>()),
-            None => Ok(self
-                .dire_map
-                .iter()
-                .filter(|(seq_key, _)| seq_key.chain_id.eq(&chain_id))
-                .skip(offset)
-                .take(limit)
-                .map(|(seq_key, dire)| (seq_key.seq, dire.to_owned()))
-                .collect::<Vec<_>>()),
-        }
+        let dires = self
+            .queued_directives(&chain_id, 0)
+            .filter(|(_, dire)| {
+                topic
+                    .as_ref()
+                    .map_or(true, |topic| dire.to_topic() == *topic)
+            })
+            .skip(offset)
+            .take(limit)
+            .map(|(seq_key, dire)| (seq_key.seq, dire))
+            .collect::<Vec<_>>();
+        Ok(dires)
+    }
+
+    /// pull the directives of the topic from the seq on
+    pub fn pull_directives_from_seq(
+        &self,
+        chain_id: ChainId,
+        topic: Option<Topic>,
+        from_seq: Seq,
+        limit: usize,
+    ) -> Result<Vec<(Seq, Directive)>, Error> {
+        let dires = self
+            .queued_directives(&chain_id, from_seq)
+            .filter(|(_, dire)| {
+                topic
+                    .as_ref()
+                    .map_or(true, |topic| dire.to_topic() == *topic)
+            })
+            .take(limit)
+            .map(|(seq_key, dire)| (seq_key.seq, dire))
+            .collect::<Vec<_>>();
+        Ok(dires)
+    }
+
+    /// the queued directives of the chain from the seq on, the entries not migrated
+    /// yet precede the entries in the stable queue
+    fn queued_directives<'a>(
+        &'a self,
+        chain_id: &'a ChainId,
+        from_seq: Seq,
+    ) -> impl Iterator<Item = (SeqKey, Directive)> + 'a {
+        let start = SeqKey::from(chain_id.to_string(), from_seq);
+        self.queue_migration
+            .iter()
+            .flat_map(move |m| {
+                m.dire_map
+                    .range(SeqKey::from(chain_id.to_string(), from_seq)..)
+            })
+            .take_while(move |(seq_key, _)| seq_key.chain_id.eq(chain_id))
+            .map(|(seq_key, dire)| (seq_key.to_owned(), dire.to_owned()))
+            .chain(
+                self.dire_queue
+                    .range(start..)
+                    .take_while(move |(seq_key, _)| seq_key.chain_id.eq(chain_id)),
+            )
     }
 
     pub fn add_token_position(&mut self, position: TokenKey, amount: u128) -> Result<(), Error> {
@@ -570,7 +728,9 @@ impl HubState {
     // check the ticket availability
     pub fn check_and_update(&mut self, ticket: &Ticket) -> Result<(), Error> {
         // check ticket id repetitive
-        if self.cross_ledger.contains_key(&ticket.ticket_id) {
+        if self.cross_ledger.contains_key(&ticket.ticket_id)
+            || self.held_tickets.contains_key(&ticket.ticket_id)
+        {
             log!(
                 WARNING,
                 "The ticket id (`{}`) already exists!`",
@@ -583,8 +743,20 @@ impl HubState {
         self.available_chain(&ticket.src_chain)?;
         self.available_chain(&ticket.dst_chain)?;
 
-        //parse ticket token amount to unsigned bigint
-        let ticket_amount: u128 = ticket.amount.parse().map_err(|e: ParseIntError| {
+        let ticket_amount = Self::ticket_amount(ticket)?;
+
+        // park the ticket if it breaches the rate limits
+        self.check_rate_limit(ticket, ticket_amount)?;
+
+        self.update_ticket_position(ticket, ticket_amount)?;
+        self.record_ticket_volume(ticket, ticket_amount);
+
+        Ok(())
+    }
+
+    //parse ticket token amount to unsigned bigint
+    fn ticket_amount(ticket: &Ticket) -> Result<u128, Error> {
+        ticket.amount.parse().map_err(|e: ParseIntError| {
             log!(
                 WARNING,
                 "The ticket amount(`{}`) parse error: `{}`",
@@ -592,8 +764,11 @@ impl HubState {
                 e.to_string()
             );
             Error::TicketAmountParseError(ticket.amount.to_string(), e.to_string())
-        })?;
+        })
+    }
 
+    // update the token amount on the src and dst chain
+    fn update_ticket_position(&mut self, ticket: &Ticket, ticket_amount: u128) -> Result<(), Error> {
         // check token on chain availability
         match ticket.action {
             TxAction::Transfer => {
@@ -683,6 +858,162 @@ impl HubState {
         Ok(())
     }
 
+    // check the rolling window volumes of the ticket token, the ticket breaching the limits
+    // is held and its src chain is deactivated if the circuit breaker is tripped
+    fn check_rate_limit(&mut self, ticket: &Ticket, ticket_amount: u128) -> Result<(), Error> {
+        let limit = match self.rate_limits.get(&ticket.token) {
+            Some(limit) => limit.clone(),
+            None => return Ok(()),
+        };
+        let now = ic_cdk::api::time();
+        let volumes = self
+            .ticket_volumes
+            .entry(ticket.token.to_string())
+            .or_default();
+        // remove the volumes out of the window
+        while volumes
+            .front()
+            .is_some_and(|v| v.timestamp.saturating_add(limit.window) < now)
+        {
+            volumes.pop_front();
+        }
+        let window_volume = WindowVolume::new(volumes.iter(), ticket, ticket_amount);
+
+        let mut reason = window_volume.breach(&limit, ticket_amount);
+        if window_volume.trips_circuit_breaker(&limit) {
+            self.deactivate_chain(&ticket.src_chain, "tripped the circuit breaker");
+            reason = Some(format!(
+                "the src chain volume {} trips the circuit breaker {:?}",
+                window_volume.src_chain, limit.circuit_breaker
+            ));
+        }
+
+        match reason {
+            Some(reason) => {
+                self.hold_ticket(ticket.clone(), reason.clone());
+                Err(Error::TicketHeld(ticket.ticket_id.to_string(), reason))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn record_ticket_volume(&mut self, ticket: &Ticket, ticket_amount: u128) {
+        // only the tokens with rate limit need the volumes
+        if self.rate_limits.contains_key(&ticket.token) {
+            let volume = TicketVolume {
+                timestamp: ic_cdk::api::time(),
+                src_chain: ticket.src_chain.to_string(),
+                dst_chain: ticket.dst_chain.to_string(),
+                amount: ticket_amount,
+            };
+            self.ticket_volumes
+                .entry(ticket.token.to_string())
+                .or_default()
+                .push_back(volume.clone());
+            // the volumes are rebuilt from the event log
+            record_event(&Event::RecordedTicketVolume {
+                token_id: ticket.token.to_string(),
+                volume,
+            });
+        }
+    }
+
+    fn deactivate_chain(&mut self, chain_id: &ChainId, reason: &str) {
+        let toggle_state = ToggleState {
+            chain_id: chain_id.to_string(),
+            action: ToggleAction::Deactivate,
+        };
+        if self.available_state(&toggle_state).is_err() {
+            return;
+        }
+        log!(
+            WARNING,
+            "The chain (`{}`) {}, deactivate it!",
+            chain_id.to_string(),
+            reason
+        );
+        if let Err(e) =
+            self.pub_directive(None, &Directive::ToggleChainState(toggle_state.clone()))
+        {
+            log!(ERROR, "failed to publish the toggle chain state directive: {}", e);
+        }
+        if let Err(e) = self.update_chain_state(&toggle_state) {
+            log!(ERROR, "failed to deactivate the chain (`{}`): {}", chain_id, e);
+        }
+    }
+
+    fn hold_ticket(&mut self, ticket: Ticket, reason: String) {
+        log!(
+            WARNING,
+            "The ticket (`{}`) is held: {}",
+            ticket.ticket_id.to_string(),
+            reason
+        );
+        self.update_ticket_status(
+            &ticket.ticket_id,
+            TicketStatus::Held {
+                reason: reason.to_string(),
+            },
+        );
+        let held_ticket = HeldTicket {
+            ticket,
+            reason,
+            held_at: ic_cdk::api::time(),
+        };
+        self.held_tickets.insert(
+            held_ticket.ticket.ticket_id.to_string(),
+            held_ticket.clone(),
+        );
+        record_event(&Event::ParkedTicket(held_ticket));
+    }
+
+    /// update the token position for the held ticket and push it into queue
+    pub fn release_held_ticket(&mut self, ticket_id: &TicketId) -> Result<(), Error> {
+        let held_ticket = self
+            .held_tickets
+            .get(ticket_id)
+            .ok_or(Error::NotFoundTicketId(ticket_id.to_string()))?;
+        let ticket = held_ticket.ticket;
+        // the src chain may be deactivated by the circuit breaker
+        self.available_chain(&ticket.dst_chain)?;
+
+        let ticket_amount = Self::ticket_amount(&ticket)?;
+        self.update_ticket_position(&ticket, ticket_amount)?;
+        self.record_ticket_volume(&ticket, ticket_amount);
+
+        self.held_tickets.remove(ticket_id);
+        record_event(&Event::ReleasedTicket {
+            ticket_id: ticket_id.to_string(),
+        });
+        self.push_ticket(ticket)
+    }
+
+    pub fn update_rate_limit(&mut self, limit: TokenRateLimit) -> Result<(), Error> {
+        self.rate_limits
+            .insert(limit.token_id.to_string(), limit.clone());
+        record_event(&Event::UpdatedRateLimit(limit));
+
+        Ok(())
+    }
+
+    pub fn remove_rate_limit(&mut self, token_id: &TokenId) -> Result<(), Error> {
+        self.rate_limits.remove(token_id);
+        self.ticket_volumes.remove(token_id);
+        record_event(&Event::RemovedRateLimit(token_id.to_string()));
+
+        Ok(())
+    }
+
+    /// check and push the ticket into queue, the held ticket is accepted
+    /// and will be pushed once it`s released
+    pub fn send_ticket(&mut self, ticket: Ticket) -> Result<(), Error> {
+        match self.check_and_update(&ticket) {
+            Ok(()) => self.push_ticket(ticket),
+            Err(Error::TicketHeld(..)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
     pub fn pending_ticket(&mut self, ticket: Ticket) -> Result<(), Error> {
         // check ticket id repetitive
         if self.pending_tickets.contains_key(&ticket.ticket_id) {
@@ -697,6 +1028,7 @@ impl HubState {
         self.pending_tickets
             .insert(ticket.ticket_id.to_string(), ticket.clone());
         log!(INFO, "[Consolidation]Hub: pending ticket: {:?}", &ticket);
+        self.update_ticket_status(&ticket.ticket_id, TicketStatus::Pending);
         record_event(&Event::PendingTicket { ticket });
         
         Ok(())
@@ -708,9 +1040,13 @@ impl HubState {
             .get(ticket_id)
             .ok_or(Error::NotFoundTicketId(ticket_id.to_string()))?;
         // check ticket and update token on chain
-        self.check_and_update(&ticket)?;
-        // push ticket into queue
-        self.push_ticket(ticket)?;
+        match self.check_and_update(&ticket) {
+            // push ticket into queue
+            Ok(()) => self.push_ticket(ticket)?,
+            // the held ticket will be pushed once it`s released
+            Err(Error::TicketHeld(..)) => {}
+            Err(e) => return Err(e),
+        }
         // remove pending ticket
         self.pending_tickets
             .remove(&ticket_id)
@@ -726,6 +1062,10 @@ impl HubState {
     }
 
     pub fn push_ticket(&mut self, ticket: Ticket) -> Result<(), Error> {
+        self.enqueue_ticket(ticket, 0)
+    }
+
+    fn enqueue_ticket(&mut self, ticket: Ticket, auto_resubmitted: u32) -> Result<(), Error> {
         // get latest ticket seq
         let latest_ticket_seq = self
             .ticket_seq
@@ -735,13 +1075,18 @@ impl HubState {
 
         // create new ticket
         let seq_key = SeqKey::from(ticket.dst_chain.to_string(), *latest_ticket_seq);
-        self.ticket_map
-            .insert(seq_key.clone(), ticket.ticket_id.to_string());
+        self.ticket_queue.insert(seq_key.clone(), ticket.clone());
 
         //save ticket
         self.cross_ledger
             .insert(ticket.ticket_id.to_string(), ticket.clone());
-        
+        self.update_ticket_status(&ticket.ticket_id, TicketStatus::Accepted);
+        self.track_delivery(&ticket, auto_resubmitted);
+        if self.push_subscribers.contains(&ticket.dst_chain) {
+            self.pending_ticket_notifications
+                .insert(ticket.dst_chain.to_string());
+        }
+
         log!(INFO, "[Consolidation] hub received ticket: {:?}", &ticket);
         //update ticket metrice
         with_metrics_mut(|metrics| metrics.update_ticket_metric(ticket.clone()));
@@ -752,39 +1097,54 @@ impl HubState {
         Ok(())
     }
 
-    pub fn resubmit_ticket(&mut self, ticket: Ticket) -> Result<(), Error> {
-        let now = ic_cdk::api::time();
-        if now - self.last_resubmit_ticket_time < 6 * HOUR {
-            log!(ERROR, "The resumit ticket sent too often");
-            return Err(Error::ResubmitTicketSentTooOften);
-        }
+    pub fn resubmit_ticket(&mut self, ticket: Ticket, now: u64) -> Result<(), Error> {
         match self.cross_ledger.get(&ticket.ticket_id) {
             Some(old_ticket) => {
                 if ticket != old_ticket {
                     log!(ERROR, "The resubmit ticket must same as the old ticket!");
                     return Err(Error::ResubmitTicketMustSame);
                 }
-                let ticket_id = format!("{}_{}", ticket.ticket_id, now);
-                let new_ticket = Ticket {
-                    ticket_id: ticket_id.clone(),
-                    ticket_type: TicketType::Resubmit,
-                    ticket_time: now,
-                    src_chain: ticket.src_chain,
-                    dst_chain: ticket.dst_chain,
-                    action: ticket.action,
-                    token: ticket.token,
-                    amount: ticket.amount,
-                    sender: ticket.sender,
-                    receiver: ticket.receiver,
-                    memo: ticket.memo,
-                };
-                self.push_ticket(new_ticket)?;
-                self.last_resubmit_ticket_time = now;
-
-                record_event(&Event::ResubmitTicket {
-                    ticket_id,
-                    timestamp: now,
-                });
+                // the original ticket id or any resubmitted id resubmits the latest ticket
+                let resubmission = self.get_resubmission(&ticket.ticket_id);
+                // the tokens of an executed or refunded ticket were already paid out,
+                // a held ticket is released by the hub
+                for ticket_id in resubmission.ticket_ids() {
+                    if let Some(
+                        status @ (TicketStatus::Executed { .. }
+                        | TicketStatus::Refunded { .. }
+                        | TicketStatus::Held { .. }),
+                    ) = self.ticket_status.get(ticket_id)
+                    {
+                        log!(
+                            ERROR,
+                            "The ticket (`{}`) is {:?}, it can not be resubmitted",
+                            ticket_id,
+                            status
+                        );
+                        return Err(Error::CustomError(format!(
+                            "The ticket (`{}`) is {:?}, it can not be resubmitted",
+                            ticket_id, status
+                        )));
+                    }
+                }
+                let origin_ticket_time = self
+                    .cross_ledger
+                    .get(&resubmission.origin_ticket_id)
+                    .map_or(old_ticket.ticket_time, |origin| origin.ticket_time);
+                let cooldown = self
+                    .resubmit_cooldowns
+                    .get(&ticket.dst_chain)
+                    .copied()
+                    .unwrap_or(DEFAULT_RESUBMIT_COOLDOWN);
+                if resubmission
+                    .cooldown_remaining(origin_ticket_time, cooldown, now)
+                    .is_some()
+                {
+                    log!(ERROR, "The resumit ticket sent too often");
+                    return Err(Error::ResubmitTicketSentTooOften);
+                }
+                let latest_ticket_id = resubmission.latest_ticket_id().to_string();
+                self.requeue_ticket(&latest_ticket_id, now, false)?;
                 Ok(())
             }
             None => {
@@ -794,11 +1154,126 @@ impl HubState {
         }
     }
 
+    /// push a copy of the ticket with the new id `{origin_ticket_id}_{now}` into the queue
+    pub fn requeue_ticket(
+        &mut self,
+        ticket_id: &TicketId,
+        now: u64,
+        auto_resubmit: bool,
+    ) -> Result<TicketId, Error> {
+        let ticket = self
+            .cross_ledger
+            .get(ticket_id)
+            .ok_or(Error::NotFoundTicketId(ticket_id.to_string()))?;
+        let auto_resubmitted = self
+            .deliveries
+            .get(ticket_id)
+            .map_or(0, |delivery| delivery.auto_resubmitted)
+            + auto_resubmit as u32;
+
+        let new_id = format!(
+            "{}_{}",
+            self.get_resubmission(ticket_id).origin_ticket_id,
+            now
+        );
+        let new_ticket = Ticket {
+            ticket_id: new_id.clone(),
+            ticket_type: TicketType::Resubmit,
+            ticket_time: now,
+            ..ticket
+        };
+        self.enqueue_ticket(new_ticket, auto_resubmitted)?;
+        self.resolve_delivery(ticket_id);
+        self.record_resubmission(ticket_id, &new_id, now, auto_resubmit);
+        self.update_ticket_status(
+            ticket_id,
+            TicketStatus::Resubmitted {
+                new_id: new_id.clone(),
+            },
+        );
+        Ok(new_id)
+    }
+
+    fn record_resubmission(
+        &mut self,
+        ticket_id: &TicketId,
+        new_id: &TicketId,
+        now: u64,
+        auto: bool,
+    ) {
+        let mut resubmission = self.get_resubmission(ticket_id);
+        resubmission.tickets.push(ResubmittedTicket {
+            ticket_id: new_id.to_string(),
+            resubmitted_at: now,
+            auto,
+        });
+        self.resubmission_origins.insert(
+            new_id.to_string(),
+            resubmission.origin_ticket_id.to_string(),
+        );
+        self.resubmissions
+            .insert(resubmission.origin_ticket_id.to_string(), resubmission.clone());
+        record_event(&Event::UpdatedResubmission(resubmission));
+    }
+
+    /// get the resubmission chain by the original ticket id or any resubmitted ticket id
+    pub fn get_resubmission(&self, ticket_id: &TicketId) -> Resubmission {
+        let origin_ticket_id = self
+            .resubmission_origins
+            .get(ticket_id)
+            .unwrap_or(ticket_id.to_string());
+        self.resubmissions
+            .get(&origin_ticket_id)
+            .unwrap_or(Resubmission::new(origin_ticket_id))
+    }
+
+    pub fn update_resubmit_cooldown(
+        &mut self,
+        chain_id: ChainId,
+        cooldown: u64,
+    ) -> Result<(), Error> {
+        self.resubmit_cooldowns
+            .insert(chain_id.to_string(), cooldown);
+        record_event(&Event::UpdatedResubmitCooldown { chain_id, cooldown });
+
+        Ok(())
+    }
+
     pub fn update_tx_hash(&mut self, ticket_id: TicketId, tx_hash: TxHash) -> Result<(), Error> {
         match self.cross_ledger.get(&ticket_id) {
             Some(_) => {
+                match self.ticket_status.get(&ticket_id) {
+                    // the tickets without status were accepted before the status was tracked,
+                    // a failed ticket may still be executed by the retry of the route
+                    None
+                    | Some(
+                        TicketStatus::Accepted
+                        | TicketStatus::DeliveredToRoute
+                        | TicketStatus::Failed { .. }
+                        | TicketStatus::Executed { .. },
+                    ) => {}
+                    Some(status) => {
+                        log!(
+                            ERROR,
+                            "The ticket (`{}`) is {:?}, it can not be executed",
+                            ticket_id,
+                            status
+                        );
+                        return Err(Error::CustomError(format!(
+                            "The ticket (`{}`) is {:?}, it can not be executed",
+                            ticket_id, status
+                        )));
+                    }
+                }
                 self.tx_hashes
                     .insert(ticket_id.to_string(), tx_hash.to_string());
+                self.resolve_delivery(&ticket_id);
+                self.update_ticket_status(
+                    &ticket_id,
+                    TicketStatus::Executed {
+                        tx_hash: tx_hash.to_string(),
+                    },
+                );
 
                 record_event(&&Event::UpdatedTxHash { ticket_id, tx_hash });
                 Ok(())
@@ -809,27 +1284,500 @@ impl HubState {
             }
         }
     }
+    pub fn update_ticket_status(&mut self, ticket_id: &TicketId, status: TicketStatus) {
+        self.track_in_flight(ticket_id, &status);
+        self.ticket_status
+            .insert(ticket_id.to_string(), status.clone());
+        record_event(&Event::UpdatedTicketStatus {
+            ticket_id: ticket_id.to_string(),
+            status,
+        });
+    }
+
+    /// the token positions are updated once the ticket is accepted,
+    /// the ticket is in flight until it`s executed, resubmitted or refunded
+    pub fn track_in_flight(&mut self, ticket_id: &TicketId, status: &TicketStatus) {
+        match status {
+            TicketStatus::Accepted
+            | TicketStatus::DeliveredToRoute
+            | TicketStatus::Failed { .. } => {
+                self.in_flight_tickets.insert(ticket_id.to_string());
+            }
+            _ => {
+                self.in_flight_tickets.remove(ticket_id);
+            }
+        }
+    }
+
+    /// the route acknowledges it received the tickets up to the seq
+    pub fn ack_tickets(&mut self, chain_id: &ChainId, seq: Seq) -> Result<(), Error> {
+        let latest_seq = self
+            .ticket_seq
+            .get(chain_id)
+            .ok_or(Error::CustomError(format!(
+                "There is no ticket for the chain: {}",
+                chain_id
+            )))?;
+        if seq > *latest_seq {
+            return Err(Error::CustomError(format!(
+                "The acked seq {} is greater than the latest seq {}",
+                seq, latest_seq
+            )));
+        }
+        let from_seq = match self.delivered_seq.get(chain_id) {
+            Some(delivered_seq) if *delivered_seq >= seq => return Ok(()),
+            Some(delivered_seq) => delivered_seq + 1,
+            None => 0,
+        };
+
+        let delivered_tickets = self
+            .queued_tickets(chain_id, from_seq)
+            .take_while(|(seq_key, _)| seq_key.seq <= seq)
+            .map(|(_, ticket)| ticket.ticket_id)
+            .filter(|ticket_id| {
+                matches!(self.ticket_status.get(ticket_id), Some(TicketStatus::Accepted))
+            })
+            .collect::<Vec<_>>();
+        delivered_tickets.iter().for_each(|ticket_id| {
+            self.update_ticket_status(ticket_id, TicketStatus::DeliveredToRoute)
+        });
+
+        self.delivered_seq.insert(chain_id.to_string(), seq);
+        record_event(&Event::AckedTickets {
+            chain_id: chain_id.to_string(),
+            seq,
+        });
+        Ok(())
+    }
+
+    /// the route acknowledges it received the directives up to the seq
+    pub fn ack_directives(&mut self, chain_id: &ChainId, seq: Seq) -> Result<(), Error> {
+        let latest_seq = self
+            .directive_seq
+            .get(chain_id)
+            .ok_or(Error::CustomError(format!(
+                "There is no directive for the chain: {}",
+                chain_id
+            )))?;
+        if seq > *latest_seq {
+            return Err(Error::CustomError(format!(
+                "The acked seq {} is greater than the latest seq {}",
+                seq, latest_seq
+            )));
+        }
+        if self
+            .acked_directive_seq
+            .get(chain_id)
+            .is_some_and(|acked_seq| *acked_seq >= seq)
+        {
+            return Ok(());
+        }
+        self.acked_directive_seq.insert(chain_id.to_string(), seq);
+        record_event(&Event::AckedDirectives {
+            chain_id: chain_id.to_string(),
+            seq,
+        });
+        Ok(())
+    }
+
+    /// the subscribed route is notified of the new tickets and directives
+    /// instead of polling the hub
+    pub fn update_push_subscription(
+        &mut self,
+        chain_id: &ChainId,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        if !self.chains.contains_key(chain_id) {
+            return Err(Error::NotFoundChain(chain_id.to_string()));
+        }
+        if enabled {
+            self.push_subscribers.insert(chain_id.to_string());
+        } else {
+            self.push_subscribers.remove(chain_id);
+        }
+        record_event(&Event::UpdatedPushSubscription {
+            chain_id: chain_id.to_string(),
+            enabled,
+        });
+        Ok(())
+    }
+
+    /// the dst route reports the ticket can not be executed
+    /// the permanently failed ticket is refunded automatically
+    pub fn report_ticket_failure(
+        &mut self,
+        ticket_id: &TicketId,
+        reason: String,
+        permanent: bool,
+    ) -> Result<(), Error> {
+        if !self.cross_ledger.contains_key(ticket_id) {
+            log!(ERROR, "The ticket id is not exists!");
+            return Err(Error::NotFoundTicketId(ticket_id.to_string()));
+        }
+        if let Some(
+            status @ (TicketStatus::Executed { .. }
+            | TicketStatus::Refunded { .. }
+            | TicketStatus::Resubmitted { .. }),
+        ) = self.ticket_status.get(ticket_id)
+        {
+            return Err(Error::CustomError(format!(
+                "The ticket (`{}`) is already {:?}",
+                ticket_id, status
+            )));
+        }
+        log!(
+            WARNING,
+            "The ticket (`{}`) failed: {}",
+            ticket_id.to_string(),
+            reason
+        );
+        self.resolve_delivery(ticket_id);
+        self.update_ticket_status(ticket_id, TicketStatus::Failed { reason });
+        if permanent {
+            self.refund_ticket(ticket_id, ic_cdk::api::time())?;
+        }
+        Ok(())
+    }
+
+    /// send the reverse ticket of the undeliverable ticket back to the sender on the src chain,
+    /// the token positions are restored by the reverse ticket
+    pub fn refund_ticket(&mut self, ticket_id: &TicketId, now: u64) -> Result<TicketId, Error> {
+        let held_ticket = self.held_tickets.get(ticket_id);
+        let ticket = match &held_ticket {
+            Some(held_ticket) => held_ticket.ticket.clone(),
+            None => self
+                .cross_ledger
+                .get(ticket_id)
+                .ok_or(Error::NotFoundTicketId(ticket_id.to_string()))?,
+        };
+        // only the tickets the route gave up on or the hub never released are refunded,
+        // the others may still be executed by the route
+        match self.ticket_status.get(ticket_id) {
+            Some(TicketStatus::Failed { .. } | TicketStatus::Held { .. }) => {}
+            status => {
+                log!(
+                    ERROR,
+                    "The ticket (`{}`) is {:?}, it can not be refunded",
+                    ticket_id,
+                    status
+                );
+                return Err(Error::CustomError(format!(
+                    "The ticket (`{}`) is {:?}, it can not be refunded",
+                    ticket_id, status
+                )));
+            }
+        }
+        if matches!(ticket.ticket_type, TicketType::Refund) {
+            return Err(Error::CustomError(
+                "The refund ticket can not be refunded".to_string(),
+            ));
+        }
+        let receiver = ticket.sender.clone().ok_or(Error::CustomError(format!(
+            "The ticket (`{}`) has no sender to refund",
+            ticket_id
+        )))?;
+        let action = match ticket.action {
+            // the token goes back to its issue chain
+            TxAction::Transfer if self.is_origin(&ticket.src_chain, &ticket.token)? => {
+                TxAction::Redeem
+            }
+            TxAction::Transfer | TxAction::Redeem => TxAction::Transfer,
+            _ => {
+                return Err(Error::CustomError(format!(
+                    "The {:?} ticket can not be refunded",
+                    ticket.action
+                )))
+            }
+        };
+        self.available_chain(&ticket.src_chain)?;
+
+        let refund_id = format!("{}_refund", ticket_id);
+        if self.cross_ledger.contains_key(&refund_id) {
+            log!(ERROR, "The ticket (`{}`) is already refunded", ticket_id);
+            return Err(Error::CustomError(format!(
+                "The ticket (`{}`) is already refunded by `{}`",
+                ticket_id, refund_id
+            )));
+        }
+        let refund_ticket = Ticket {
+            ticket_id: refund_id.clone(),
+            ticket_type: TicketType::Refund,
+            ticket_time: now,
+            src_chain: ticket.dst_chain,
+            dst_chain: ticket.src_chain,
+            action,
+            token: ticket.token,
+            amount: ticket.amount,
+            sender: Some(ticket.receiver),
+            receiver,
+            memo: None,
+        };
+
+        if held_ticket.is_some() {
+            // the token positions were not updated by the held ticket
+            self.held_tickets.remove(ticket_id);
+            record_event(&Event::ReleasedTicket {
+                ticket_id: ticket_id.to_string(),
+            });
+        } else {
+            let ticket_amount = Self::ticket_amount(&refund_ticket)?;
+            self.update_ticket_position(&refund_ticket, ticket_amount)?;
+        }
+        self.resolve_delivery(ticket_id);
+        self.push_ticket(refund_ticket)?;
+        self.update_ticket_status(
+            ticket_id,
+            TicketStatus::Refunded {
+                refund_id: refund_id.clone(),
+            },
+        );
+        log!(
+            INFO,
+            "The ticket (`{}`) is refunded by (`{}`)",
+            ticket_id,
+            refund_id
+        );
+        Ok(refund_id)
+    }
+
+    pub fn update_supply_audit(&mut self, config: SupplyAuditConfig) -> Result<(), Error> {
+        self.supply_audit = Some(config.clone());
+        record_event(&Event::UpdatedSupplyAudit(config));
+
+        Ok(())
+    }
+
+    /// the mismatched supply is recorded as an event
+    pub fn update_supply_report(&mut self, mut report: SupplyReport, config: &SupplyAuditConfig) {
+        let token_key = TokenKey::from(report.chain_id.to_string(), report.token_id.to_string());
+        let mismatches = self
+            .supply_reports
+            .get(&token_key)
+            .map_or(0, |last_report| last_report.mismatches);
+        report.mismatches = if report.mismatched {
+            mismatches + 1
+        } else if report.error.is_some() {
+            // the failed read neither confirms nor clears the mismatch
+            mismatches
+        } else {
+            0
+        };
+        self.supply_reports.insert(token_key, report.clone());
+        if !report.mismatched {
+            return;
+        }
+        log!(
+            WARNING,
+            "The supply of {} on {} is {:?}, but the expected is {} ({} consecutive mismatches)",
+            report.token_id,
+            report.chain_id,
+            report.actual,
+            report.expected,
+            report.mismatches
+        );
+        record_event(&Event::MismatchedSupply(report.clone()));
+        if config.auto_deactivate && report.mismatches >= DEACTIVATE_AFTER_MISMATCHES {
+            self.deactivate_chain(&report.chain_id, "mismatched the token supply");
+        }
+    }
+
+    pub fn update_delivery_policy(&mut self, policy: DeliveryPolicy) -> Result<(), Error> {
+        self.delivery_policies
+            .insert(policy.chain_id.to_string(), policy.clone());
+        record_event(&Event::UpdatedDeliveryPolicy(policy));
+
+        Ok(())
+    }
+
+    /// start the delivery deadline if the dst chain has the delivery policy
+    fn track_delivery(&mut self, ticket: &Ticket, auto_resubmitted: u32) {
+        let Some(policy) = self.delivery_policies.get(&ticket.dst_chain) else {
+            return;
+        };
+        let delivery = Delivery {
+            dst_chain: ticket.dst_chain.to_string(),
+            deadline: ic_cdk::api::time().saturating_add(policy.deadline),
+            auto_resubmitted,
+        };
+        self.deliveries
+            .insert(ticket.ticket_id.to_string(), delivery.clone());
+        record_event(&Event::TrackedDelivery {
+            ticket_id: ticket.ticket_id.to_string(),
+            delivery,
+        });
+    }
+
+    fn resolve_delivery(&mut self, ticket_id: &TicketId) {
+        if self.deliveries.remove(ticket_id).is_some() {
+            record_event(&Event::ResolvedDelivery {
+                ticket_id: ticket_id.to_string(),
+            });
+        }
+    }
+
+    pub fn get_overdue_tickets(
+        &self,
+        chain_id: Option<ChainId>,
+        now: u64,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<OverdueTicket> {
+        self.deliveries
+            .iter()
+            .filter(|(_, delivery)| {
+                delivery.is_overdue(now)
+                    && chain_id
+                        .as_ref()
+                        .map_or(true, |chain_id| delivery.dst_chain.eq(chain_id))
+            })
+            .skip(offset)
+            .take(limit)
+            .map(|(ticket_id, delivery)| OverdueTicket {
+                status: self.ticket_status.get(&ticket_id),
+                ticket_id,
+                dst_chain: delivery.dst_chain,
+                deadline: delivery.deadline,
+                auto_resubmitted: delivery.auto_resubmitted,
+            })
+            .collect()
+    }
+
+    /// the overdue tickets whose dst chain allows resubmitting them automatically
+    pub fn auto_resubmittable_tickets(&self, now: u64, limit: usize) -> Vec<TicketId> {
+        self.deliveries
+            .iter()
+            .filter(|(_, delivery)| {
+                delivery.is_overdue(now)
+                    && self
+                        .delivery_policies
+                        .get(&delivery.dst_chain)
+                        .is_some_and(|policy| {
+                            policy.auto_resubmit
+                                && delivery.auto_resubmitted < policy.max_auto_resubmit
+                        })
+                    && self
+                        .chains
+                        .get(&delivery.dst_chain)
+                        .is_some_and(|chain| matches!(chain.chain_state, ChainState::Active))
+            })
+            .map(|(ticket_id, _)| ticket_id)
+            .take(limit)
+            .collect()
+    }
+
+    pub fn get_ticket_status(&self, ticket_id: &TicketId) -> Result<TicketStatus, Error> {
+        self.ticket_status
+            .get(ticket_id)
+            .ok_or(Error::NotFoundTicketId(ticket_id.to_string()))
+    }
+
+    /// the payload of the status is ignored
+    pub fn get_tickets_by_status(
+        &self,
+        status: &TicketStatus,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<(TicketId, TicketStatus)> {
+        self.ticket_status
+            .iter()
+            .filter(|(_, s)| s.same_kind(status))
+            .skip(offset)
+            .take(limit)
+            .collect()
+    }
+
+    /// the offset is the number of the queued tickets to skip
     pub fn pull_tickets(
         &self,
         chain_id: &ChainId,
         offset: usize,
         limit: usize,
     ) -> Result<Vec<(Seq, Ticket)>, Error> {
-        let targets = self
-            .ticket_map
-            .iter()
-            .filter(|(seq_key, _)| seq_key.chain_id.eq(chain_id))
+        let tickets = self
+            .queued_tickets(chain_id, 0)
             .skip(offset)
             .take(limit)
-            .map(|(seq_key, ticket_id)| (seq_key.seq, ticket_id))
+            // the refunded ticket is blocked, its tokens went back to the sender, it`s
+            // dropped after the skip so the offset still counts it
+            .filter(|(_, ticket)| !self.is_refunded(&ticket.ticket_id))
+            .map(|(seq_key, ticket)| (seq_key.seq, ticket))
+            .collect::<Vec<_>>();
+        Ok(tickets)
+    }
+
+    /// pull the tickets from the seq on
+    pub fn pull_tickets_from_seq(
+        &self,
+        chain_id: &ChainId,
+        from_seq: Seq,
+        limit: usize,
+    ) -> Result<Vec<(Seq, Ticket)>, Error> {
+        let tickets = self
+            .queued_tickets(chain_id, from_seq)
+            .filter(|(_, ticket)| !self.is_refunded(&ticket.ticket_id))
+            .take(limit)
+            .map(|(seq_key, ticket)| (seq_key.seq, ticket))
             .collect::<Vec<_>>();
-        let mut tickets = Vec::new();
-        for (seq, ticket_id) in targets {
-            if let Some(dire) = self.cross_ledger.get(&ticket_id) {
-                tickets.push((seq, dire))
+        Ok(tickets)
+    }
+
+    fn is_refunded(&self, ticket_id: &TicketId) -> bool {
+        matches!(
+            self.ticket_status.get(ticket_id),
+            Some(TicketStatus::Refunded { .. })
+        )
+    }
+
+    /// the queued tickets of the chain from the seq on, the entries not migrated
+    /// yet precede the entries in the stable queue
+    fn queued_tickets<'a>(
+        &'a self,
+        chain_id: &'a ChainId,
+        from_seq: Seq,
+    ) -> impl Iterator<Item = (SeqKey, Ticket)> + 'a {
+        let start = SeqKey::from(chain_id.to_string(), from_seq);
+        self.queue_migration
+            .iter()
+            .flat_map(move |m| {
+                m.ticket_map
+                    .range(SeqKey::from(chain_id.to_string(), from_seq)..)
+            })
+            .take_while(move |(seq_key, _)| seq_key.chain_id.eq(chain_id))
+            .filter_map(|(seq_key, ticket_id)| {
+                self.cross_ledger
+                    .get(ticket_id)
+                    .map(|ticket| (seq_key.to_owned(), ticket))
+            })
+            .chain(
+                self.ticket_queue
+                    .range(start..)
+                    .take_while(move |(seq_key, _)| seq_key.chain_id.eq(chain_id)),
+            )
+    }
+
+    /// move at most `limit` entries of the heap queues into the stable queues,
+    /// returns true once the migration is done
+    pub fn migrate_queues(&mut self, limit: usize) -> bool {
+        let Some(queue_migration) = self.queue_migration.as_mut() else {
+            return true;
+        };
+        for _ in 0..limit {
+            if let Some((seq_key, dire)) = queue_migration.dire_map.pop_last() {
+                self.dire_queue.insert(seq_key, dire);
+            } else if let Some((seq_key, ticket_id)) = queue_migration.ticket_map.pop_last() {
+                if let Some(ticket) = self.cross_ledger.get(&ticket_id) {
+                    self.ticket_queue.insert(seq_key, ticket);
+                }
+            } else {
+                break;
             }
         }
-        Ok(tickets)
+        if !queue_migration.is_empty() {
+            return false;
+        }
+        self.queue_migration = None;
+        log!(INFO, "moved the heap queues into the stable queues");
+        true
     }
 
     pub fn get_tx_hash(&self, ticket_id: &TicketId) -> Result<TxHash, Error> {
@@ -870,18 +1818,18 @@ impl HubState {
     }
 
     pub fn delete_directives(&mut self, chain_id: &ChainId, topics: &[Topic]) -> Result<(), Error> {
-        for (seq_key, dir) in self
-            .dire_map
-            .iter()
-            .filter(|(seq_key, _)| seq_key.chain_id.eq(chain_id))
-            .map(|(seq_key, dire)| (seq_key.to_owned(), dire.to_owned()))
-            .collect::<Vec<_>>()
-        {
-            let topics = BTreeSet::from_iter(topics.iter());
-            if topics.contains(&dir.to_topic()) {
-                self.dire_map.remove(&seq_key);
-                record_event(&Event::DeletedDirective(seq_key.clone()));
+        let topics = BTreeSet::from_iter(topics.iter());
+        let seq_keys = self
+            .queued_directives(chain_id, 0)
+            .filter(|(_, dire)| topics.contains(&dire.to_topic()))
+            .map(|(seq_key, _)| seq_key)
+            .collect::<Vec<_>>();
+        for seq_key in seq_keys {
+            if let Some(queue_migration) = self.queue_migration.as_mut() {
+                queue_migration.dire_map.remove(&seq_key);
             }
+            self.dire_queue.remove(&seq_key);
+            record_event(&Event::DeletedDirective(seq_key));
         }
         Ok(())
     }
@@ -906,3 +1854,156 @@ impl HubState {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::replay;
+
+    const COOLDOWN: u64 = 100;
+
+    fn ticket() -> Ticket {
+        Ticket {
+            ticket_id: "ticket".to_string(),
+            ticket_type: TicketType::Normal,
+            ticket_time: 1_000,
+            src_chain: "Bitcoin".to_string(),
+            dst_chain: "eICP".to_string(),
+            action: TxAction::Transfer,
+            token: "Bitcoin-RUNES-WTF".to_string(),
+            amount: "1000".to_string(),
+            sender: Some("sender".to_string()),
+            receiver: Principal::anonymous().to_string(),
+            memo: None,
+        }
+    }
+
+    fn hub_state(status: TicketStatus) -> HubState {
+        replay(
+            vec![
+                Event::Init(InitArgs {
+                    admin: Principal::anonymous(),
+                }),
+                Event::ReceivedTicket {
+                    seq_key: SeqKey::from("eICP".to_string(), 0),
+                    ticket: ticket(),
+                },
+                Event::UpdatedTicketStatus {
+                    ticket_id: "ticket".to_string(),
+                    status,
+                },
+                Event::UpdatedResubmitCooldown {
+                    chain_id: "eICP".to_string(),
+                    cooldown: COOLDOWN,
+                },
+            ]
+            .into_iter(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resubmit_ticket() {
+        let mut state = hub_state(TicketStatus::Accepted);
+        // the first resubmission is throttled from the ticket time
+        assert!(matches!(
+            state.resubmit_ticket(ticket(), 1_050),
+            Err(Error::ResubmitTicketSentTooOften)
+        ));
+        assert!(state.resubmit_ticket(ticket(), 1_100).is_ok());
+        assert!(matches!(
+            state.resubmit_ticket(ticket(), 1_150),
+            Err(Error::ResubmitTicketSentTooOften)
+        ));
+        assert!(state.resubmit_ticket(ticket(), 1_200).is_ok());
+
+        // the new ids are built from the original ticket id
+        let resubmission = state.get_resubmission(&"ticket".to_string());
+        assert_eq!(
+            resubmission.ticket_ids().collect::<Vec<_>>(),
+            vec!["ticket", "ticket_1100", "ticket_1200"]
+        );
+        assert_eq!(
+            state.get_ticket_status(&"ticket_1100".to_string()).ok(),
+            Some(TicketStatus::Resubmitted {
+                new_id: "ticket_1200".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_resubmit_refunded_ticket() {
+        let mut state = hub_state(TicketStatus::Refunded {
+            refund_id: "ticket_refund".to_string(),
+        });
+        assert!(matches!(
+            state.resubmit_ticket(ticket(), 10_000),
+            Err(Error::CustomError(_))
+        ));
+        assert_eq!(state.get_resubmission(&"ticket".to_string()).count(), 0);
+
+        let mut state = hub_state(TicketStatus::Executed {
+            tx_hash: "hash".to_string(),
+        });
+        assert!(matches!(
+            state.resubmit_ticket(ticket(), 10_000),
+            Err(Error::CustomError(_))
+        ));
+    }
+
+    #[test]
+    fn test_pull_tickets_during_queue_migration() {
+        let events = vec![Event::Init(InitArgs {
+            admin: Principal::anonymous(),
+        })]
+        .into_iter()
+        .chain((0..5).map(|seq| Event::ReceivedTicket {
+            seq_key: SeqKey::from("eICP".to_string(), seq),
+            ticket: Ticket {
+                ticket_id: format!("ticket_{}", seq),
+                ..ticket()
+            },
+        }))
+        .chain(vec![Event::UpdatedTicketStatus {
+            ticket_id: "ticket_1".to_string(),
+            status: TicketStatus::Refunded {
+                refund_id: "ticket_refund".to_string(),
+            },
+        }]);
+        let mut state = replay(events).unwrap();
+        // the first three tickets are still in the heap queue
+        let mut ticket_map = BTreeMap::new();
+        for seq in 0..3 {
+            let seq_key = SeqKey::from("eICP".to_string(), seq);
+            state.ticket_queue.remove(&seq_key);
+            ticket_map.insert(seq_key, format!("ticket_{}", seq));
+        }
+        state.queue_migration = Some(QueueMigration {
+            dire_map: BTreeMap::new(),
+            ticket_map,
+        });
+
+        let chain_id = "eICP".to_string();
+        let seqs = |tickets: Result<Vec<(Seq, Ticket)>, Error>| {
+            tickets
+                .unwrap()
+                .into_iter()
+                .map(|(seq, _)| seq)
+                .collect::<Vec<_>>()
+        };
+        // the offset counts the refunded ticket
+        assert_eq!(seqs(state.pull_tickets(&chain_id, 1, 3)), vec![2, 3]);
+        assert_eq!(seqs(state.pull_tickets(&chain_id, 3, 10)), vec![3, 4]);
+        assert_eq!(
+            seqs(state.pull_tickets_from_seq(&chain_id, 0, 2)),
+            vec![0, 2]
+        );
+
+        assert!(!state.migrate_queues(2));
+        assert_eq!(seqs(state.pull_tickets(&chain_id, 0, 10)), vec![0, 2, 3, 4]);
+        assert!(state.migrate_queues(2));
+        assert!(state.queue_migration.is_none());
+        assert_eq!(state.ticket_queue.len(), 5);
+        assert_eq!(seqs(state.pull_tickets(&chain_id, 0, 10)), vec![0, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,286 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Principal};
+use ic_canister_log::log;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use omnity_types::hub_types::TokenKey;
+use omnity_types::ic_log::{ERROR, INFO};
+use omnity_types::{ChainId, ChainState, Timestamp, TokenId};
+use serde::{Deserialize, Serialize};
+
+use crate::state::{with_state, with_state_mut};
+
+pub const RECONCILE_SUPPLY_INTERVAL: u64 = 60 * 60;
+// the consecutive mismatching reports before the chain is deactivated, a single report
+// may race with the tickets executed between the position and the supply reads
+pub const DEACTIVATE_AFTER_MISMATCHES: u32 = 3;
+
+thread_local! {
+    static RECONCILING: Cell<bool> = Cell::new(false);
+}
+
+/// releases the reconciliation even if the task traps after an await
+struct ReconcileGuard;
+
+impl ReconcileGuard {
+    fn new() -> Option<Self> {
+        if RECONCILING.with(|r| r.replace(true)) {
+            return None;
+        }
+        Some(ReconcileGuard)
+    }
+}
+
+impl Drop for ReconcileGuard {
+    fn drop(&mut self) {
+        RECONCILING.with(|r| r.set(false));
+    }
+}
+
+/// The token supply reported by the route or customs compared with the token position
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SupplyReport {
+    pub chain_id: ChainId,
+    pub token_id: TokenId,
+    // the token position on the chain, or the total positions on the other chains
+    // if the chain is the issue chain of the token, adjusted by the in-flight tickets
+    pub expected: u128,
+    // the amount of the in-flight tickets to or from the chain
+    #[serde(default)]
+    pub in_flight: u128,
+    pub actual: Option<u128>,
+    pub error: Option<String>,
+    pub mismatched: bool,
+    // the consecutive mismatching reports of the token on the chain
+    #[serde(default)]
+    pub mismatches: u32,
+    pub checked_at: Timestamp,
+}
+
+impl SupplyReport {
+    pub fn is_mismatched(expected: u128, actual: u128, tolerance_bps: u32) -> bool {
+        let drift = expected.abs_diff(actual);
+        drift.saturating_mul(10_000) > expected.saturating_mul(tolerance_bps as u128)
+    }
+}
+
+impl Storable for SupplyReport {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = vec![];
+        let _ = ciborium::ser::into_writer(self, &mut bytes);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let report =
+            ciborium::de::from_reader(bytes.as_ref()).expect("failed to decode SupplyReport");
+        report
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// the expected supply and the in-flight amount of each token on each active chain
+fn expected_supplies() -> Vec<(Principal, TokenKey, u128, u128)> {
+    with_state(|hub_state| {
+        let mut expected = BTreeMap::new();
+        for (token_key, amount) in hub_state.token_position.iter() {
+            let Some(token) = hub_state.tokens.get(&token_key.token_id) else {
+                continue;
+            };
+            *expected
+                .entry(TokenKey::from(
+                    token.issue_chain,
+                    token_key.token_id.to_string(),
+                ))
+                .or_insert(0u128) += amount;
+            *expected.entry(token_key).or_insert(0u128) += amount;
+        }
+
+        // the positions are updated once the ticket is accepted, but the supply on the
+        // dst chain changes only after the ticket is executed
+        let mut in_flight = BTreeMap::new();
+        for ticket_id in hub_state.in_flight_tickets.iter() {
+            let Some(ticket) = hub_state.cross_ledger.get(ticket_id) else {
+                continue;
+            };
+            let Some(token) = hub_state.tokens.get(&ticket.token) else {
+                continue;
+            };
+            let Ok(amount) = ticket.amount.parse::<u128>() else {
+                continue;
+            };
+            let token_key = TokenKey::from(ticket.dst_chain.to_string(), ticket.token.to_string());
+            let supply = expected.entry(token_key.clone()).or_insert(0u128);
+            if ticket.dst_chain == token.issue_chain {
+                // the redeemed tokens are still locked by the customs
+                *supply = supply.saturating_add(amount);
+            } else {
+                // the transferred tokens are not minted yet
+                *supply = supply.saturating_sub(amount);
+            }
+            *in_flight.entry(token_key).or_insert(0u128) += amount;
+        }
+
+        expected
+            .into_iter()
+            .filter_map(|(token_key, amount)| {
+                let chain = hub_state.chains.get(&token_key.chain_id)?;
+                if !matches!(chain.chain_state, ChainState::Active) {
+                    return None;
+                }
+                let canister_id = Principal::from_text(&chain.canister_id).ok()?;
+                let in_flight = in_flight.get(&token_key).copied().unwrap_or_default();
+                Some((canister_id, token_key, amount, in_flight))
+            })
+            .collect()
+    })
+}
+
+async fn get_token_supply(canister_id: Principal, token_id: &TokenId) -> Result<u128, String> {
+    let (supply,): (Result<u128, String>,) =
+        ic_cdk::api::call::call(canister_id, "get_token_supply", (token_id,))
+            .await
+            .map_err(|(code, msg)| format!("{:?}: {}", code, msg))?;
+    supply
+}
+
+/// compare the token supply on each chain with the token position
+pub async fn reconcile_supply_task() {
+    let Some(_guard) = ReconcileGuard::new() else {
+        return;
+    };
+    let Some(config) = with_state(|hub_state| hub_state.supply_audit.clone()) else {
+        return;
+    };
+
+    for (canister_id, token_key, expected, in_flight) in expected_supplies() {
+        let supply = get_token_supply(canister_id, &token_key.token_id).await;
+        let mut report = SupplyReport {
+            chain_id: token_key.chain_id,
+            token_id: token_key.token_id,
+            expected,
+            in_flight,
+            actual: None,
+            error: None,
+            mismatched: false,
+            mismatches: 0,
+            checked_at: ic_cdk::api::time(),
+        };
+        match supply {
+            Ok(actual) => {
+                report.actual = Some(actual);
+                report.mismatched =
+                    SupplyReport::is_mismatched(expected, actual, config.tolerance_bps);
+            }
+            Err(e) => {
+                log!(
+                    ERROR,
+                    "failed to get the supply of {} on {}: {}",
+                    report.token_id,
+                    report.chain_id,
+                    e
+                );
+                report.error = Some(e);
+            }
+        }
+        with_state_mut(|hub_state| hub_state.update_supply_report(report, &config));
+    }
+    log!(INFO, "reconciled the token supply");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{replay, Event};
+    use crate::lifecycle::init::InitArgs;
+    use crate::state::HubState;
+    use omnity_types::hub_types::{ChainMeta, SupplyAuditConfig};
+    use omnity_types::ChainType;
+
+    #[test]
+    fn test_supply_mismatch() {
+        assert!(!SupplyReport::is_mismatched(0, 0, 0));
+        assert!(SupplyReport::is_mismatched(0, 1, 100));
+        assert!(!SupplyReport::is_mismatched(10_000, 10_100, 100));
+        assert!(SupplyReport::is_mismatched(10_000, 10_101, 100));
+        assert!(SupplyReport::is_mismatched(10_000, 9_899, 100));
+    }
+
+    #[test]
+    fn test_reconcile_guard() {
+        let guard = ReconcileGuard::new();
+        assert!(guard.is_some());
+        assert!(ReconcileGuard::new().is_none());
+        drop(guard);
+        assert!(ReconcileGuard::new().is_some());
+    }
+
+    fn report(actual: Option<u128>) -> SupplyReport {
+        SupplyReport {
+            chain_id: "eICP".to_string(),
+            token_id: "Bitcoin-RUNES-WTF".to_string(),
+            expected: 10_000,
+            in_flight: 0,
+            actual,
+            error: actual.map_or(Some("failed".to_string()), |_| None),
+            mismatched: actual
+                .is_some_and(|actual| SupplyReport::is_mismatched(10_000, actual, 100)),
+            mismatches: 0,
+            checked_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_deactivate_after_consecutive_mismatches() {
+        let mut state = replay(
+            vec![
+                Event::Init(InitArgs {
+                    admin: Principal::anonymous(),
+                }),
+                Event::UpdatedChain(ChainMeta {
+                    chain_id: "eICP".to_string(),
+                    chain_type: ChainType::ExecutionChain,
+                    chain_state: ChainState::Active,
+                    canister_id: "bkyz2-fmaaa-aaaaa-qaaaq-cai".to_string(),
+                    contract_address: None,
+                    counterparties: None,
+                    fee_token: None,
+                }),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        let config = SupplyAuditConfig {
+            tolerance_bps: 100,
+            auto_deactivate: true,
+        };
+        let token_key = TokenKey::from("eICP".to_string(), "Bitcoin-RUNES-WTF".to_string());
+        let chain_state =
+            |state: &HubState| state.chains.get(&"eICP".to_string()).unwrap().chain_state;
+
+        // a matching report clears the mismatches, a failed read keeps them
+        for actual in [
+            Some(9_000),
+            Some(9_000),
+            Some(10_000),
+            Some(9_000),
+            None,
+            Some(9_000),
+        ] {
+            state.update_supply_report(report(actual), &config);
+            assert!(matches!(chain_state(&state), ChainState::Active));
+        }
+        assert_eq!(state.supply_reports.get(&token_key).unwrap().mismatches, 2);
+
+        state.update_supply_report(report(Some(9_000)), &config);
+        assert_eq!(
+            state.supply_reports.get(&token_key).unwrap().mismatches,
+            DEACTIVATE_AFTER_MISMATCHES
+        );
+        assert!(matches!(chain_state(&state), ChainState::Deactive));
+    }
+}
@@ -0,0 +1,230 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use candid::CandidType;
+use ic_canister_log::log;
+use ic_stable_structures::{Memory, StableBTreeMap, Storable};
+use omnity_types::hub_types::{ChainMeta, ChainTokenFactor, TokenKey, TokenMeta};
+use omnity_types::ic_log::{INFO, WARNING};
+use omnity_types::{ChainId, Error, Factor, Ticket, TicketId, Timestamp, TokenId};
+use serde::{Deserialize, Serialize};
+
+use crate::event::{count_events, events_range, Event};
+use crate::state::{with_state, HubState};
+
+// the events replayed in one timer execution
+const VERIFY_EVENTS_PER_CHUNK: u64 = 5_000;
+// the max mismatches kept in the report
+const MAX_MISMATCHES: usize = 100;
+
+thread_local! {
+    static VERIFICATION: RefCell<Option<Verification>> = RefCell::default();
+}
+
+/// The chains, tokens, factors, positions and ledger rebuilt from the event log
+#[derive(Default)]
+struct ShadowState {
+    chains: BTreeMap<ChainId, ChainMeta>,
+    tokens: BTreeMap<TokenId, TokenMeta>,
+    target_chain_factors: BTreeMap<ChainId, u128>,
+    fee_token_factors: BTreeMap<TokenKey, ChainTokenFactor>,
+    token_position: BTreeMap<TokenKey, u128>,
+    cross_ledger: BTreeMap<TicketId, Ticket>,
+}
+
+impl ShadowState {
+    /// the same as `replay`, but only for the verified fields
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::UpdatedChain(chain) | Event::UpdatedChainCounterparties(chain) => {
+                self.chains.insert(chain.chain_id.to_string(), chain);
+            }
+            Event::ToggledChainState { chain, state } => {
+                self.chains.insert(state.chain_id, chain);
+            }
+            Event::AddedToken(token) => {
+                self.tokens.insert(token.token_id.to_string(), token);
+            }
+            Event::UpdatedFee(Factor::UpdateTargetChainFactor(cf)) => {
+                self.target_chain_factors
+                    .insert(cf.target_chain_id, cf.target_chain_factor);
+            }
+            Event::UpdatedFee(Factor::UpdateFeeTokenFactor(tf)) => {
+                for chain_id in self.target_chain_factors.keys() {
+                    self.fee_token_factors.insert(
+                        TokenKey::from(chain_id.to_string(), tf.fee_token.to_string()),
+                        ChainTokenFactor {
+                            target_chain_id: chain_id.to_string(),
+                            fee_token: tf.fee_token.to_string(),
+                            fee_token_factor: tf.fee_token_factor,
+                        },
+                    );
+                }
+            }
+            Event::AddedTokenPosition { position, amount }
+            | Event::UpdatedTokenPosition { position, amount } => {
+                self.token_position.insert(position, amount);
+            }
+            Event::ReceivedTicket { ticket, .. } => {
+                self.cross_ledger.insert(ticket.ticket_id.to_string(), ticket);
+            }
+            _ => {}
+        }
+    }
+
+    fn diff(&self, hub_state: &HubState, report: &mut VerificationReport) {
+        diff_map("chains", &self.chains, &hub_state.chains, report);
+        diff_map("tokens", &self.tokens, &hub_state.tokens, report);
+        diff_map(
+            "target_chain_factors",
+            &self.target_chain_factors,
+            &hub_state.target_chain_factors,
+            report,
+        );
+        diff_map(
+            "fee_token_factors",
+            &self.fee_token_factors,
+            &hub_state.fee_token_factors,
+            report,
+        );
+        diff_map(
+            "token_position",
+            &self.token_position,
+            &hub_state.token_position,
+            report,
+        );
+        diff_map(
+            "cross_ledger",
+            &self.cross_ledger,
+            &hub_state.cross_ledger,
+            report,
+        );
+    }
+}
+
+fn diff_map<K, V, M>(
+    name: &str,
+    replayed: &BTreeMap<K, V>,
+    live: &StableBTreeMap<K, V, M>,
+    report: &mut VerificationReport,
+) where
+    K: Storable + Ord + Clone + Debug,
+    V: Storable + PartialEq + Debug,
+    M: Memory,
+{
+    for (key, value) in replayed.iter() {
+        match live.get(key) {
+            Some(live_value) if live_value == *value => {}
+            live_value => report.add_mismatch(format!(
+                "{}: {:?} is {:?} in the event log, but {:?} in the state",
+                name, key, value, live_value
+            )),
+        }
+    }
+    for (key, value) in live.iter() {
+        if !replayed.contains_key(&key) {
+            report.add_mismatch(format!(
+                "{}: {:?} is missing in the event log, but {:?} in the state",
+                name, key, value
+            ));
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerificationReport {
+    pub started_at: Timestamp,
+    pub finished_at: Option<Timestamp>,
+    pub replayed_events: u64,
+    pub mismatch_count: u64,
+    // the first `MAX_MISMATCHES` mismatches
+    pub mismatches: Vec<String>,
+}
+
+impl VerificationReport {
+    fn add_mismatch(&mut self, mismatch: String) {
+        self.mismatch_count += 1;
+        if self.mismatches.len() < MAX_MISMATCHES {
+            self.mismatches.push(mismatch);
+        }
+    }
+}
+
+struct Verification {
+    shadow: ShadowState,
+    report: VerificationReport,
+}
+
+/// Replay the event log in chunks and compare the result with the hub state
+pub fn verify_event_log() -> Result<(), Error> {
+    let running = VERIFICATION.with(|v| {
+        v.borrow()
+            .as_ref()
+            .is_some_and(|v| v.report.finished_at.is_none())
+    });
+    if running {
+        return Err(Error::CustomError(
+            "The event log verification is running".to_string(),
+        ));
+    }
+    VERIFICATION.with(|v| {
+        *v.borrow_mut() = Some(Verification {
+            shadow: ShadowState::default(),
+            report: VerificationReport {
+                started_at: ic_cdk::api::time(),
+                ..Default::default()
+            },
+        })
+    });
+    log!(INFO, "start to verify the event log");
+    ic_cdk_timers::set_timer(Duration::ZERO, verify_chunk);
+    Ok(())
+}
+
+fn verify_chunk() {
+    let finished = VERIFICATION.with(|v| {
+        let mut verification = v.borrow_mut();
+        let Some(verification) = verification.as_mut() else {
+            return true;
+        };
+        let start = verification.report.replayed_events;
+        let events = events_range(start, VERIFY_EVENTS_PER_CHUNK);
+        verification.report.replayed_events += events.len() as u64;
+        events
+            .into_iter()
+            .for_each(|event| verification.shadow.apply(event));
+
+        // the events recorded during the verification are replayed too,
+        // the state is compared once the replay catches up with the log
+        if verification.report.replayed_events < count_events() {
+            return false;
+        }
+        with_state(|hub_state| {
+            verification
+                .shadow
+                .diff(hub_state, &mut verification.report)
+        });
+        verification.report.finished_at = Some(ic_cdk::api::time());
+        if verification.report.mismatch_count > 0 {
+            log!(
+                WARNING,
+                "the event log verification found {} mismatches",
+                verification.report.mismatch_count
+            );
+        } else {
+            log!(INFO, "the event log is consistent with the state");
+        }
+        // release the replayed state, only the report is kept
+        verification.shadow = ShadowState::default();
+        true
+    });
+    if !finished {
+        ic_cdk_timers::set_timer(Duration::ZERO, verify_chunk);
+    }
+}
+
+pub fn get_verification_report() -> Option<VerificationReport> {
+    VERIFICATION.with(|v| v.borrow().as_ref().map(|v| v.report.clone()))
+}
@@ -12,6 +12,14 @@ pub async fn finalize_ticket(hub_principal: Principal, ticket_id: String) -> Res
     call(hub_principal, "finalize_ticket".into(), (ticket_id,)).await
 }
 
+pub async fn ack_tickets(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    call(hub_principal, "ack_tickets".into(), (None::<ChainId>, seq)).await
+}
+
+pub async fn ack_directives(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    call(hub_principal, "ack_directives".into(), (None::<ChainId>, seq)).await
+}
+
 pub async fn query_tickets(
     hub_principal: Principal,
     offset: u64,
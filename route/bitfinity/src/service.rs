@@ -76,6 +76,20 @@ pub fn bridge_ticket_to_evm_task() {
     });
 }
 
+/// notified by the hub once the route subscribed the push mode,
+/// the tickets are pulled immediately instead of waiting for the next timer
+#[update(guard = "is_hub")]
+fn on_new_tickets(from_seq: u64) {
+    log!(INFO, "[on new tickets] notified from seq: {}", from_seq);
+    bridge_ticket_to_evm_task();
+}
+
+#[update(guard = "is_hub")]
+fn on_new_directives(from_seq: u64) {
+    log!(INFO, "[on new directives] notified from seq: {}", from_seq);
+    bridge_ticket_to_evm_task();
+}
+
 
 
 #[query]
@@ -201,6 +215,13 @@ fn update_fee_token(fee_token: String) {
     mutate_state(|s| s.fee_token_id = fee_token);
 }
 
+fn is_hub() -> Result<(), String> {
+    match read_state(|s| s.hub_principal == ic_cdk::caller()) {
+        true => Ok(()),
+        false => Err("caller is not hub".to_string()),
+    }
+}
+
 fn is_admin() -> Result<(), String> {
     let c = ic_cdk::caller();
     match ic_cdk::api::is_controller(&c) || read_state(|s| s.admins.contains(&c)) {
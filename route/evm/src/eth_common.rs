@@ -391,6 +391,45 @@ pub async fn get_balance(addr: String, rpc: RpcApi) -> Result<U256, Error> {
     Ok(U256::from(r))
 }
 
+// the selector of `totalSupply()`
+const TOTAL_SUPPLY_SELECTOR: &str = "0x18160ddd";
+
+pub async fn get_token_supply(token_addr: String, rpc: RpcApi) -> Result<U256, Error> {
+    let params = (
+        RpcService::Custom(rpc.clone()),
+        serde_json::json!({
+            "method": "eth_call",
+            "params": [{"to": token_addr, "data": TOTAL_SUPPLY_SELECTOR}, "latest"],
+            "id": 1,
+            "jsonrpc": "2.0",
+        })
+        .to_string(),
+        1000u64,
+    );
+    // Get cycles cost
+    let (cycles_result,): (std::result::Result<u128, RpcError>,) =
+        ic_cdk::api::call::call(state::rpc_addr(), "requestCost", params.clone())
+            .await
+            .map_err(|err| Error::IcCallError(err.0, err.1))?;
+    let cycles = cycles_result.map_err(|e| {
+        log!(WARNING, "[evm route] evm request error: {:?}", e);
+        Error::Custom(format!("error in `request_cost`: {:?}", e))
+    })?;
+    // Call with expected number of cycles
+    let (result,): (std::result::Result<String, RpcError>,) =
+        ic_cdk::api::call::call_with_payment128(state::rpc_addr(), "request", params, cycles)
+            .await
+            .map_err(|err| Error::IcCallError(err.0, err.1))?;
+    let r = result.map_err(|e| {
+        log!(WARNING, "[evm route]query token total supply error: {:?}", &e);
+        Error::Custom(format!("[evm route]query token total supply error: {:?}", &e))
+    })?;
+    let r: JsonRpcResponse<String> =
+        serde_json::from_str(r.as_str()).map_err(|e| Error::Fatal(e.to_string()))?;
+    let r = r.result.strip_prefix("0x").unwrap_or(r.result.as_str());
+    U256::from_str_radix(r, 16).map_err(|e| Error::Fatal(e.to_string()))
+}
+
 pub async fn get_transaction_receipt(
     hash: &String,
     rpcs: Vec<RpcApi>,
@@ -58,6 +58,14 @@ pub async fn pending_ticket(hub_principal: Principal, ticket: Ticket) -> Result<
     call(hub_principal, "pending_ticket".into(), (ticket,)).await
 }
 
+pub async fn ack_tickets(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    call(hub_principal, "ack_tickets".into(), (None::<ChainId>, seq)).await
+}
+
+pub async fn ack_directives(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    call(hub_principal, "ack_directives".into(), (None::<ChainId>, seq)).await
+}
+
 async fn call<T: ArgumentEncoder, R>(
     hub_principal: Principal,
     method: String,
@@ -17,6 +17,12 @@ pub async fn process_tickets() {
     match hub::query_tickets(hub_principal, offset, BATCH_QUERY_LIMIT).await {
         Ok(tickets) => {
             store_tickets(tickets, offset);
+            let next_seq = read_state(|s| s.next_ticket_seq);
+            if next_seq > offset {
+                if let Err(err) = hub::ack_tickets(hub_principal, next_seq - 1).await {
+                    log!(WARNING, "[process tickets] failed to ack tickets, err: {}", err);
+                }
+            }
         }
         Err(err) => {
             log!(WARNING, "[process tickets] failed to query tickets, err: {}", err);
@@ -87,6 +93,14 @@ pub async fn process_directives() {
             mutate_state(|s| {
                 s.next_directive_seq = next_seq;
             });
+            if next_seq > offset {
+                if let Err(err) = hub::ack_directives(hub_principal, next_seq - 1).await {
+                    log!(WARNING,
+                        "[process directives] failed to ack directives, err: {:?}",
+                        err
+                    );
+                }
+            }
         }
         Err(err) => {
             log!(WARNING,
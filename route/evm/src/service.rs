@@ -12,7 +12,7 @@ use ic_cdk::api::management_canister::http_request::TransformArgs;
 use ic_cdk_timers::set_timer_interval;
 use serde_derive::Deserialize;
 
-use crate::{get_time_secs, hub};
+use crate::{eth_common, get_time_secs, hub};
 use crate::const_args::{BATCH_QUERY_LIMIT, MONITOR_PRINCIPAL, PERIODIC_TASK_INTERVAL, SEND_EVM_TASK_NAME};
 use crate::eth_common::{call_rpc_with_retry, EvmAddress, EvmTxType, get_balance};
 use crate::evm_scan::{create_ticket_by_tx, scan_evm_task};
@@ -79,6 +79,20 @@ pub fn bridge_ticket_to_evm_task() {
     });
 }
 
+/// notified by the hub once the route subscribed the push mode,
+/// the tickets are pulled immediately instead of waiting for the next timer
+#[update(guard = "is_hub")]
+fn on_new_tickets(from_seq: u64) {
+    log!(INFO, "[on new tickets] notified from seq: {}", from_seq);
+    bridge_ticket_to_evm_task();
+}
+
+#[update(guard = "is_hub")]
+fn on_new_directives(from_seq: u64) {
+    log!(INFO, "[on new directives] notified from seq: {}", from_seq);
+    bridge_ticket_to_evm_task();
+}
+
 #[query]
 fn get_ticket(ticket_id: String) -> Option<(u64, Ticket)> {
     let r = read_state(|s| {
@@ -182,6 +196,17 @@ fn mint_token_status(ticket_id: String) -> MintTokenStatus {
     })
 }
 
+/// the total supply of the token contract, it`s used by the hub to reconcile the token position
+#[update(guard = "is_hub_or_admin")]
+async fn get_token_supply(token_id: String) -> Result<u128, String> {
+    let token_addr = read_state(|s| s.token_contracts.get(&token_id).cloned())
+        .ok_or(format!("token contract not found: {}", token_id))?;
+    let supply = call_rpc_with_retry(token_addr, eth_common::get_token_supply)
+        .await
+        .map_err(|e| e.to_string())?;
+    u128::try_from(supply).map_err(|e| e.to_string())
+}
+
 #[query]
 fn get_fee(chain_id: ChainId) -> Option<u64> {
     get_redeem_fee(chain_id)
@@ -227,6 +252,21 @@ fn update_rpc_check_rate(min_resp_count: usize, total_required_rpc_count: usize)
     });
 }
 
+fn is_hub() -> Result<(), String> {
+    match read_state(|s| s.hub_principal == ic_cdk::caller()) {
+        true => Ok(()),
+        false => Err("caller is not hub".to_string()),
+    }
+}
+
+fn is_hub_or_admin() -> Result<(), String> {
+    let c = ic_cdk::caller();
+    match read_state(|s| s.hub_principal == c) {
+        true => Ok(()),
+        false => is_admin(),
+    }
+}
+
 fn is_admin() -> Result<(), String> {
     let c = ic_cdk::caller();
     match ic_cdk::api::is_controller(&c) || read_state(|s| s.admins.contains(&c)) {
@@ -217,6 +217,7 @@ pub enum TicketType {
     #[default]
     Normal,
     Resubmit,
+    Refund,
 }
 
 #[derive(
@@ -1,5 +1,11 @@
+use std::cell::Cell;
+
 use crate::state::mutate_state;
 
+thread_local! {
+    static PROCESSING_DIRECTIVES: Cell<bool> = Cell::new(false);
+}
+
 #[must_use]
 pub struct TimerLogicGuard(());
 
@@ -22,3 +28,23 @@ impl Drop for TimerLogicGuard {
         });
     }
 }
+
+/// the directives are processed by the timer and when notified by the hub,
+/// the guard keeps them from overlapping and is released even if the task traps
+#[must_use]
+pub struct DirectiveLogicGuard(());
+
+impl DirectiveLogicGuard {
+    pub fn new() -> Option<Self> {
+        if PROCESSING_DIRECTIVES.with(|p| p.replace(true)) {
+            return None;
+        }
+        Some(DirectiveLogicGuard(()))
+    }
+}
+
+impl Drop for DirectiveLogicGuard {
+    fn drop(&mut self) {
+        PROCESSING_DIRECTIVES.with(|p| p.set(false));
+    }
+}
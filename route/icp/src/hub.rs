@@ -90,3 +90,33 @@ pub async fn update_tx_hash(
     })?;
     Ok(())
 }
+
+pub async fn ack_tickets(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    let resp: (Result<(), omnity_types::Error>,) =
+        ic_cdk::api::call::call(hub_principal, "ack_tickets", (None::<ChainId>, seq))
+            .await
+            .map_err(|(code, message)| CallError {
+                method: "ack_tickets".to_string(),
+                reason: Reason::from_reject(code, message),
+            })?;
+    resp.0.map_err(|err| CallError {
+        method: "ack_tickets".to_string(),
+        reason: Reason::CanisterError(err.to_string()),
+    })?;
+    Ok(())
+}
+
+pub async fn ack_directives(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    let resp: (Result<(), omnity_types::Error>,) =
+        ic_cdk::api::call::call(hub_principal, "ack_directives", (None::<ChainId>, seq))
+            .await
+            .map_err(|(code, message)| CallError {
+                method: "ack_directives".to_string(),
+                reason: Reason::from_reject(code, message),
+            })?;
+    resp.0.map_err(|err| CallError {
+        method: "ack_directives".to_string(),
+        reason: Reason::CanisterError(err.to_string()),
+    })?;
+    Ok(())
+}
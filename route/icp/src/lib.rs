@@ -102,7 +102,12 @@ async fn process_tickets() {
                 }
                 next_seq = seq + 1;
             }
-            mutate_state(|s| s.next_ticket_seq = next_seq)
+            mutate_state(|s| s.next_ticket_seq = next_seq);
+            if next_seq > offset {
+                if let Err(err) = hub::ack_tickets(hub_principal, next_seq - 1).await {
+                    log!(ERROR, "[process tickets] failed to ack tickets, err: {}", err);
+                }
+            }
         }
         Err(err) => {
             log!(ERROR, "[process tickets] failed to query tickets, err: {}", err);
@@ -152,6 +157,14 @@ async fn process_directives() {
             mutate_state(|s| {
                 s.next_directive_seq = next_seq;
             });
+            if next_seq > offset {
+                if let Err(err) = hub::ack_directives(hub_principal, next_seq - 1).await {
+                    log!(ERROR,
+                        "[process directives] failed to ack directives, err: {}",
+                        err
+                    );
+                }
+            }
         }
         Err(err) => {
             log!(ERROR,
@@ -164,7 +177,10 @@ async fn process_directives() {
 
 pub fn process_directive_msg_task() {
     ic_cdk::spawn(async {
-        // Considering that the directive is queried once a minute, guard protection is not needed.
+        let _guard = match crate::guard::DirectiveLogicGuard::new() {
+            Some(guard) => guard,
+            None => return,
+        };
         process_directives().await;
     });
 }
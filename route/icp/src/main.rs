@@ -26,6 +26,7 @@ use icrc_ledger_types::icrc1::transfer::TransferArg;
 
 pub use ic_canister_log::log;
 pub use omnity_types::ic_log::{ERROR, INFO};
+use num_traits::cast::ToPrimitive;
 use omnity_types::{Chain, ChainId, Ticket};
 use std::time::Duration;
 
@@ -97,6 +98,36 @@ pub fn is_controller() -> Result<(), String> {
     }
 }
 
+pub fn is_hub() -> Result<(), String> {
+    if read_state(|s| s.hub_principal) == ic_cdk::caller() {
+        Ok(())
+    } else {
+        Err("caller is not hub".to_string())
+    }
+}
+
+pub fn is_hub_or_controller() -> Result<(), String> {
+    if read_state(|s| s.hub_principal) == ic_cdk::caller() {
+        Ok(())
+    } else {
+        is_controller()
+    }
+}
+
+/// notified by the hub once the route subscribed the push mode,
+/// the tickets are pulled immediately instead of waiting for the next timer
+#[update(guard = "is_hub")]
+fn on_new_tickets(from_seq: u64) {
+    log!(INFO, "[on new tickets] notified from seq: {}", from_seq);
+    process_ticket_msg_task();
+}
+
+#[update(guard = "is_hub")]
+fn on_new_directives(from_seq: u64) {
+    log!(INFO, "[on new directives] notified from seq: {}", from_seq);
+    process_directive_msg_task();
+}
+
 #[update(guard = "is_controller")]
 pub async fn remove_controller(
     canister_id: Principal,
@@ -275,6 +306,27 @@ fn get_token_ledger(token_id: String) -> Option<Principal> {
     read_state(|s| s.token_ledgers.get(&token_id).cloned())
 }
 
+/// the total supply of the token ledger, it`s used by the hub to reconcile the token position
+#[update(guard = "is_hub_or_controller")]
+async fn get_token_supply(token_id: String) -> Result<u128, String> {
+    let ledger_id = read_state(|s| s.token_ledgers.get(&token_id).cloned())
+        .ok_or(format!("token ledger not found: {}", token_id))?;
+    let client = ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id: ledger_id,
+    };
+    let total_supply = client.total_supply().await.map_err(|(code, msg)| {
+        format!(
+            "failed to get total supply from ledger: {} code: {} msg: {}",
+            ledger_id, code, msg
+        )
+    })?;
+    total_supply
+        .0
+        .to_u128()
+        .ok_or(format!("total supply does not fit into u128: {}", total_supply))
+}
+
 #[query(hidden = true, guard = "is_controller")]
 pub fn get_log_records(offset: usize, limit: usize) -> Logs {
     ic_log::take_memory_records(limit, offset)
@@ -22,6 +22,18 @@ pub fn is_admin() -> Result<(), String> {
     })
 }
 
+pub fn is_hub() -> Result<(), String> {
+    let caller = ic_cdk::api::caller();
+    read_state(|s| {
+        if s.hub_principal == caller {
+            Ok(())
+        } else {
+            ic_cdk::eprintln!("{:?} Not Hub!", caller.to_string());
+            Err("Not Hub!".into())
+        }
+    })
+}
+
 pub fn auth_update() -> Result<(), String> {
     let caller = ic_cdk::api::caller();
     ic_cdk::println!("auth update for caller: {:?}", caller.to_string());
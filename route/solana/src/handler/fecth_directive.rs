@@ -60,6 +60,15 @@ pub async fn query_directives() {
             mutate_state(|s| {
                 s.seqs.next_directive_seq = next_seq;
             });
+            if next_seq > offset {
+                if let Err(err) = inner_ack_directives(hub_principal, next_seq - 1).await {
+                    log!(
+                        ERROR,
+                        "[query_directives] failed to ack directives, err: {:?}",
+                        err
+                    );
+                }
+            }
         }
         Err(err) => {
             log!(
@@ -97,3 +106,18 @@ pub async fn inner_query_directives(
     })?;
     Ok(data)
 }
+
+/// acknowledge the hub the directives up to the seq are received
+pub async fn inner_ack_directives(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    let resp: (Result<(), Error>,) =
+        ic_cdk::api::call::call(hub_principal, "ack_directives", (None::<ChainId>, seq))
+            .await
+            .map_err(|(code, message)| CallError {
+                method: "ack_directives".to_string(),
+                reason: Reason::from_reject(code, message),
+            })?;
+    resp.0.map_err(|err| CallError {
+        method: "ack_directives".to_string(),
+        reason: Reason::CanisterError(err.to_string()),
+    })
+}
@@ -51,7 +51,16 @@ pub async fn query_tickets() {
                 mutate_state(|s| s.tickets_queue.insert(*seq, ticket.to_owned()));
                 next_seq = seq + 1;
             }
-            mutate_state(|s| s.seqs.next_ticket_seq = next_seq)
+            mutate_state(|s| s.seqs.next_ticket_seq = next_seq);
+            if next_seq > offset {
+                if let Err(e) = inner_ack_tickets(hub_principal, next_seq - 1).await {
+                    log!(
+                        ERROR,
+                        "[fetch_ticket::query_tickets] failed to ack tickets, err: {}",
+                        e.to_string()
+                    );
+                }
+            }
         }
         Err(e) => {
             log!(
@@ -85,3 +94,18 @@ pub async fn inner_query_tickets(
     })?;
     Ok(data)
 }
+
+/// acknowledge the hub the tickets up to the seq are received
+pub async fn inner_ack_tickets(hub_principal: Principal, seq: Seq) -> Result<(), CallError> {
+    let resp: (Result<(), Error>,) =
+        ic_cdk::api::call::call(hub_principal, "ack_tickets", (None::<ChainId>, seq))
+            .await
+            .map_err(|(code, message)| CallError {
+                method: "ack_tickets".to_string(),
+                reason: Reason::from_reject(code, message),
+            })?;
+    resp.0.map_err(|err| CallError {
+        method: "ack_tickets".to_string(),
+        reason: Reason::CanisterError(err.to_string()),
+    })
+}
@@ -21,20 +21,39 @@ thread_local! {
     static TIMER_GUARD: RefCell<HashMap<TaskType,TimerId>> = RefCell::new(HashMap::default());
 }
 
+/// run by the timer and when notified of the new directives by the hub
+pub fn query_directives_task() {
+    ic_cdk::spawn(async {
+        let _guard = match TimerGuard::new(TaskType::GetDirectives) {
+            Ok(guard) => guard,
+            Err(e) => {
+                log!(WARNING, "TaskType::GetDirectives error : {:?}", e);
+                return;
+            }
+        };
+        fecth_directive::query_directives().await;
+    });
+}
+
+/// run by the timer and when notified of the new tickets by the hub
+pub fn query_tickets_task() {
+    ic_cdk::spawn(async {
+        let _guard = match TimerGuard::new(TaskType::GetTickets) {
+            Ok(guard) => guard,
+            Err(e) => {
+                log!(WARNING, "TaskType::GetTickets error : {:?}", e);
+                return;
+            }
+        };
+
+        fetch_ticket::query_tickets().await;
+    });
+}
+
 pub fn start_schedule() {
     // query_directives task
-    let directive_timer_id = ic_cdk_timers::set_timer_interval(QUERY_DERECTIVE_INTERVAL, || {
-        ic_cdk::spawn(async {
-            let _guard = match TimerGuard::new(TaskType::GetDirectives) {
-                Ok(guard) => guard,
-                Err(e) => {
-                    log!(WARNING, "TaskType::GetDirectives error : {:?}", e);
-                    return;
-                }
-            };
-            fecth_directive::query_directives().await;
-        });
-    });
+    let directive_timer_id =
+        ic_cdk_timers::set_timer_interval(QUERY_DERECTIVE_INTERVAL, query_directives_task);
     log!(DEBUG, " GetDirectives task id : {:?}", directive_timer_id);
     TIMER_GUARD.with_borrow_mut(|guard| {
         guard.insert(TaskType::GetDirectives, directive_timer_id);
@@ -77,19 +96,8 @@ pub fn start_schedule() {
     });
 
     // query_tickets task
-    let query_ticket_timer_id = ic_cdk_timers::set_timer_interval(QUERY_TICKET_INTERVAL, || {
-        ic_cdk::spawn(async {
-            let _guard = match TimerGuard::new(TaskType::GetTickets) {
-                Ok(guard) => guard,
-                Err(e) => {
-                    log!(WARNING, "TaskType::GetTickets error : {:?}", e);
-                    return;
-                }
-            };
-
-            fetch_ticket::query_tickets().await;
-        });
-    });
+    let query_ticket_timer_id =
+        ic_cdk_timers::set_timer_interval(QUERY_TICKET_INTERVAL, query_tickets_task);
     log!(DEBUG, "GetTickets task id : {:?}", query_ticket_timer_id);
     TIMER_GUARD.with_borrow_mut(|guard| {
         guard.insert(TaskType::GetTickets, query_ticket_timer_id);
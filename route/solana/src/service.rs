@@ -1,4 +1,4 @@
-use crate::auth::{is_admin, set_perms, Permission};
+use crate::auth::{is_admin, is_hub, set_perms, Permission};
 use crate::call_error::{CallError, Reason};
 use crate::guard::TaskType;
 use crate::handler::associated_account::update_ata_status;
@@ -748,6 +748,20 @@ pub async fn remove_ticket_from_quene(ticket_id: String) -> Option<Ticket> {
 }
 
 
+/// notified by the hub once the route subscribed the push mode,
+/// the tickets are pulled immediately instead of waiting for the next timer
+#[update(guard = "is_hub")]
+fn on_new_tickets(from_seq: u64) {
+    log!(DEBUG, "[on_new_tickets] notified from seq: {}", from_seq);
+    scheduler::query_tickets_task();
+}
+
+#[update(guard = "is_hub")]
+fn on_new_directives(from_seq: u64) {
+    log!(DEBUG, "[on_new_directives] notified from seq: {}", from_seq);
+    scheduler::query_directives_task();
+}
+
 // query mint_token_statue for the given ticket id
 #[query]
 pub async fn mint_token_status(ticket_id: String) -> Result<TxStatus, CallError> {
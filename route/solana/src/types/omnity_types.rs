@@ -179,6 +179,7 @@ pub enum TicketType {
     #[default]
     Normal,
     Resubmit,
+    Refund,
 }
 
 #[derive(
@@ -16,6 +16,12 @@ pub async fn process_tickets() {
                     s.next_ticket_seq = seq + 1
                 });
             }
+            let next_seq = read_state(|s| s.next_ticket_seq);
+            if next_seq > offset {
+                if let Err(err) = hub::ack_tickets(hub_principal, next_seq - 1).await {
+                    log::error!("[process tickets] failed to ack tickets, err: {}", err);
+                }
+            }
         }
         Err(err) => {
             log::error!("[process tickets] failed to query tickets, err: {}", err);
@@ -64,6 +70,15 @@ async fn process_directives() {
             mutate_state(|s| {
                 s.next_directive_seq = next_seq;
             });
+            if next_seq > offset {
+                if let Err(err) = hub::ack_directives(hub_principal, next_seq - 1).await {
+                    log!(
+                        ERROR,
+                        "[process directives] failed to ack directives, err: {:?}",
+                        err
+                    );
+                }
+            }
         }
         Err(err) => {
             log!(
@@ -18,7 +18,9 @@ use crate::state::{
     bridge_fee, mutate_state, read_state, replace_state, StateProfile, TonRouteState,
 };
 use crate::ton_to_route::scan_mint_events_task;
-use crate::toncenter::{check_bridge_fee, create_ticket_by_generate_ticket, get_account_seqno};
+use crate::toncenter::{
+    check_bridge_fee, create_ticket_by_generate_ticket, get_account_seqno, query_jetton_supply,
+};
 use crate::types::{MintTokenStatus, PendingDirectiveStatus, PendingTicketStatus, TokenResp};
 use omnity_types::ic_log::INFO;
 use omnity_types::{Chain, ChainId, Directive, Seq, Ticket};
@@ -98,6 +100,20 @@ fn start_tasks() {
     );
 }
 
+/// notified by the hub once the route subscribed the push mode,
+/// the tickets are pulled immediately instead of waiting for the next timer
+#[update(guard = "is_hub")]
+fn on_new_tickets(from_seq: u64) {
+    log!(INFO, "[on new tickets] notified from seq: {}", from_seq);
+    fetch_hub_ticket_task();
+}
+
+#[update(guard = "is_hub")]
+fn on_new_directives(from_seq: u64) {
+    log!(INFO, "[on new directives] notified from seq: {}", from_seq);
+    fetch_hub_directive_task();
+}
+
 pub fn bridge_to_ton_task() {
     ic_cdk::spawn(async {
         let _guard = match crate::guard::TimerLogicGuard::new(SEND_TON_TASK_NAME.to_string()) {
@@ -198,6 +214,16 @@ fn mint_token_status(ticket_id: String) -> MintTokenStatus {
     })
 }
 
+/// the total supply of the jetton, it`s used by the hub to reconcile the token position
+#[update(guard = "is_hub_or_admin")]
+async fn get_token_supply(token_id: String) -> Result<u128, String> {
+    let jetton_master = read_state(|s| s.token_jetton_master_map.get(&token_id).cloned())
+        .ok_or(format!("jetton master not found: {}", token_id))?;
+    query_jetton_supply(&jetton_master)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[query]
 fn get_fee(chain_id: ChainId) -> (Option<u64>, String) {
     let r = bridge_fee(&chain_id);
@@ -255,6 +281,21 @@ fn update_admins(admins: Vec<Principal>) {
     mutate_state(|s| s.admins = admins);
 }
 
+fn is_hub() -> Result<(), String> {
+    match read_state(|s| s.hub_principal == ic_cdk::caller()) {
+        true => Ok(()),
+        false => Err("caller is not hub".to_string()),
+    }
+}
+
+fn is_hub_or_admin() -> Result<(), String> {
+    let c = ic_cdk::caller();
+    match read_state(|s| s.hub_principal == c) {
+        true => Ok(()),
+        false => is_admin(),
+    }
+}
+
 fn is_admin() -> Result<(), String> {
     let c = ic_cdk::caller();
     match ic_cdk::api::is_controller(&c) || read_state(|s| s.admins.contains(&c)) {
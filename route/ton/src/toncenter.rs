@@ -121,6 +121,40 @@ pub async fn query_burn_events(
         .map_err(|_| anyhow!("failed to decode transaction from json".to_string()))
 }
 
+pub async fn query_jetton_supply(jetton_master: &str) -> anyhow::Result<u128> {
+    let jetton_master = urlencoding::encode(jetton_master).to_string();
+    let url = format!("{TONCENTER_BASE_URL}/api/v3/jetton/masters?address={jetton_master}&limit=1&offset=0");
+    let mut request = CanisterHttpRequestArgument {
+        url,
+        method: HttpMethod::GET,
+        body: None,
+        max_response_bytes: Some(10000),
+        transform: Some(TransformContext {
+            function: TransformFunc(candid::Func {
+                principal: ic_cdk::api::id(),
+                method: "transform".to_string(),
+            }),
+            context: vec![],
+        }),
+        headers: vec![HttpHeader {
+            name: "Content-Type".to_string(),
+            value: "application/json".to_string(),
+        }],
+    };
+    proxy_request(&mut request);
+    let resp_body = do_http_request(request).await?;
+    let resp: QueryJettonMasterResponse = serde_json::from_str(&resp_body)
+        .map_err(|_| anyhow!("failed to decode jetton masters from json".to_string()))?;
+    let master = resp
+        .jetton_masters
+        .first()
+        .ok_or(anyhow!("jetton master not found"))?;
+    master
+        .total_supply
+        .parse::<u128>()
+        .map_err(|e| anyhow!(e.to_string()))
+}
+
 pub async fn query_mint_message() -> anyhow::Result<QueryMessageResponse> {
     let source_addr = urlencoding::encode(&minter_addr()).to_string();
     let url = format!("{TONCENTER_BASE_URL}/api/v3/messages?source={source_addr}&opcode=15&limit=20&offset=0&sort=desc");
@@ -353,6 +387,17 @@ struct TonTransferDetail {
     pub encrypted: bool,
 }
 
+#[derive(Serialize, Default, Deserialize, Clone)]
+struct QueryJettonMasterResponse {
+    pub jetton_masters: Vec<JettonMaster>,
+}
+
+#[derive(Serialize, Default, Deserialize, Clone)]
+struct JettonMaster {
+    pub address: String,
+    pub total_supply: String,
+}
+
 #[derive(Serialize, Default, Deserialize, Clone, CandidType)]
 pub struct QueryJettonBurnResponse {
     pub(self) jetton_burns: Vec<JettonBurnEvent>,
@@ -2,16 +2,18 @@ use std::borrow::Cow;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 
-use candid::CandidType;
+use candid::{CandidType, Principal};
 use ic_stable_structures::Storable;
 use ic_stable_structures::storable::Bound;
 use serde::{Deserialize, Serialize};
 
-use crate::{Chain, ChainId, ChainState, ChainType, Factor, ToggleState, Token, TokenId};
+use crate::{
+    Chain, ChainId, ChainState, ChainType, Factor, TicketId, ToggleState, Token, TokenId, TxHash,
+};
 
 
 
-#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
 pub enum Proposal {
     AddChain(ChainMeta),
     AddToken(TokenMeta),
@@ -19,6 +21,15 @@ pub enum Proposal {
     UpdateToken(TokenMeta),
     ToggleChainState(ToggleState),
     UpdateFee(Factor),
+    UpdateGovernance(GovernanceConfig),
+    UpdateRateLimit(TokenRateLimit),
+    RemoveRateLimit(TokenId),
+    ReleaseHeldTicket(TicketId),
+    UpdateDeliveryPolicy(DeliveryPolicy),
+    // the chain id and the cooldown(ns) between two resubmissions of a ticket
+    UpdateResubmitCooldown(ChainId, u64),
+    RefundTicket(TicketId),
+    UpdateSupplyAudit(SupplyAuditConfig),
 }
 
 impl Storable for Proposal {
@@ -37,6 +48,105 @@ impl Storable for Proposal {
     const BOUND: Bound = Bound::Unbounded;
 }
 
+/// The approvers of the hub proposals,
+/// a proposal is approved once `threshold` of the `approvers` voted for it
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct GovernanceConfig {
+    pub approvers: BTreeSet<Principal>,
+    pub threshold: u32,
+    // the delay(ns) between approval and execution
+    pub timelock: u64,
+    // the period(ns) an open proposal can be voted
+    pub voting_period: u64,
+}
+
+impl core::fmt::Display for GovernanceConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(
+            f,
+            "\napprovers:{:?} \nthreshold:{} \ntimelock:{} \nvoting period:{}",
+            self.approvers, self.threshold, self.timelock, self.voting_period,
+        )
+    }
+}
+
+/// The rolling window volume limits of a token,
+/// the tickets breaching the limits are held until they are released by a proposal
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct TokenRateLimit {
+    pub token_id: TokenId,
+    // the rolling window(ns)
+    pub window: u64,
+    // the max amount of the token moved in the window
+    pub token_limit: Option<u128>,
+    // the max amount of the token sent from one chain in the window
+    pub src_chain_limit: Option<u128>,
+    // the max amount of the token sent to one chain in the window
+    pub dst_chain_limit: Option<u128>,
+    // the max amount of a single ticket
+    pub ticket_limit: Option<u128>,
+    // the src chain will be deactivated once its volume in the window exceeds it
+    pub circuit_breaker: Option<u128>,
+}
+
+impl core::fmt::Display for TokenRateLimit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(
+            f,
+            "\ntoken id:{} \nwindow:{} \ntoken limit:{:?} \nsrc chain limit:{:?} \ndst chain limit:{:?} \nticket limit:{:?} \ncircuit breaker:{:?}",
+            self.token_id,
+            self.window,
+            self.token_limit,
+            self.src_chain_limit,
+            self.dst_chain_limit,
+            self.ticket_limit,
+            self.circuit_breaker,
+        )
+    }
+}
+
+/// The delivery deadline of the tickets sent to a chain,
+/// the ticket without the tx hash after the deadline is overdue
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct DeliveryPolicy {
+    pub chain_id: ChainId,
+    // the deadline(ns) since the ticket was accepted
+    pub deadline: u64,
+    // resubmit the overdue tickets automatically
+    pub auto_resubmit: bool,
+    // the max times a ticket can be resubmitted automatically
+    pub max_auto_resubmit: u32,
+}
+
+impl core::fmt::Display for DeliveryPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(
+            f,
+            "\nchain id:{} \ndeadline:{} \nauto resubmit:{} \nmax auto resubmit:{}",
+            self.chain_id, self.deadline, self.auto_resubmit, self.max_auto_resubmit,
+        )
+    }
+}
+
+/// The token supply on each chain is compared with the token position periodically
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct SupplyAuditConfig {
+    // the tolerated drift between the supply and the position, in basis points
+    pub tolerance_bps: u32,
+    // deactivate the chain once its supply mismatches in consecutive reconciliations
+    pub auto_deactivate: bool,
+}
+
+impl core::fmt::Display for SupplyAuditConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(
+            f,
+            "\ntolerance bps:{} \nauto deactivate:{}",
+            self.tolerance_bps, self.auto_deactivate,
+        )
+    }
+}
+
 /// chain id spec:
 /// for settlement chain, the chain id is: Bitcoin, Ethereum,or ICP
 /// for execution chain, the chain id spec is: type-chain_name,eg: EVM-Base,Cosmos-Gaia, Substrate-Xxx
@@ -270,3 +380,44 @@ impl Storable for Subscribers {
     const BOUND: Bound = Bound::Unbounded;
 }
 
+
+/// The lifecycle status of a ticket in the hub
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub enum TicketStatus {
+    // waiting for finalization
+    Pending,
+    // parked by the rate limits
+    Held { reason: String },
+    // checked and pushed into the queue of the dst chain
+    Accepted,
+    // the dst route acknowledged it received the ticket
+    DeliveredToRoute,
+    Executed { tx_hash: TxHash },
+    Failed { reason: String },
+    Resubmitted { new_id: TicketId },
+    // the reverse ticket was sent back to the src chain
+    Refunded { refund_id: TicketId },
+}
+
+impl TicketStatus {
+    /// compare the status without the payload
+    pub fn same_kind(&self, other: &TicketStatus) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl Storable for TicketStatus {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = vec![];
+        let _ = ciborium::ser::into_writer(self, &mut bytes);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let status =
+            ciborium::de::from_reader(bytes.as_ref()).expect("failed to decode TicketStatus");
+        status
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
@@ -185,6 +185,8 @@ pub enum TicketType {
     #[default]
     Normal,
     Resubmit,
+    // the reverse ticket of an undeliverable ticket
+    Refund,
 }
 
 #[derive(
@@ -778,6 +780,8 @@ pub enum Error {
     ResubmitTicketMustSame,
     #[error("The resumit ticket sent too often")]
     ResubmitTicketSentTooOften,
+    #[error("The ticket (`{0}`) is held: `{1}`")]
+    TicketHeld(String, String),
     #[error("not found chain: (`{0}`)")]
     NotFoundChain(String),
     #[error("not found token: (`{0}`)")]